@@ -1,7 +1,11 @@
 #!/bin/sh
 
 cargo clean
+cargo rel3
+(cd ../symtab && cargo run --release -- ../kernel/target/aarch64-unknown-none/release/picrust)
 cargo img3
+cargo rel4
+(cd ../symtab && cargo run --release -- ../kernel/target/aarch64-unknown-none/release/picrust)
 cargo img4
 
 
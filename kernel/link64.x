@@ -21,6 +21,12 @@ SECTIONS
 	.data : ALIGN(0x1000) { 
 		*(.data .data.* .gnu.linkonce.d*)	
 	}
+	/* reserved for the symbol table, filled in after linking by the symtab tool */
+	.symbols : ALIGN(0x1000) {
+		__symbols_start = .;
+		KEEP(*(.symbols))
+		__symbols_end = .;
+	}
 	.bss (NOLOAD) : ALIGN(0x1000) {
 		__bss_start = .;
 		*(.bss .bss.*)
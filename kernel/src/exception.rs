@@ -1,8 +1,12 @@
+use core::sync::atomic::{AtomicPtr, Ordering};
 use core::{arch::global_asm, fmt::Debug};
 
 use mystd::{bit_field, bitfield::BitField};
 
-use crate::system::{arm_core::registers::aarch64::special_purpose::elr_elx, peripherals::{interrupts, system_timer, uart::{self, UART_0}}};
+use crate::println_log;
+use crate::system::arm_core::mmu::walk;
+use crate::system::backtrace::Backtrace;
+use crate::system::{arm_core::registers::aarch64::special_purpose::elr_elx, peripherals::uart::UART_0};
 
 #[inline]
 pub fn return_from_el3(address: *const ()) -> ! {
@@ -55,79 +59,159 @@ pub fn securemonitor_call() -> ! {
 }
 
 
+/// The state of the interrupted code, saved by the exception vectors on the stack and restored
+/// from it before the `eret`. Handlers may change it, e.g. to return a value in `x[0]` or to
+/// continue after the faulting instruction.
+#[repr(C)]
+pub struct TrapFrame {
+    /// The general purpose registers `x0` to `x30`, `x30` being the link register.
+    pub x: [u64; 31],
+    pub sp_el0: u64,
+    /// Where `eret` continues.
+    pub elr: u64,
+    pub spsr: u64,
+    /// Only saved, changing it has no effect.
+    pub esr: u64,
+    /// Only saved, changing it has no effect.
+    pub far: u64,
+    pub fpsr: u64,
+    pub fpcr: u64,
+    /// The SIMD and floating point registers `q0` to `q31`.
+    pub q: [u128; 32],
+}
+
+// the exception vectors depend on this layout
+const _: () = {
+    assert!(core::mem::offset_of!(TrapFrame, sp_el0) == 248);
+    assert!(core::mem::offset_of!(TrapFrame, elr) == 256);
+    assert!(core::mem::offset_of!(TrapFrame, esr) == 272);
+    assert!(core::mem::offset_of!(TrapFrame, fpsr) == 288);
+    assert!(core::mem::offset_of!(TrapFrame, q) == 304);
+    assert!(core::mem::size_of::<TrapFrame>() == 816);
+};
+
+impl TrapFrame {
+    pub fn syndrome(&self) -> ExceptionSyndrome {
+        ExceptionSyndrome(BitField::new(self.esr as usize))
+    }
+
+    /// Continues after the instruction that caused the exception when returning. `svc`, `hvc` and
+    /// `smc` already return to the next instruction.
+    pub fn skip_instruction(&mut self) {
+        self.elr += match self.syndrome().instruction_length() {
+            InstructionLength::Trapped16bitInstruction => 2,
+            InstructionLength::Trapped32bitInstruction => 4,
+        };
+    }
+}
+
+impl core::fmt::Display for TrapFrame {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        for (i, pair) in self.x.chunks(2).enumerate() {
+            match pair {
+                [a, b] => writeln!(f, "x{:<2} {:016x}  x{:<2} {:016x}", i * 2, a, i * 2 + 1, b)?,
+                [lr] => writeln!(f, "x{:<2} {:016x}  SP_EL0 {:016x}", i * 2, lr, self.sp_el0)?,
+                _ => {}
+            }
+        }
+        writeln!(f, "ELR  {:016x}  SPSR {:016x}", self.elr, self.spsr)?;
+        writeln!(f, "ESR  {:016x}  FAR  {:016x}", self.esr, self.far)?;
+        write!(f, "FPSR {:016x}  FPCR {:016x}", self.fpsr, self.fpcr)
+    }
+}
+
+/// Handles a synchronous exception by changing the [TrapFrame], returns `false` if it couldn't.
+pub type ExceptionHandler = fn(&mut TrapFrame) -> bool;
+
+#[allow(clippy::declare_interior_mutable_const)]
+const NO_HANDLER: AtomicPtr<()> = AtomicPtr::new(core::ptr::null_mut());
+static HANDLERS: [AtomicPtr<()>; 64] = [NO_HANDLER; 64];
+
+/// Calls `handler` for the exceptions of `class`, instead of the previous handler. Unhandled
+/// exceptions print the registers and panic, except for `brk` which is skipped.
+pub fn set_handler(class: ExceptionClass, handler: ExceptionHandler) {
+    HANDLERS[class as usize].store(handler as *mut (), Ordering::Release);
+}
+
+pub fn remove_handler(class: ExceptionClass) {
+    HANDLERS[class as usize].store(core::ptr::null_mut(), Ordering::Release);
+}
+
+fn handler(class: ExceptionClass) -> Option<ExceptionHandler> {
+    let ptr = HANDLERS[class as usize].load(Ordering::Acquire);
+    if ptr.is_null() {
+        None
+    } else {
+        // SAFETY: only `set_handler` stores non-null pointers, and those are `ExceptionHandler`s
+        Some(unsafe { core::mem::transmute::<*mut (), ExceptionHandler>(ptr) })
+    }
+}
+
+/// Prints the breakpoint and continues after it.
+fn breakpoint(frame: &mut TrapFrame) -> bool {
+    println_log!(
+        "Breakpoint #{:#x} at {:016x}\n{}",
+        frame.syndrome().instruction_specific_syndrome() & 0xffff,
+        frame.elr,
+        frame
+    );
+    frame.skip_instruction();
+    true
+}
+
 #[no_mangle]
-pub extern "C" fn exc_handler(
-    exception_data: AuxExceptionData,
-    syndrome: ExceptionSyndrome,
-    elr: usize,
-    spsr: usize,
-    far: usize,
-) -> ! {
+pub extern "C" fn exc_handler(exception_data: AuxExceptionData, frame: &mut TrapFrame) {
+    // exceptions of programs at EL0 are theirs, they never take the kernel down
+    if matches!(exception_data.origin().value(), Ok(ExceptionOrigin::LowerElAarch64)) {
+        return crate::system::process::handle_exception(&exception_data, frame);
+    }
+    let class = frame.syndrome().exception_class();
+    let is_synchronous = matches!(
+        exception_data.exception_type().value(),
+        Ok(ExceptionType::Synchronous)
+    );
+    if is_synchronous {
+        let handled = match (handler(class), class) {
+            (Some(handler), _) => handler(frame),
+            (None, ExceptionClass::BRKInstructionAArch64) => breakpoint(frame),
+            _ => false,
+        };
+        if handled {
+            return;
+        }
+    }
+
     use mystd::io::Write;
     let mut uart = UART_0;
     uart.init();
     writeln!(&mut uart, "Exception Handler!").unwrap_or_default();
     writeln!(&mut uart, "Exception Data: {:?}", exception_data).unwrap_or_default();
-    writeln!(&mut uart, "{:#?}", syndrome).unwrap_or_default();
-    writeln!(
-        &mut uart,
-        "ELR:  {:0width$x}",
-        elr,
-        width = core::mem::size_of::<usize>() * 2
-    )
-    .unwrap_or_default();
-    writeln!(
-        &mut uart,
-        "SPSR: {:0width$x}",
-        spsr,
-        width = core::mem::size_of::<usize>() * 2
-    )
-    .unwrap_or_default();
-    writeln!(
+    writeln!(&mut uart, "{:#?}", frame.syndrome()).unwrap_or_default();
+    if let Some(abort) = Abort::from_syndrome(&frame.syndrome()) {
+        writeln!(&mut uart, "{}", abort.report(frame.far)).unwrap_or_default();
+        if let Some(walk) = abort.is_far_valid().then(|| walk::walk_active(frame.far)).flatten() {
+            writeln!(&mut uart, "{}", walk).unwrap_or_default();
+        }
+    }
+    writeln!(&mut uart, "{}", frame).unwrap_or_default();
+    write!(
         &mut uart,
-        "FAR:  {:0width$x}",
-        far,
-        width = core::mem::size_of::<usize>() * 2
+        "{}",
+        Backtrace::from_exception(frame.elr as usize, frame.x[29] as usize)
     )
     .unwrap_or_default();
-
-    // Uart0::putc(b'\n');
-    // Uart0::put_memory(elr as *const u8, 16);
     panic!("EXCEPTION");
 }
 
-
+/// Called by `_handle_irq_and_return_el1` like [exc_handler], with the vector's data in `x0` and
+/// the saved frame in `x1`. Returns the frame to continue with, which is another thread's when
+/// the time slice is over.
 #[no_mangle]
-pub extern "C" fn irq_handler() {
-    let pending_base = interrupts::IrqPendingBase::read_register();
-    //println_debug!("Pending {:#?}", pending_base);
-    if pending_base.pend_reg_1().is_set() {
-        let pending_gpu1 = interrupts::GpuIrqs1::read_pending();
-        //println_debug!("Pending Gpu1 {:#?}", pending_gpu1);
-        if pending_gpu1.system_timers().value() != 0 {
-            let matches = system_timer::SystemTimer::matches();
-            //println_log!("Timer Matches {:#b}", matches.to_underlying());
-            system_timer::SystemTimer::clear_matches(matches);
-            let _ = crate::tests::TEST_LATCH.set();
-        }
-    }
-    if pending_base.pend_reg_2().is_set() {
-        let gpu2 = interrupts::GpuIrqs2::read_pending();
-        //println_debug!("Pending Gpu2 {:#?}", gpu2);
-        if gpu2.uart_int().is_set() {
-            uart::handle_interrupts();
-        }
-    }
-    // if pending_base.is_all_clear() {
-    //     panic!("No pending IRQs?!")
-    // }
-    if pending_base.arm_timer().is_set() {
-
-    }
+pub extern "C" fn irq_handler(_data: AuxExceptionData, frame: &mut TrapFrame) -> *mut TrapFrame {
+    crate::system::hal::interrupts::handle_irq();
+    crate::system::hal::scheduler::preempt(frame)
 }
 
-
-
 bit_field!(pub AuxExceptionData (u64) {
     5:4 => exception_handler_level,
     3:2 => origin: enum ExceptionOrigin {
@@ -145,7 +229,8 @@ bit_field!(pub AuxExceptionData (u64) {
 });
 
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
 pub enum ExceptionClass {
     Unknown = 0b000000,
     TrappedWFxInstructionExecution = 0b000001,
@@ -158,11 +243,12 @@ pub enum ExceptionClass {
     Reserved0x08,
     Reserved0x09,
     TrappedLD64bOrST64bInstruction = 0b001010,
-    Reserved0x0c,
+    Reserved0x0b,
     TrappedMRRCAcessCoproc0xE = 0b001100,
     BranchTargetException = 0b001101,
     IllegalExecutionState = 0b001110,
-    Reserved0x11,
+    Reserved0x0f,
+    Reserved0x10,
     TrappedSVCInstructionAArch32 = 0b010001,
     Reserved0x12,
     Reserved0x13,
@@ -245,7 +331,222 @@ impl ExceptionSyndrome {
     }
 
     pub fn instruction_specific_syndrome(&self) -> u32 {
-        self.0.field(0, 13) as u32
+        self.0.field(0, 25) as u32
+    }
+}
+
+
+bit_field!(pub DataAbortSyndrome(u32) {
+    /// Instruction Syndrome Valid, SAS, SSE, SRT, SF and AR are only valid if set.
+    24 => isv,
+    23:22 => sas: enum AccessSize {
+        Byte = 0,
+        Halfword = 1,
+        Word = 2,
+        Doubleword = 3,
+    },
+    /// The loaded value was sign extended.
+    21 => sse,
+    /// The register that was loaded or stored.
+    20:16 => srt,
+    /// The register is 64 bit wide.
+    15 => sf,
+    /// Acquire/release semantics.
+    14 => ar,
+    /// FAR is not valid.
+    10 => fnv,
+    /// External abort.
+    9 => ea,
+    /// Caused by a cache maintenance instruction.
+    8 => cm,
+    /// Caused by the stage 2 translation of a stage 1 table walk.
+    7 => s1ptw,
+    /// Caused by a write, or a read if clear.
+    6 => wnr,
+    5:0 => dfsc,
+});
+
+bit_field!(pub InstructionAbortSyndrome(u32) {
+    /// FAR is not valid.
+    10 => fnv,
+    /// External abort.
+    9 => ea,
+    /// Caused by the stage 2 translation of a stage 1 table walk.
+    7 => s1ptw,
+    5:0 => ifsc,
+});
+
+/// The cause of an abort, decoded from the DFSC or IFSC.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FaultStatus {
+    AddressSize { level: u8 },
+    Translation { level: u8 },
+    AccessFlag { level: u8 },
+    Permission { level: u8 },
+    SynchronousExternal,
+    SynchronousExternalOnWalk { level: u8 },
+    TagCheck,
+    Parity,
+    ParityOnWalk { level: u8 },
+    Alignment,
+    TlbConflict,
+    UnsupportedAtomicUpdate,
+    Other(u8),
+}
+
+impl FaultStatus {
+    pub fn from_code(code: u8) -> Self {
+        let level = code & 0b11;
+        match code {
+            0b00_0000..=0b00_0011 => Self::AddressSize { level },
+            0b00_0100..=0b00_0111 => Self::Translation { level },
+            0b00_1001..=0b00_1011 => Self::AccessFlag { level },
+            0b00_1101..=0b00_1111 => Self::Permission { level },
+            0b01_0000 => Self::SynchronousExternal,
+            0b01_0001 => Self::TagCheck,
+            0b01_0100..=0b01_0111 => Self::SynchronousExternalOnWalk { level },
+            0b01_1000 => Self::Parity,
+            0b01_1100..=0b01_1111 => Self::ParityOnWalk { level },
+            0b10_0001 => Self::Alignment,
+            0b11_0000 => Self::TlbConflict,
+            0b11_0001 => Self::UnsupportedAtomicUpdate,
+            other => Self::Other(other),
+        }
+    }
+
+    /// The translation level that faulted, if the fault has one.
+    pub fn level(&self) -> Option<u8> {
+        match *self {
+            Self::AddressSize { level }
+            | Self::Translation { level }
+            | Self::AccessFlag { level }
+            | Self::Permission { level }
+            | Self::SynchronousExternalOnWalk { level }
+            | Self::ParityOnWalk { level } => Some(level),
+            _ => None,
+        }
+    }
+}
+
+impl core::fmt::Display for FaultStatus {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        if let Some(level) = self.level() {
+            write!(f, "Level {} ", level)?;
+        }
+        match self {
+            Self::AddressSize { .. } => write!(f, "address size fault"),
+            Self::Translation { .. } => write!(f, "translation fault"),
+            Self::AccessFlag { .. } => write!(f, "access flag fault"),
+            Self::Permission { .. } => write!(f, "permission fault"),
+            Self::SynchronousExternal => write!(f, "synchronous external abort"),
+            Self::SynchronousExternalOnWalk { .. } => {
+                write!(f, "synchronous external abort on table walk")
+            }
+            Self::TagCheck => write!(f, "tag check fault"),
+            Self::Parity => write!(f, "parity or ECC error"),
+            Self::ParityOnWalk { .. } => write!(f, "parity or ECC error on table walk"),
+            Self::Alignment => write!(f, "alignment fault"),
+            Self::TlbConflict => write!(f, "TLB conflict abort"),
+            Self::UnsupportedAtomicUpdate => write!(f, "unsupported atomic hardware update"),
+            Self::Other(code) => write!(f, "fault status {:#08b}", code),
+        }
+    }
+}
+
+/// A data or instruction abort, decoded from the syndrome.
+#[derive(Debug, Clone, Copy)]
+pub enum Abort {
+    Data(DataAbortSyndrome),
+    Instruction(InstructionAbortSyndrome),
+}
+
+impl Abort {
+    pub fn from_syndrome(syndrome: &ExceptionSyndrome) -> Option<Self> {
+        let iss = syndrome.instruction_specific_syndrome();
+        match syndrome.exception_class() {
+            ExceptionClass::DataAbortFromLowerEL | ExceptionClass::DataAbortFromSameEL => {
+                Some(Self::Data(iss.into()))
+            }
+            ExceptionClass::InstructionAbortFromLowerEL
+            | ExceptionClass::InstructionAbortFromSameEL => Some(Self::Instruction(iss.into())),
+            _ => None,
+        }
+    }
+
+    pub fn fault_status(&self) -> FaultStatus {
+        match self {
+            Self::Data(iss) => FaultStatus::from_code(iss.dfsc().value() as u8),
+            Self::Instruction(iss) => FaultStatus::from_code(iss.ifsc().value() as u8),
+        }
+    }
+
+    /// Whether the FAR holds the faulting address.
+    pub fn is_far_valid(&self) -> bool {
+        match self {
+            Self::Data(iss) => iss.fnv().is_clear(),
+            Self::Instruction(iss) => iss.fnv().is_clear(),
+        }
+    }
+
+    /// Describes the abort in one line, e.g. "Level 2 translation fault on write to
+    /// FAR=0x0000000040000000".
+    pub fn report(&self, far: u64) -> AbortReport {
+        AbortReport { abort: *self, far }
+    }
+}
+
+pub struct AbortReport {
+    abort: Abort,
+    far: u64,
+}
+
+impl core::fmt::Display for AbortReport {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{} on ", self.abort.fault_status())?;
+        let (external, walk) = match self.abort {
+            Abort::Data(iss) => {
+                if iss.cm().is_set() {
+                    write!(f, "cache maintenance ")?;
+                }
+                write!(f, "{}", if iss.wnr().is_set() { "write" } else { "read" })?;
+                (iss.ea().is_set(), iss.s1ptw().is_set())
+            }
+            Abort::Instruction(iss) => {
+                write!(f, "instruction fetch")?;
+                (iss.ea().is_set(), iss.s1ptw().is_set())
+            }
+        };
+        if self.abort.is_far_valid() {
+            write!(f, " to FAR={:#018x}", self.far)?;
+        } else {
+            write!(f, " (FAR not valid)")?;
+        }
+        if let Abort::Data(iss) = self.abort {
+            if iss.isv().is_set() {
+                let size = match iss.sas().value() {
+                    Ok(AccessSize::Byte) => 8,
+                    Ok(AccessSize::Halfword) => 16,
+                    Ok(AccessSize::Word) => 32,
+                    Ok(AccessSize::Doubleword) => 64,
+                    Err(_) => 0,
+                };
+                let register = if iss.sf().is_set() { 'x' } else { 'w' };
+                write!(f, ", {} bit access with {}{}", size, register, iss.srt().value())?;
+                if iss.sse().is_set() {
+                    write!(f, " sign extended")?;
+                }
+                if iss.ar().is_set() {
+                    write!(f, " acquire/release")?;
+                }
+            }
+        }
+        if external {
+            write!(f, ", external abort")?;
+        }
+        if walk {
+            write!(f, ", on the stage 2 translation of a table walk")?;
+        }
+        Ok(())
     }
 }
 
@@ -254,7 +555,7 @@ global_asm!(
     r#"
     .section ".text.vector"
 .macro push_registers
-	sub 	sp, sp,     #256
+	sub 	sp, sp,     #816 // size_of::<TrapFrame>()
 	stp 	x0, x1,     [sp, #16 * 0]
 	stp 	x2, x3,     [sp, #16 * 1]
 	stp	    x4, x5,     [sp, #16 * 2]
@@ -290,7 +591,67 @@ global_asm!(
 	ldp	x26, x27, [sp, #16 * 13]
 	ldp	x28, x29, [sp, #16 * 14]
 	ldr	x30, [sp, #16 * 15] 
-	add	sp, sp, #256
+	add	sp, sp, #816
+.endm
+
+// completes the TrapFrame that push_registers started, x9 to x13 are free to use
+.macro save_context el
+    mrs     x9, sp_el0
+    str     x9, [sp, #248]
+    mrs     x10, elr_el\el
+    mrs     x11, spsr_el\el
+    stp     x10, x11, [sp, #256]
+    mrs     x12, esr_el\el
+    mrs     x13, far_el\el
+    stp     x12, x13, [sp, #272]
+    mrs     x9, fpsr
+    mrs     x10, fpcr
+    stp     x9, x10, [sp, #288]
+    stp     q0, q1, [sp, #304 + 32 * 0]
+    stp     q2, q3, [sp, #304 + 32 * 1]
+    stp     q4, q5, [sp, #304 + 32 * 2]
+    stp     q6, q7, [sp, #304 + 32 * 3]
+    stp     q8, q9, [sp, #304 + 32 * 4]
+    stp     q10, q11, [sp, #304 + 32 * 5]
+    stp     q12, q13, [sp, #304 + 32 * 6]
+    stp     q14, q15, [sp, #304 + 32 * 7]
+    stp     q16, q17, [sp, #304 + 32 * 8]
+    stp     q18, q19, [sp, #304 + 32 * 9]
+    stp     q20, q21, [sp, #304 + 32 * 10]
+    stp     q22, q23, [sp, #304 + 32 * 11]
+    stp     q24, q25, [sp, #304 + 32 * 12]
+    stp     q26, q27, [sp, #304 + 32 * 13]
+    stp     q28, q29, [sp, #304 + 32 * 14]
+    stp     q30, q31, [sp, #304 + 32 * 15]
+.endm
+
+// restores what save_context saved, except ESR and FAR, from a possibly changed TrapFrame
+.macro restore_context el
+    ldp     q0, q1, [sp, #304 + 32 * 0]
+    ldp     q2, q3, [sp, #304 + 32 * 1]
+    ldp     q4, q5, [sp, #304 + 32 * 2]
+    ldp     q6, q7, [sp, #304 + 32 * 3]
+    ldp     q8, q9, [sp, #304 + 32 * 4]
+    ldp     q10, q11, [sp, #304 + 32 * 5]
+    ldp     q12, q13, [sp, #304 + 32 * 6]
+    ldp     q14, q15, [sp, #304 + 32 * 7]
+    ldp     q16, q17, [sp, #304 + 32 * 8]
+    ldp     q18, q19, [sp, #304 + 32 * 9]
+    ldp     q20, q21, [sp, #304 + 32 * 10]
+    ldp     q22, q23, [sp, #304 + 32 * 11]
+    ldp     q24, q25, [sp, #304 + 32 * 12]
+    ldp     q26, q27, [sp, #304 + 32 * 13]
+    ldp     q28, q29, [sp, #304 + 32 * 14]
+    ldp     q30, q31, [sp, #304 + 32 * 15]
+    ldp     x9, x10, [sp, #288]
+    msr     fpsr, x9
+    msr     fpcr, x10
+    ldr     x9, [sp, #248]
+    msr     sp_el0, x9
+    ldp     x10, x11, [sp, #256]
+    msr     elr_el\el, x10
+    msr     spsr_el\el, x11
+    pop_registers
 .endm
 
 .macro el1_push_regs_and_go_handle id
@@ -439,26 +800,44 @@ global_asm!(
 
 
         _handle_irq_and_return_el1:
+            save_context 1
+            mov     x1, sp
             bl      irq_handler
-            pop_registers
+            mov     sp, x0
+            bl      thread_switch_finished
+            restore_context 1
+            eret
+
+        // saves the calling thread like an IRQ would, continuing at the ret once it is resumed
+        .global _thread_switch
+        _thread_switch:
+            push_registers
+            save_context 1
+            adr     x9, 1f
+            mrs     x10, daif
+            mov     x11, #0b0101 // EL1h
+            orr     x10, x10, x11
+            stp     x9, x10, [sp, #256]
+            mov     x0, sp
+            bl      thread_switch
+            mov     sp, x0
+            bl      thread_switch_finished
+            restore_context 1
             eret
+        1:  ret
 
         _handle_exc_and_return_el1:
-            mrs     x1, esr_el1
-            mrs     x2, elr_el1
-            mrs     x3, spsr_el1
-            mrs     x4, far_el1
+            save_context 1
+            mov     x1, sp
             bl      exc_handler
-            pop_registers
+            restore_context 1
             eret
         
         _handle_exc_and_return_el2:
-            mrs     x1, esr_el2
-            mrs     x2, elr_el2
-            mrs     x3, spsr_el2
-            mrs     x4, far_el2
+            save_context 2
+            mov     x1, sp
             bl      exc_handler
-            pop_registers
+            restore_context 2
             eret
     "#
 );
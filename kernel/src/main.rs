@@ -7,6 +7,8 @@ compile_error!("target arch not supported! Only aarch64 allowed!");
 #[cfg(any(all(feature = "raspi4", feature = "raspi3b")))]
 compile_error!("Can't compile for multiple Raspberry Pi Models.");
 
+extern crate alloc;
+
 mod exception;
 mod system;
 mod tests;
@@ -44,6 +46,7 @@ use system::peripherals::uart;
 use crate::system::arm_core::wait_for_event;
 use crate::system::arm_core::CoreId;
 use crate::system::hal::led::status_blink_twice;
+use crate::system::hal::info::MemoryMap;
 use crate::system::hal::signal::new_latch;
 
 #[panic_handler]
@@ -57,11 +60,21 @@ fn on_panic(info: &core::panic::PanicInfo) -> ! {
         let mut uart = uart::UART_0;
         // status_blink_twice(500);
         let _ = writeln!(uart, "Doki Doki! {info}");
+        let _ = write!(uart, "{}", system::backtrace::Backtrace::capture());
         loop {
             let _ = writeln!(uart, "press m for monitor, r to reset");
             'inner: loop {
                 match uart.try_get_byte() {
-                    Ok(b'm') => monitor::Monitor::new(uart, uart).run(),
+                    // reads time out, so the XMODEM upload can repeat its start request
+                    Ok(b'm') => monitor::Monitor::new(
+                        uart.with_read_blocking(uart::Blocking::TimeoutAfter(Duration::from_secs(1))),
+                        uart,
+                        system::commands::MONITOR_COMMANDS,
+                    )
+                        .with_symbols(system::symbols::table())
+                        .with_dma_copy(peripherals::dma::dma_copy_slice::<u8>)
+                        .with_registers(&system::registers::SYSTEM_REGISTERS)
+                        .run(),
                     Ok(b'r') => {
                         writeln!(uart, "Resetting...").unwrap();
                         arm_core::reset();
@@ -103,6 +116,13 @@ pub extern "C" fn main() -> ! {
     // println_debug!("Continue after Init.");
     //tests::test_screen();
     // tests::test_dma();
+    // tests::test_exceptions();
+    // tests::test_threads();
+    // tests::test_heap();
+    // tests::test_processes();
+    // tests::test_sd();
+    // tests::test_fat();
+    // tests::test_gpio();
     // if core_id == 0 {
     //print_log!("Bye!");
     // } else {
@@ -123,20 +143,15 @@ pub extern "C" fn main() -> ! {
 #[no_mangle]
 pub extern "C" fn secondary() -> ! {
     let core_num = get_core_num();
+    arm_core::mmu::mmu_init_secondary();
     thread::spin_wait_for(Duration::from_secs(core_num.num() * 3));
     print_init!("Core {} ready for duty", core_num.num());
-    loop {
-        // if matches!(core_num, CoreId::Core1) {
-        //     status_blink_twice(100);
-        // }
-        core::hint::spin_loop();
-    }
+    thread::run_idle()
 }
 
 //global_asm!(".section .font", ".incbin \"901447-10.bin\"");
 
 extern "C" {
-    static __stack_top: usize;
     static mut __bss_start: u8;
     static __bss_end: u8;
 
@@ -158,20 +173,11 @@ pub extern "C" fn _start() -> ! {
 
     // set up the stacks we'll use
     // every core gets 1 / 4 of the first 0x80000 -> 512k / 4 -> 128 kbyte
-    let stack_top = unsafe { core::ptr::addr_of!(__stack_top) } as u64;
-    let core_stack_total_size = stack_top / 4;
-    // EL 2 and EL 3 get two pages (8kb) of stack each
-    const CORE_STACK_EL3_SIZE: u64 = 0x2000;
-    const CORE_STACK_EL2_SIZE: u64 = 0x2000;
-    // EL 0 gets 4 pages (16 kb) of stack
-    const CORE_STACK_EL0_SIZE: u64 = 0x4000;
-    // EL 1 gets the remaining 24 pages (96 kb) of stack 
-    let core_stack_el1_size = core_stack_total_size.wrapping_sub(CORE_STACK_EL3_SIZE + CORE_STACK_EL2_SIZE + CORE_STACK_EL0_SIZE);
-    let core_stack_base = core_stack_total_size * (3 - core_id.num());
-    let core_stack_el3 = core_stack_base + CORE_STACK_EL3_SIZE;
-    let core_stack_el2 = core_stack_el3 + CORE_STACK_EL2_SIZE;
-    let core_stack_el1 = core_stack_el2 + core_stack_el1_size;
-    let core_stack_el0 = core_stack_el1 + CORE_STACK_EL0_SIZE;
+    let stack_top = |el| MemoryMap::core_stack(core_id, el).top() as u64;
+    let core_stack_el3 = stack_top(ExceptionLevel::EL3);
+    let core_stack_el2 = stack_top(ExceptionLevel::EL2);
+    let core_stack_el1 = stack_top(ExceptionLevel::EL1);
+    let core_stack_el0 = stack_top(ExceptionLevel::EL0);
 
     // clear the bss section
     if core_id.is_main() {
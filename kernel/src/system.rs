@@ -1,10 +1,17 @@
 use crate::{print_init, println_debug, println_log};
 
 pub mod arm_core;
+pub mod backtrace;
+pub mod commands;
+pub mod frames;
 pub mod hal;
+pub mod heap;
 pub mod peripherals;
+pub mod process;
 pub mod screen;
 pub mod output;
+pub mod registers;
+pub mod symbols;
 
 
 pub fn initialize() {
@@ -13,7 +20,10 @@ pub fn initialize() {
         arm_core::mmu::mmu_init()
             .expect("MMU should be initialised");
         print_init!("after mmu");
+        frames::init();
+        heap::init().expect("the heap should get memory");
     }
+    hal::interrupts::controller().init();
     if cfg!(feature = "serial_uart") {
         print_init!("before serial uart");
         output::init_serial_uart();
@@ -23,13 +33,15 @@ pub fn initialize() {
     }
     
     if cfg!(feature = "framebuffer") {
-        screen::create_screen(0x50_0000 as *mut u8);
-        output::init_fb_console(0x60_0000 as *mut u8);
+        screen::create_screen(hal::info::MemoryMap::screen_buffer().bottom().cast_mut());
+        output::init_fb_console(hal::info::MemoryMap::console_buffer().bottom().cast_mut());
     //    println_log!("Framebuffer Console created...");
         // print a memory map
     //    println_debug!("{:#?}", hal::info::MemoryMap());
     }
 
+    hal::thread::init().expect("the main thread should start");
+
     //let _a = std_out().lock();
     //writeln!(std_out(), "System Initialized").expect("second write should work");
 }
@@ -37,6 +37,31 @@ pub fn wait_for_interrupt() {
     unsafe { asm!("wfi") }
 }
 
+/// Writes the cache lines covering `start..start + len` back to memory and invalidates them, so a
+/// DMA master sees what the core wrote and the core sees what the DMA master wrote afterwards.
+pub fn clean_and_invalidate_data_cache(start: usize, len: usize) {
+    let ctr: usize;
+    unsafe { asm!("mrs {}, ctr_el0", out(reg) ctr) };
+    // CTR_EL0.DminLine is log2 of the number of words in the smallest data cache line
+    let line = 4 << ((ctr >> 16) & 0xf);
+    for address in (start & !(line - 1)..start + len).step_by(line) {
+        unsafe { asm!("dc civac, {}", in(reg) address) };
+    }
+    unsafe { asm!("dsb sy") };
+}
+
+/// Makes instructions written to `start..start + len` visible to instruction fetches of all cores,
+/// e.g. after loading a program.
+pub fn sync_instruction_cache(start: usize, len: usize) {
+    let ctr: usize;
+    unsafe { asm!("mrs {}, ctr_el0", out(reg) ctr) };
+    let line = 4 << ((ctr >> 16) & 0xf);
+    for address in (start & !(line - 1)..start + len).step_by(line) {
+        unsafe { asm!("dc cvau, {}", in(reg) address) };
+    }
+    unsafe { asm!("dsb ish", "ic ialluis", "dsb ish", "isb") };
+}
+
 pub fn stop_core() -> ! {
     loop { wait_for_event() }
 }
@@ -1,13 +1,20 @@
 pub mod descriptors;
+pub mod mapping;
+pub mod tlb;
+pub mod walk;
 
 use core::arch::asm;
+use core::sync::atomic::{AtomicU64, Ordering};
 
 use mystd::byte_value::ByteValue;
+use mystd::paging::AddressSpace;
 
+use crate::system::arm_core::registers::aarch64::general_sys_ctrl::sctlr_el1::SctlrEl1;
 use crate::system::peripherals::BCM_HOST;
 use crate::system::{arm_core::mmu::descriptors::AddressingMode, hal::info::MemoryMap};
 
 use self::descriptors::{BlockDescriptor, PageDescriptor, TableDescriptor};
+use self::mapping::PhysicalTables;
 
 #[derive(Debug)]
 pub enum MMUInitError {
@@ -22,6 +29,35 @@ pub fn mmu_init() -> Result<(), MMUInitError> {
     Err(MMUInitError::NotImplementedError)
 }
 
+/// The TTBR0_EL1 of the kernel's tables, 0 until [mmu_init] set them up.
+static KERNEL_TTBR0: AtomicU64 = AtomicU64::new(0);
+static KERNEL_TTBR1: AtomicU64 = AtomicU64::new(0);
+
+pub fn kernel_ttbr0() -> u64 {
+    KERNEL_TTBR0.load(Ordering::Relaxed)
+}
+
+/// Whether the MMU of the calling core is on. Until then the memory is Device memory, where
+/// the exclusive accesses of locks and atomic read-modify-writes don't work.
+pub fn is_enabled() -> bool {
+    SctlrEl1::read_register_ordered_ish().m().is_set()
+}
+
+pub fn ttbr0() -> u64 {
+    let value: u64;
+    unsafe { asm!("mrs {}, ttbr0_el1", out(reg) value) };
+    value
+}
+
+/// Switches the tables of the lower address range, and with them the ASID in the top 16 bits of
+/// `value`.
+///
+/// # Safety
+/// The code and data in use have to be mapped the same way by the new tables.
+pub unsafe fn set_ttbr0(value: u64) {
+    asm!("msr ttbr0_el1, {}", "isb", in(reg) value);
+}
+
 const MEMORY_ATTR_IDX_NORMAL: u64 = 0;
 const MEMORY_ATTR_IDX_DEVICE: u64 = 1;
 const MEMORY_ATTR_IDX_NON_CACHEABLE: u64 = 2;
@@ -30,14 +66,8 @@ const MEMORY_ATTR_IDX_NON_CACHEABLE: u64 = 2;
 pub fn mmu_init() -> Result<(), MMUInitError> {
     use crate::print_init;
     // check for 4k granule and at least 36 bits physical address bus */
-    use crate::system::arm_core::mmu::descriptors::Shareability;
     use crate::system::arm_core::registers::aarch64::general_sys_ctrl;
-    use crate::system::arm_core::registers::aarch64::general_sys_ctrl::id_aa64mmfr0_el1::AsidBitNum;
-    use crate::system::arm_core::registers::aarch64::general_sys_ctrl::tcr_el1::GranuleSize;
     use general_sys_ctrl::id_aa64mmfr0_el1 as memory_model_features;
-    use general_sys_ctrl::mair_el1 as memory_attributes;
-    use general_sys_ctrl::sctlr_el1::SctlrEl1;
-    use general_sys_ctrl::tcr_el1::TcrEl1;
 
     let mm_feats = memory_model_features::IdAa64Mmfr0El1::read_register();
 
@@ -51,16 +81,13 @@ pub fn mmu_init() -> Result<(), MMUInitError> {
     }
     print_init!("before table");
     let table = unsafe {
-        let table_ptr = ByteValue::from_mibi(2).as_bytes() as *mut TranslationTable4KB;
+        let table_ptr = MemoryMap::translation_tables().bottom() as *mut TranslationTable4KB;
         TranslationTable4KB::init(table_ptr)
-        // for ia in 0..(1024 * 1024) {
-        //     let ia = ia * 1024;
-        //     let oa = (*table_ptr).simulate_walk(ia).expect("Table Walk should complete");
-        //     println_debug!("Table Walk IA: {:?}, OA: {:?}", ia, oa);
-        //     assert_eq!(ia, oa, "WALK FAILED");
-        // }
-        // panic!("hold it");
     };
+    // the tables map everything to itself
+    for address in [0x8_0000, MemoryMap::screen_buffer().bottom() as u64, BCM_HOST.peripheral_address as u64] {
+        debug_assert_eq!(table.simulate_walk(address), Ok(address), "identity map broken");
+    }
 
     // const PAGE_ENTRY_COUNT: usize = PAGESIZE / core::mem::size_of::<usize>();
     // // granularity
@@ -203,6 +230,48 @@ pub fn mmu_init() -> Result<(), MMUInitError> {
     //         .wrapping_add(5 * PAGE_ENTRY_COUNT)
     //         .write_volatile(physical_address | flags);
     // }
+    // the other cores turn on their MMU with the same tables once these are set, stored while the
+    // caches are still off so that they reach the memory
+    KERNEL_TTBR1.store(table.base_address_rg1(), Ordering::Relaxed);
+    KERNEL_TTBR0.store(table.base_address_rg0(), Ordering::Release);
+    super::send_event();
+    enable_translation(table.base_address_rg0(), table.base_address_rg1());
+    print_init!("End of MMU init");
+    Ok(())
+}
+
+/// Turns on the MMU and caches of a secondary core with the kernel's tables, after waiting for
+/// [mmu_init] on the main core to set them up.
+#[cfg(feature = "mmu")]
+pub fn mmu_init_secondary() {
+    let ttbr0 = loop {
+        match KERNEL_TTBR0.load(Ordering::Acquire) {
+            0 => super::wait_for_event(),
+            ttbr0 => break ttbr0,
+        }
+    };
+    // translations cached before the reset are not to be trusted
+    tlb::invalidate_all();
+    enable_translation(ttbr0, KERNEL_TTBR1.load(Ordering::Relaxed));
+}
+
+#[cfg(not(feature = "mmu"))]
+pub fn mmu_init_secondary() {}
+
+/// Sets the memory attributes and translation control and turns on the MMU and caches with the
+/// tables at `ttbr0` and `ttbr1`.
+#[cfg(feature = "mmu")]
+fn enable_translation(ttbr0: u64, ttbr1: u64) {
+    use crate::system::arm_core::mmu::descriptors::Shareability;
+    use crate::system::arm_core::registers::aarch64::general_sys_ctrl;
+    use crate::system::arm_core::registers::aarch64::general_sys_ctrl::id_aa64mmfr0_el1::AsidBitNum;
+    use crate::system::arm_core::registers::aarch64::general_sys_ctrl::tcr_el1::GranuleSize;
+    use general_sys_ctrl::id_aa64mmfr0_el1 as memory_model_features;
+    use general_sys_ctrl::mair_el1 as memory_attributes;
+    use general_sys_ctrl::tcr_el1::TcrEl1;
+
+    let mm_feats = memory_model_features::IdAa64Mmfr0El1::read_register();
+
     /* okay, now we have to set system registers to enable MMU */
 
     // A72 5.5 p. 5-280: You must set CPUECTLR.SMPEN to 1 before the caches and MMU are enabled,
@@ -316,8 +385,8 @@ pub fn mmu_init() -> Result<(), MMUInitError> {
     // tell the MMU where our translation tables are. TTBR_ENABLE bit not documented, but required
     // lower half, user space
     unsafe {
-        asm!("msr ttbr0_el1, {}", in(reg) table.base_address_rg0());
-        asm!("msr ttbr1_el1, {}", in(reg) table.base_address_rg1());
+        asm!("msr ttbr0_el1, {}", in(reg) ttbr0);
+        asm!("msr ttbr1_el1, {}", in(reg) ttbr1);
     }
     //println_debug!("TTBR0 is set {:#x}", ttbr0_address);
     // upper half, kernel space
@@ -362,8 +431,6 @@ pub fn mmu_init() -> Result<(), MMUInitError> {
     //        (1 << 2) |    // clear C, no cache at all
     //        (1 << 1)); // clear A, no aligment check
     // r |= 1 << 0; // set M, enable MMU
-    print_init!("End of MMU init");
-    Ok(())
 }
 
 #[derive(Clone, Copy)]
@@ -627,13 +694,22 @@ impl TranslationTable4KB {
     fn initialize_level_0(&mut self) {
         // init Level 0 (might not be necessary, depending on T0SZ)
         // map each 512 GB to the first entry in the next table
+        // EL0 gets nothing of the kernel's mappings, processes have tables of their own
         let next_table_range0 = TableDescriptor::default()
-            .with_next_level_table_at(self.range_0_level_1.as_ptr() as u64, Self::ADDRESSING);
+            .with_next_level_table_at(self.range_0_level_1.as_ptr() as u64, Self::ADDRESSING)
+            .stage1_ap()
+            .set_value(descriptors::APTable::UnpriviledgedAccessNotPermitted)
+            .stage1_xn_uxn()
+            .set();
         // // reject addresses over 512 GB
         self.range_0_level_0.fill(next_table_range0);
 
         let next_table_range1 = TableDescriptor::default()
-            .with_next_level_table_at(self.range_1_level_1.as_ptr() as u64, Self::ADDRESSING);
+            .with_next_level_table_at(self.range_1_level_1.as_ptr() as u64, Self::ADDRESSING)
+            .stage1_ap()
+            .set_value(descriptors::APTable::UnpriviledgedAccessNotPermitted)
+            .stage1_xn_uxn()
+            .set();
         // // reject addresses over 512 GB
         self.range_1_level_0.fill(next_table_range1);
     }
@@ -744,56 +820,12 @@ impl TranslationTable4KB {
         }
     }
 
-    // pub fn simulate_walk(&self, input_address: u64) -> Result<u64, u8> {
-    //     const MASK_8: u64 = 0xff;
-    //     const MASK_9: u64 = 0x1ff;
-    //     const MODE: AddressingMode = AddressingMode::Gran4KBAddr48bit;
-    //     const LEVEL_0_OA_MASK: u64 = (1 << 39) - 1;
-    //     const LEVEL_1_OA_MASK: u64 = (1 << 30) - 1;
-    //     const LEVEL_2_OA_MASK: u64 = (1 << 21) - 1;
-    //     const LEVEL_3_OA_MASK: u64 = (1 << 12) - 1;
-    //     let level0idx = (input_address >> 39) & MASK_8;
-    //     let level1idx = (input_address >> 30) & MASK_9;
-    //     let level2idx = (input_address >> 21) & MASK_9;
-    //     let level3idx = (input_address >> 12) & MASK_9;
-
-    //     let table_entry = self.level0[level0idx as usize];
-    //     let next_table_address = match table_entry.format().value().expect("all values are handled") {
-    //         descriptors::TableOrBlockDescriptorFormat::Invalid00 => return Err(0),
-    //         descriptors::TableOrBlockDescriptorFormat::Invalid10 => return Err(0),
-    //         descriptors::TableOrBlockDescriptorFormat::Block => return Err(0),
-    //         descriptors::TableOrBlockDescriptorFormat::Table => table_entry.next_level_table_address(MODE),
-    //     };
-
-    //     assert_eq!(next_table_address, self.level1.as_ptr() as u64);
-
-    //     let table_entry = self.level1[level1idx as usize];
-    //     if table_entry.is_invalid() {
-    //         return Err(1);
-    //     } else if let Some(block) = table_entry.block_descriptor() {
-    //         return Ok(input_address & LEVEL_1_OA_MASK | block.output_address(MODE, descriptors::BlockLevel::Level1));
-    //     }
-
-    //     let next_table_address = table_entry.table_descriptor().expect("this should be a table descriptor now").next_level_table_address(MODE);
-    //     assert_eq!(next_table_address, self.level2.as_ptr() as u64);
-
-    //     let table_entry = self.level2[level2idx as usize];
-    //     if table_entry.is_invalid() {
-    //         return Err(2);
-    //     } else if let Some(block) = table_entry.block_descriptor() {
-    //         return Ok(input_address & LEVEL_2_OA_MASK | block.output_address(MODE, descriptors::BlockLevel::Level2));
-    //     }
-
-    //     let next_table_address = table_entry.table_descriptor().expect("this should be a table descriptor now").next_level_table_address(MODE);
-    //     assert_eq!(next_table_address, self.level3_0.as_ptr() as u64);
-
-    //     let page = self.level3_0[level3idx as usize];
-    //     match page.format().value().expect("all values are handled") {
-    //         descriptors::PageDescriptorFormat::Page => {
-    //             return Ok(input_address & LEVEL_3_OA_MASK | page.output_address(MODE));
-    //         },
-    //         _ => Err(3)
-    //     }
-
-    // }
+    /// Translates `input_address` of the TTBR0 range in software, the way the MMU would, or
+    /// returns the level of the invalid descriptor it ends at.
+    pub fn simulate_walk(&self, input_address: u64) -> Result<u64, u8> {
+        AddressSpace::new(self.base_address_rg0(), 0)
+            .translate(&PhysicalTables::new(), input_address)
+            .map(|translation| translation.output_address)
+            .map_err(|fault| fault.level)
+    }
 }
@@ -0,0 +1,293 @@
+//! Changes the mappings of the tables [super::mmu_init] set up, while the MMU is on.
+//!
+//! The work on the tables is done by [mystd::paging], this adds the descriptor attributes, the
+//! memory the tables are in, and the TLB maintenance afterwards. New tables come from
+//! [crate::system::frames].
+//!
+//! The tables of `mmu_init` repeat themselves: every level 0 entry refers to the same level 1
+//! table, and the level 1 entries to the 8 level 2 tables in turn. A mapping in the first 8 GiB
+//! shows up at every multiple of 8 GiB above as well.
+//!
+//! Valid descriptors, also of blocks that are split into tables, are replaced break-before-make:
+//! the mapping is missing for a moment, and accesses of other cores to it fault. Mappings of the
+//! memory the kernel runs on can't go missing, changes that would break them fail with
+//! [paging::MapError::InUse].
+
+use mystd::paging::{self, AddressSpace, Descriptor, TableMemory, PAGE_SIZE};
+use mystd::sync::mutex::Mutex;
+
+use super::descriptors::{BlockDescriptor, Shareability};
+use super::walk::active_space;
+use super::{tlb, MEMORY_ATTR_IDX_DEVICE, MEMORY_ATTR_IDX_NON_CACHEABLE, MEMORY_ATTR_IDX_NORMAL};
+use crate::peripherals::interrupts::with_locked;
+use crate::system::frames;
+use crate::system::hal::info::MemoryMap;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryKind {
+    Normal,
+    Device,
+    NonCacheable,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MapAttributes {
+    pub memory: MemoryKind,
+    pub writable: bool,
+    pub executable: bool,
+    /// Accessible from EL0. EL1 can't execute what EL0 can. User mappings are not global, they
+    /// are only used with the ASID of the tables they are in.
+    pub user: bool,
+}
+
+impl MapAttributes {
+    pub const KERNEL_DATA: Self = Self {
+        memory: MemoryKind::Normal,
+        writable: true,
+        executable: false,
+        user: false,
+    };
+    pub const KERNEL_CODE: Self = Self {
+        memory: MemoryKind::Normal,
+        writable: false,
+        executable: true,
+        user: false,
+    };
+    /// Readable and writable by the program and the kernel.
+    pub const USER_DATA: Self = Self {
+        memory: MemoryKind::Normal,
+        writable: true,
+        executable: false,
+        user: true,
+    };
+    pub const DEVICE: Self = Self {
+        memory: MemoryKind::Device,
+        writable: true,
+        executable: false,
+        user: false,
+    };
+
+    /// The attribute bits of a block or page descriptor.
+    pub fn descriptor_bits(self) -> u64 {
+        let (attr_index, shareability) = match self.memory {
+            MemoryKind::Normal => (MEMORY_ATTR_IDX_NORMAL, Shareability::InnerShareable),
+            MemoryKind::Device => (MEMORY_ATTR_IDX_DEVICE, Shareability::OuterShareable),
+            MemoryKind::NonCacheable => {
+                (MEMORY_ATTR_IDX_NON_CACHEABLE, Shareability::OuterShareable)
+            }
+        };
+        // AP[2] makes it read-only, AP[1] accessible from EL0
+        let access = (!self.writable as u64) << 1 | self.user as u64;
+        // PXN is the low bit, UXN the high one
+        let execute_never = match (self.executable, self.user) {
+            (false, _) => 0b11,
+            (true, false) => 0b10,
+            (true, true) => 0b01,
+        };
+        BlockDescriptor::zero()
+            .af()
+            .set()
+            .nse_ng_fnxs()
+            .set_value(self.user)
+            .sh()
+            .set_value(shareability)
+            .stage_1_mem_attr_indx()
+            .set_value(attr_index)
+            .ap_s2ap()
+            .set_value(access)
+            .xn_uxn_pxn()
+            .set_value(execute_never)
+            .to_underlying()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MapError {
+    /// The MMU is off, or the virtual address is in neither the range of TTBR0 nor the one of
+    /// TTBR1. The lock needs cacheable memory, so the tables are only changed with the MMU on.
+    NotTranslated,
+    Table(paging::MapError),
+}
+
+impl From<paging::MapError> for MapError {
+    fn from(value: paging::MapError) -> Self {
+        Self::Table(value)
+    }
+}
+
+/// The nG bit of a block or page descriptor, set for the mappings of programs.
+const NOT_GLOBAL: u64 = 1 << 11;
+
+/// The translation tables at their physical addresses, which the kernel maps to themselves.
+/// Tables that aren't used anymore go back to the frame allocator only once no TLB holds them.
+pub struct PhysicalTables {
+    freed: [u64; 16],
+    freed_count: usize,
+    /// Added to the input addresses of the tables for the virtual addresses of the TLB.
+    virtual_offset: u64,
+}
+
+impl PhysicalTables {
+    pub const fn new() -> Self {
+        Self {
+            freed: [0; 16],
+            freed_count: 0,
+            virtual_offset: 0,
+        }
+    }
+
+    /// Gives the freed tables to the frame allocator, the TLBs have to be clean of them.
+    fn release(&mut self) {
+        for &table in &self.freed[..self.freed_count] {
+            frames::free(table).expect("freed tables should be allocated frames");
+        }
+        self.freed_count = 0;
+    }
+}
+
+impl Default for PhysicalTables {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TableMemory for PhysicalTables {
+    fn read(&self, address: u64) -> u64 {
+        // SAFETY: the walk only reads descriptors of tables
+        unsafe { (address as *const u64).read_volatile() }
+    }
+
+    fn write(&mut self, address: u64, descriptor: u64) {
+        // SAFETY: the caller holds the mapping lock
+        unsafe { (address as *mut u64).write_volatile(descriptor) }
+    }
+
+    fn allocate_table(&mut self) -> Option<u64> {
+        let table = frames::allocate()?;
+        // SAFETY: the frame is new and nothing else refers to it
+        unsafe { core::ptr::write_bytes(table as *mut u8, 0, PAGE_SIZE as usize) };
+        Some(table)
+    }
+
+    fn free_table(&mut self, address: u64) {
+        // the tables of mmu_init aren't frames and stay where they are
+        if !frames::contains(address) {
+            return;
+        }
+        if self.freed_count == self.freed.len() {
+            tlb::invalidate_all();
+            self.release();
+        }
+        self.freed[self.freed_count] = address;
+        self.freed_count += 1;
+    }
+
+    fn may_break(&self, input_address: u64, level: u8, descriptor: u64) -> bool {
+        // the stacks, the image, the frames with the tables and the heap
+        let kernel_end = MemoryMap::heap().unwrap_or(MemoryMap::frames()).top() as u64;
+        let virtual_address = input_address.wrapping_add(self.virtual_offset);
+        // a mapping elsewhere might still be the kernel's, where the tables repeat themselves
+        let maps_kernel = match Descriptor::decode(descriptor, level) {
+            Descriptor::Leaf(output) => descriptor & NOT_GLOBAL == 0 && output < kernel_end,
+            _ => false,
+        };
+        virtual_address >= kernel_end && !maps_kernel
+    }
+
+    fn invalidate(&mut self, input_address: u64, size: u64) {
+        tlb::invalidate_range(input_address.wrapping_add(self.virtual_offset), size);
+    }
+}
+
+static MAPPING: Mutex<()> = Mutex::new(());
+
+/// Applies `change` to `space` and drops the old translations of `size` bytes from
+/// `virtual_address`, which is `input_address` in `space`.
+fn change_mapping(
+    space: AddressSpace,
+    input_address: u64,
+    virtual_address: u64,
+    size: u64,
+    change: impl FnOnce(&AddressSpace, &mut PhysicalTables, u64) -> Result<(), paging::MapError>,
+) -> Result<(), MapError> {
+    let mut tables = PhysicalTables {
+        virtual_offset: virtual_address.wrapping_sub(input_address),
+        ..PhysicalTables::new()
+    };
+    with_locked(&MAPPING, |_| {
+        let result = change(&space, &mut tables, input_address);
+        // also after a partial change, which may have replaced descriptors already
+        tlb::invalidate_range(virtual_address, size);
+        tables.release();
+        result.map_err(MapError::from)
+    })
+}
+
+/// Maps `size` bytes from `virtual_address` to `physical_address`, replacing what was mapped
+/// there. All three have to be multiples of 4KB.
+pub fn map(
+    virtual_address: u64,
+    physical_address: u64,
+    size: u64,
+    attributes: MapAttributes,
+) -> Result<(), MapError> {
+    let (space, input_address) = active_space(virtual_address).ok_or(MapError::NotTranslated)?;
+    map_in(space, input_address, physical_address, size, attributes)
+}
+
+/// Like [map], in the tables of `space` instead of the active ones, which needn't be in use.
+/// `virtual_address` is in the lower address range.
+pub fn map_in(
+    space: AddressSpace,
+    virtual_address: u64,
+    physical_address: u64,
+    size: u64,
+    attributes: MapAttributes,
+) -> Result<(), MapError> {
+    let bits = attributes.descriptor_bits();
+    change_mapping(
+        space,
+        virtual_address,
+        virtual_address,
+        size,
+        |space, tables, input_address| {
+            space.map(tables, input_address, physical_address, size, bits)
+        },
+    )
+}
+
+/// Removes the mappings of `size` bytes from `virtual_address`, an access there faults afterwards.
+pub fn unmap(virtual_address: u64, size: u64) -> Result<(), MapError> {
+    let (space, input_address) = active_space(virtual_address).ok_or(MapError::NotTranslated)?;
+    change_mapping(
+        space,
+        input_address,
+        virtual_address,
+        size,
+        |space, tables, input_address| space.unmap(tables, input_address, size),
+    )
+}
+
+/// Like [unmap], in the tables of `space`. Frees the tables that become empty.
+pub fn unmap_in(space: AddressSpace, virtual_address: u64, size: u64) -> Result<(), MapError> {
+    change_mapping(
+        space,
+        virtual_address,
+        virtual_address,
+        size,
+        |space, tables, input_address| space.unmap(tables, input_address, size),
+    )
+}
+
+/// Changes the attributes of the mapped range of `size` bytes from `virtual_address`.
+pub fn protect(virtual_address: u64, size: u64, attributes: MapAttributes) -> Result<(), MapError> {
+    let (space, input_address) = active_space(virtual_address).ok_or(MapError::NotTranslated)?;
+    let bits = attributes.descriptor_bits();
+    change_mapping(
+        space,
+        input_address,
+        virtual_address,
+        size,
+        |space, tables, input_address| space.protect(tables, input_address, size, bits),
+    )
+}
@@ -0,0 +1,43 @@
+//! TLB maintenance after changing translation tables, broadcast to all cores of the inner
+//! shareable domain.
+
+use core::arch::asm;
+
+use mystd::paging::PAGE_SIZE;
+
+/// Above this many pages a range is dropped with the whole TLB instead of page by page.
+const MAX_PAGES_BY_ADDRESS: u64 = 64;
+
+/// Drops all EL1&0 translations of every core.
+pub fn invalidate_all() {
+    // SAFETY: only drops cached translations, the tables are still there
+    unsafe {
+        asm!(
+            "dsb ishst",
+            "tlbi vmalle1is",
+            "dsb ish",
+            "isb",
+            options(nostack, preserves_flags)
+        )
+    };
+}
+
+/// Drops the translations of `size` bytes from `virtual_address` of every core, for all ASIDs and
+/// all levels, so that changed table descriptors are read again as well.
+pub fn invalidate_range(virtual_address: u64, size: u64) {
+    let first = virtual_address / PAGE_SIZE;
+    let pages = size.div_ceil(PAGE_SIZE);
+    if pages > MAX_PAGES_BY_ADDRESS {
+        return invalidate_all();
+    }
+    // SAFETY: see invalidate_all
+    unsafe {
+        asm!("dsb ishst", options(nostack, preserves_flags));
+        for page in first..first + pages {
+            // the operand is VA[55:12]
+            let operand = page & ((1 << 44) - 1);
+            asm!("tlbi vaae1is, {}", in(reg) operand, options(nostack, preserves_flags));
+        }
+        asm!("dsb ish", "isb", options(nostack, preserves_flags));
+    }
+}
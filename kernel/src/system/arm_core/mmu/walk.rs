@@ -0,0 +1,194 @@
+//! Walks translation tables the way the MMU does, to show which descriptor an address ends up
+//! at. Only the 4KB granule with 48 bit addresses is supported, like in [super::mmu_init].
+
+use core::arch::asm;
+use core::fmt;
+
+use crate::system::arm_core::registers::aarch64::general_sys_ctrl::sctlr_el1::SctlrEl1;
+use crate::system::arm_core::registers::aarch64::general_sys_ctrl::tcr_el1::TcrEl1;
+
+use mystd::paging::AddressSpace;
+
+use super::descriptors::{
+    AddressingMode, BlockDescriptor, BlockLevel, PageDescriptor, TableDescriptor,
+};
+use super::mapping::PhysicalTables;
+
+const MODE: AddressingMode = AddressingMode::Gran4KBAddr48bit;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DescriptorKind {
+    Invalid,
+    /// With the address of the next level table.
+    Table(u64),
+    /// With the output address of the block.
+    Block(u64),
+    /// With the output address of the page.
+    Page(u64),
+}
+
+/// One descriptor read during a walk.
+#[derive(Debug, Clone, Copy)]
+pub struct WalkStep {
+    pub level: u8,
+    /// Where the descriptor was read from.
+    pub address: u64,
+    pub descriptor: u64,
+}
+
+impl WalkStep {
+    pub fn kind(&self) -> DescriptorKind {
+        match (self.level, self.descriptor & 0b11) {
+            (3, 0b11) => DescriptorKind::Page(PageDescriptor::from(self.descriptor).output_address(MODE)),
+            (0..=2, 0b11) => DescriptorKind::Table(
+                TableDescriptor::from(self.descriptor).next_level_table_address(MODE),
+            ),
+            (1, 0b01) => DescriptorKind::Block(
+                BlockDescriptor::from(self.descriptor).output_address(MODE, BlockLevel::Level1),
+            ),
+            (2, 0b01) => DescriptorKind::Block(
+                BlockDescriptor::from(self.descriptor).output_address(MODE, BlockLevel::Level2),
+            ),
+            _ => DescriptorKind::Invalid,
+        }
+    }
+
+    /// The number of input address bits a block or page at this level maps directly.
+    const fn offset_bits(level: u8) -> u32 {
+        12 + 9 * (3 - level as u32)
+    }
+}
+
+impl fmt::Display for WalkStep {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "L{} [{:#x}] = {:#018x} ", self.level, self.address, self.descriptor)?;
+        let attributes = match self.kind() {
+            DescriptorKind::Invalid => return write!(f, "invalid"),
+            DescriptorKind::Table(next) => return write!(f, "table at {:#x}", next),
+            DescriptorKind::Block(output) => {
+                write!(f, "block at {:#x}", output)?;
+                BlockDescriptor::from(self.descriptor)
+            }
+            // pages and blocks share their attributes
+            DescriptorKind::Page(output) => {
+                write!(f, "page at {:#x}", output)?;
+                BlockDescriptor::from(self.descriptor)
+            }
+        };
+        write!(
+            f,
+            " AF={} AP={:#04b} XN={:#04b} SH={:#04b} AttrIndx={}",
+            attributes.af().is_set() as u8,
+            attributes.ap_s2ap().value(),
+            attributes.xn_uxn_pxn().value(),
+            self.descriptor >> 8 & 0b11,
+            attributes.stage_1_mem_attr_indx().value(),
+        )
+    }
+}
+
+/// The descriptors the MMU reads to translate an input address, up to the first block, page or
+/// invalid descriptor.
+#[derive(Debug, Clone, Copy)]
+pub struct TableWalk {
+    pub input_address: u64,
+    pub steps: [Option<WalkStep>; 4],
+}
+
+impl TableWalk {
+    /// Walks the tables at `table` from `start_level`.
+    ///
+    /// # Safety
+    /// `table` and every table it refers to have to be readable at their physical addresses.
+    pub unsafe fn from_table(table: u64, start_level: u8, input_address: u64) -> Self {
+        let walk = AddressSpace::new(table, start_level).walk(&PhysicalTables::new(), input_address);
+        Self {
+            input_address,
+            steps: walk.steps.map(|step| {
+                step.map(|step| WalkStep {
+                    level: step.level,
+                    address: step.address,
+                    descriptor: step.descriptor,
+                })
+            }),
+        }
+    }
+
+    /// The last descriptor read, the one that decided the translation.
+    pub fn last(&self) -> Option<&WalkStep> {
+        self.steps.iter().rev().flatten().next()
+    }
+
+    /// The translated address, or `None` if the walk ended at an invalid descriptor.
+    pub fn output_address(&self) -> Option<u64> {
+        let last = self.last()?;
+        let offset = self.input_address & ((1 << WalkStep::offset_bits(last.level)) - 1);
+        match last.kind() {
+            DescriptorKind::Block(output) | DescriptorKind::Page(output) => Some(output | offset),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for TableWalk {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Table walk for {:#018x}:", self.input_address)?;
+        for step in self.steps.iter().flatten() {
+            writeln!(f, "  {}", step)?;
+        }
+        match (self.output_address(), self.last()) {
+            (Some(output), _) => write!(f, "  -> {:#x}", output),
+            (None, Some(last)) => write!(f, "  -> translation fault at level {}", last.level),
+            (None, None) => write!(f, "  -> no table"),
+        }
+    }
+}
+
+/// The tables of TTBR0_EL1 or TTBR1_EL1, whichever translates `virtual_address`, and the input
+/// address within them. Returns `None` if the MMU is off, or the address is in neither range.
+pub fn active_space(virtual_address: u64) -> Option<(AddressSpace, u64)> {
+    if SctlrEl1::read_register_ordered_ish().m().is_clear() {
+        return None;
+    }
+    let tcr = TcrEl1::read_register();
+    let upper_range = virtual_address >> 55 & 1 == 1;
+    let (table, tnsz, disabled): (u64, u64, bool) = unsafe {
+        let table: u64;
+        if upper_range {
+            asm!("mrs {}, ttbr1_el1", out(reg) table);
+            (table, tcr.t1sz().value(), tcr.epd1().is_set())
+        } else {
+            asm!("mrs {}, ttbr0_el1", out(reg) table);
+            (table, tcr.t0sz().value(), tcr.epd0().is_set())
+        }
+    };
+    let region_bits = 64 - tnsz;
+    let input_address = virtual_address & ((1 << region_bits) - 1);
+    let unused_bits = virtual_address >> region_bits;
+    let in_range = if upper_range {
+        unused_bits == (u64::MAX >> region_bits)
+    } else {
+        unused_bits == 0
+    };
+    if disabled || !in_range {
+        return None;
+    }
+    // for the 4KB granule each level resolves 9 bits, starting below bit 48
+    let start_level = match tnsz {
+        0..=24 => 0,
+        25..=33 => 1,
+        34..=42 => 2,
+        _ => 3,
+    };
+    // the lowest bits of the TTBR are flags, the ASID is at the top
+    let table = table & 0x0000_ffff_ffff_fffe;
+    Some((AddressSpace::new(table, start_level), input_address))
+}
+
+/// Walks the tables of TTBR0_EL1 or TTBR1_EL1, whichever translates `virtual_address`. Returns
+/// `None` if the MMU is off, or the address is in neither range.
+pub fn walk_active(virtual_address: u64) -> Option<TableWalk> {
+    let (space, input_address) = active_space(virtual_address)?;
+    // SAFETY: the kernel maps its tables to their physical addresses
+    Some(unsafe { TableWalk::from_table(space.root, space.start_level, input_address) })
+}
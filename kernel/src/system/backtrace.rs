@@ -0,0 +1,128 @@
+//! Backtraces from the frame records on the stack.
+//!
+//! Every function that sets up a frame stores the caller's frame pointer (`x29`) and its return
+//! address (`x30`) next to each other, and points `x29` at them. The kernel is built with
+//! `-C force-frame-pointers=yes` (see `.cargo/config.toml`), so the records form a chain through
+//! all Rust frames. The walk only follows records inside one of the stacks `_start` sets up or a
+//! thread stack, and only towards the top of the stack, so a corrupted chain ends the backtrace
+//! instead of faulting.
+
+use core::fmt;
+
+use super::hal::info::MemoryMap;
+use super::hal::scheduler;
+use super::symbols;
+
+/// Deeper backtraces are cut off.
+pub const MAX_FRAMES: usize = 32;
+
+/// The return addresses of the frames, innermost first.
+#[derive(Clone, Copy)]
+pub struct Backtrace {
+    addresses: [usize; MAX_FRAMES],
+    len: usize,
+    /// The first address is where an exception happened, not a return address.
+    starts_at_instruction: bool,
+}
+
+impl Backtrace {
+    /// The backtrace of the caller.
+    #[inline(always)]
+    pub fn capture() -> Self {
+        let frame_pointer: usize;
+        unsafe { core::arch::asm!("mov {}, x29", out(reg) frame_pointer) };
+        Self::from_frame_pointer(frame_pointer)
+    }
+
+    /// Follows the chain of frame records from `frame_pointer`.
+    pub fn from_frame_pointer(frame_pointer: usize) -> Self {
+        let mut backtrace = Self::empty();
+        backtrace.walk(frame_pointer);
+        backtrace
+    }
+
+    /// The backtrace of code interrupted by an exception at `elr`, with the frame pointer it had.
+    pub fn from_exception(elr: usize, frame_pointer: usize) -> Self {
+        let mut backtrace = Self::empty();
+        backtrace.push(elr);
+        backtrace.starts_at_instruction = true;
+        backtrace.walk(frame_pointer);
+        backtrace
+    }
+
+    fn walk(&mut self, frame_pointer: usize) {
+        let Some(stack) = MemoryMap::core_stack_containing(frame_pointer)
+            .or_else(|| scheduler::stack_containing(frame_pointer))
+        else {
+            return;
+        };
+        let (bottom, top) = (stack.bottom() as usize, stack.top() as usize);
+        let mut frame_pointer = frame_pointer;
+        while self.len < MAX_FRAMES
+            && frame_pointer.is_multiple_of(16)
+            && frame_pointer >= bottom
+            && frame_pointer + 16 <= top
+        {
+            // SAFETY: the record lies within the stack
+            let (next, return_address) = unsafe {
+                let record = frame_pointer as *const usize;
+                (record.read_volatile(), record.add(1).read_volatile())
+            };
+            if return_address == 0 {
+                break;
+            }
+            self.push(return_address);
+            // the caller's frame is further up the stack, anything else ends the chain
+            if next <= frame_pointer {
+                break;
+            }
+            frame_pointer = next;
+        }
+    }
+
+    pub const fn empty() -> Self {
+        Self {
+            addresses: [0; MAX_FRAMES],
+            len: 0,
+            starts_at_instruction: false,
+        }
+    }
+
+    /// Adds an address as the outermost frame.
+    pub fn push(&mut self, address: usize) {
+        if self.len < MAX_FRAMES {
+            self.addresses[self.len] = address;
+            self.len += 1;
+        }
+    }
+
+    pub fn addresses(&self) -> &[usize] {
+        &self.addresses[..self.len]
+    }
+}
+
+impl fmt::Display for Backtrace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let symbols = symbols::table();
+        writeln!(f, "Backtrace:")?;
+        for (i, &address) in self.addresses().iter().enumerate() {
+            write!(f, "{:>3}: {:#018x}", i, address)?;
+            // a return address may already be past the end of a function that doesn't return,
+            // the call itself is one instruction before
+            let back = if i == 0 && self.starts_at_instruction {
+                0
+            } else {
+                4
+            };
+            if let Some((symbol, offset)) = symbols.lookup(address.saturating_sub(back)) {
+                let name = core::str::from_utf8(symbol.name).unwrap_or("?");
+                write!(f, " <{}+{:#x}>", name, offset + back)?;
+            }
+            writeln!(f)?;
+        }
+        if self.len == MAX_FRAMES {
+            writeln!(f, "  ...")?;
+        }
+        Ok(())
+    }
+}
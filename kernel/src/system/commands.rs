@@ -0,0 +1,347 @@
+//! Kernel commands for the monitor, `?` lists them.
+
+use core::fmt::Write;
+
+use monitor::extension::{
+    Argument, ArgumentValue, Arguments, ExtensionValues, MonitorExtension, MAX_EXTENSION_VALUES,
+};
+use monitor::CommandParseError;
+use mystd::block::{BlockDevice, BLOCK_SIZE};
+use mystd::byte_value::ByteValue;
+
+use super::arm_core::mmu::walk;
+use super::hal::clocks::Clock;
+use super::hal::led::Led;
+use super::hal::sd;
+use super::hal::usb;
+use super::process::{self, ExitStatus};
+use super::{frames, heap};
+
+pub static MONITOR_COMMANDS: &[&dyn MonitorExtension] = &[
+    &LedCommand,
+    &ClockCommand,
+    &UsbCommand,
+    &SdCommand,
+    &WalkCommand,
+    &HeapCommand,
+    &RunCommand,
+];
+
+/// Takes the next argument if it is one of `words`, returns its index.
+fn word(arguments: &mut core::iter::Peekable<Arguments>, words: &[&str]) -> Option<usize> {
+    let Some(Argument {
+        value: ArgumentValue::Word(word),
+        ..
+    }) = arguments.peek()
+    else {
+        return None;
+    };
+    let index = words
+        .iter()
+        .position(|w| w.as_bytes().eq_ignore_ascii_case(word))?;
+    arguments.next();
+    Some(index)
+}
+
+/// Takes the next argument, which has to be a number. `end` is where a missing one would be.
+fn number<'a>(
+    arguments: &mut impl Iterator<Item = Argument<'a>>,
+    end: usize,
+) -> Result<usize, CommandParseError> {
+    match arguments.next() {
+        Some(Argument {
+            value: ArgumentValue::Number(number),
+            ..
+        }) => Ok(number),
+        Some(Argument { position, .. }) => Err(CommandParseError::InvalidArgument { position }),
+        None => Err(CommandParseError::MissingValue { position: end }),
+    }
+}
+
+fn no_more<'a>(mut arguments: impl Iterator<Item = Argument<'a>>) -> Result<(), CommandParseError> {
+    match arguments.next() {
+        Some(Argument { position, .. }) => Err(CommandParseError::TooManyValues { position }),
+        None => Ok(()),
+    }
+}
+
+/// `LED [STATUS|POWER] [ON|OFF]` switches an onboard LED, or shows whether it is on.
+struct LedCommand;
+
+impl MonitorExtension for LedCommand {
+    fn name(&self) -> &str {
+        "LED"
+    }
+
+    fn help(&self) -> &str {
+        "[STATUS|POWER] [ON|OFF]  show or switch an LED"
+    }
+
+    fn parse(&self, arguments: Arguments) -> Result<ExtensionValues, CommandParseError> {
+        let mut arguments = arguments.peekable();
+        let mut values = [0; MAX_EXTENSION_VALUES];
+        values[0] = word(&mut arguments, &["STATUS", "POWER"]).unwrap_or(0);
+        // 0 shows the state, 1 switches on, 2 off
+        values[1] = word(&mut arguments, &["ON", "OFF"]).map_or(0, |i| i + 1);
+        no_more(arguments)?;
+        Ok(values)
+    }
+
+    fn run(&self, values: &ExtensionValues, out: &mut dyn Write) {
+        let led = if values[0] == 0 {
+            Led::Status
+        } else {
+            Led::Power
+        };
+        match values[1] {
+            1 => led.on(),
+            2 => led.off(),
+            _ => {}
+        }
+        let _ = write!(out, "{}", if led.get() { "on" } else { "off" });
+    }
+}
+
+/// `CLOCK` lists the clocks the firmware knows, with their state and rate.
+struct ClockCommand;
+
+const CLOCKS: &[Clock] = &[
+    Clock::Emmc,
+    Clock::Uart,
+    Clock::Arm,
+    Clock::Core,
+    Clock::V3D,
+    Clock::H264,
+    Clock::Isp,
+    Clock::Sdram,
+    Clock::Pixel,
+    Clock::Pwm,
+    Clock::Hevc,
+    Clock::Emmc2,
+    Clock::M2Mc,
+    Clock::PixelBVB,
+];
+
+impl MonitorExtension for ClockCommand {
+    fn name(&self) -> &str {
+        "CLOCK"
+    }
+
+    fn help(&self) -> &str {
+        "  list the clocks and their rates"
+    }
+
+    fn parse(&self, arguments: Arguments) -> Result<ExtensionValues, CommandParseError> {
+        no_more(arguments)?;
+        Ok([0; MAX_EXTENSION_VALUES])
+    }
+
+    fn run(&self, _values: &ExtensionValues, out: &mut dyn Write) {
+        let mut first = true;
+        for clock in CLOCKS {
+            let Some(state) = clock.state().filter(|state| state.exists()) else {
+                continue;
+            };
+            if !first {
+                let _ = writeln!(out);
+            }
+            first = false;
+            let _ = write!(
+                out,
+                "{:>10} Hz {:<3} {:?}",
+                clock.rate().unwrap_or(0),
+                if state.is_on() { "on" } else { "off" },
+                clock
+            );
+        }
+    }
+}
+
+/// `USB` shows the tree of attached USB devices, `USB INIT` enumerates them again.
+struct UsbCommand;
+
+impl MonitorExtension for UsbCommand {
+    fn name(&self) -> &str {
+        "USB"
+    }
+
+    fn help(&self) -> &str {
+        "[INIT]  show the USB devices, or enumerate them"
+    }
+
+    fn parse(&self, arguments: Arguments) -> Result<ExtensionValues, CommandParseError> {
+        let mut arguments = arguments.peekable();
+        let mut values = [0; MAX_EXTENSION_VALUES];
+        values[0] = word(&mut arguments, &["INIT"]).map_or(0, |_| 1);
+        no_more(arguments)?;
+        Ok(values)
+    }
+
+    fn run(&self, values: &ExtensionValues, out: &mut dyn Write) {
+        if values[0] == 1 {
+            if let Err(error) = usb::init() {
+                let _ = write!(out, "{:?}", error);
+                return;
+            }
+        }
+        if usb::with_host(|host| write!(out, "{}", host.tree())).is_none() {
+            let _ = write!(out, "not initialized, try USB INIT");
+        }
+    }
+}
+
+/// `SD` shows the SD card, `SD INIT` identifies it again. `SD READ BLOCK ADDRESS COUNT` copies
+/// blocks of the card to memory, `SD WRITE BLOCK ADDRESS COUNT` from memory to the card.
+struct SdCommand;
+
+impl MonitorExtension for SdCommand {
+    fn name(&self) -> &str {
+        "SD"
+    }
+
+    fn help(&self) -> &str {
+        "[INIT|READ|WRITE BLOCK ADDRESS COUNT]  show the SD card, or copy blocks"
+    }
+
+    fn parse(&self, arguments: Arguments) -> Result<ExtensionValues, CommandParseError> {
+        let end = arguments.end();
+        let mut arguments = arguments.peekable();
+        let mut values = [0; MAX_EXTENSION_VALUES];
+        values[0] = word(&mut arguments, &["INIT", "READ", "WRITE"]).map_or(0, |i| i + 1);
+        if values[0] >= 2 {
+            for value in &mut values[1..4] {
+                *value = number(&mut arguments, end)?;
+            }
+        }
+        no_more(arguments)?;
+        Ok(values)
+    }
+
+    fn run(&self, values: &ExtensionValues, out: &mut dyn Write) {
+        let [action, block, address, count, ..] = *values;
+        if action == 1 {
+            if let Err(error) = sd::init() {
+                let _ = write!(out, "{:?}", error);
+                return;
+            }
+        }
+        // only READ and WRITE have an address
+        let memory = || {
+            // SAFETY: the monitor hands out any memory
+            unsafe { core::slice::from_raw_parts_mut(address as *mut u8, count * BLOCK_SIZE) }
+        };
+        let shown = sd::with_card(|card| {
+            let result = match action {
+                2 => card.read_blocks(block as u64, memory()),
+                3 => card.write_blocks(block as u64, memory()),
+                _ => Ok(()),
+            };
+            match result {
+                Ok(()) => write!(
+                    out,
+                    "{}, {}",
+                    card.cid(),
+                    ByteValue(card.block_count() * BLOCK_SIZE as u64)
+                ),
+                Err(error) => write!(out, "{:?}", error),
+            }
+        });
+        if shown.is_none() {
+            let _ = write!(out, "not initialized, try SD INIT");
+        }
+    }
+}
+
+/// `WALK ADDRESS` shows the translation table descriptors for a virtual address.
+struct WalkCommand;
+
+impl MonitorExtension for WalkCommand {
+    fn name(&self) -> &str {
+        "WALK"
+    }
+
+    fn help(&self) -> &str {
+        "ADDRESS  show how the MMU translates an address"
+    }
+
+    fn parse(&self, mut arguments: Arguments) -> Result<ExtensionValues, CommandParseError> {
+        let mut values = [0; MAX_EXTENSION_VALUES];
+        let end = arguments.end();
+        values[0] = number(&mut arguments, end)?;
+        no_more(arguments)?;
+        Ok(values)
+    }
+
+    fn run(&self, values: &ExtensionValues, out: &mut dyn Write) {
+        match walk::walk_active(values[0] as u64) {
+            Some(walk) => {
+                let _ = write!(out, "{}", walk);
+            }
+            None => {
+                let _ = write!(out, "MMU off or address not mapped by TTBR0/TTBR1");
+            }
+        }
+    }
+}
+
+/// `HEAP` shows the allocation statistics of the kernel heap and the free page frames.
+struct HeapCommand;
+
+impl MonitorExtension for HeapCommand {
+    fn name(&self) -> &str {
+        "HEAP"
+    }
+
+    fn help(&self) -> &str {
+        "  show the allocation statistics of the kernel heap and the free page frames"
+    }
+
+    fn parse(&self, arguments: Arguments) -> Result<ExtensionValues, CommandParseError> {
+        no_more(arguments)?;
+        Ok([0; MAX_EXTENSION_VALUES])
+    }
+
+    fn run(&self, _values: &ExtensionValues, out: &mut dyn Write) {
+        let _ = write!(
+            out,
+            "{}, largest free block {}",
+            heap::stats(),
+            ByteValue(heap::largest_free_block() as u64)
+        );
+        let (free, total) = frames::counts();
+        let _ = write!(out, "\n{} of {} page frames free", free, total);
+    }
+}
+
+/// `RUN ADDRESS SIZE` runs the ELF or flat program loaded to memory as a process and waits for it.
+struct RunCommand;
+
+impl MonitorExtension for RunCommand {
+    fn name(&self) -> &str {
+        "RUN"
+    }
+
+    fn help(&self) -> &str {
+        "ADDRESS SIZE  run the program in memory at EL0 and wait for it to end"
+    }
+
+    fn parse(&self, mut arguments: Arguments) -> Result<ExtensionValues, CommandParseError> {
+        let mut values = [0; MAX_EXTENSION_VALUES];
+        let end = arguments.end();
+        for value in &mut values[..2] {
+            *value = number(&mut arguments, end)?;
+        }
+        no_more(arguments)?;
+        Ok(values)
+    }
+
+    fn run(&self, values: &ExtensionValues, out: &mut dyn Write) {
+        // SAFETY: the monitor hands out any memory, the program is copied before it runs
+        let image = unsafe { core::slice::from_raw_parts(values[0] as *const u8, values[1]) };
+        let _ = match process::spawn(image).map(process::Process::wait) {
+            Ok(ExitStatus::Exited(code)) => write!(out, "exited with {}", code),
+            Ok(ExitStatus::Killed) => write!(out, "killed"),
+            Err(error) => write!(out, "{:?}", error),
+        };
+    }
+}
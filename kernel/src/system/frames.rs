@@ -0,0 +1,52 @@
+//! The physical page frames above the kernel image, handed out one by one for translation
+//! tables and anything else that needs whole pages at a known physical address.
+
+use mystd::frames::{FrameAllocator, FrameError};
+use mystd::sync::mutex::Mutex;
+
+use super::hal::info::{MemoryBlock, MemoryMap};
+use crate::peripherals::interrupts::with_locked;
+
+/// 256 words track the 64 MiB of [MemoryMap::frames].
+static FRAMES: Mutex<FrameAllocator<256>> = Mutex::new(FrameAllocator::empty());
+
+fn with_frames<R>(f: impl FnOnce(&mut FrameAllocator<256>) -> R) -> R {
+    with_locked(&FRAMES, f)
+}
+
+/// Takes over the memory of [MemoryMap::frames], except the fixed areas the translation tables
+/// and the frame buffers already use. Needs the MMU on, like the heap.
+pub fn init() -> MemoryBlock {
+    let block = MemoryMap::frames();
+    with_frames(|frames| {
+        *frames = FrameAllocator::new(block.bottom() as u64, block.top() as u64);
+        for area in MemoryMap::fixed_areas() {
+            frames.reserve(area.bottom() as u64, area.top() as u64);
+        }
+    });
+    block
+}
+
+/// The physical address of a free frame, or `None` if all are in use.
+pub fn allocate() -> Option<u64> {
+    with_frames(|frames| frames.allocate())
+}
+
+/// The physical address of the first of `count` adjacent free frames.
+pub fn allocate_contiguous(count: usize) -> Option<u64> {
+    with_frames(|frames| frames.allocate_contiguous(count))
+}
+
+pub fn free(address: u64) -> Result<(), FrameError> {
+    with_frames(|frames| frames.free(address))
+}
+
+/// Whether `address` is in a frame the allocator manages.
+pub fn contains(address: u64) -> bool {
+    with_frames(|frames| frames.contains(address))
+}
+
+/// The number of free frames and of all frames.
+pub fn counts() -> (usize, usize) {
+    with_frames(|frames| (frames.free_count(), frames.frame_count()))
+}
@@ -5,7 +5,12 @@ pub mod counter;
 pub mod display;
 pub mod framebuffer;
 pub mod info;
+pub mod interrupts;
+pub mod keyboard;
 pub mod led;
+pub mod scheduler;
+pub mod sd;
 pub mod signal;
 pub mod thread;
+pub mod usb;
 pub mod console;
\ No newline at end of file
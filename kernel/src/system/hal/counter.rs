@@ -135,4 +135,24 @@ pub fn frequency() -> u32 {
 
 pub fn uptime() -> core::time::Duration {
     PointInTime::now().time_since_zero()
-}
\ No newline at end of file
+}
+/// [poll] gave up waiting.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Timeout;
+
+/// Polls `f` until it returns `Some`, or `timeout` passed.
+pub fn poll<R>(
+    mut f: impl FnMut() -> Option<R>,
+    timeout: core::time::Duration,
+) -> Result<R, Timeout> {
+    let deadline = PointInTime::now() + timeout;
+    loop {
+        if let Some(result) = f() {
+            return Ok(result);
+        }
+        if !deadline.is_in_the_future() {
+            return Err(Timeout);
+        }
+        core::hint::spin_loop();
+    }
+}
@@ -3,6 +3,7 @@ use core::{
     ptr::null,
 };
 
+use crate::system::arm_core::{CoreId, ExceptionLevel};
 use crate::{peripherals::mailbox, system::peripherals};
 
 #[derive(Debug)]
@@ -432,11 +433,101 @@ impl MemoryBlock {
     }
 }
 
+extern "C" {
+    static __stack_top: usize;
+    static __kernel_end: usize;
+}
+
 pub struct MemoryMap();
 impl MemoryMap {
+    // every core gets 1 / 4 of the space below the kernel, split by exception level.
+    // EL 3 and EL 2 get two pages (8kb) each, EL 0 four pages (16 kb) and EL 1 the rest
+    const CORE_STACK_EL3_SIZE: usize = 0x2000;
+    const CORE_STACK_EL2_SIZE: usize = 0x2000;
+    const CORE_STACK_EL0_SIZE: usize = 0x4000;
+
+    const TRANSLATION_TABLES: usize = 0x20_0000;
+    const SCREEN_BUFFER: usize = 0x50_0000;
+    const CONSOLE_BUFFER: usize = 0x60_0000;
+    const BUFFER_SIZE: usize = 0x10_0000;
+    /// The memory the frame allocator manages, the most a `FrameAllocator<256>` can track.
+    const FRAMES_SIZE: usize = 0x400_0000;
+
+    /// Where `mmu_init` puts the translation tables, up to the screen buffer.
+    pub const fn translation_tables() -> MemoryBlock {
+        MemoryBlock::from_address_and_size(
+            Self::TRANSLATION_TABLES,
+            Self::SCREEN_BUFFER - Self::TRANSLATION_TABLES,
+        )
+    }
+
+    pub const fn screen_buffer() -> MemoryBlock {
+        MemoryBlock::from_address_and_size(Self::SCREEN_BUFFER, Self::BUFFER_SIZE)
+    }
+
+    pub const fn console_buffer() -> MemoryBlock {
+        MemoryBlock::from_address_and_size(Self::CONSOLE_BUFFER, Self::BUFFER_SIZE)
+    }
+
+    /// The areas at fixed addresses, they are inside [Self::frames] and have to be reserved there.
+    pub const fn fixed_areas() -> [MemoryBlock; 3] {
+        [
+            Self::translation_tables(),
+            Self::screen_buffer(),
+            Self::console_buffer(),
+        ]
+    }
+
+    /// The page frames right above the kernel image, for the frame allocator.
+    pub fn frames() -> MemoryBlock {
+        let kernel_end = core::ptr::addr_of!(__kernel_end) as usize;
+        MemoryBlock::from_address_and_size(kernel_end, Self::FRAMES_SIZE)
+    }
+
+    /// The ARM memory above the frames, for the heap.
+    pub fn heap() -> Option<MemoryBlock> {
+        let arm_memory = get_arm_memory()?;
+        let start = Self::frames().top() as usize;
+        let end = arm_memory.top() as usize;
+        (start < end).then(|| MemoryBlock::from_address_and_size(start, end - start))
+    }
+
     pub fn main_stack() -> MemoryBlock {
         MemoryBlock::from_address_and_size(0, 0x80000)
     }
+
+    /// The stack `_start` sets up for `core` at exception level `el`. Core 3 has the lowest
+    /// addresses, and in every core's part EL 3 the lowest and EL 0 the highest.
+    pub fn core_stack(core: CoreId, el: ExceptionLevel) -> MemoryBlock {
+        let stack_top = core::ptr::addr_of!(__stack_top) as usize;
+        let core_stack_total_size = stack_top / 4;
+        let core_stack_el1_size = core_stack_total_size.wrapping_sub(
+            Self::CORE_STACK_EL3_SIZE + Self::CORE_STACK_EL2_SIZE + Self::CORE_STACK_EL0_SIZE,
+        );
+        let core_stack_base = core_stack_total_size * (3 - core.num() as usize);
+        let (offset, size) = match el {
+            ExceptionLevel::EL3 => (0, Self::CORE_STACK_EL3_SIZE),
+            ExceptionLevel::EL2 => (Self::CORE_STACK_EL3_SIZE, Self::CORE_STACK_EL2_SIZE),
+            ExceptionLevel::EL1 => (
+                Self::CORE_STACK_EL3_SIZE + Self::CORE_STACK_EL2_SIZE,
+                core_stack_el1_size,
+            ),
+            ExceptionLevel::EL0 => (
+                core_stack_total_size - Self::CORE_STACK_EL0_SIZE,
+                Self::CORE_STACK_EL0_SIZE,
+            ),
+        };
+        MemoryBlock::from_address_and_size(core_stack_base + offset, size)
+    }
+
+    /// The stack set up by `_start` that contains `address`, if any.
+    pub fn core_stack_containing(address: usize) -> Option<MemoryBlock> {
+        use ExceptionLevel::*;
+        [CoreId::Core0, CoreId::Core1, CoreId::Core2, CoreId::Core3]
+            .into_iter()
+            .flat_map(|core| [EL0, EL1, EL2, EL3].map(|el| Self::core_stack(core, el)))
+            .find(|stack| (stack.bottom() as usize..stack.top() as usize).contains(&address))
+    }
 }
 
 impl core::fmt::Debug for MemoryMap {
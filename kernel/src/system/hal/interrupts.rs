@@ -0,0 +1,371 @@
+//! Interrupt handling independent of the interrupt controller.
+//!
+//! Handlers are registered per [Irq] and called by [handle_irq] from the IRQ exception vector.
+//! The [InterruptController] of the board is returned by [controller]: the BCM2835 style
+//! controller of the BCM2837 together with the ARM local interrupts, or the GIC-400 of the
+//! BCM2711.
+
+use core::sync::atomic::{AtomicPtr, AtomicU64, Ordering};
+
+use crate::peripherals::uart::UART_0;
+use crate::system::arm_core::{self, CoreId};
+
+/// An interrupt number, as the [controller] numbers it. Use the constants or [Irq::peripheral],
+/// since the numbers differ between controllers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Irq(pub u32);
+
+impl Irq {
+    /// The first interrupt of the ARM peripherals (BCM2837 only).
+    #[cfg(not(feature = "bcm2711"))]
+    const ARM_BASE: u32 = 64;
+    /// The first per-core interrupt of the ARM local peripherals (BCM2837 only), followed by the
+    /// bits of [crate::peripherals::interrupts::CoreInterruptSource].
+    #[cfg(not(feature = "bcm2711"))]
+    const LOCAL_BASE: u32 = 96;
+    /// The GIC-400 sees the VideoCore interrupts as shared peripheral interrupts 64 and up.
+    #[cfg(feature = "bcm2711")]
+    const PERIPHERAL_BASE: u32 = 96;
+
+    pub const USB: Self = Self::peripheral(9);
+    pub const AUX: Self = Self::peripheral(29);
+    pub const GPIO_0: Self = Self::peripheral(49);
+    pub const GPIO_1: Self = Self::peripheral(50);
+    pub const GPIO_2: Self = Self::peripheral(51);
+    pub const GPIO_3: Self = Self::peripheral(52);
+    pub const UART: Self = Self::peripheral(57);
+    pub const EMMC: Self = Self::peripheral(62);
+
+    #[cfg(not(feature = "bcm2711"))]
+    pub const ARM_TIMER: Self = Self(Self::ARM_BASE);
+    #[cfg(not(feature = "bcm2711"))]
+    pub const ARM_MAILBOX: Self = Self(Self::ARM_BASE + 1);
+
+    /// The non-secure physical timer of the generic timer of the core.
+    #[cfg(not(feature = "bcm2711"))]
+    pub const PHYSICAL_TIMER: Self = Self(Self::LOCAL_BASE + 1);
+    #[cfg(feature = "bcm2711")]
+    pub const PHYSICAL_TIMER: Self = Self(30);
+    /// The virtual timer of the generic timer of the core.
+    #[cfg(not(feature = "bcm2711"))]
+    pub const VIRTUAL_TIMER: Self = Self(Self::LOCAL_BASE + 3);
+    #[cfg(feature = "bcm2711")]
+    pub const VIRTUAL_TIMER: Self = Self(27);
+
+    /// One of the 64 VideoCore peripheral interrupts, numbered as in the BCM2835 datasheet.
+    pub const fn peripheral(n: u32) -> Self {
+        #[cfg(feature = "bcm2711")]
+        return Self(Self::PERIPHERAL_BASE + n);
+        #[cfg(not(feature = "bcm2711"))]
+        return Self(n);
+    }
+
+    /// Compare channel `n` (0 to 3) of the system timer. 0 and 2 are used by the GPU.
+    pub const fn system_timer(n: u32) -> Self {
+        Self::peripheral(n)
+    }
+}
+
+impl core::fmt::Display for Irq {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "IRQ {}", self.0)
+    }
+}
+
+pub const MAX_IRQS: usize = 256;
+
+pub type Handler = fn(Irq);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InterruptError {
+    OutOfRange,
+    AlreadyRegistered,
+}
+
+#[allow(clippy::declare_interior_mutable_const)]
+const NO_HANDLER: AtomicPtr<()> = AtomicPtr::new(core::ptr::null_mut());
+static HANDLERS: [AtomicPtr<()>; MAX_IRQS] = [NO_HANDLER; MAX_IRQS];
+
+/// Calls `handler` from the IRQ exception whenever `irq` is raised. The handler has to clear the
+/// interrupt at its source. Enable the interrupt with the [controller] afterwards.
+pub fn register(irq: Irq, handler: Handler) -> Result<(), InterruptError> {
+    let slot = HANDLERS
+        .get(irq.0 as usize)
+        .ok_or(InterruptError::OutOfRange)?;
+    slot.compare_exchange(
+        core::ptr::null_mut(),
+        handler as *mut (),
+        Ordering::AcqRel,
+        Ordering::Acquire,
+    )
+    .map(|_| ())
+    .map_err(|_| InterruptError::AlreadyRegistered)
+}
+
+/// Disables `irq` and removes its handler.
+pub fn unregister(irq: Irq) -> Result<(), InterruptError> {
+    let slot = HANDLERS
+        .get(irq.0 as usize)
+        .ok_or(InterruptError::OutOfRange)?;
+    controller().disable(irq);
+    slot.store(core::ptr::null_mut(), Ordering::Release);
+    Ok(())
+}
+
+pub fn handler(irq: Irq) -> Option<Handler> {
+    let ptr = HANDLERS.get(irq.0 as usize)?.load(Ordering::Acquire);
+    if ptr.is_null() {
+        None
+    } else {
+        // SAFETY: only `register` stores non-null pointers, and those are `Handler`s
+        Some(unsafe { core::mem::transmute::<*mut (), Handler>(ptr) })
+    }
+}
+
+pub trait InterruptController: Sync {
+    /// Sets up the controller for the calling core, all interrupts disabled.
+    fn init(&self);
+    fn enable(&self, irq: Irq);
+    fn disable(&self, irq: Irq);
+    /// Sends `irq` to `core`. Per-core interrupts like the timers are taken by the core that
+    /// enabled them and can't be routed.
+    fn route(&self, irq: Irq, core: CoreId);
+    /// The highest priority pending interrupt of the calling core, if any.
+    fn acknowledge(&self) -> Option<Irq>;
+    fn end_of_interrupt(&self, irq: Irq);
+
+    /// Calls the handlers of all pending interrupts. Interrupts without a handler are disabled,
+    /// so they don't fire over and over again.
+    fn dispatch(&self) {
+        for _ in 0..MAX_IRQS {
+            let Some(irq) = self.acknowledge() else {
+                return;
+            };
+            match handler(irq) {
+                Some(handler) => handler(irq),
+                None => {
+                    self.disable(irq);
+                    report_unhandled(irq);
+                }
+            }
+            self.end_of_interrupt(irq);
+        }
+    }
+}
+
+/// The interrupts that came without a handler, so each is reported once.
+static UNHANDLED: [AtomicU64; MAX_IRQS / 64] = [const { AtomicU64::new(0) }; MAX_IRQS / 64];
+
+fn report_unhandled(irq: Irq) {
+    let bit = 1 << (irq.0 % 64);
+    let reported = UNHANDLED[irq.0 as usize / 64].fetch_or(bit, Ordering::Relaxed) & bit != 0;
+    if !reported && cfg!(feature = "serial_uart") {
+        use mystd::io::Write;
+        // what was interrupted might hold the lock of the console
+        let mut uart = UART_0;
+        let _ = writeln!(uart, "{irq} has no handler and was disabled");
+    }
+}
+
+/// Called from the IRQ exception vector.
+pub fn handle_irq() {
+    controller().dispatch()
+}
+
+#[cfg(not(feature = "bcm2711"))]
+pub fn controller() -> &'static dyn InterruptController {
+    &bcm2835::Bcm2835
+}
+
+#[cfg(feature = "bcm2711")]
+pub fn controller() -> &'static dyn InterruptController {
+    &gic400::Gic400
+}
+
+fn current_core() -> usize {
+    arm_core::get_core_num().num() as usize
+}
+
+#[cfg(not(feature = "bcm2711"))]
+mod bcm2835 {
+    use crate::peripherals::interrupts::{
+        ArmLocal, BasicIrqs, CoreInterruptSource, CoreMailboxInterrupts, CoreTimerInterrupts,
+        GpuIrqs1, GpuIrqs2,
+    };
+    use crate::system::arm_core::CoreId;
+
+    use super::{current_core, InterruptController, Irq};
+
+    /// The interrupt controller of the ARM peripherals, for the 64 GPU and 8 ARM interrupts that
+    /// all go to one core, and the ARM local interrupts for the per-core timers and mailboxes.
+    pub struct Bcm2835;
+
+    impl Bcm2835 {
+        fn set_enabled(irq: Irq, enabled: bool) {
+            let n = irq.0;
+            let core = current_core();
+            match n {
+                0..=31 if enabled => GpuIrqs1::from(1 << n).write_enable(),
+                0..=31 => GpuIrqs1::from(1 << n).write_disable(),
+                32..=63 if enabled => GpuIrqs2::from(1 << (n - 32)).write_enable(),
+                32..=63 => GpuIrqs2::from(1 << (n - 32)).write_disable(),
+                64..=71 if enabled => BasicIrqs::from(1 << (n - 64)).write_enable(),
+                64..=71 => BasicIrqs::from(1 << (n - 64)).write_disable(),
+                96..=99 => {
+                    let bit = 1 << (n - 96);
+                    let timers = ArmLocal::timer_interrupts(core).to_underlying();
+                    let timers = if enabled { timers | bit } else { timers & !bit };
+                    ArmLocal::set_timer_interrupts(core, CoreTimerInterrupts::from(timers));
+                }
+                100..=103 => {
+                    let bit = 1 << (n - 100);
+                    let mailboxes = ArmLocal::mailbox_interrupts(core).to_underlying();
+                    let mailboxes = if enabled {
+                        mailboxes | bit
+                    } else {
+                        mailboxes & !bit
+                    };
+                    ArmLocal::set_mailbox_interrupts(core, CoreMailboxInterrupts::from(mailboxes));
+                }
+                105 => ArmLocal::set_pmu_interrupt(core, enabled),
+                106 => ArmLocal::set_axi_outstanding_interrupt(enabled),
+                107 => ArmLocal::set_local_timer_interrupt(enabled),
+                // 104 is the GPU interrupt, its peripherals are switched one by one
+                _ => {}
+            }
+        }
+
+        fn pending_peripheral() -> Option<Irq> {
+            let basic = BasicIrqs::read_pending().to_underlying()
+                & BasicIrqs::read_enable().to_underlying()
+                & 0xff;
+            if basic != 0 {
+                return Some(Irq(64 + basic.trailing_zeros()));
+            }
+            let gpu1 =
+                GpuIrqs1::read_pending().to_underlying() & GpuIrqs1::read_enable().to_underlying();
+            if gpu1 != 0 {
+                return Some(Irq(gpu1.trailing_zeros()));
+            }
+            let gpu2 =
+                GpuIrqs2::read_pending().to_underlying() & GpuIrqs2::read_enable().to_underlying();
+            if gpu2 != 0 {
+                return Some(Irq(32 + gpu2.trailing_zeros()));
+            }
+            None
+        }
+    }
+
+    impl InterruptController for Bcm2835 {
+        fn init(&self) {
+            let core = current_core();
+            ArmLocal::set_timer_interrupts(core, CoreTimerInterrupts::zero());
+            ArmLocal::set_mailbox_interrupts(core, CoreMailboxInterrupts::zero());
+            if core == 0 {
+                GpuIrqs1::all_set().write_disable();
+                GpuIrqs2::all_set().write_disable();
+                BasicIrqs::all_set().write_disable();
+                ArmLocal::set_gpu_irq_core(0);
+            }
+        }
+
+        fn enable(&self, irq: Irq) {
+            Self::set_enabled(irq, true)
+        }
+
+        fn disable(&self, irq: Irq) {
+            Self::set_enabled(irq, false)
+        }
+
+        fn route(&self, irq: Irq, core: CoreId) {
+            // the GPU and ARM interrupts can only go to one core together
+            if irq.0 < 96 {
+                ArmLocal::set_gpu_irq_core(core.num() as usize)
+            }
+        }
+
+        fn acknowledge(&self) -> Option<Irq> {
+            let source = ArmLocal::irq_source(current_core());
+            let local =
+                source.to_underlying() & !CoreInterruptSource::zero().gpu().set().to_underlying();
+            if local != 0 {
+                return Some(Irq(96 + local.trailing_zeros()));
+            }
+            if source.gpu().is_set() {
+                Self::pending_peripheral()
+            } else {
+                None
+            }
+        }
+
+        fn end_of_interrupt(&self, _irq: Irq) {
+            // level triggered, the handler clears the source
+        }
+    }
+}
+
+#[cfg(feature = "bcm2711")]
+mod gic400 {
+    use crate::peripherals::gic::{
+        CpuInterface, CpuInterfaceControl, Distributor, DistributorControl, InterruptAcknowledge,
+        SPURIOUS_INTERRUPT,
+    };
+    use crate::system::arm_core::CoreId;
+
+    use super::{current_core, InterruptController, Irq};
+
+    const DEFAULT_PRIORITY: u8 = 0xa0;
+
+    /// The GIC-400, see [crate::peripherals::gic].
+    pub struct Gic400;
+
+    impl InterruptController for Gic400 {
+        fn init(&self) {
+            // the distributor is shared, the main core sets it up
+            if current_core() == 0 {
+                Distributor::set_control(DistributorControl::zero());
+                for id in 32..Distributor::interrupt_count() as u32 {
+                    Distributor::disable(id);
+                    Distributor::clear_pending(id);
+                    Distributor::set_priority(id, DEFAULT_PRIORITY);
+                    Distributor::set_targets(id, 0b1);
+                }
+                Distributor::set_control(DistributorControl::zero().enable().set());
+            }
+            // the private interrupts are banked per core
+            for id in 0..32 {
+                Distributor::disable(id);
+                Distributor::clear_pending(id);
+                Distributor::set_priority(id, DEFAULT_PRIORITY);
+            }
+            CpuInterface::set_priority_mask(0xf0);
+            CpuInterface::set_control(CpuInterfaceControl::zero().enable().set());
+        }
+
+        fn enable(&self, irq: Irq) {
+            Distributor::enable(irq.0)
+        }
+
+        fn disable(&self, irq: Irq) {
+            Distributor::disable(irq.0)
+        }
+
+        fn route(&self, irq: Irq, core: CoreId) {
+            if irq.0 >= 32 {
+                Distributor::set_targets(irq.0, 1 << core.num())
+            }
+        }
+
+        fn acknowledge(&self) -> Option<Irq> {
+            let id = CpuInterface::acknowledge().interrupt_id().value();
+            (id < SPURIOUS_INTERRUPT).then_some(Irq(id))
+        }
+
+        fn end_of_interrupt(&self, irq: Irq) {
+            // software generated interrupts would need the sending core too, they aren't used
+            CpuInterface::end_of_interrupt(
+                InterruptAcknowledge::zero().interrupt_id().set_value(irq.0),
+            )
+        }
+    }
+}
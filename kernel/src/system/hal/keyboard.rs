@@ -0,0 +1,145 @@
+//! USB keyboards in the boot protocol, polled through the [usb] host.
+//!
+//! [poll] reads the keyboard's interrupt endpoint when it is due, and puts the bytes of the typed
+//! keys into [KEYS]. [Keyboard] reads from there and polls while it waits, so it can replace the
+//! UART as the input of the monitor.
+
+use core::cell::RefCell;
+use core::time::Duration;
+
+use mystd::collections::sync_ring::AtomicRing256;
+use mystd::io::{self, Read};
+use mystd::protocols::hid::{self, BootReport, KeyboardState, Keymap};
+use mystd::protocols::usb::{class, Speed};
+use mystd::sync::mutex::Mutex;
+
+use crate::peripherals::usb::Pid;
+
+use super::counter::PointInTime;
+use super::usb::{self, Endpoint, UsbError};
+
+/// The bytes of the typed keys, as a terminal would send them. [poll] puts them in while it holds
+/// [STATE], [Keyboard] takes them out while it holds [READING].
+static KEYS: AtomicRing256<u8> = AtomicRing256::filled(0);
+
+/// Keeps [KEYS] to one consumer, any copy of [Keyboard] may read.
+static READING: Mutex<()> = Mutex::new(());
+
+struct State {
+    endpoint: Endpoint,
+    pid: Pid,
+    interval: Duration,
+    next_poll: PointInTime,
+    keyboard: KeyboardState,
+}
+
+static STATE: Mutex<RefCell<Option<State>>> = Mutex::new(RefCell::new(None));
+
+/// Looks for a boot keyboard among the enumerated USB devices and switches it to the boot
+/// protocol. Call [usb::init] first.
+pub fn init(keymap: &'static Keymap) -> Result<(), UsbError> {
+    let state = usb::with_host(|host| {
+        let (device, interface, descriptor) = host
+            .devices()
+            .find_map(|device| {
+                device.interfaces().find_map(|interface| {
+                    let d = interface.descriptor;
+                    let is_keyboard = d.class == class::HID
+                        && d.subclass == hid::SUBCLASS_BOOT
+                        && d.protocol == hid::PROTOCOL_KEYBOARD;
+                    Some((*device, d.number, interface.interrupt_in?)).filter(|_| is_keyboard)
+                })
+            })
+            .ok_or(UsbError::NotConnected)?;
+        let control = device.control_endpoint();
+        host.control_transfer(&control, hid::set_boot_protocol(interface), &mut [])?;
+        // some keyboards don't know SET_IDLE, they repeat the report and that's fine
+        match host.control_transfer(&control, hid::set_idle_infinite(interface), &mut []) {
+            Ok(_) | Err(UsbError::Stall) => {}
+            Err(error) => return Err(error),
+        }
+        // high speed intervals are 2^(n-1) micro frames, the others are in frames
+        let interval = match device.speed {
+            Speed::High => Duration::from_micros(125 << (descriptor.interval.clamp(1, 16) - 1)),
+            _ => Duration::from_millis(descriptor.interval.max(1) as u64),
+        };
+        Ok(State {
+            endpoint: device.endpoint(&descriptor),
+            pid: Pid::Data0,
+            interval,
+            next_poll: PointInTime::now(),
+            keyboard: KeyboardState::new(keymap),
+        })
+    })
+    .ok_or(UsbError::Unavailable)??;
+    let lock = STATE.try_lock().ok_or(UsbError::Unavailable)?;
+    lock.replace(Some(state));
+    Ok(())
+}
+
+/// Changes the layout of the keyboard, e.g. to [hid::DE].
+pub fn set_keymap(keymap: &'static Keymap) {
+    if let Some(lock) = STATE.try_lock() {
+        if let Some(state) = lock.borrow_mut().as_mut() {
+            state.keyboard.set_keymap(keymap);
+        }
+    }
+}
+
+/// Reads the keyboard if it is due and puts the typed and repeated keys into [KEYS].
+pub fn poll() {
+    let Some(lock) = STATE.try_lock() else {
+        return;
+    };
+    let mut state = lock.borrow_mut();
+    let Some(state) = state.as_mut() else {
+        return;
+    };
+    let now = PointInTime::now();
+    let uptime = now.time_since_zero();
+    if !state.next_poll.is_in_the_future() {
+        state.next_poll = now + state.interval;
+        let mut bytes = [0; BootReport::LEN];
+        let received =
+            usb::with_host(|host| host.transfer_in(&state.endpoint, &mut state.pid, &mut bytes));
+        // a NAK means nothing changed
+        if let Some(Ok(len)) = received {
+            if let Some(report) = BootReport::parse(&bytes[..len]) {
+                state.keyboard.report(&report, uptime, put_key);
+            }
+        }
+    }
+    if let Some(key) = state.keyboard.repeat(uptime) {
+        put_key(key);
+    }
+}
+
+fn put_key(key: hid::Key) {
+    let mut buf = [0; 8];
+    for byte in key.encode(&mut buf) {
+        // keys typed while the queue is full are lost
+        // SAFETY: only called from poll, which holds the lock of STATE
+        let _ = unsafe { KEYS.put(*byte) };
+    }
+}
+
+/// Reads the typed keys, blocking until there is at least one.
+#[derive(Clone, Copy)]
+pub struct Keyboard;
+
+impl Read for Keyboard {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<io::Size> {
+        loop {
+            poll();
+            let result = match READING.try_lock() {
+                // SAFETY: the lock makes this the only consumer
+                Some(_reading) => unsafe { KEYS.read_available(buf) },
+                None => Err(io::Error::WouldBlock),
+            };
+            match result {
+                Err(io::Error::WouldBlock) => core::hint::spin_loop(),
+                result => return result,
+            }
+        }
+    }
+}
@@ -0,0 +1,595 @@
+//! The scheduler behind [super::thread].
+//!
+//! Every core has a run queue per [Priority] and runs the ready thread with the highest priority,
+//! round robin within a priority. When its own queues are empty a core steals the oldest ready
+//! thread of the same priority from another core before falling back to its idle thread.
+//!
+//! A thread that doesn't run is saved as a [TrapFrame] on its own stack, like an interrupted
+//! exception. Switching threads is returning from an exception with a different frame: the timer
+//! interrupt does that at the end of every [TIME_SLICE] in [preempt], and [switch] saves the
+//! calling thread with `_thread_switch` to do the same without an interrupt. The scheduler state
+//! is changed with IRQs masked, so it is only ever locked for a short time.
+
+use core::cell::UnsafeCell;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use core::time::Duration;
+
+use mystd::sync::mutex::Mutex;
+
+use crate::exception::TrapFrame;
+use crate::peripherals::interrupts::{irq_enable, with_locked, without_irqs};
+use crate::system::arm_core;
+use crate::system::arm_core::mmu;
+use crate::system::arm_core::registers::aarch64::general_sys_ctrl::tpidr_elx::TpidrElx;
+use crate::system::arm_core::registers::aarch64::generic_timer::cntfrq_el0::CntFrqEl0;
+use crate::system::arm_core::registers::aarch64::generic_timer::cntp_tval_el0::CntPTValEl0;
+
+use super::counter::{self, PointInTime};
+use super::info::MemoryBlock;
+use super::interrupts::{self, Irq};
+
+pub const MAX_THREADS: usize = 32;
+pub const STACK_SIZE: usize = 0x4000;
+pub const TIME_SLICE: Duration = Duration::from_millis(10);
+
+const CORES: usize = 4;
+const NONE: usize = usize::MAX;
+
+/// Threads with a higher priority always run first. Idle threads never wait in a run queue, a
+/// core runs its idle thread when nothing else is ready.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    Idle,
+    Low,
+    Normal,
+    High,
+}
+
+impl Priority {
+    const QUEUED: usize = 3;
+
+    fn queue(self) -> Option<usize> {
+        match self {
+            Priority::Idle => None,
+            Priority::Low => Some(0),
+            Priority::Normal => Some(1),
+            Priority::High => Some(2),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpawnError {
+    /// All [MAX_THREADS] slots are taken.
+    TooManyThreads,
+    /// [init] hasn't run yet.
+    NotRunning,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum State {
+    Free,
+    Ready,
+    Running,
+    Sleeping(PointInTime),
+    /// Waits for the thread in the slot to finish.
+    Joining(usize),
+    Finished,
+}
+
+#[derive(Clone, Copy)]
+struct Thread {
+    id: u64,
+    state: State,
+    priority: Priority,
+    /// The saved [TrapFrame] while the thread doesn't run.
+    context: usize,
+    /// The core whose run queue the thread goes to.
+    core: usize,
+    joiner: Option<usize>,
+    /// Nobody joins, the slot is freed when the thread finishes.
+    detached: bool,
+    result: usize,
+    /// The TTBR0_EL1 the thread runs with, 0 for the kernel's tables.
+    address_space: u64,
+}
+
+impl Thread {
+    const FREE: Self = Self {
+        id: 0,
+        state: State::Free,
+        priority: Priority::Normal,
+        context: 0,
+        core: 0,
+        joiner: None,
+        detached: false,
+        result: 0,
+        address_space: 0,
+    };
+}
+
+/// Thread slots in the order they became ready.
+#[derive(Clone, Copy)]
+struct RunQueue {
+    slots: [u8; MAX_THREADS],
+    len: usize,
+}
+
+impl RunQueue {
+    const EMPTY: Self = Self {
+        slots: [0; MAX_THREADS],
+        len: 0,
+    };
+
+    fn push(&mut self, slot: usize) {
+        // every thread is in at most one queue, so this never overflows
+        self.slots[self.len] = slot as u8;
+        self.len += 1;
+    }
+
+    /// Removes the first slot `f` accepts, keeping the order of the others.
+    fn take(&mut self, f: impl Fn(usize) -> bool) -> Option<usize> {
+        let position = self.slots[..self.len]
+            .iter()
+            .position(|&slot| f(slot as usize))?;
+        let slot = self.slots[position] as usize;
+        self.slots.copy_within(position + 1..self.len, position);
+        self.len -= 1;
+        Some(slot)
+    }
+}
+
+/// What the calling thread wants from [switch], passed in `x0` and `x1` of its frame.
+#[derive(Clone, Copy)]
+#[repr(u64)]
+enum Request {
+    Yield = 0,
+    /// For the nanoseconds in the argument.
+    Sleep = 1,
+    /// Until the thread in the slot in the argument finished.
+    Join = 2,
+    /// With the result in the argument.
+    Exit = 3,
+}
+
+impl Request {
+    fn from_frame(frame: &TrapFrame) -> (Self, u64) {
+        let request = match frame.x[0] {
+            1 => Self::Sleep,
+            2 => Self::Join,
+            3 => Self::Exit,
+            _ => Self::Yield,
+        };
+        (request, frame.x[1])
+    }
+}
+
+struct Scheduler {
+    threads: [Thread; MAX_THREADS],
+    queues: [[RunQueue; Priority::QUEUED]; CORES],
+    current: [Option<usize>; CORES],
+    idle: [Option<usize>; CORES],
+    next_id: u64,
+}
+
+static SCHEDULER: Mutex<Scheduler> = Mutex::new(Scheduler {
+    threads: [Thread::FREE; MAX_THREADS],
+    queues: [[RunQueue::EMPTY; Priority::QUEUED]; CORES],
+    current: [None; CORES],
+    idle: [None; CORES],
+    next_id: 1,
+});
+
+/// A thread stays on its core until the switch away from it left its stack, even if it is
+/// already back in a run queue. Other cores mustn't run or free it until then.
+#[allow(clippy::declare_interior_mutable_const)]
+const OFF_CPU: AtomicBool = AtomicBool::new(false);
+static ON_CPU: [AtomicBool; MAX_THREADS] = [OFF_CPU; MAX_THREADS];
+
+/// The thread a core just switched away from, until [thread_switch_finished].
+#[allow(clippy::declare_interior_mutable_const)]
+const NO_THREAD: AtomicUsize = AtomicUsize::new(NONE);
+static SWITCHED_FROM: [AtomicUsize; CORES] = [NO_THREAD; CORES];
+
+/// Set by the timer at the end of a time slice.
+#[allow(clippy::declare_interior_mutable_const)]
+const NO_RESCHED: AtomicBool = AtomicBool::new(false);
+static NEED_RESCHED: [AtomicBool; CORES] = [NO_RESCHED; CORES];
+
+#[repr(C, align(16))]
+struct Stacks(UnsafeCell<[[u8; STACK_SIZE]; MAX_THREADS]>);
+
+// SAFETY: every stack is only used by the thread in its slot
+unsafe impl Sync for Stacks {}
+
+/// The stacks of the spawned threads, one per slot. Threads that were running before [init] or
+/// [run_idle] keep the stack `_start` gave them.
+static STACKS: Stacks = Stacks(UnsafeCell::new([[0; STACK_SIZE]; MAX_THREADS]));
+
+fn current_core() -> usize {
+    arm_core::get_core_num().num() as usize
+}
+
+/// Runs `f` on the locked scheduler with IRQs masked.
+fn with_scheduler<R>(f: impl FnOnce(&mut Scheduler) -> R) -> R {
+    with_locked(&SCHEDULER, f)
+}
+
+impl Scheduler {
+    fn allocate(&mut self, priority: Priority, core: usize) -> Result<usize, SpawnError> {
+        let slot = self
+            .threads
+            .iter()
+            .position(|thread| thread.state == State::Free)
+            .ok_or(SpawnError::TooManyThreads)?;
+        self.threads[slot] = Thread {
+            id: self.next_id,
+            priority,
+            core,
+            ..Thread::FREE
+        };
+        self.next_id += 1;
+        Ok(slot)
+    }
+
+    /// Makes the code running on `core` the thread in a new slot, it keeps its stack.
+    fn adopt(&mut self, core: usize, priority: Priority) -> Result<usize, SpawnError> {
+        let slot = self.allocate(priority, core)?;
+        self.threads[slot].state = State::Running;
+        self.current[core] = Some(slot);
+        ON_CPU[slot].store(true, Ordering::Release);
+        TpidrElx::write_register_el1(self.threads[slot].id);
+        Ok(slot)
+    }
+
+    /// Prepares a thread that starts in [thread_start] on its own stack.
+    fn create(
+        &mut self,
+        entry: fn(usize) -> usize,
+        arg: usize,
+        priority: Priority,
+        core: usize,
+    ) -> Result<usize, SpawnError> {
+        let slot = self.allocate(priority, core)?;
+        let top = stack(slot).top() as usize;
+        let frame = (top - core::mem::size_of::<TrapFrame>()) as *mut TrapFrame;
+        // SAFETY: the slot was free, nobody uses its stack
+        unsafe {
+            core::ptr::write_bytes(frame, 0, 1);
+            let frame = &mut *frame;
+            frame.x[0] = entry as *const () as u64;
+            frame.x[1] = arg as u64;
+            frame.elr = thread_start as *const () as u64;
+            // EL1 with SP_EL1 and all exceptions unmasked
+            frame.spsr = 0b0101;
+        }
+        self.threads[slot].context = frame as usize;
+        Ok(slot)
+    }
+
+    fn make_ready(&mut self, slot: usize) {
+        let thread = &mut self.threads[slot];
+        thread.state = State::Ready;
+        if let Some(queue) = thread.priority.queue() {
+            self.queues[thread.core][queue].push(slot);
+        }
+    }
+
+    fn wake_sleepers(&mut self) {
+        for slot in 0..MAX_THREADS {
+            if let State::Sleeping(until) = self.threads[slot].state {
+                if !until.is_in_the_future() {
+                    self.make_ready(slot);
+                }
+            }
+        }
+    }
+
+    /// The next thread for `core`, the highest priority first, from its own queue before the
+    /// queues of the other cores.
+    fn pick(&mut self, core: usize) -> Option<usize> {
+        let current = self.current[core];
+        let runnable = |slot: usize| Some(slot) == current || !ON_CPU[slot].load(Ordering::Acquire);
+        for queue in (0..Priority::QUEUED).rev() {
+            for other in (0..CORES).map(|i| (core + i) % CORES) {
+                if let Some(slot) = self.queues[other][queue].take(runnable) {
+                    self.threads[slot].core = core;
+                    return Some(slot);
+                }
+            }
+        }
+        self.idle[core]
+    }
+
+    /// Saves the current thread of `core` in `frame`, handles its request and returns the frame
+    /// of the thread to continue with.
+    fn switch(
+        &mut self,
+        core: usize,
+        frame: *mut TrapFrame,
+        request: Request,
+        arg: u64,
+    ) -> *mut TrapFrame {
+        let Some(current) = self.current[core] else {
+            return frame;
+        };
+        self.threads[current].context = frame as usize;
+        match request {
+            Request::Yield => self.make_ready(current),
+            Request::Sleep => {
+                let until = PointInTime::now() + Duration::from_nanos(arg);
+                self.threads[current].state = State::Sleeping(until);
+            }
+            Request::Join => {
+                let target = arg as usize;
+                if self.threads[target].state == State::Finished {
+                    // still on its way off another core
+                    self.make_ready(current)
+                } else {
+                    self.threads[current].state = State::Joining(target);
+                    self.threads[target].joiner = Some(current);
+                }
+            }
+            Request::Exit => {
+                let thread = &mut self.threads[current];
+                thread.state = State::Finished;
+                thread.result = arg as usize;
+                if let Some(joiner) = thread.joiner.take() {
+                    if self.threads[joiner].state == State::Joining(current) {
+                        self.make_ready(joiner);
+                    }
+                }
+            }
+        }
+        self.wake_sleepers();
+        let next = self.pick(core).expect("no idle thread on this core");
+        let thread = &mut self.threads[next];
+        thread.state = State::Running;
+        self.current[core] = Some(next);
+        if next != current {
+            ON_CPU[next].store(true, Ordering::Release);
+            SWITCHED_FROM[core].store(current, Ordering::Release);
+        }
+        TpidrElx::write_register_el1(thread.id);
+        let address_space = ttbr0_of(thread.address_space);
+        if address_space != 0 && address_space != mmu::ttbr0() {
+            // SAFETY: all tables map the kernel the same way
+            unsafe { mmu::set_ttbr0(address_space) };
+        }
+        thread.context as *mut TrapFrame
+    }
+
+    /// The result of the finished thread in `slot`, freeing the slot. `None` while it runs.
+    fn reap(&mut self, slot: usize) -> Option<usize> {
+        let thread = &mut self.threads[slot];
+        if thread.state != State::Finished || ON_CPU[slot].load(Ordering::Acquire) {
+            return None;
+        }
+        let result = thread.result;
+        *thread = Thread::FREE;
+        Some(result)
+    }
+}
+
+/// The TTBR0_EL1 for the address space of a thread.
+fn ttbr0_of(address_space: u64) -> u64 {
+    match address_space {
+        0 => mmu::kernel_ttbr0(),
+        process => process,
+    }
+}
+
+/// The stack of the thread in `slot`.
+fn stack(slot: usize) -> MemoryBlock {
+    let stacks = STACKS.0.get() as usize;
+    MemoryBlock::from_address_and_size(stacks + slot * STACK_SIZE, STACK_SIZE)
+}
+
+/// The thread stack that contains `address`, if any.
+pub fn stack_containing(address: usize) -> Option<MemoryBlock> {
+    let stacks = STACKS.0.get() as usize;
+    (stacks..stacks + MAX_THREADS * STACK_SIZE)
+        .contains(&address)
+        .then(|| stack((address - stacks) / STACK_SIZE))
+}
+
+/// Where spawned threads begin, the frame [Scheduler::create] prepared holds the arguments.
+extern "C" fn thread_start(entry: usize, arg: usize) -> ! {
+    // SAFETY: `create` stored a `fn(usize) -> usize`
+    let entry = unsafe { core::mem::transmute::<usize, fn(usize) -> usize>(entry) };
+    exit(entry(arg))
+}
+
+fn tick(_irq: Irq) {
+    start_time_slice();
+    NEED_RESCHED[current_core()].store(true, Ordering::Release);
+}
+
+fn start_time_slice() {
+    let frequency = CntFrqEl0::read_register().to_underlying();
+    let ticks = frequency * TIME_SLICE.as_micros() as u64 / 1_000_000;
+    CntPTValEl0::zero()
+        .timer_value()
+        .set_value(ticks)
+        .write_register();
+}
+
+/// Starts the time slices of the calling core and unmasks IRQs.
+fn start_preemption() {
+    // every core registers, the handler is the same
+    let _ = interrupts::register(Irq::PHYSICAL_TIMER, tick);
+    start_time_slice();
+    counter::enable_interrupt();
+    counter::unmask_interrupt();
+    interrupts::controller().enable(Irq::PHYSICAL_TIMER);
+    irq_enable();
+}
+
+fn idle(_: usize) -> usize {
+    loop {
+        arm_core::wait_for_interrupt();
+    }
+}
+
+/// Set once [init] made the main core's thread, the other cores wait for it in [run_idle].
+static STARTED: AtomicBool = AtomicBool::new(false);
+
+/// Makes the calling code the first thread of the main core and starts preemption on it.
+pub fn init() -> Result<(), SpawnError> {
+    let core = current_core();
+    with_scheduler(|scheduler| {
+        scheduler.adopt(core, Priority::Normal)?;
+        let idle = scheduler.create(idle, 0, Priority::Idle, core)?;
+        scheduler.idle[core] = Some(idle);
+        Ok(())
+    })?;
+    start_preemption();
+    STARTED.store(true, Ordering::Release);
+    arm_core::send_event();
+    Ok(())
+}
+
+/// Makes the calling core take part in scheduling once [init] ran, the calling code becomes its
+/// idle thread.
+pub fn run_idle() -> ! {
+    let core = current_core();
+    while !STARTED.load(Ordering::Acquire) {
+        arm_core::wait_for_event();
+    }
+    interrupts::controller().init();
+    with_scheduler(|scheduler| {
+        let slot = scheduler.adopt(core, Priority::Idle)?;
+        scheduler.idle[core] = Some(slot);
+        Ok::<_, SpawnError>(())
+    })
+    .expect("no slot for the idle thread");
+    start_preemption();
+    idle(0);
+    unreachable!()
+}
+
+/// A ready thread on the run queue of the calling core.
+pub fn spawn(
+    entry: fn(usize) -> usize,
+    arg: usize,
+    priority: Priority,
+) -> Result<(usize, u64), SpawnError> {
+    let core = current_core();
+    with_scheduler(|scheduler| {
+        if scheduler.current[core].is_none() {
+            return Err(SpawnError::NotRunning);
+        }
+        let slot = scheduler.create(entry, arg, priority, core)?;
+        scheduler.make_ready(slot);
+        Ok((slot, scheduler.threads[slot].id))
+    })
+}
+
+extern "C" {
+    /// Saves the calling thread like an exception would and continues with the frame
+    /// [thread_switch] returns. Returns when the thread is switched back to.
+    fn _thread_switch(request: u64, arg: u64);
+}
+
+/// Called by `_thread_switch` with the frame of the calling thread.
+#[no_mangle]
+extern "C" fn thread_switch(frame: *mut TrapFrame) -> *mut TrapFrame {
+    // SAFETY: `_thread_switch` passes the frame it just saved
+    let (request, arg) = Request::from_frame(unsafe { &*frame });
+    let core = current_core();
+    with_scheduler(|scheduler| scheduler.switch(core, frame, request, arg))
+}
+
+/// Called on the stack of the next thread, the previous one can run elsewhere from now on.
+#[no_mangle]
+extern "C" fn thread_switch_finished() {
+    let previous = SWITCHED_FROM[current_core()].swap(NONE, Ordering::AcqRel);
+    if previous == NONE {
+        return;
+    }
+    ON_CPU[previous].store(false, Ordering::Release);
+    with_scheduler(|scheduler| {
+        if scheduler.threads[previous].detached {
+            scheduler.reap(previous);
+        }
+    });
+}
+
+/// Called at the end of the IRQ exception, switches threads if the time slice is over.
+pub fn preempt(frame: *mut TrapFrame) -> *mut TrapFrame {
+    let core = current_core();
+    if !NEED_RESCHED[core].swap(false, Ordering::AcqRel) {
+        return frame;
+    }
+    with_scheduler(|scheduler| scheduler.switch(core, frame, Request::Yield, 0))
+}
+
+fn switch(request: Request, arg: u64) {
+    // SAFETY: the assembly saves and restores everything
+    without_irqs(|| unsafe { _thread_switch(request as u64, arg) });
+}
+
+pub fn is_running() -> bool {
+    let core = current_core();
+    with_scheduler(|scheduler| scheduler.current[core].is_some())
+}
+
+pub fn yield_now() {
+    if is_running() {
+        switch(Request::Yield, 0)
+    }
+}
+
+pub fn sleep(duration: Duration) {
+    if is_running() {
+        switch(Request::Sleep, duration.as_nanos() as u64)
+    } else {
+        super::thread::spin_wait_for(duration)
+    }
+}
+
+pub fn exit(result: usize) -> ! {
+    switch(Request::Exit, result as u64);
+    unreachable!("finished thread continued")
+}
+
+/// Waits for the thread in `slot` to finish and frees it. `None` if `id` doesn't run there.
+pub fn join(slot: usize, id: u64) -> Option<usize> {
+    loop {
+        let result = with_scheduler(|scheduler| {
+            if scheduler.threads[slot].id != id || scheduler.threads[slot].state == State::Free {
+                return Err(());
+            }
+            Ok(scheduler.reap(slot))
+        });
+        match result {
+            Err(()) => return None,
+            Ok(Some(result)) => return Some(result),
+            Ok(None) => switch(Request::Join, slot as u64),
+        }
+    }
+}
+
+/// Makes the calling thread run with the tables of `ttbr0`, from now on and whenever it is
+/// switched to. 0 goes back to the kernel's tables.
+///
+/// # Safety
+/// The tables have to map the kernel like the kernel's tables do.
+pub unsafe fn set_address_space(ttbr0: u64) {
+    let core = current_core();
+    with_scheduler(|scheduler| {
+        if let Some(current) = scheduler.current[core] {
+            scheduler.threads[current].address_space = ttbr0;
+        }
+        mmu::set_ttbr0(ttbr0_of(ttbr0));
+    })
+}
+
+/// Lets the thread in `slot` free itself when it finishes.
+pub fn detach(slot: usize, id: u64) {
+    with_scheduler(|scheduler| {
+        if scheduler.threads[slot].id == id && scheduler.reap(slot).is_none() {
+            scheduler.threads[slot].detached = true;
+        }
+    })
+}
@@ -0,0 +1,579 @@
+//! SD cards on the EMMC controller, see [crate::peripherals::emmc].
+//!
+//! [init] identifies the card at 400 kHz on the 1 bit bus, then switches to 25 MHz and the 4 bit
+//! bus. Blocks move through a buffer of the driver, with the DMA engine paced by the controller on
+//! the BCM2837. EMMC2 of the BCM2711 can't pace the DMA engine, there the blocks are copied
+//! through the data register instead. The controller is polled, one command at a time.
+//! [with_card] gives access to the [SdCard], which is a [BlockDevice].
+
+use core::cell::RefCell;
+use core::time::Duration;
+
+use mystd::block::{check_transfer, BlockDevice, BlockError, BLOCK_SIZE};
+use mystd::protocols::sd::{
+    app_command, command, CardState, CardStatus, Cid, Csd, CsdError, Ocr, BUS_WIDTH_4,
+    IF_COND_CHECK,
+};
+use mystd::sync::mutex::Mutex;
+
+#[cfg(feature = "bcm2837")]
+use crate::peripherals::dma::{self, DmaControlBlock, DmaPeripheral, DmaTransferInformation};
+#[cfg(feature = "bcm2837")]
+use crate::peripherals::emmc::EMMC_DATA_BUS_ADDRESS;
+use crate::peripherals::emmc::{
+    AutoCommand, Emmc, EmmcBlockSizeCount, EmmcCommand, EmmcControl1, EmmcInterrupts, ResponseType,
+};
+use crate::peripherals::gpio::GpioError;
+use crate::peripherals::power::PowerDevice;
+#[cfg(feature = "bcm2837")]
+use crate::peripherals::BCM_HOST;
+#[cfg(feature = "bcm2837")]
+use crate::system::arm_core;
+
+use super::clocks::Clock;
+use super::counter::{poll, Timeout};
+use super::thread;
+
+#[cfg(feature = "bcm2837")]
+const CLOCK: Clock = Clock::Emmc;
+#[cfg(feature = "bcm2711")]
+const CLOCK: Clock = Clock::Emmc2;
+
+const IDENTIFICATION_RATE: u32 = 400_000;
+/// The default speed mode every card supports.
+const TRANSFER_RATE: u32 = 25_000_000;
+
+/// The DMA channel of the driver.
+#[cfg(feature = "bcm2837")]
+const DMA_CHANNEL: dma::DmaStandardChannel = dma::DMA_4;
+/// Blocks per transfer.
+const BUFFER_BLOCKS: usize = 8;
+
+const RESET_TIMEOUT: Duration = Duration::from_millis(100);
+const COMMAND_TIMEOUT: Duration = Duration::from_millis(100);
+/// Cards may take up to 250ms to write a block.
+const DATA_TIMEOUT: Duration = Duration::from_millis(500);
+/// How long a card may stay busy after power up.
+const POWER_UP_TIMEOUT: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SdError {
+    /// The firmware didn't power or clock the controller.
+    PowerOn,
+    /// The controller didn't reset, or a command or transfer didn't finish in time.
+    Timeout,
+    /// No card answered the command, e.g. because none is inserted.
+    NoResponse,
+    /// CRC, end bit or index errors on the CMD or DAT lines, with the interrupt flags.
+    Transfer(u32),
+    /// The card reported errors in its status.
+    Card(u32),
+    /// The card doesn't work at 3.3V, or isn't an SD memory card.
+    Unsupported,
+    Csd(CsdError),
+    /// The DMA engine failed to access memory.
+    Dma,
+    Block(BlockError),
+    /// Another driver has the GPIO pins of the card slot.
+    Pins(GpioError),
+    /// [init] wasn't called, or the card is in use.
+    Unavailable,
+}
+
+impl From<BlockError> for SdError {
+    fn from(value: BlockError) -> Self {
+        Self::Block(value)
+    }
+}
+
+impl From<CsdError> for SdError {
+    fn from(value: CsdError) -> Self {
+        Self::Csd(value)
+    }
+}
+
+impl From<Timeout> for SdError {
+    fn from(_: Timeout) -> Self {
+        Self::Timeout
+    }
+}
+
+/// What a command expects back from the card.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Response {
+    None,
+    /// The card status.
+    R1,
+    /// The card status, then the card is busy for a while.
+    R1b,
+    /// The CID or CSD register.
+    R2,
+    /// The OCR register, without CRC.
+    R3,
+    /// The relative card address and some status bits.
+    R6,
+    /// The echo of SEND_IF_COND.
+    R7,
+}
+
+impl Response {
+    fn command(self, index: u8) -> EmmcCommand {
+        let response_type = match self {
+            Self::None => ResponseType::None,
+            Self::R2 => ResponseType::Bits136,
+            Self::R1b => ResponseType::Bits48Busy,
+            Self::R1 | Self::R3 | Self::R6 | Self::R7 => ResponseType::Bits48,
+        };
+        let has_index = !matches!(self, Self::None | Self::R2 | Self::R3);
+        let has_crc = !matches!(self, Self::None | Self::R3);
+        EmmcCommand::zero()
+            .index()
+            .set_value(index as u32)
+            .response_type()
+            .set_value(response_type)
+            .check_index()
+            .set_value(has_index)
+            .check_crc()
+            .set_value(has_crc)
+    }
+}
+
+/// Where the blocks of a transfer go, aligned for the DMA engine.
+#[repr(C, align(64))]
+struct DmaBuffer([u8; BUFFER_BLOCKS * BLOCK_SIZE]);
+
+pub struct SdCard {
+    buffer: DmaBuffer,
+    cid: Cid,
+    relative_address: u32,
+    /// Blocks are addressed by number, otherwise by byte.
+    high_capacity: bool,
+    block_count: u64,
+}
+
+static CARD: Mutex<RefCell<Option<SdCard>>> = Mutex::new(RefCell::new(None));
+
+/// Powers and resets the controller, then identifies the card and selects it for transfers.
+pub fn init() -> Result<(), SdError> {
+    let lock = CARD.try_lock().ok_or(SdError::Unavailable)?;
+    let mut card = lock.borrow_mut();
+    *card = None;
+    power_on()?;
+    reset_host()?;
+    *card = Some(identify()?);
+    Ok(())
+}
+
+/// Runs `f` with the card, `None` if [init] wasn't called or the card is in use.
+pub fn with_card<R, F: FnOnce(&mut SdCard) -> R>(f: F) -> Option<R> {
+    let lock = CARD.try_lock()?;
+    let mut card = lock.borrow_mut();
+    card.as_mut().map(f)
+}
+
+fn power_on() -> Result<(), SdError> {
+    let device = PowerDevice::SdCard;
+    let state = device.state().ok_or(SdError::PowerOn)?;
+    if !state.is_on() {
+        device.set_state(state.with_on().with_wait_set());
+        thread::spin_wait_for(Duration::from_millis(device.timing_ms().unwrap_or(0) as u64));
+    }
+    let state = CLOCK.state().ok_or(SdError::PowerOn)?;
+    if !state.is_on() {
+        CLOCK.set_state(state.with_on());
+    }
+    route_pins()
+}
+
+/// The SD card slot of the Pi 3 is on GPIO 48 to 53, which the firmware gives to its own SD host
+/// controller. ALT3 connects them to the EMMC controller instead.
+#[cfg(feature = "bcm2837")]
+fn route_pins() -> Result<(), SdError> {
+    use crate::peripherals::gpio::{Gpio, PinFunction, PinSet, Resistor};
+    Gpio::claim(PinSet::select(&[48, 49, 50, 51, 52, 53]), "SD card").map_err(SdError::Pins)?;
+    Gpio::set_functions(PinSet::select(&[48, 49, 50, 51, 52, 53]), PinFunction::Alt3);
+    Gpio::set_pull_resistors(PinSet::select(&[48]), Resistor::None);
+    Gpio::set_pull_resistors(PinSet::select(&[49, 50, 51, 52, 53]), Resistor::PullUp);
+    Ok(())
+}
+
+/// The SD card slot of the Pi 4 is wired to the EMMC2 controller.
+#[cfg(feature = "bcm2711")]
+fn route_pins() -> Result<(), SdError> {
+    Ok(())
+}
+
+fn reset_host() -> Result<(), SdError> {
+    Emmc::set_control1(EmmcControl1::zero().reset_host().set());
+    poll(
+        || Some(()).filter(|_| Emmc::control1().reset_host().is_clear()),
+        RESET_TIMEOUT,
+    )?;
+    Emmc::set_control0(Emmc::control0().bus_width_4_bit().clear());
+    // every flag is reported in the interrupt register, none raises the interrupt
+    Emmc::set_interrupt_mask(EmmcInterrupts::all_set());
+    Emmc::set_interrupt_enable(EmmcInterrupts::zero());
+    Emmc::clear_interrupts(EmmcInterrupts::all_set());
+    set_clock_rate(IDENTIFICATION_RATE)
+}
+
+/// The divider for `rate` or less from `base_rate`. Hosts of SDHCI 3.0 divide by twice the 10
+/// bit divider, older ones by twice a power of two up to 128.
+fn clock_divider(base_rate: u32, rate: u32) -> u32 {
+    let divider = base_rate.div_ceil(2 * rate);
+    if Emmc::version().host_version().value() >= 2 {
+        divider.min(0x3ff)
+    } else {
+        divider.next_power_of_two().min(0x80)
+    }
+}
+
+fn set_clock_rate(rate: u32) -> Result<(), SdError> {
+    let base_rate = CLOCK.rate().ok_or(SdError::PowerOn)?;
+    let divider = clock_divider(base_rate, rate);
+    // the card clock has to stop while the divider changes
+    Emmc::set_control1(Emmc::control1().clock_enable().clear());
+    let control = Emmc::control1()
+        .clock_divider_low()
+        .set_value(divider & 0xff)
+        .clock_divider_high()
+        .set_value(divider >> 8)
+        .data_timeout()
+        .set_value(0xe)
+        .clock_internal_enable()
+        .set();
+    Emmc::set_control1(control);
+    poll(
+        || Some(()).filter(|_| Emmc::control1().clock_stable().is_set()),
+        RESET_TIMEOUT,
+    )?;
+    Emmc::set_control1(Emmc::control1().clock_enable().set());
+    thread::spin_wait_for(Duration::from_millis(2));
+    Ok(())
+}
+
+/// Resets the CMD and DAT line state machines after an error.
+fn reset_lines() {
+    Emmc::set_control1(Emmc::control1().reset_command().set().reset_data().set());
+    let _ = poll(
+        || {
+            let control = Emmc::control1();
+            Some(())
+                .filter(|_| control.reset_command().is_clear() && control.reset_data().is_clear())
+        },
+        RESET_TIMEOUT,
+    );
+}
+
+/// Waits for any of `flags`, clears them and returns all flags. Errors end the wait.
+fn wait_for_interrupt(flags: EmmcInterrupts, timeout: Duration) -> Result<EmmcInterrupts, SdError> {
+    let wanted = flags.to_underlying() | EmmcInterrupts::errors().to_underlying();
+    let interrupts = poll(
+        || Some(Emmc::interrupts()).filter(|i| i.to_underlying() & wanted != 0),
+        timeout,
+    );
+    let interrupts = match interrupts {
+        Ok(interrupts) => interrupts,
+        Err(Timeout) => {
+            reset_lines();
+            return Err(SdError::Timeout);
+        }
+    };
+    Emmc::clear_interrupts(EmmcInterrupts::new(interrupts.to_underlying() & wanted));
+    if interrupts.has_error() {
+        reset_lines();
+        if interrupts.command_timeout_error().is_set() {
+            return Err(SdError::NoResponse);
+        }
+        return Err(SdError::Transfer(interrupts.to_underlying()));
+    }
+    Ok(interrupts)
+}
+
+/// Sends a command without data and returns its response.
+fn send(index: u8, argument: u32, response: Response) -> Result<[u32; 4], SdError> {
+    send_command(response.command(index), argument, response)
+}
+
+fn send_command(
+    command: EmmcCommand,
+    argument: u32,
+    response: Response,
+) -> Result<[u32; 4], SdError> {
+    let uses_data_lines = command.is_data().is_set() || response == Response::R1b;
+    poll(
+        || {
+            let status = Emmc::status();
+            let busy = status.command_inhibit().is_set()
+                || (uses_data_lines && status.data_inhibit().is_set());
+            Some(()).filter(|_| !busy)
+        },
+        COMMAND_TIMEOUT,
+    )?;
+    Emmc::clear_interrupts(EmmcInterrupts::all_set());
+    Emmc::send_command(command, argument);
+    wait_for_interrupt(EmmcInterrupts::zero().command_done().set(), COMMAND_TIMEOUT)?;
+    if response == Response::R1b {
+        wait_for_interrupt(EmmcInterrupts::zero().data_done().set(), DATA_TIMEOUT)?;
+    }
+    let words = Emmc::response();
+    if matches!(response, Response::R1 | Response::R1b) {
+        let status = CardStatus::new(words[0]);
+        if status.has_error() {
+            return Err(SdError::Card(words[0]));
+        }
+    }
+    Ok(words)
+}
+
+fn send_app(
+    index: u8,
+    argument: u32,
+    response: Response,
+    relative_address: u32,
+) -> Result<[u32; 4], SdError> {
+    match send(command::APP_CMD, relative_address << 16, Response::R1) {
+        // before the card has an address, its status still reports SEND_IF_COND as illegal if
+        // it is an old one
+        Err(SdError::Card(_)) if relative_address == 0 => {}
+        result => {
+            result?;
+        }
+    }
+    send(index, argument, response)
+}
+
+/// CMD0, 8, 55 + 41, 2, 3, 9 and 7, then the 4 bit bus at full speed.
+fn identify() -> Result<SdCard, SdError> {
+    send(command::GO_IDLE_STATE, 0, Response::None)?;
+    // cards of version 2.0 and later answer, older ones don't know the command
+    let version_2 = match send(command::SEND_IF_COND, IF_COND_CHECK, Response::R7) {
+        Ok(response) if response[0] & 0xfff == IF_COND_CHECK => true,
+        Ok(_) => return Err(SdError::Unsupported),
+        Err(SdError::NoResponse) => false,
+        Err(error) => return Err(error),
+    };
+
+    let request = Ocr::host_request(version_2).to_underlying();
+    let ocr = poll(
+        || {
+            let response = send_app(app_command::SD_SEND_OP_COND, request, Response::R3, 0);
+            match response.map(|words| Ocr::new(words[0])) {
+                Ok(ocr) if ocr.powered_up().is_set() => Some(Ok(ocr)),
+                Ok(_) => {
+                    thread::spin_wait_for(Duration::from_millis(10));
+                    None
+                }
+                Err(SdError::NoResponse) => Some(Err(SdError::Unsupported)),
+                Err(error) => Some(Err(error)),
+            }
+        },
+        POWER_UP_TIMEOUT,
+    )??;
+    if ocr.voltage_window().value() == 0 {
+        return Err(SdError::Unsupported);
+    }
+
+    let cid = Cid::from_response(send(command::ALL_SEND_CID, 0, Response::R2)?);
+    let relative_address = send(command::SEND_RELATIVE_ADDR, 0, Response::R6)?[0] >> 16;
+    let csd = Csd::from_response(send(
+        command::SEND_CSD,
+        relative_address << 16,
+        Response::R2,
+    )?)?;
+
+    set_clock_rate(TRANSFER_RATE)?;
+    send(command::SELECT_CARD, relative_address << 16, Response::R1b)?;
+    send_app(
+        app_command::SET_BUS_WIDTH,
+        BUS_WIDTH_4,
+        Response::R1,
+        relative_address,
+    )?;
+    Emmc::set_control0(Emmc::control0().bus_width_4_bit().set());
+    let high_capacity = ocr.card_capacity_status().is_set();
+    if !high_capacity {
+        send(command::SET_BLOCKLEN, BLOCK_SIZE as u32, Response::R1)?;
+    }
+
+    Ok(SdCard {
+        buffer: DmaBuffer([0; BUFFER_BLOCKS * BLOCK_SIZE]),
+        cid,
+        relative_address,
+        high_capacity,
+        block_count: csd.block_count,
+    })
+}
+
+#[cfg(feature = "bcm2837")]
+fn bus_address(address: usize) -> u32 {
+    (BCM_HOST.sdram_address | address) as u32
+}
+
+impl SdCard {
+    pub fn cid(&self) -> &Cid {
+        &self.cid
+    }
+
+    pub fn is_high_capacity(&self) -> bool {
+        self.high_capacity
+    }
+
+    /// Waits until the card finished programming and is back in the transfer state.
+    fn wait_until_ready(&self) -> Result<(), SdError> {
+        poll(
+            || {
+                let status = send(
+                    command::SEND_STATUS,
+                    self.relative_address << 16,
+                    Response::R1,
+                );
+                match status.map(|words| CardStatus::new(words[0])) {
+                    Ok(status)
+                        if status.ready_for_data().is_set()
+                            && status.current_state().value() == Ok(CardState::Transfer) =>
+                    {
+                        Some(Ok(()))
+                    }
+                    Ok(_) => None,
+                    Err(error) => Some(Err(error)),
+                }
+            },
+            DATA_TIMEOUT,
+        )?
+    }
+
+    /// Moves `count` blocks from `first` on between the card and the buffer.
+    fn transfer(&mut self, first: u64, count: usize, write: bool) -> Result<(), SdError> {
+        let len = count * BLOCK_SIZE;
+        let address = if self.high_capacity {
+            first
+        } else {
+            first * BLOCK_SIZE as u64
+        };
+        let index = match (write, count > 1) {
+            (false, false) => command::READ_SINGLE_BLOCK,
+            (false, true) => command::READ_MULTIPLE_BLOCK,
+            (true, false) => command::WRITE_BLOCK,
+            (true, true) => command::WRITE_MULTIPLE_BLOCK,
+        };
+        let mut command = Response::R1
+            .command(index)
+            .is_data()
+            .set()
+            .data_direction_read()
+            .set_value(!write);
+        if count > 1 {
+            command = command
+                .multi_block()
+                .set()
+                .block_count_enable()
+                .set()
+                .auto_command()
+                .set_value(AutoCommand::Cmd12);
+        }
+
+        Emmc::set_block_size_count(
+            EmmcBlockSizeCount::zero()
+                .block_size()
+                .set_value(BLOCK_SIZE as u32)
+                .block_count()
+                .set_value(count as u32),
+        );
+        send_command(command, address as u32, Response::R1)?;
+        self.move_data(len, write)?;
+        if write {
+            self.wait_until_ready()?;
+        }
+        Ok(())
+    }
+
+    /// Moves `len` bytes of the buffer for the command just sent, with the DMA engine.
+    #[cfg(feature = "bcm2837")]
+    fn move_data(&mut self, len: usize, write: bool) -> Result<(), SdError> {
+        let buffer = self.buffer.0.as_ptr() as usize;
+        arm_core::clean_and_invalidate_data_cache(buffer, len);
+        let (information, source, destination) = if write {
+            let information = DmaTransferInformation::to_peripheral(DmaPeripheral::Emmc);
+            (information, bus_address(buffer), EMMC_DATA_BUS_ADDRESS)
+        } else {
+            let information = DmaTransferInformation::from_peripheral(DmaPeripheral::Emmc);
+            (information, EMMC_DATA_BUS_ADDRESS, bus_address(buffer))
+        };
+        let control_block =
+            DmaControlBlock::new_linear_copy(information, source, destination, len as u32, 0);
+        let control_block_address = core::ptr::addr_of!(control_block) as usize;
+        arm_core::clean_and_invalidate_data_cache(
+            control_block_address,
+            size_of::<DmaControlBlock>(),
+        );
+
+        DMA_CHANNEL.start_transfer(&control_block);
+        let done = wait_for_interrupt(EmmcInterrupts::zero().data_done().set(), DATA_TIMEOUT);
+        let dma = poll(
+            || {
+                let status = DMA_CHANNEL.control_and_status().read();
+                Some(status.error().is_clear())
+                    .filter(|_| status.end().is_set() || status.error().is_set())
+            },
+            COMMAND_TIMEOUT,
+        );
+        DMA_CHANNEL.reset();
+        arm_core::clean_and_invalidate_data_cache(buffer, len);
+        done?;
+        if !dma? {
+            return Err(SdError::Dma);
+        }
+        Ok(())
+    }
+
+    /// Moves `len` bytes of the buffer for the command just sent, a block whenever the
+    /// controller's buffer is ready for one.
+    #[cfg(feature = "bcm2711")]
+    fn move_data(&mut self, len: usize, write: bool) -> Result<(), SdError> {
+        let ready = match write {
+            true => EmmcInterrupts::zero().write_ready().set(),
+            false => EmmcInterrupts::zero().read_ready().set(),
+        };
+        for block in self.buffer.0[..len].chunks_exact_mut(BLOCK_SIZE) {
+            wait_for_interrupt(ready, DATA_TIMEOUT)?;
+            for word in block.chunks_exact_mut(4) {
+                if write {
+                    Emmc::write_data(u32::from_le_bytes([word[0], word[1], word[2], word[3]]));
+                } else {
+                    word.copy_from_slice(&Emmc::read_data().to_le_bytes());
+                }
+            }
+        }
+        wait_for_interrupt(EmmcInterrupts::zero().data_done().set(), DATA_TIMEOUT)?;
+        Ok(())
+    }
+}
+
+impl BlockDevice for SdCard {
+    type Error = SdError;
+
+    fn block_count(&self) -> u64 {
+        self.block_count
+    }
+
+    fn read_blocks(&mut self, first: u64, buffer: &mut [u8]) -> Result<(), Self::Error> {
+        check_transfer(first, buffer.len(), self.block_count)?;
+        for (i, chunk) in buffer.chunks_mut(BUFFER_BLOCKS * BLOCK_SIZE).enumerate() {
+            let count = chunk.len() / BLOCK_SIZE;
+            self.transfer(first + (i * BUFFER_BLOCKS) as u64, count, false)?;
+            chunk.copy_from_slice(&self.buffer.0[..chunk.len()]);
+        }
+        Ok(())
+    }
+
+    fn write_blocks(&mut self, first: u64, buffer: &[u8]) -> Result<(), Self::Error> {
+        check_transfer(first, buffer.len(), self.block_count)?;
+        for (i, chunk) in buffer.chunks(BUFFER_BLOCKS * BLOCK_SIZE).enumerate() {
+            let count = chunk.len() / BLOCK_SIZE;
+            self.buffer.0[..chunk.len()].copy_from_slice(chunk);
+            self.transfer(first + (i * BUFFER_BLOCKS) as u64, count, true)?;
+        }
+        Ok(())
+    }
+}
@@ -1,7 +1,14 @@
-use crate::system::arm_core::registers::aarch64::general_sys_ctrl::tpidr_elx::TpidrElx;
+//! Kernel threads, preemptively scheduled on all cores by [super::scheduler].
+//!
+//! The code that calls [init] becomes the first thread, the other cores join with [run_idle].
+//! Threads run a `fn(usize) -> usize` and hand its result to [JoinHandle::join].
 
+use crate::system::arm_core::registers::aarch64::general_sys_ctrl::tpidr_elx::TpidrElx;
 
+use super::scheduler;
+pub use super::scheduler::{Priority, SpawnError};
 
+/// The id of the running thread, 0 before [init].
 pub fn id() -> u64 {
     TpidrElx::read_register()
 }
@@ -10,6 +17,69 @@ pub fn set_id(thread_id: u64) {
     TpidrElx::write_register(thread_id)
 }
 
+/// Makes the calling code a thread and starts preempting it. Unmasks IRQs.
+pub fn init() -> Result<(), SpawnError> {
+    scheduler::init()
+}
+
+/// Lets the calling core run threads once [init] ran, with the calling code as its idle thread.
+pub fn run_idle() -> ! {
+    scheduler::run_idle()
+}
+
+/// Owns a spawned thread. Dropping it detaches the thread.
+pub struct JoinHandle {
+    slot: usize,
+    id: u64,
+}
+
+impl JoinHandle {
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Waits for the thread to finish and returns its result.
+    pub fn join(self) -> usize {
+        let this = core::mem::ManuallyDrop::new(self);
+        scheduler::join(this.slot, this.id).expect("joined thread is gone")
+    }
+}
+
+impl Drop for JoinHandle {
+    fn drop(&mut self) {
+        scheduler::detach(self.slot, self.id)
+    }
+}
+
+/// Runs `entry(arg)` in a new thread with [Priority::Normal].
+pub fn spawn(entry: fn(usize) -> usize, arg: usize) -> Result<JoinHandle, SpawnError> {
+    spawn_with_priority(entry, arg, Priority::Normal)
+}
+
+pub fn spawn_with_priority(
+    entry: fn(usize) -> usize,
+    arg: usize,
+    priority: Priority,
+) -> Result<JoinHandle, SpawnError> {
+    let (slot, id) = scheduler::spawn(entry, arg, priority)?;
+    Ok(JoinHandle { slot, id })
+}
+
+/// Lets other ready threads run first.
+pub fn yield_now() {
+    scheduler::yield_now()
+}
+
+/// Gives up the core for at least `duration`, spins if threads aren't running yet.
+pub fn sleep(duration: core::time::Duration) {
+    scheduler::sleep(duration)
+}
+
+/// Ends the calling thread with `result`.
+pub fn exit(result: usize) -> ! {
+    scheduler::exit(result)
+}
+
 pub fn spin_wait_cycles(mut count: usize) {
     while count > 0 {
         count -= 1;
@@ -28,4 +98,3 @@ pub fn spin_wait_for(duration: core::time::Duration) {
         }
     }
 }
-
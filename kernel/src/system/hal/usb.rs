@@ -0,0 +1,840 @@
+//! USB host on the Synopsys DesignWare OTG controller (DWC2) of the Raspberry Pi.
+//!
+//! The controller runs in host mode with its internal DMA and is polled, one transfer at a time
+//! on a single channel. [init] powers it, resets the root port and enumerates the devices behind
+//! it, hubs included. [with_host] gives access to the [UsbHost] afterwards, its [UsbHost::tree]
+//! shows what is attached.
+
+use core::cell::RefCell;
+use core::time::Duration;
+
+use mystd::protocols::usb::{
+    class, descriptor_type, port_feature, ConfigurationDescriptor, Descriptor, DescriptorError,
+    Descriptors, DeviceDescriptor, EndpointDescriptor, HubDescriptor, InterfaceDescriptor,
+    PortStatus, SetupPacket, Speed, TransferType,
+};
+use mystd::sync::mutex::Mutex;
+
+use crate::peripherals::power::PowerDevice;
+use crate::peripherals::usb::{
+    DwHciChannel, DwHciChannelChar, DwHciChannelInterrupts, DwHciChannelSplit,
+    DwHciChannelTransferSize, DwHciCore, DwHciCoreInterrupts, DwHciCoreReset, DwHciHost,
+    DwHciHostPort, EndpointType, FsPhyType, HsPhyType, PhyClock, Pid, PortSpeed,
+};
+use crate::peripherals::BCM_HOST;
+use crate::system::arm_core;
+
+use super::counter::{poll, PointInTime, Timeout};
+use super::thread;
+
+pub const MAX_DEVICES: usize = 16;
+/// Interfaces of a device beyond this are ignored.
+pub const MAX_INTERFACES: usize = 4;
+/// Hubs can be chained 5 deep below the root port.
+const MAX_HUB_DEPTH: u8 = 5;
+
+/// FIFO sizes in words, the controller has 4080 words of FIFO RAM.
+const RX_FIFO_SIZE: u32 = 1024;
+const NON_PERIODIC_TX_FIFO_SIZE: u32 = 1024;
+const PERIODIC_TX_FIFO_SIZE: u32 = 1024;
+
+/// All transfers use this channel.
+const CHANNEL: usize = 0;
+const DMA_BUFFER_LEN: usize = 512;
+const TRANSFER_TIMEOUT: Duration = Duration::from_millis(100);
+/// Number of NAKs a control or bulk transfer retries before it gives up.
+const MAX_NAKS: usize = 1000;
+/// Number of bus errors a transaction retries before it gives up.
+const MAX_ERRORS: usize = 3;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UsbError {
+    /// The firmware didn't power the controller on.
+    PowerOn,
+    /// The controller didn't finish a reset or a transfer in time.
+    Timeout,
+    /// The device answered with STALL, it doesn't support the request.
+    Stall,
+    /// The device kept answering NAK. For interrupt endpoints that means it has nothing to report.
+    Nak,
+    /// CRC, bit stuffing, babble or data toggle errors on the bus.
+    Transaction,
+    /// The DMA failed to access memory.
+    Dma,
+    /// Nothing is attached, or the port didn't enable after its reset.
+    NotConnected,
+    TooManyDevices,
+    /// More data than fits the DMA buffer.
+    TooLarge,
+    /// [init] wasn't called, or the host is in use.
+    Unavailable,
+    Descriptor(DescriptorError),
+}
+
+impl From<DescriptorError> for UsbError {
+    fn from(value: DescriptorError) -> Self {
+        Self::Descriptor(value)
+    }
+}
+
+impl From<Timeout> for UsbError {
+    fn from(_: Timeout) -> Self {
+        Self::Timeout
+    }
+}
+
+/// Where a transfer goes.
+#[derive(Clone, Copy, Debug)]
+pub struct Endpoint {
+    pub device_address: u8,
+    pub number: u8,
+    pub speed: Speed,
+    pub max_packet_size: u16,
+    pub transfer_type: TransferType,
+    /// Address and port of the high speed hub that translates for a low or full speed device.
+    pub transaction_translator: Option<(u8, u8)>,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Interface {
+    pub descriptor: InterfaceDescriptor,
+    /// The first interrupt IN endpoint, where HID devices and hubs report.
+    pub interrupt_in: Option<EndpointDescriptor>,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Device {
+    pub address: u8,
+    pub speed: Speed,
+    /// Address of the hub the device is attached to, 0 for the root port.
+    pub hub_address: u8,
+    /// The port of that hub, starting at 1.
+    pub port: u8,
+    /// Number of hubs between the device and the root port.
+    pub depth: u8,
+    pub descriptor: DeviceDescriptor,
+    pub configuration: u8,
+    pub interfaces: [Option<Interface>; MAX_INTERFACES],
+    /// Number of downstream ports if the device is a hub.
+    pub hub_ports: u8,
+    pub transaction_translator: Option<(u8, u8)>,
+}
+
+impl Device {
+    pub fn control_endpoint(&self) -> Endpoint {
+        Endpoint {
+            device_address: self.address,
+            number: 0,
+            speed: self.speed,
+            max_packet_size: self.descriptor.max_packet_size0 as u16,
+            transfer_type: TransferType::Control,
+            transaction_translator: self.transaction_translator,
+        }
+    }
+
+    pub fn endpoint(&self, descriptor: &EndpointDescriptor) -> Endpoint {
+        Endpoint {
+            device_address: self.address,
+            number: descriptor.number(),
+            speed: self.speed,
+            max_packet_size: descriptor.max_packet_size & 0x7ff,
+            transfer_type: descriptor.transfer_type(),
+            transaction_translator: self.transaction_translator,
+        }
+    }
+
+    pub fn interfaces(&self) -> impl Iterator<Item = &Interface> {
+        self.interfaces.iter().flatten()
+    }
+
+    pub fn is_hub(&self) -> bool {
+        self.descriptor.class == class::HUB
+            || self.interfaces().any(|i| i.descriptor.class == class::HUB)
+    }
+}
+
+fn class_name(class: u8, protocol: u8) -> &'static str {
+    match (class, protocol) {
+        (class::HID, 1) => "keyboard",
+        (class::HID, 2) => "mouse",
+        (class::HID, _) => "HID",
+        (class::HUB, _) => "hub",
+        (1, _) => "audio",
+        (2, _) => "communications",
+        (7, _) => "printer",
+        (8, _) => "mass storage",
+        (0xe0, _) => "wireless",
+        (0xff, _) => "vendor specific",
+        _ => "unknown class",
+    }
+}
+
+impl core::fmt::Display for Device {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "{:04x}:{:04x} {:?} speed, address {}",
+            self.descriptor.vendor_id, self.descriptor.product_id, self.speed, self.address
+        )?;
+        if self.is_hub() {
+            return write!(f, ", hub with {} ports", self.hub_ports);
+        }
+        for interface in self.interfaces() {
+            let descriptor = interface.descriptor;
+            write!(f, ", {}", class_name(descriptor.class, descriptor.protocol))?;
+        }
+        Ok(())
+    }
+}
+
+/// The attached devices below the root port, indented by their depth.
+pub struct DeviceTree<'a>(&'a [Option<Device>]);
+
+impl core::fmt::Display for DeviceTree<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "root port")?;
+        // devices are enumerated depth first, so the table is already in tree order
+        for device in self.0.iter().flatten() {
+            write!(f, "\n{:indent$}", "", indent = 2 + 2 * device.depth as usize)?;
+            if device.hub_address != 0 {
+                write!(f, "port {}: ", device.port)?;
+            }
+            write!(f, "{}", device)?;
+        }
+        if self.0.iter().all(Option::is_none) {
+            write!(f, "\n  no devices")?;
+        }
+        Ok(())
+    }
+}
+
+#[repr(C, align(64))]
+struct DmaBuffer([u8; DMA_BUFFER_LEN]);
+
+pub struct UsbHost {
+    buffer: DmaBuffer,
+    devices: [Option<Device>; MAX_DEVICES],
+    next_address: u8,
+}
+
+static HOST: Mutex<RefCell<Option<UsbHost>>> = Mutex::new(RefCell::new(None));
+
+/// Powers the controller, resets the root port and enumerates the attached devices.
+pub fn init() -> Result<(), UsbError> {
+    let lock = HOST.try_lock().ok_or(UsbError::Unavailable)?;
+    let mut host = lock.borrow_mut();
+    let host = host.insert(UsbHost {
+        buffer: DmaBuffer([0; DMA_BUFFER_LEN]),
+        devices: [None; MAX_DEVICES],
+        next_address: 1,
+    });
+    power_on()?;
+    init_core()?;
+    init_host()?;
+    host.enumerate_root_port()
+}
+
+/// Runs `f` with the host, `None` if [init] wasn't called or the host is in use.
+pub fn with_host<R, F: FnOnce(&mut UsbHost) -> R>(f: F) -> Option<R> {
+    let lock = HOST.try_lock()?;
+    let mut host = lock.borrow_mut();
+    host.as_mut().map(f)
+}
+
+fn power_on() -> Result<(), UsbError> {
+    let device = PowerDevice::UsbHcd;
+    let state = device.state().ok_or(UsbError::PowerOn)?;
+    if state.is_on() {
+        return Ok(());
+    }
+    device.set_state(state.with_on().with_wait_set());
+    thread::spin_wait_for(Duration::from_millis(
+        device.timing_ms().unwrap_or(0) as u64,
+    ));
+    match device.state() {
+        Some(state) if state.is_on() => Ok(()),
+        _ => Err(UsbError::PowerOn),
+    }
+}
+
+fn reset_core() -> Result<(), UsbError> {
+    poll(
+        || Some(()).filter(|_| DwHciCore::get_reset().ahb_idle().is_set()),
+        Duration::from_millis(100),
+    )?;
+    DwHciCore::set_reset(DwHciCoreReset::zero().soft_reset().set());
+    poll(
+        || Some(()).filter(|_| DwHciCore::get_reset().soft_reset().is_clear()),
+        Duration::from_millis(100),
+    )?;
+    thread::spin_wait_for(Duration::from_millis(100));
+    Ok(())
+}
+
+fn init_core() -> Result<(), UsbError> {
+    let usb_config = DwHciCore::usb_config()
+        .ulpi_ext_vbus_drv()
+        .clear()
+        .term_sel_dl_pulse()
+        .clear();
+    DwHciCore::set_usb_config(usb_config);
+
+    reset_core()?;
+
+    // the internal UTMI+ PHY
+    let usb_config = DwHciCore::usb_config().ulpi_utmi_sel().clear().phyif().clear();
+    DwHciCore::set_usb_config(usb_config);
+
+    let (_, hw_cfg2, _, _) = DwHciCore::hw_config();
+    let usb_config = DwHciCore::usb_config();
+    let usb_config = if is_ulpi_fs_ls(hw_cfg2.fs_phy_type().value(), hw_cfg2.hs_phy_type().value())
+    {
+        usb_config.ulpi_clk_sus_m().set().ulpi_fsls().set()
+    } else {
+        usb_config.ulpi_clk_sus_m().clear().ulpi_fsls().clear()
+    };
+    DwHciCore::set_usb_config(usb_config);
+
+    // internal DMA only
+    let ahb_config = DwHciCore::ahb_config()
+        .enable_dma()
+        .set()
+        .wait_axi_writes()
+        .set()
+        .max_axi_burst()
+        .set_value(0);
+    DwHciCore::set_ahb_config(ahb_config);
+
+    // HNP and SRP are not used
+    let usb_config = DwHciCore::usb_config().srp_capable().clear().hnp_capable().clear();
+    DwHciCore::set_usb_config(usb_config);
+
+    // everything is polled
+    DwHciCore::set_interrupt_mask(DwHciCoreInterrupts::zero());
+    DwHciCore::set_interrupt_state(DwHciCoreInterrupts::all_set());
+    Ok(())
+}
+
+fn is_ulpi_fs_ls<E>(fs: Result<FsPhyType, E>, hs: Result<HsPhyType, E>) -> bool {
+    matches!((fs, hs), (Ok(FsPhyType::Dedicated), Ok(HsPhyType::Ulpi)))
+}
+
+fn init_host() -> Result<(), UsbError> {
+    DwHciHost::power_on();
+
+    let (_, hw_cfg2, _, _) = DwHciCore::hw_config();
+    let clock = if is_ulpi_fs_ls(hw_cfg2.fs_phy_type().value(), hw_cfg2.hs_phy_type().value())
+        && DwHciCore::usb_config().ulpi_fsls().is_set()
+    {
+        PhyClock::Clock48MHz
+    } else {
+        PhyClock::Clock30_60MHz
+    };
+    DwHciHost::set_config(DwHciHost::config().fs_ls_phy_clock_select().set_value(clock));
+
+    DwHciCore::set_fifo_sizes(RX_FIFO_SIZE, NON_PERIODIC_TX_FIFO_SIZE, PERIODIC_TX_FIFO_SIZE);
+    flush_fifos()?;
+
+    // halt all channels, they may still run from before a reset of the ARM
+    let channels = hw_cfg2.num_host_channels_actual() as usize;
+    for index in 0..channels {
+        let channel = DwHciHost::channel(index);
+        channel.set_characteristics(channel.characteristics().enable().clear().disable().set());
+    }
+    for index in 0..channels {
+        halt_channel(DwHciHost::channel(index))?;
+    }
+
+    let port = DwHciHost::port();
+    if port.power().is_clear() {
+        DwHciHost::set_port(port.power().set(), DwHciHostPort::zero());
+    }
+    Ok(())
+}
+
+fn flush_fifos() -> Result<(), UsbError> {
+    // 0x10 flushes all transmit FIFOs
+    DwHciCore::set_reset(
+        DwHciCoreReset::zero().tx_fifo_flush().set().tx_fifo_num().set_value(0x10),
+    );
+    poll(
+        || Some(()).filter(|_| DwHciCore::get_reset().tx_fifo_flush().is_clear()),
+        Duration::from_millis(10),
+    )?;
+    DwHciCore::set_reset(DwHciCoreReset::zero().rx_fifo_flush().set());
+    poll(
+        || Some(()).filter(|_| DwHciCore::get_reset().rx_fifo_flush().is_clear()),
+        Duration::from_millis(10),
+    )?;
+    Ok(())
+}
+
+fn halt_channel(channel: DwHciChannel) -> Result<(), UsbError> {
+    let characteristics = channel.characteristics();
+    if characteristics.enable().is_clear() {
+        return Ok(());
+    }
+    channel.set_characteristics(characteristics.enable().set().disable().set());
+    poll(
+        || Some(()).filter(|_| channel.characteristics().enable().is_clear()),
+        Duration::from_millis(10),
+    )?;
+    Ok(())
+}
+
+fn reset_root_port() -> Result<Speed, UsbError> {
+    DwHciHost::set_port(DwHciHost::port().reset().set(), DwHciHostPort::zero());
+    thread::spin_wait_for(Duration::from_millis(50));
+    DwHciHost::set_port(DwHciHost::port().reset().clear(), DwHciHostPort::zero());
+    // reset recovery
+    thread::spin_wait_for(Duration::from_millis(20));
+    let port = DwHciHost::port();
+    DwHciHost::set_port(port, DwHciHostPort::all_set());
+    if port.connect().is_clear() || port.enable().is_clear() {
+        return Err(UsbError::NotConnected);
+    }
+    Ok(match port.speed().value() {
+        Ok(PortSpeed::High) => Speed::High,
+        Ok(PortSpeed::Low) => Speed::Low,
+        _ => Speed::Full,
+    })
+}
+
+/// The address the controller's DMA uses for `address`, through the uncached alias of the SDRAM.
+fn bus_address(address: usize) -> u32 {
+    (BCM_HOST.sdram_address | address) as u32
+}
+
+/// Toggles between DATA0 and DATA1 once per packet of `len` bytes.
+fn next_pid(pid: Pid, len: usize, max_packet_size: usize) -> Pid {
+    let packets = len.div_ceil(max_packet_size).max(1);
+    match (pid, packets % 2) {
+        (Pid::Setup, _) => Pid::Data1,
+        (Pid::Data0, 1) => Pid::Data1,
+        (Pid::Data1, 1) => Pid::Data0,
+        (pid, _) => pid,
+    }
+}
+
+impl UsbHost {
+    pub fn devices(&self) -> impl Iterator<Item = &Device> {
+        self.devices.iter().flatten()
+    }
+
+    pub fn tree(&self) -> DeviceTree<'_> {
+        DeviceTree(&self.devices)
+    }
+
+    /// Runs a control transfer: the setup packet, the data stage into or from `data` if the
+    /// setup has a length, and the status stage. Returns the number of bytes transferred.
+    pub fn control_transfer(
+        &mut self,
+        endpoint: &Endpoint,
+        setup: SetupPacket,
+        data: &mut [u8],
+    ) -> Result<usize, UsbError> {
+        let len = setup.length as usize;
+        if len > DMA_BUFFER_LEN || len > data.len() {
+            return Err(UsbError::TooLarge);
+        }
+        self.buffer.0[..8].copy_from_slice(&setup.to_bytes());
+        self.transfer(endpoint, false, Pid::Setup, 8)?;
+        let mut transferred = 0;
+        if len > 0 {
+            if setup.is_device_to_host() {
+                transferred = self.transfer(endpoint, true, Pid::Data1, len)?.0;
+                data[..transferred].copy_from_slice(&self.buffer.0[..transferred]);
+            } else {
+                self.buffer.0[..len].copy_from_slice(&data[..len]);
+                transferred = self.transfer(endpoint, false, Pid::Data1, len)?.0;
+            }
+        }
+        // the status stage goes the other way
+        let status_in = len == 0 || !setup.is_device_to_host();
+        self.transfer(endpoint, status_in, Pid::Data1, 0)?;
+        Ok(transferred)
+    }
+
+    /// Reads up to `data.len()` bytes from a bulk or interrupt IN endpoint. `pid` is the data
+    /// toggle, DATA0 for the first transfer, and is advanced for the next one.
+    pub fn transfer_in(
+        &mut self,
+        endpoint: &Endpoint,
+        pid: &mut Pid,
+        data: &mut [u8],
+    ) -> Result<usize, UsbError> {
+        let len = data.len();
+        if len > DMA_BUFFER_LEN {
+            return Err(UsbError::TooLarge);
+        }
+        let (transferred, next) = self.transfer(endpoint, true, *pid, len)?;
+        data[..transferred].copy_from_slice(&self.buffer.0[..transferred]);
+        *pid = next;
+        Ok(transferred)
+    }
+
+    /// Transfers `len` bytes between the DMA buffer and the endpoint. Returns the number of bytes
+    /// transferred and the data toggle for the next transfer.
+    fn transfer(
+        &mut self,
+        endpoint: &Endpoint,
+        direction_in: bool,
+        mut pid: Pid,
+        len: usize,
+    ) -> Result<(usize, Pid), UsbError> {
+        let max_packet_size = (endpoint.max_packet_size as usize).max(8);
+        // split transactions carry one packet each
+        let chunk_len = if endpoint.transaction_translator.is_some() {
+            max_packet_size
+        } else {
+            DMA_BUFFER_LEN
+        };
+        let mut offset = 0;
+        loop {
+            let chunk = chunk_len.min(len - offset);
+            let transferred = self.transfer_chunk(endpoint, direction_in, pid, offset, chunk)?;
+            pid = next_pid(pid, transferred, max_packet_size);
+            offset += transferred;
+            // a short packet ends the transfer
+            if offset == len || transferred < chunk || chunk == 0 {
+                return Ok((offset, pid));
+            }
+        }
+    }
+
+    fn transfer_chunk(
+        &mut self,
+        endpoint: &Endpoint,
+        direction_in: bool,
+        pid: Pid,
+        offset: usize,
+        len: usize,
+    ) -> Result<usize, UsbError> {
+        let channel = DwHciHost::channel(CHANNEL);
+        let mut complete_split = false;
+        let mut naks = 0;
+        let mut errors = 0;
+        loop {
+            let interrupts =
+                self.run_channel(channel, endpoint, direction_in, pid, offset, len, complete_split)?;
+            if interrupts.stall().is_set() {
+                return Err(UsbError::Stall);
+            }
+            if interrupts.ahb_error().is_set() {
+                return Err(UsbError::Dma);
+            }
+            if interrupts.transaction_error().is_set()
+                || interrupts.babble_error().is_set()
+                || interrupts.data_toggle_error().is_set()
+                || interrupts.frame_overrun().is_set()
+            {
+                errors += 1;
+                if errors > MAX_ERRORS {
+                    return Err(UsbError::Transaction);
+                }
+                complete_split = false;
+                continue;
+            }
+            if interrupts.nak().is_set() {
+                // a NAK to the complete split means the hub gave up, start again
+                complete_split = false;
+                naks += 1;
+                if endpoint.transfer_type == TransferType::Interrupt || naks > MAX_NAKS {
+                    return Err(UsbError::Nak);
+                }
+                thread::spin_wait_for(Duration::from_micros(100));
+                continue;
+            }
+            if interrupts.nyet().is_set() && complete_split {
+                // the hub has no answer from the device yet
+                thread::spin_wait_for(Duration::from_micros(100));
+                continue;
+            }
+            if endpoint.transaction_translator.is_some() && !complete_split {
+                if interrupts.ack().is_set() {
+                    complete_split = true;
+                    continue;
+                }
+                return Err(UsbError::Transaction);
+            }
+            if interrupts.transfer_completed().is_set() {
+                return Ok(if direction_in {
+                    len - channel.transfer_size().size().value() as usize
+                } else {
+                    len
+                });
+            }
+            return Err(UsbError::Transaction);
+        }
+    }
+
+    /// Starts one transaction on `channel` and waits until it halts, returns why it halted.
+    #[allow(clippy::too_many_arguments)]
+    fn run_channel(
+        &mut self,
+        channel: DwHciChannel,
+        endpoint: &Endpoint,
+        direction_in: bool,
+        pid: Pid,
+        offset: usize,
+        len: usize,
+        complete_split: bool,
+    ) -> Result<DwHciChannelInterrupts, UsbError> {
+        let max_packet_size = (endpoint.max_packet_size as usize).max(8);
+        channel.clear_interrupts(DwHciChannelInterrupts::all_set());
+        channel.set_interrupt_mask(DwHciChannelInterrupts::zero());
+
+        let split = match endpoint.transaction_translator {
+            Some((hub_address, port)) => DwHciChannelSplit::zero()
+                .split_enable()
+                .set()
+                .hub_address()
+                .set_value(hub_address as u32)
+                .port_address()
+                .set_value(port as u32)
+                .complete_split()
+                .set_value(complete_split),
+            None => DwHciChannelSplit::zero(),
+        };
+        channel.set_split(split);
+
+        channel.set_transfer_size(
+            DwHciChannelTransferSize::zero()
+                .size()
+                .set_value(len as u32)
+                .packet_count()
+                .set_value(len.div_ceil(max_packet_size).max(1) as u32)
+                .pid()
+                .set_value(pid),
+        );
+
+        let buffer = &self.buffer.0[offset..];
+        arm_core::clean_and_invalidate_data_cache(buffer.as_ptr() as usize, len);
+        channel.set_dma_address(bus_address(buffer.as_ptr() as usize));
+
+        let endpoint_type = match endpoint.transfer_type {
+            TransferType::Control => EndpointType::Control,
+            TransferType::Isochronous => EndpointType::Isochronous,
+            TransferType::Bulk => EndpointType::Bulk,
+            TransferType::Interrupt => EndpointType::Interrupt,
+        };
+        let mut characteristics = DwHciChannelChar::zero()
+            .max_packet_size()
+            .set_value(max_packet_size as u32)
+            .endpoint_number()
+            .set_value(endpoint.number as u32)
+            .endpoint_direction_in()
+            .set_value(direction_in)
+            .low_speed()
+            .set_value(endpoint.speed == Speed::Low)
+            .endpoint_type()
+            .set_value(endpoint_type)
+            .multi_count()
+            .set_value(1)
+            .device_address()
+            .set_value(endpoint.device_address as u32);
+        if endpoint.transfer_type == TransferType::Interrupt {
+            // periodic transactions go out in the next frame
+            characteristics = characteristics
+                .odd_frame()
+                .set_value(DwHciHost::frame_number() & 1 == 0);
+        }
+        channel.set_characteristics(characteristics.enable().set());
+
+        let halted = poll(
+            || Some(channel.interrupts()).filter(|i| i.halted().is_set()),
+            TRANSFER_TIMEOUT,
+        );
+        arm_core::clean_and_invalidate_data_cache(buffer.as_ptr() as usize, len);
+        match halted {
+            Ok(interrupts) => Ok(interrupts),
+            Err(Timeout) => {
+                let _ = halt_channel(channel);
+                Err(UsbError::Timeout)
+            }
+        }
+    }
+
+    fn enumerate_root_port(&mut self) -> Result<(), UsbError> {
+        // give the device time to connect after the port was powered
+        thread::spin_wait_for(Duration::from_millis(100));
+        if DwHciHost::port().connect().is_clear() {
+            return Ok(());
+        }
+        let speed = reset_root_port()?;
+        self.enumerate(speed, 0, 0, 0, None)
+    }
+
+    /// Assigns an address to the device that was just reset, reads its descriptors and sets its
+    /// first configuration. Hubs get their ports enumerated.
+    fn enumerate(
+        &mut self,
+        speed: Speed,
+        hub_address: u8,
+        port: u8,
+        depth: u8,
+        transaction_translator: Option<(u8, u8)>,
+    ) -> Result<(), UsbError> {
+        let slot = self
+            .devices
+            .iter()
+            .position(Option::is_none)
+            .ok_or(UsbError::TooManyDevices)?;
+        let address = self.next_address;
+        if address > 127 {
+            return Err(UsbError::TooManyDevices);
+        }
+
+        // the default address, the device tells its packet size in the first 8 bytes
+        let mut endpoint = Endpoint {
+            device_address: 0,
+            number: 0,
+            speed,
+            max_packet_size: if speed == Speed::Low { 8 } else { 64 },
+            transfer_type: TransferType::Control,
+            transaction_translator,
+        };
+        let mut bytes = [0_u8; DeviceDescriptor::LEN];
+        let setup = SetupPacket::get_descriptor(descriptor_type::DEVICE, 0, 8);
+        self.control_transfer(&endpoint, setup, &mut bytes[..8])?;
+        endpoint.max_packet_size = bytes[7] as u16;
+
+        self.control_transfer(&endpoint, SetupPacket::set_address(address), &mut [])?;
+        self.next_address += 1;
+        // SET_ADDRESS recovery
+        thread::spin_wait_for(Duration::from_millis(10));
+        endpoint.device_address = address;
+
+        let setup = SetupPacket::get_descriptor(descriptor_type::DEVICE, 0, bytes.len() as u16);
+        let len = self.control_transfer(&endpoint, setup, &mut bytes)?;
+        let descriptor = DeviceDescriptor::parse(&bytes[..len])?;
+
+        let mut bytes = [0_u8; DMA_BUFFER_LEN];
+        let setup = SetupPacket::get_descriptor(
+            descriptor_type::CONFIGURATION,
+            0,
+            ConfigurationDescriptor::LEN as u16,
+        );
+        let len = self.control_transfer(&endpoint, setup, &mut bytes)?;
+        let configuration = ConfigurationDescriptor::parse(&bytes[..len])?;
+        let total_length = (configuration.total_length as usize).min(DMA_BUFFER_LEN);
+        let setup =
+            SetupPacket::get_descriptor(descriptor_type::CONFIGURATION, 0, total_length as u16);
+        let len = self.control_transfer(&endpoint, setup, &mut bytes)?;
+
+        let mut interfaces = [None; MAX_INTERFACES];
+        let mut current = None;
+        for descriptor in Descriptors::new(&bytes[..len]) {
+            match descriptor {
+                Descriptor::Interface(interface) if interface.alternate_setting == 0 => {
+                    current = interfaces.iter().position(Option::is_none);
+                    if let Some(index) = current {
+                        interfaces[index] = Some(Interface {
+                            descriptor: interface,
+                            interrupt_in: None,
+                        });
+                    }
+                }
+                Descriptor::Interface(_) => current = None,
+                Descriptor::Endpoint(endpoint)
+                    if endpoint.is_in() && endpoint.transfer_type() == TransferType::Interrupt =>
+                {
+                    if let Some(Some(interface)) = current.map(|index| &mut interfaces[index]) {
+                        interface.interrupt_in.get_or_insert(endpoint);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let setup = SetupPacket::set_configuration(configuration.configuration_value);
+        self.control_transfer(&endpoint, setup, &mut [])?;
+
+        let device = Device {
+            address,
+            speed,
+            hub_address,
+            port,
+            depth,
+            descriptor,
+            configuration: configuration.configuration_value,
+            interfaces,
+            hub_ports: 0,
+            transaction_translator,
+        };
+        self.devices[slot] = Some(device);
+        if device.is_hub() && depth < MAX_HUB_DEPTH {
+            self.enumerate_hub(slot)?;
+        }
+        Ok(())
+    }
+
+    fn port_status(&mut self, hub: &Endpoint, port: u8) -> Result<PortStatus, UsbError> {
+        let mut bytes = [0_u8; 4];
+        self.control_transfer(hub, SetupPacket::get_port_status(port), &mut bytes)?;
+        Ok(PortStatus::from_bytes(bytes))
+    }
+
+    /// Powers the ports of the hub in `slot`, then resets and enumerates what is connected.
+    fn enumerate_hub(&mut self, slot: usize) -> Result<(), UsbError> {
+        let Some(hub) = self.devices[slot] else {
+            return Ok(());
+        };
+        let endpoint = hub.control_endpoint();
+        let mut bytes = [0_u8; HubDescriptor::LEN];
+        let setup = SetupPacket::get_hub_descriptor(bytes.len() as u16);
+        let len = self.control_transfer(&endpoint, setup, &mut bytes)?;
+        let descriptor = HubDescriptor::parse(&bytes[..len])?;
+        if let Some(device) = self.devices[slot].as_mut() {
+            device.hub_ports = descriptor.num_ports;
+        }
+
+        for port in 1..=descriptor.num_ports {
+            let setup = SetupPacket::set_port_feature(port, port_feature::POWER);
+            self.control_transfer(&endpoint, setup, &mut [])?;
+        }
+        let power_on_to_good = 2 * descriptor.power_on_to_good as u64;
+        thread::spin_wait_for(Duration::from_millis(power_on_to_good.max(100)));
+
+        for port in 1..=descriptor.num_ports {
+            // a device that fails to enumerate is left out of the tree
+            let _ = self.enumerate_hub_port(&hub, port);
+        }
+        Ok(())
+    }
+
+    fn enumerate_hub_port(&mut self, hub: &Device, port: u8) -> Result<(), UsbError> {
+        let endpoint = hub.control_endpoint();
+        let status = self.port_status(&endpoint, port)?;
+        if status.connection().is_clear() {
+            return Ok(());
+        }
+        let setup = SetupPacket::set_port_feature(port, port_feature::RESET);
+        self.control_transfer(&endpoint, setup, &mut [])?;
+        let mut status = status;
+        let deadline = PointInTime::now() + Duration::from_millis(500);
+        while status.reset_changed().is_clear() {
+            if !deadline.is_in_the_future() {
+                return Err(UsbError::Timeout);
+            }
+            thread::spin_wait_for(Duration::from_millis(10));
+            status = self.port_status(&endpoint, port)?;
+        }
+        for feature in [port_feature::C_RESET, port_feature::C_CONNECTION] {
+            let setup = SetupPacket::clear_port_feature(port, feature);
+            self.control_transfer(&endpoint, setup, &mut [])?;
+        }
+        // reset recovery
+        thread::spin_wait_for(Duration::from_millis(10));
+        if status.enable().is_clear() {
+            return Err(UsbError::NotConnected);
+        }
+        let speed = status.speed();
+        let transaction_translator = if hub.speed == Speed::High && speed != Speed::High {
+            Some((hub.address, port))
+        } else {
+            hub.transaction_translator
+        };
+        self.enumerate(speed, hub.address, port, hub.depth + 1, transaction_translator)
+    }
+}
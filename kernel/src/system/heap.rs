@@ -0,0 +1,56 @@
+//! The kernel heap behind the `alloc` crate, a [LockedHeap] over the memory
+//! [MemoryMap::heap] leaves free.
+
+use core::alloc::{GlobalAlloc, Layout};
+
+use mystd::heap::{HeapStats, LockedHeap};
+
+use super::hal::info::{MemoryBlock, MemoryMap};
+use crate::peripherals::interrupts::without_irqs;
+
+#[global_allocator]
+static HEAP: KernelHeap = KernelHeap(LockedHeap::new());
+
+/// Masks IRQs while the heap is locked, so an interrupt handler that allocates can't wait for a
+/// lock its own core holds.
+struct KernelHeap(LockedHeap);
+
+unsafe impl GlobalAlloc for KernelHeap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        without_irqs(|| self.0.alloc(layout))
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        without_irqs(|| self.0.dealloc(ptr, layout))
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        without_irqs(|| self.0.realloc(ptr, layout, new_size))
+    }
+}
+
+#[derive(Debug)]
+pub enum HeapError {
+    /// The firmware didn't report the ARM memory.
+    NoMemoryInfo,
+}
+
+/// Hands the free memory to the heap. Needs the MMU on, the lock uses exclusive accesses that
+/// only work on cacheable memory.
+pub fn init() -> Result<MemoryBlock, HeapError> {
+    let block = MemoryMap::heap().ok_or(HeapError::NoMemoryInfo)?;
+    // SAFETY: nothing else uses the memory, and it is only added once
+    without_irqs(|| unsafe {
+        HEAP.0
+            .add_region(block.bottom() as usize, block.top() as usize)
+    });
+    Ok(block)
+}
+
+pub fn stats() -> HeapStats {
+    without_irqs(|| HEAP.0.stats())
+}
+
+pub fn largest_free_block() -> usize {
+    without_irqs(|| HEAP.0.largest_free_block())
+}
@@ -57,6 +57,11 @@ impl Stdout {
             inner: unsafe { self.inner.lock() },
         }
     }
+
+    /// The lock, or `None` while someone else writes.
+    pub fn try_lock(&self) -> Option<StdoutLock<'static>> {
+        self.inner.try_lock().map(|inner| StdoutLock { inner })
+    }
 }
 
 static OUT_WRITER: Mutex<RefCell<CombinedWriter>> = Mutex::new(RefCell::new(SplitWriter::empty()));
@@ -70,6 +75,10 @@ pub fn init_serial_uart() {
     use crate::print_init;
     print_init!("Before init");
     uart.init();
+    if cfg!(feature = "uart_flow_control") {
+        uart.enable_flow_control().expect("the RTS/CTS pins are free");
+    }
+    uart::enable_interrupts().expect("the UART interrupt is free");
     print_init!("Before lock");
     let locked_out = unsafe { OUT_WRITER.lock() };
     print_init!("after lock");
@@ -3,6 +3,9 @@
 use super::hal::info::MemoryBlock;
 
 pub mod dma;
+pub mod emmc;
+#[cfg(feature = "bcm2711")]
+pub mod gic;
 pub mod gpio;
 pub mod mailbox;
 pub mod mmio;
@@ -17,6 +20,9 @@ pub struct BcmHost {
     pub peripheral_size: usize,
     pub peripheral_range_inclusive: (usize, usize),
     pub sdram_address: usize,
+    /// The ARM local peripherals: per-core interrupt routing, timers and mailboxes, and the
+    /// GIC-400 on the BCM2711.
+    pub local_peripheral_address: usize,
 }
 
 #[cfg(feature = "bcm2711")]
@@ -25,14 +31,17 @@ pub const BCM_HOST: BcmHost = BcmHost {
     peripheral_size: 0x0180_0000,
     peripheral_range_inclusive: (0xFE00_0000, 0xFFFF_FFFF),
     sdram_address: 0xC000_0000,
+    local_peripheral_address: 0xFF80_0000,
 };
 
 #[cfg(any(feature = "bcm2837"))]
 pub const BCM_HOST: BcmHost = BcmHost {
     peripheral_address: 0x3F00_0000,
     peripheral_size: 0x0100_0000,
-    peripheral_range_inclusive: (0x3F00_0000, 0x3FFF_FFFF),
+    // includes the ARM local peripherals, so they are mapped as device memory too
+    peripheral_range_inclusive: (0x3F00_0000, 0x4000_00FF),
     sdram_address: 0xC000_0000,
+    local_peripheral_address: 0x4000_0000,
 };
 
 pub struct PeripheralMap();
@@ -113,7 +122,10 @@ impl core::fmt::Debug for PeripheralMap {
             )
             .field(
                 "EMMC",
-                &MemoryBlock::from_address_and_size(BCM_HOST.peripheral_address + 0x300000, 0x100),
+                &MemoryBlock::from_address_and_size(
+                    BCM_HOST.peripheral_address + emmc::EMMC_BASE,
+                    0x100,
+                ),
             )
             .field(
                 "BSC1",
@@ -5,6 +5,8 @@ use core::
 use mystd::{bit_field, slice::slice2d::{traits::{MutSlice2dTrait, Slice2dTrait}, MutSlice2d, Slice2d}};
 
 
+use crate::system::arm_core;
+
 use super::mmio::PeripheralRegister;
 
 pub const DMA_BASE: usize = 0x7000;
@@ -88,6 +90,11 @@ impl DmaStandardChannel {
         }
         self.control_and_status().update(|cs| cs.end().set());
     }
+
+    /// Stops the channel in the middle of a transfer.
+    pub fn reset(&self) {
+        self.control_and_status().write(DmaControlAndStatus::zero().reset().set());
+    }
 }
 
 bit_field!(pub DmaControlAndStatus(u32) {
@@ -356,6 +363,48 @@ impl DmaTransferInformation {
             .burst_transfer_length()
             .set_value(8)
     }
+
+    /// From the data register of `peripheral` to memory, a word whenever the peripheral has one.
+    pub fn from_peripheral(peripheral: DmaPeripheral) -> Self {
+        Self::zero()
+            .peripheral_mapping()
+            .set_value(peripheral as u32)
+            .src_use_data_request()
+            .set()
+            .src_transfer_width()
+            .set_value(DmaTransferWidth::Bit32)
+            .dest_address_increment()
+            .set()
+            .dest_transfer_width()
+            .set_value(DmaTransferWidth::Bit32)
+            .wait_for_write_response()
+            .set()
+    }
+
+    /// From memory to the data register of `peripheral`, a word whenever the peripheral takes one.
+    pub fn to_peripheral(peripheral: DmaPeripheral) -> Self {
+        Self::zero()
+            .peripheral_mapping()
+            .set_value(peripheral as u32)
+            .src_address_increment()
+            .set()
+            .src_transfer_width()
+            .set_value(DmaTransferWidth::Bit32)
+            .dest_use_data_request()
+            .set()
+            .dest_transfer_width()
+            .set_value(DmaTransferWidth::Bit32)
+            .wait_for_write_response()
+            .set()
+    }
+}
+
+/// The peripherals that pace transfers with their DREQ signal, by their `peripheral_mapping`.
+#[derive(Clone, Copy, Debug)]
+#[repr(u32)]
+pub enum DmaPeripheral {
+    /// The Arasan EMMC controller. EMMC2 of the BCM2711 has no DREQ.
+    Emmc = 11,
 }
 
 #[repr(C)]
@@ -439,11 +488,20 @@ bit_field!(pub DmaDebug(u32) {
 });
 
 
+/// Copies `src` to `dst` with the DMA engine, which doesn't see the data cache: the source and
+/// the control block are cleaned first, and the destination invalidated before and after, so
+/// neither stale nor dirty lines get in the way.
 pub fn dma_copy_slice<T>(src: &[T], dst: &mut [T]) {
     let control_block = DmaControlBlock::copy_slice(src, dst);
-    
+    let (src_len, dst_len) = (core::mem::size_of_val(src), core::mem::size_of_val(dst));
+    arm_core::clean_and_invalidate_data_cache(src.as_ptr() as usize, src_len);
+    arm_core::clean_and_invalidate_data_cache(dst.as_ptr() as usize, dst_len);
+    let control_block_address = core::ptr::addr_of!(control_block) as usize;
+    arm_core::clean_and_invalidate_data_cache(control_block_address, core::mem::size_of::<DmaControlBlock>());
+
     DMA_0.start_transfer(&control_block);
     DMA_0.wait_for_end();
+    arm_core::clean_and_invalidate_data_cache(dst.as_ptr() as usize, dst_len);
 }
 
 pub fn dma_copy_slice2d<T>(src: &Slice2d<T>, dst: &mut MutSlice2d<T>) {   
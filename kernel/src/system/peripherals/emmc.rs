@@ -0,0 +1,206 @@
+//! The Arasan SDHCI controller (EMMC) of the BCM2837, and the EMMC2 controller of the BCM2711
+//! that the SD card slot of the Pi 4 is connected to.
+
+#[cfg(feature = "bcm2837")]
+pub const EMMC_BASE: usize = 0x300000;
+#[cfg(feature = "bcm2711")]
+pub const EMMC_BASE: usize = 0x340000;
+
+/// The data port, as the DMA engine sees it.
+pub const EMMC_DATA_BUS_ADDRESS: u32 = 0x7E00_0000 + EMMC_BASE as u32 + 0x20;
+
+use super::mmio::TypedMMIO;
+use mystd::bit_field;
+
+type EmmcBlockSizeCountReg = TypedMMIO<EmmcBlockSizeCount, EMMC_BASE, 0x04>;
+type EmmcArgument1Reg = TypedMMIO<u32, EMMC_BASE, 0x08>;
+type EmmcCommandReg = TypedMMIO<EmmcCommand, EMMC_BASE, 0x0c>;
+type EmmcResponse0Reg = TypedMMIO<u32, EMMC_BASE, 0x10>;
+type EmmcResponse1Reg = TypedMMIO<u32, EMMC_BASE, 0x14>;
+type EmmcResponse2Reg = TypedMMIO<u32, EMMC_BASE, 0x18>;
+type EmmcResponse3Reg = TypedMMIO<u32, EMMC_BASE, 0x1c>;
+type EmmcDataReg = TypedMMIO<u32, EMMC_BASE, 0x20>;
+type EmmcStatusReg = TypedMMIO<EmmcStatus, EMMC_BASE, 0x24>;
+type EmmcControl0Reg = TypedMMIO<EmmcControl0, EMMC_BASE, 0x28>;
+type EmmcControl1Reg = TypedMMIO<EmmcControl1, EMMC_BASE, 0x2c>;
+type EmmcInterruptReg = TypedMMIO<EmmcInterrupts, EMMC_BASE, 0x30>;
+type EmmcInterruptMaskReg = TypedMMIO<EmmcInterrupts, EMMC_BASE, 0x34>;
+type EmmcInterruptEnableReg = TypedMMIO<EmmcInterrupts, EMMC_BASE, 0x38>;
+type EmmcSlotInterruptVersionReg = TypedMMIO<EmmcSlotInterruptVersion, EMMC_BASE, 0xfc>;
+
+#[derive(Clone, Copy)]
+pub struct Emmc {}
+
+impl Emmc {
+    /// Issues `command` with `argument`, the controller must not be inhibited for it.
+    pub fn send_command(command: EmmcCommand, argument: u32) {
+        EmmcArgument1Reg::write(argument);
+        EmmcCommandReg::write(command);
+    }
+
+    pub fn set_block_size_count(size_count: EmmcBlockSizeCount) {
+        EmmcBlockSizeCountReg::write(size_count)
+    }
+
+    /// The response of the last command: bits 8..40 of a 48 bit response in the first word, the
+    /// 120 bits of a 136 bit response without the CRC in all four.
+    pub fn response() -> [u32; 4] {
+        [
+            EmmcResponse0Reg::read(),
+            EmmcResponse1Reg::read(),
+            EmmcResponse2Reg::read(),
+            EmmcResponse3Reg::read(),
+        ]
+    }
+
+    pub fn read_data() -> u32 {
+        EmmcDataReg::read()
+    }
+
+    pub fn write_data(data: u32) {
+        EmmcDataReg::write(data)
+    }
+
+    pub fn status() -> EmmcStatus {
+        EmmcStatusReg::read()
+    }
+
+    pub fn control0() -> EmmcControl0 {
+        EmmcControl0Reg::read()
+    }
+
+    pub fn set_control0(control: EmmcControl0) {
+        EmmcControl0Reg::write(control)
+    }
+
+    pub fn control1() -> EmmcControl1 {
+        EmmcControl1Reg::read()
+    }
+
+    pub fn set_control1(control: EmmcControl1) {
+        EmmcControl1Reg::write(control)
+    }
+
+    pub fn interrupts() -> EmmcInterrupts {
+        EmmcInterruptReg::read()
+    }
+
+    /// Clears the set interrupt flags.
+    pub fn clear_interrupts(interrupts: EmmcInterrupts) {
+        EmmcInterruptReg::write(interrupts)
+    }
+
+    /// Which interrupt flags are set in [Self::interrupts].
+    pub fn set_interrupt_mask(mask: EmmcInterrupts) {
+        EmmcInterruptMaskReg::write(mask)
+    }
+
+    /// Which interrupt flags raise the EMMC interrupt.
+    pub fn set_interrupt_enable(enable: EmmcInterrupts) {
+        EmmcInterruptEnableReg::write(enable)
+    }
+
+    pub fn version() -> EmmcSlotInterruptVersion {
+        EmmcSlotInterruptVersionReg::read()
+    }
+}
+
+bit_field!(pub EmmcBlockSizeCount(u32){
+    16:31 => block_count,
+    0:9 => block_size
+});
+
+bit_field!(pub EmmcCommand(u32){
+    24:29 => index,
+    22:23 => command_type: enum CommandType {
+        Normal = 0b00,
+        Suspend = 0b01,
+        Resume = 0b10,
+        Abort = 0b11
+    },
+    21 => is_data,
+    20 => check_index,
+    19 => check_crc,
+    16:17 => response_type: enum ResponseType {
+        None = 0b00,
+        Bits136 = 0b01,
+        Bits48 = 0b10,
+        Bits48Busy = 0b11
+    },
+    5 => multi_block,
+    /// Set for transfers from the card to the host.
+    4 => data_direction_read,
+    2:3 => auto_command: enum AutoCommand {
+        None = 0b00,
+        Cmd12 = 0b01
+    },
+    1 => block_count_enable
+});
+
+bit_field!(pub EmmcStatus(u32){
+    /// The CMD line level.
+    24 => command_level,
+    /// The levels of DAT0 to DAT3, DAT0 low means busy.
+    20:23 => data_level,
+    9 => read_transfer,
+    8 => write_transfer,
+    2 => data_active,
+    1 => data_inhibit,
+    0 => command_inhibit
+});
+
+bit_field!(pub EmmcControl0(u32){
+    5 => bus_width_8_bit,
+    2 => high_speed,
+    /// 4 bit data bus, 1 bit if clear.
+    1 => bus_width_4_bit
+});
+
+bit_field!(pub EmmcControl1(u32){
+    26 => reset_data,
+    25 => reset_command,
+    24 => reset_host,
+    /// The data timeout is the card clock times 2 to the power of 13 plus this.
+    16:19 => data_timeout,
+    /// The lower 8 bits of the 10 bit clock divider.
+    8:15 => clock_divider_low,
+    6:7 => clock_divider_high,
+    5 => clock_programmable,
+    2 => clock_enable,
+    1 => clock_stable,
+    0 => clock_internal_enable
+});
+
+bit_field!(pub EmmcInterrupts(u32){
+    24 => auto_command_error,
+    22 => data_end_bit_error,
+    21 => data_crc_error,
+    20 => data_timeout_error,
+    19 => command_index_error,
+    18 => command_end_bit_error,
+    17 => command_crc_error,
+    16 => command_timeout_error,
+    15 => error,
+    8 => card_interrupt,
+    5 => read_ready,
+    4 => write_ready,
+    1 => data_done,
+    0 => command_done
+});
+
+impl EmmcInterrupts {
+    /// Every error flag.
+    pub fn errors() -> Self {
+        Self::new(0x017f_8000)
+    }
+
+    pub fn has_error(self) -> bool {
+        self.to_underlying() & Self::errors().to_underlying() != 0
+    }
+}
+
+bit_field!(pub EmmcSlotInterruptVersion(u32){
+    /// 0 for SDHCI 1.0, 1 for 2.0, 2 for 3.0.
+    24:31 => host_version,
+    16:23 => vendor_version
+});
@@ -0,0 +1,135 @@
+//! The GIC-400 interrupt controller of the BCM2711, a GICv2 with a distributor that is shared by
+//! all cores and a CPU interface per core, banked at the same address.
+
+use mystd::bit_field;
+
+use super::mmio::Register;
+use super::BCM_HOST;
+
+pub const GIC_BASE: usize = BCM_HOST.local_peripheral_address + 0x4_0000;
+pub const GICD_BASE: usize = GIC_BASE + 0x1000;
+pub const GICC_BASE: usize = GIC_BASE + 0x2000;
+
+/// Interrupt ids from this one on are special, 1023 means there was nothing pending.
+pub const SPURIOUS_INTERRUPT: u32 = 1020;
+
+type DistributorRegister<const OFFSET: usize, T> = Register<GICD_BASE, OFFSET, T>;
+type CpuInterfaceRegister<const OFFSET: usize, T> = Register<GICC_BASE, OFFSET, T>;
+
+pub struct Distributor {}
+
+impl Distributor {
+    pub fn control() -> DistributorControl {
+        DistributorRegister::<0x000, _>::no_offset().read()
+    }
+
+    pub fn set_control(value: DistributorControl) {
+        DistributorRegister::<0x000, _>::no_offset().write(value)
+    }
+
+    pub fn controller_type() -> DistributorType {
+        DistributorRegister::<0x004, _>::no_offset().read()
+    }
+
+    /// The number of interrupt ids the distributor handles, a multiple of 32.
+    pub fn interrupt_count() -> usize {
+        (Self::controller_type().it_lines_number().value() as usize + 1) * 32
+    }
+
+    pub fn enable(id: u32) {
+        DistributorRegister::<0x100, u32>::at(Self::word(id)).write(Self::bit(id))
+    }
+
+    pub fn disable(id: u32) {
+        DistributorRegister::<0x180, u32>::at(Self::word(id)).write(Self::bit(id))
+    }
+
+    pub fn is_enabled(id: u32) -> bool {
+        DistributorRegister::<0x100, u32>::at(Self::word(id)).read() & Self::bit(id) != 0
+    }
+
+    pub fn clear_pending(id: u32) {
+        DistributorRegister::<0x280, u32>::at(Self::word(id)).write(Self::bit(id))
+    }
+
+    /// Lower values mean higher priority.
+    pub fn set_priority(id: u32, priority: u8) {
+        DistributorRegister::<0x400, u8>::at(id as usize).write(priority)
+    }
+
+    /// Sets the cores (bit n for core n) a shared peripheral interrupt is sent to. The targets of
+    /// the private interrupts (ids below 32) are read-only.
+    pub fn set_targets(id: u32, cores: u8) {
+        DistributorRegister::<0x800, u8>::at(id as usize).write(cores)
+    }
+
+    pub fn set_trigger(id: u32, trigger: Trigger) {
+        let shift = (id % 16) * 2;
+        let edge = matches!(trigger, Trigger::Edge) as u32;
+        DistributorRegister::<0xc00, u32>::at((id as usize / 16) * 4)
+            .update(|cfg| (cfg & !(0b10 << shift)) | (edge << (shift + 1)))
+    }
+
+    const fn word(id: u32) -> usize {
+        (id as usize / 32) * 4
+    }
+
+    const fn bit(id: u32) -> u32 {
+        1 << (id % 32)
+    }
+}
+
+pub enum Trigger {
+    Level,
+    Edge,
+}
+
+pub struct CpuInterface {}
+
+impl CpuInterface {
+    pub fn control() -> CpuInterfaceControl {
+        CpuInterfaceRegister::<0x000, _>::no_offset().read()
+    }
+
+    pub fn set_control(value: CpuInterfaceControl) {
+        CpuInterfaceRegister::<0x000, _>::no_offset().write(value)
+    }
+
+    /// Only interrupts with a higher priority (lower value) than `mask` are signalled to the core.
+    pub fn set_priority_mask(mask: u8) {
+        CpuInterfaceRegister::<0x004, u32>::no_offset().write(mask as u32)
+    }
+
+    /// Takes the highest priority pending interrupt, it stays active until [Self::end_of_interrupt].
+    pub fn acknowledge() -> InterruptAcknowledge {
+        CpuInterfaceRegister::<0x00c, _>::no_offset().read()
+    }
+
+    pub fn end_of_interrupt(value: InterruptAcknowledge) {
+        CpuInterfaceRegister::<0x010, _>::no_offset().write(value)
+    }
+}
+
+bit_field!(pub DistributorControl(u32) {
+    0 => enable,
+});
+
+bit_field!(pub DistributorType(u32) {
+    15:11 => lspi,
+    10 => security_extensions,
+    7:5 => cpu_number,
+    4:0 => it_lines_number,
+});
+
+bit_field!(pub CpuInterfaceControl(u32) {
+    9 => eoi_mode_ns,
+    6 => irq_bypass_disable,
+    5 => fiq_bypass_disable,
+    0 => enable,
+});
+
+bit_field!(pub InterruptAcknowledge(u32) {
+    /// The core that sent a software generated interrupt.
+    12:10 => cpu_id,
+    9:0 => interrupt_id,
+});
@@ -1,13 +1,47 @@
-use crate::{peripherals::mmio::Mmio, system::hal::thread};
+use core::sync::atomic::{AtomicPtr, AtomicU8, Ordering};
+
+use mystd::sync::mutex::Mutex;
+
+use crate::peripherals::interrupts::with_locked;
+use crate::peripherals::mmio::{Mmio, PeripheralRegister};
+use crate::system::arm_core::mmu;
+use crate::system::hal::interrupts::{self, InterruptError, Irq};
+use crate::system::hal::thread;
 
 pub struct Gpio();
 
 pub const GPIO_BASE: usize = 0x200000;
 
-#[derive(Clone, Copy)]
+pub const PIN_COUNT: usize = 54;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
 pub struct PinSet(u32, u32);
 
 impl PinSet {
+    pub const fn empty() -> Self {
+        Self(0, 0)
+    }
+
+    pub const fn single(pin: u8) -> Self {
+        Self::select(&[pin])
+    }
+
+    pub const fn contains(self, pin: u8) -> bool {
+        match pin {
+            0..=31 => self.0 & (1 << pin) != 0,
+            32..=53 => self.1 & (1 << (pin - 32)) != 0,
+            _ => false,
+        }
+    }
+
+    pub const fn is_empty(self) -> bool {
+        self.0 == 0 && self.1 == 0
+    }
+
+    pub const fn intersection(self, other: Self) -> Self {
+        Self(self.0 & other.0, self.1 & other.1)
+    }
+
     pub const fn select(pins: &[u8]) -> Self {
         let mut i = 0;
         let mut result = Self(0, 0);
@@ -253,3 +287,407 @@ impl Gpio {
         }
     }
 }
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Level {
+    Low,
+    High,
+}
+
+/// What sets the event status bit of a pin. The synchronous edges are sampled with the system
+/// clock and need a level to stay for two samples, the asynchronous ones catch short pulses too.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Event {
+    RisingEdge,
+    FallingEdge,
+    /// As long as the pin is high.
+    High,
+    /// As long as the pin is low.
+    Low,
+    AsyncRisingEdge,
+    AsyncFallingEdge,
+}
+
+impl Event {
+    pub const ALL: [Event; 6] = [
+        Event::RisingEdge,
+        Event::FallingEdge,
+        Event::High,
+        Event::Low,
+        Event::AsyncRisingEdge,
+        Event::AsyncFallingEdge,
+    ];
+
+    /// GPREN0, GPFEN0, GPHEN0, GPLEN0, GPAREN0 and GPAFEN0, each followed by the register of
+    /// the second bank.
+    const fn enable_register(self) -> usize {
+        match self {
+            Event::RisingEdge => 0x4c,
+            Event::FallingEdge => 0x58,
+            Event::High => 0x64,
+            Event::Low => 0x70,
+            Event::AsyncRisingEdge => 0x7c,
+            Event::AsyncFallingEdge => 0x88,
+        }
+    }
+
+    /// The bit of the event in the masks of [PARKED].
+    const fn bit(self) -> u8 {
+        1 << self as u8
+    }
+}
+
+type GpioRegister = PeripheralRegister<GPIO_BASE, u32>;
+
+impl Gpio {
+    // Output Set, Output Clear, Pin Level and Event Detect Status Registers, two banks each
+    const GPSET0: usize = 0x1c;
+    const GPCLR0: usize = 0x28;
+    const GPLEV0: usize = 0x34;
+    const GPEDS0: usize = 0x40;
+
+    fn bank_register(first: usize, bank: usize) -> GpioRegister {
+        GpioRegister::at(first + 4 * bank)
+    }
+
+    fn read_banks(first: usize) -> PinSet {
+        PinSet(
+            Self::bank_register(first, 0).read(),
+            Self::bank_register(first, 1).read() & 0x3f_ffff,
+        )
+    }
+
+    /// Writes the bits of `pins` to registers that only act on the set ones.
+    fn write_banks(first: usize, pins: PinSet) {
+        for (bank, bits) in [pins.0, pins.1].into_iter().enumerate() {
+            if bits != 0 {
+                Self::bank_register(first, bank).write(bits);
+            }
+        }
+    }
+
+    /// Drives the output pins in `pins` high.
+    pub fn set_high(pins: PinSet) {
+        Self::write_banks(Self::GPSET0, pins)
+    }
+
+    /// Drives the output pins in `pins` low.
+    pub fn set_low(pins: PinSet) {
+        Self::write_banks(Self::GPCLR0, pins)
+    }
+
+    /// The pins that are high, inputs and outputs alike.
+    pub fn levels() -> PinSet {
+        Self::read_banks(Self::GPLEV0)
+    }
+
+    /// The pins that had an event they are enabled for since it was cleared.
+    pub fn events() -> PinSet {
+        Self::read_banks(Self::GPEDS0)
+    }
+
+    pub fn clear_events(pins: PinSet) {
+        Self::write_banks(Self::GPEDS0, pins)
+    }
+
+    /// The events that set the event status of `pin`.
+    fn event_detection(pin: u8) -> u8 {
+        let (bank, bit) = (pin as usize / 32, pin % 32);
+        Event::ALL
+            .into_iter()
+            .filter(|event| Self::bank_register(event.enable_register(), bank).read() & (1 << bit) != 0)
+            .fold(0, |events, event| events | event.bit())
+    }
+
+    /// Lets `event` set the event status of `pins`, or not. The enable registers are shared by
+    /// the pins of a bank, so the update holds a lock against the other cores.
+    pub fn set_event_detection(pins: PinSet, event: Event, enabled: bool) {
+        with_locked(&EVENT_DETECTION, |_| {
+            for (bank, bits) in [pins.0, pins.1].into_iter().enumerate() {
+                if bits != 0 {
+                    Self::bank_register(event.enable_register(), bank).update(|enabled_pins| {
+                        if enabled {
+                            enabled_pins | bits
+                        } else {
+                            enabled_pins & !bits
+                        }
+                    });
+                }
+            }
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GpioError {
+    OutOfRange(u8),
+    /// Another driver has the pin.
+    Claimed { pin: u8, owner: &'static str },
+    /// The pin has a callback already.
+    CallbackRegistered(u8),
+    Interrupt(InterruptError),
+}
+
+/// Serializes the read-modify-write of the event enable registers.
+static EVENT_DETECTION: Mutex<()> = Mutex::new(());
+
+static OWNERS: Mutex<[Option<&'static str>; PIN_COUNT]> = Mutex::new([None; PIN_COUNT]);
+
+fn with_owners<R>(f: impl FnOnce(&mut [Option<&'static str>; PIN_COUNT]) -> R) -> R {
+    if !mmu::is_enabled() {
+        // the console claims its pins before the lock works, the other cores wait for the MMU
+        // SAFETY: only the main core runs, and it doesn't take IRQs yet
+        return f(unsafe { OWNERS.get_unlocked() });
+    }
+    with_locked(&OWNERS, f)
+}
+
+/// Marks `pins` as `owner`'s, all or none of them. Pins it has already are fine unless
+/// `exclusive` is set.
+fn claim_pins(pins: PinSet, owner: &'static str, exclusive: bool) -> Result<(), GpioError> {
+    with_owners(|owners| {
+        for pin in pins {
+            match owners[pin as usize] {
+                Some(current) if exclusive || current != owner => {
+                    return Err(GpioError::Claimed { pin, owner: current })
+                }
+                _ => {}
+            }
+        }
+        for pin in pins {
+            owners[pin as usize] = Some(owner);
+        }
+        Ok(())
+    })
+}
+
+impl Gpio {
+    /// Gives `pins` to `owner` for good, for drivers that set the pins up once and keep them.
+    /// Claiming them again is fine, a pin of another owner is an error.
+    pub fn claim(pins: PinSet, owner: &'static str) -> Result<(), GpioError> {
+        claim_pins(pins, owner, false)
+    }
+
+    /// Gives back those of `pins` that `owner` has.
+    pub fn release(pins: PinSet, owner: &'static str) {
+        with_owners(|owners| {
+            for pin in pins {
+                if owners[pin as usize] == Some(owner) {
+                    owners[pin as usize] = None;
+                }
+            }
+        })
+    }
+
+    pub fn owner(pin: u8) -> Option<&'static str> {
+        with_owners(|owners| owners.get(pin as usize).copied().flatten())
+    }
+}
+
+/// Called from the IRQ exception with the number of a pin that had an event.
+pub type Callback = fn(u8);
+
+#[allow(clippy::declare_interior_mutable_const)]
+const NO_CALLBACK: AtomicPtr<()> = AtomicPtr::new(core::ptr::null_mut());
+static CALLBACKS: [AtomicPtr<()>; PIN_COUNT] = [NO_CALLBACK; PIN_COUNT];
+/// The banks of [IRQ_BANKS] whose interrupt has [handle_irq], one bit each.
+static IRQS_REGISTERED: Mutex<u8> = Mutex::new(0);
+
+/// The event status of a pin without a callback, which [handle_irq] took out of the `gpio_int`
+/// interrupt: [PARKED_EVENT] with the [Event::bit]s of the events it had enabled. They stay off
+/// until [Pin::clear_event], the event is still detected meanwhile.
+#[allow(clippy::declare_interior_mutable_const)]
+const NOT_PARKED: AtomicU8 = AtomicU8::new(0);
+static PARKED: [AtomicU8; PIN_COUNT] = [NOT_PARKED; PIN_COUNT];
+const PARKED_EVENT: u8 = 0x80;
+
+/// The pins of each of the `gpio_int` interrupts of the banks.
+const IRQ_BANKS: [(Irq, PinSet); 3] = [
+    (Irq::GPIO_0, PinSet(0x0fff_ffff, 0)),
+    (Irq::GPIO_1, PinSet(0xf000_0000, 0x3fff)),
+    (Irq::GPIO_2, PinSet(0, 0x3f_c000)),
+];
+
+fn callback(pin: u8) -> Option<Callback> {
+    let ptr = CALLBACKS.get(pin as usize)?.load(Ordering::Acquire);
+    if ptr.is_null() {
+        None
+    } else {
+        // SAFETY: only `Pin::on_event` stores non-null pointers, and those are `Callback`s
+        Some(unsafe { core::mem::transmute::<*mut (), Callback>(ptr) })
+    }
+}
+
+/// Clears the events of the pins of `irq` that have a callback and calls it. The events of
+/// polled pins are parked, see [PARKED].
+fn handle_irq(irq: Irq) {
+    let Some(&(_, bank_pins)) = IRQ_BANKS.iter().find(|(bank_irq, _)| *bank_irq == irq) else {
+        return;
+    };
+    for pin in Gpio::events().intersection(bank_pins) {
+        match callback(pin) {
+            Some(callback) => {
+                Gpio::clear_events(PinSet::single(pin));
+                callback(pin);
+            }
+            None => park(pin),
+        }
+    }
+}
+
+/// Turns off the events of a polled pin that raised the interrupt, otherwise it would keep
+/// raising it until the pin's driver gets around to clear the event.
+fn park(pin: u8) {
+    let events = Gpio::event_detection(pin);
+    for event in Event::ALL {
+        if events & event.bit() != 0 {
+            Gpio::set_event_detection(PinSet::single(pin), event, false);
+        }
+    }
+    Gpio::clear_events(PinSet::single(pin));
+    PARKED[pin as usize].fetch_or(PARKED_EVENT | events, Ordering::AcqRel);
+}
+
+/// Registers [handle_irq] for the `gpio_int` interrupts and enables them, once.
+fn register_irqs() -> Result<(), GpioError> {
+    with_locked(&IRQS_REGISTERED, |registered| {
+        for (bank, (irq, _)) in IRQ_BANKS.into_iter().enumerate() {
+            if *registered & 1 << bank == 0 {
+                interrupts::register(irq, handle_irq).map_err(GpioError::Interrupt)?;
+                interrupts::controller().enable(irq);
+                *registered |= 1 << bank;
+            }
+        }
+        Ok(())
+    })
+}
+
+/// A pin that a driver has for itself until it drops it. Dropping it also turns off its events
+/// and removes its callback.
+pub struct Pin {
+    number: u8,
+    owner: &'static str,
+}
+
+impl Pin {
+    pub fn claim(number: u8, owner: &'static str) -> Result<Self, GpioError> {
+        if number as usize >= PIN_COUNT {
+            return Err(GpioError::OutOfRange(number));
+        }
+        claim_pins(PinSet::single(number), owner, true)?;
+        Ok(Self { number, owner })
+    }
+
+    pub fn number(&self) -> u8 {
+        self.number
+    }
+
+    fn set(&self) -> PinSet {
+        PinSet::single(self.number)
+    }
+
+    pub fn set_function(&self, function: PinFunction) {
+        Gpio::set_functions(self.set(), function)
+    }
+
+    pub fn set_pull_resistor(&self, resistor: Resistor) {
+        Gpio::set_pull_resistors(self.set(), resistor)
+    }
+
+    pub fn set_level(&self, level: Level) {
+        match level {
+            Level::Low => Gpio::set_low(self.set()),
+            Level::High => Gpio::set_high(self.set()),
+        }
+    }
+
+    pub fn set_high(&self) {
+        Gpio::set_high(self.set())
+    }
+
+    pub fn set_low(&self) {
+        Gpio::set_low(self.set())
+    }
+
+    pub fn level(&self) -> Level {
+        if self.is_high() {
+            Level::High
+        } else {
+            Level::Low
+        }
+    }
+
+    pub fn is_high(&self) -> bool {
+        Gpio::levels().contains(self.number)
+    }
+
+    pub fn enable_event(&self, event: Event) {
+        let parked = &PARKED[self.number as usize];
+        // a parked pin gets its events back with clear_event
+        let still_parked = parked
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |events| {
+                (events != 0).then_some(events | event.bit())
+            })
+            .is_ok();
+        if !still_parked {
+            Gpio::set_event_detection(self.set(), event, true)
+        }
+    }
+
+    pub fn disable_event(&self, event: Event) {
+        PARKED[self.number as usize].fetch_and(!event.bit(), Ordering::AcqRel);
+        Gpio::set_event_detection(self.set(), event, false)
+    }
+
+    pub fn disable_events(&self) {
+        for event in Event::ALL {
+            self.disable_event(event)
+        }
+    }
+
+    /// Whether an enabled event happened since [Self::clear_event].
+    pub fn event_detected(&self) -> bool {
+        Gpio::events().contains(self.number)
+            || PARKED[self.number as usize].load(Ordering::Acquire) != 0
+    }
+
+    pub fn clear_event(&self) {
+        Gpio::clear_events(self.set());
+        let parked = PARKED[self.number as usize].swap(0, Ordering::AcqRel);
+        for event in Event::ALL {
+            if parked & event.bit() != 0 {
+                Gpio::set_event_detection(self.set(), event, true);
+            }
+        }
+    }
+
+    /// Calls `callback` from the IRQ exception whenever one of the enabled events happens. The
+    /// event is cleared before; a level event fires again right away unless the callback
+    /// disables it or changes the level. Pins without a callback are polled with
+    /// [Self::event_detected].
+    pub fn on_event(&self, callback: Callback) -> Result<(), GpioError> {
+        register_irqs()?;
+        CALLBACKS[self.number as usize]
+            .compare_exchange(
+                core::ptr::null_mut(),
+                callback as *mut (),
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            )
+            .map(|_| ())
+            .map_err(|_| GpioError::CallbackRegistered(self.number))
+    }
+
+    pub fn remove_callback(&self) {
+        CALLBACKS[self.number as usize].store(core::ptr::null_mut(), Ordering::Release)
+    }
+}
+
+impl Drop for Pin {
+    fn drop(&mut self) {
+        self.disable_events();
+        self.clear_event();
+        self.remove_callback();
+        Gpio::release(self.set(), self.owner);
+    }
+}
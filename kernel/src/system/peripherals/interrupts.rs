@@ -1,8 +1,10 @@
 use mystd::bit_field;
+use mystd::sync::mutex::Mutex;
 
 use crate::system::arm_core::registers::aarch64::special_purpose;
 
-use super::mmio::Mmio;
+use super::mmio::{Mmio, Register};
+use super::BCM_HOST;
 
 const IRQ_BASE: usize = 0xB000;
 
@@ -26,6 +28,25 @@ pub fn irq_disable() {
     special_purpose::Daif::read_register().irq_masked().set().write_register();
 }
 
+/// Runs `f` with IRQs masked, e.g. to hold a lock an interrupt handler might want too.
+#[inline]
+pub fn without_irqs<R>(f: impl FnOnce() -> R) -> R {
+    let masked = irq_disabled();
+    irq_disable();
+    let result = f();
+    if !masked {
+        irq_enable();
+    }
+    result
+}
+
+/// Runs `f` on what `lock` protects, holding it with IRQs masked so an interrupt handler on the
+/// same core can't wait for it. `f` mustn't take `lock` again.
+pub fn with_locked<T, R>(lock: &Mutex<T>, f: impl FnOnce(&mut T) -> R) -> R {
+    // SAFETY: IRQs are masked, so the lock is never taken twice by the same core
+    without_irqs(|| f(&mut *unsafe { lock.lock() }))
+}
+
 impl IrqPendingBase {
 
     const REG: Mmio<IRQ_BASE, 0x200> = Mmio();
@@ -52,11 +73,11 @@ impl GpuIrqs1 {
     }
 
     pub fn read_disable() -> Self {
-        Self::ENABLE.read().into()
+        Self::DISABLE.read().into()
     }
 
     pub fn write_disable(&self) {
-        Self::ENABLE.write(self.0)
+        Self::DISABLE.write(self.0)
     }
 }
 
@@ -78,11 +99,11 @@ impl GpuIrqs2 {
     }
 
     pub fn read_disable() -> Self {
-        Self::ENABLE.read().into()
+        Self::DISABLE.read().into()
     }
 
     pub fn write_disable(&self) {
-        Self::ENABLE.write(self.0)
+        Self::DISABLE.write(self.0)
     }
 }
 
@@ -104,11 +125,11 @@ impl BasicIrqs {
     }
 
     pub fn read_disable() -> Self {
-        Self::ENABLE.read().into()
+        Self::DISABLE.read().into()
     }
 
     pub fn write_disable(&self) {
-        Self::ENABLE.write(self.0)
+        Self::DISABLE.write(self.0)
     }
 }
 
@@ -124,6 +145,88 @@ impl Fiq {
     }
 }
 
+type LocalRegister<const OFFSET: usize, T> =
+    Register<{ BCM_HOST.local_peripheral_address }, OFFSET, T>;
+
+/// The interrupt registers of the ARM local peripherals (BCM2836 and later), which route the
+/// interrupts of the ARM peripherals to the cores and collect the per-core ones.
+pub struct ArmLocal {}
+
+impl ArmLocal {
+    /// Sends all interrupts of the ARM peripherals (GPU and basic) to `core`.
+    pub fn set_gpu_irq_core(core: usize) {
+        LocalRegister::<0x0c, u32>::no_offset().update(|routing| (routing & !0b11) | core as u32)
+    }
+
+    pub fn timer_interrupts(core: usize) -> CoreTimerInterrupts {
+        LocalRegister::<0x40, CoreTimerInterrupts>::at(core * 4).read()
+    }
+
+    pub fn set_timer_interrupts(core: usize, value: CoreTimerInterrupts) {
+        LocalRegister::<0x40, CoreTimerInterrupts>::at(core * 4).write(value)
+    }
+
+    pub fn mailbox_interrupts(core: usize) -> CoreMailboxInterrupts {
+        LocalRegister::<0x50, CoreMailboxInterrupts>::at(core * 4).read()
+    }
+
+    pub fn set_mailbox_interrupts(core: usize, value: CoreMailboxInterrupts) {
+        LocalRegister::<0x50, CoreMailboxInterrupts>::at(core * 4).write(value)
+    }
+
+    /// The pending interrupts of `core`.
+    pub fn irq_source(core: usize) -> CoreInterruptSource {
+        LocalRegister::<0x60, CoreInterruptSource>::at(core * 4).read()
+    }
+
+    /// Sends the performance monitor interrupt of `core` to its IRQ, or nowhere.
+    pub fn set_pmu_interrupt(core: usize, enabled: bool) {
+        if enabled {
+            LocalRegister::<0x10, u32>::no_offset().write(1 << core)
+        } else {
+            LocalRegister::<0x14, u32>::no_offset().write(1 << core)
+        }
+    }
+
+    /// The interrupt of the AXI outstanding counters, which only goes to core 0.
+    pub fn set_axi_outstanding_interrupt(enabled: bool) {
+        LocalRegister::<0x2c, u32>::no_offset()
+            .update(|control| if enabled { control | 1 << 20 } else { control & !(1 << 20) })
+    }
+
+    /// The interrupt of the local timer, which goes to the core its routing says.
+    pub fn set_local_timer_interrupt(enabled: bool) {
+        LocalRegister::<0x34, u32>::no_offset()
+            .update(|control| if enabled { control | 1 << 29 } else { control & !(1 << 29) })
+    }
+}
+
+bit_field!(pub CoreTimerInterrupts(u32) {
+    0 => secure_physical_irq,
+    1 => non_secure_physical_irq,
+    2 => hypervisor_irq,
+    3 => virtual_irq,
+    4 => secure_physical_fiq,
+    5 => non_secure_physical_fiq,
+    6 => hypervisor_fiq,
+    7 => virtual_fiq,
+});
+
+bit_field!(pub CoreMailboxInterrupts(u32) {
+    0:3 => irq,
+    4:7 => fiq,
+});
+
+bit_field!(pub CoreInterruptSource(u32) {
+    /// The 4 timers, in the order of [CoreTimerInterrupts].
+    0:3 => timers,
+    4:7 => mailboxes,
+    /// One of the ARM peripherals, only for the core they are routed to.
+    8 => gpu,
+    9 => pmu,
+    10 => axi_outstanding,
+    11 => local_timer,
+});
 
 bit_field!(pub IrqPendingBase(u32) {
     /// GPUIRQ62
@@ -1,8 +1,16 @@
+use core::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+
 use mystd::bit_field;
+use mystd::collections::sync_ring::AtomicRing256;
 use mystd::fixed_point::FxU32;
-use crate::peripherals::gpio;
+use mystd::sync::mutex::Mutex;
+use crate::peripherals::gpio::{self, GpioError};
+use crate::peripherals::interrupts::{irq_enabled, without_irqs};
+use crate::system::arm_core;
+use crate::system::arm_core::mmu;
 use crate::system::hal::clocks::Clock;
 use crate::system::hal::counter::PointInTime;
+use crate::system::hal::interrupts::{self, InterruptError, Irq};
 
 use super::gpio::PinSet;
 use super::mmio::PeripheralRegister;
@@ -19,12 +27,22 @@ pub enum Uart {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum UartReadError {
     ReceiveFifoEmpty,
-    Status(UartStatus)
+    /// The receive FIFO was full, characters after this one were lost.
+    Overrun(u8),
+    /// The character had no valid stop bit.
+    Framing,
+    /// The character's parity bit was wrong.
+    Parity,
+    /// The line was held low for longer than a character.
+    Break,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum UartWriteError {
+    /// The transmit FIFO, or the TX ring with interrupts, is full.
     TransmitFifoFull
 }
 
@@ -48,17 +66,241 @@ impl UartBehavior {
             write_blocking: Blocking::Indefinetly,
         }
     } 
+
+    pub const fn with_read_blocking(self, read_blocking: Blocking) -> Self {
+        Self { read_blocking, ..self }
+    }
 }
 
-pub const UART_BASE: usize = 0x201000;
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UartError {
+    /// No UART of the SoC has its registers at this address.
+    NoSuchUart(usize),
+    /// The baud rate can't be divided from the UART's clock, or the mini UART can't do the
+    /// setting: it has 7 or 8 data bits, no parity and one stop bit.
+    Unsupported,
+    /// The UART can't have its TXD on this pin.
+    NoSuchPins(u8),
+    Pins(GpioError),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Parity {
+    None,
+    Odd,
+    Even,
+    /// The parity bit is always 1.
+    Mark,
+    /// The parity bit is always 0.
+    Space,
+}
 
-static mut UART0_IS_INIT: bool = false;
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopBits {
+    One,
+    Two,
+}
+
+/// How full a FIFO is when it raises its interrupt: the receive FIFO at or above the level, the
+/// transmit FIFO at or below it.
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FifoLevel {
+    Eighth = 0b000,
+    Quarter = 0b001,
+    Half = 0b010,
+    ThreeQuarters = 0b011,
+    SevenEighths = 0b100,
+}
+
+/// The line settings of a UART, see [Uart::configure].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct UartConfig {
+    pub baud_rate: u32,
+    pub data_bits: UartWordLength,
+    pub parity: Parity,
+    pub stop_bits: StopBits,
+    /// The mini UART has no trigger levels and ignores these.
+    pub receive_level: FifoLevel,
+    pub transmit_level: FifoLevel,
+    /// The TXD pin of the pins to move to, `None` keeps the pins in use, at first the first ones
+    /// of the UART.
+    pub txd_pin: Option<u8>,
+}
+
+impl UartConfig {
+    /// 115200 8N1. The receive interrupt comes at a quarter full, to give the handler time, the
+    /// transmit interrupt at an eighth.
+    pub const fn default() -> Self {
+        Self {
+            baud_rate: UartBitrate::Baud115200 as u32,
+            data_bits: UartWordLength::Bit8,
+            parity: Parity::None,
+            stop_bits: StopBits::One,
+            receive_level: FifoLevel::Quarter,
+            transmit_level: FifoLevel::Eighth,
+            txd_pin: None,
+        }
+    }
+
+    pub const fn with_bitrate(self, bitrate: UartBitrate) -> Self {
+        Self { baud_rate: bitrate as u32, ..self }
+    }
+
+    /// Any baud rate the UART's clock can be divided down to.
+    pub const fn with_baud_rate(self, baud_rate: u32) -> Self {
+        Self { baud_rate, ..self }
+    }
+
+    pub const fn with_data_bits(self, data_bits: UartWordLength) -> Self {
+        Self { data_bits, ..self }
+    }
+
+    pub const fn with_parity(self, parity: Parity) -> Self {
+        Self { parity, ..self }
+    }
+
+    pub const fn with_stop_bits(self, stop_bits: StopBits) -> Self {
+        Self { stop_bits, ..self }
+    }
+
+    pub const fn with_fifo_levels(self, receive_level: FifoLevel, transmit_level: FifoLevel) -> Self {
+        Self { receive_level, transmit_level, ..self }
+    }
+
+    /// Routes the UART to the pins with TXD on `txd_pin`, e.g. the mini UART to 32 or 40 instead
+    /// of UART0's 14.
+    pub const fn with_pins(self, txd_pin: u8) -> Self {
+        Self { txd_pin: Some(txd_pin), ..self }
+    }
+
+    fn line_control(&self) -> UartLineControl {
+        // see the table of UartLineControl
+        let (enabled, even, stick) = match self.parity {
+            Parity::None => (false, false, false),
+            Parity::Odd => (true, false, false),
+            Parity::Even => (true, true, false),
+            Parity::Mark => (true, false, true),
+            Parity::Space => (true, true, true),
+        };
+        UartLineControl::zero()
+            .word_length()
+                .set_value(self.data_bits)
+            .parity_enabled()
+                .set_value(enabled)
+            .even_parity_select()
+                .set_value(even)
+            .stick_parity_select()
+                .set_value(stick)
+            .two_stop_bits()
+                .set_value(self.stop_bits == StopBits::Two)
+            .fifo_enabled()
+                .set()
+    }
+
+    /// The value of the UART_IFLS register.
+    fn fifo_levels(&self) -> u32 {
+        (self.receive_level as u32) << 3 | self.transmit_level as u32
+    }
+}
+
+/// Gives the CTS and RTS pins to the UART.
+fn route_flow_control(uart_pins: &UartPins) {
+    let pins = PinSet::select(&uart_pins.flow_control);
+    gpio::Gpio::set_functions(pins, uart_pins.flow_control_function);
+    // CTS is active low, a pull down keeps the UART sending with nothing connected
+    gpio::Gpio::set_pull_resistors(pins, gpio::Resistor::PullDown);
+}
+
+/// The integer and fractional baud rate divisors of a PL011, like [UartBitrate::to_int_frac].
+/// None if `baud_rate` is out of their range.
+fn pl011_divisors(clock_rate: u32, baud_rate: u32) -> Option<(u32, u32)> {
+    let f_uart_clk: FxU32<6> = clock_rate.into();
+    let baud_rate_divisor = f_uart_clk / 16u32.checked_mul(baud_rate).filter(|rate| *rate != 0)?;
+    let (brd_int, brd_frac) = baud_rate_divisor.split_int_frac();
+    (1..0xffff).contains(&brd_int).then_some((brd_int, brd_frac))
+}
+
+/// The baud rate register of the mini UART, which divides the core clock by 8 * (register + 1).
+fn mini_uart_divisor(clock_rate: u32, baud_rate: u32) -> Option<u32> {
+    let divisor = clock_rate.checked_div(8u32.checked_mul(baud_rate)?)?;
+    divisor.checked_sub(1).filter(|divisor| *divisor <= 0xffff)
+}
+
+pub const UART_BASE: usize = 0x201000;
+/// The auxiliary peripherals, the mini UART and SPI1 and 2.
+pub const AUX_BASE: usize = 0x215000;
 
 pub const UART_0: Uart = Uart::Pl011Uart{ address: UART_BASE, behavior: UartBehavior::default() };
-// pub type Uart2 = Pl011Uart<0x201400>;
-// pub type Uart3 = Pl011Uart<0x201600>;
-// pub type Uart4 = Pl011Uart<0x201800>;
-// pub type Uart5 = Pl011Uart<0x201a00>;
+/// The mini UART, on the same pins as UART0 unless [UartConfig::with_pins] moves it.
+pub const UART_1: Uart = Uart::MiniUart{ address: AUX_BASE, behavior: UartBehavior::default() };
+#[cfg(feature = "bcm2711")]
+pub const UART_2: Uart = Uart::Pl011Uart{ address: 0x201400, behavior: UartBehavior::default() };
+#[cfg(feature = "bcm2711")]
+pub const UART_3: Uart = Uart::Pl011Uart{ address: 0x201600, behavior: UartBehavior::default() };
+#[cfg(feature = "bcm2711")]
+pub const UART_4: Uart = Uart::Pl011Uart{ address: 0x201800, behavior: UartBehavior::default() };
+#[cfg(feature = "bcm2711")]
+pub const UART_5: Uart = Uart::Pl011Uart{ address: 0x201a00, behavior: UartBehavior::default() };
+
+/// Pins the signals of a UART can come out on.
+struct UartPins {
+    /// TXD and RXD.
+    data: [u8; 2],
+    data_function: gpio::PinFunction,
+    /// CTS and RTS.
+    flow_control: [u8; 2],
+    flow_control_function: gpio::PinFunction,
+}
+
+impl UartPins {
+    const fn new(data: [u8; 2], data_function: gpio::PinFunction, flow_control: [u8; 2], flow_control_function: gpio::PinFunction) -> Self {
+        Self { data, data_function, flow_control, flow_control_function }
+    }
+}
+
+/// A UART of the SoC: where its registers are, and the pins its signals can come out on.
+struct Instance {
+    address: usize,
+    /// The owner of its pins, see [gpio::Gpio::claim].
+    name: &'static str,
+    /// The first ones are used unless [UartConfig::with_pins] picks others.
+    pins: &'static [UartPins],
+}
+
+impl Instance {
+    const fn new(address: usize, name: &'static str, pins: &'static [UartPins]) -> Self {
+        Self { address, name, pins }
+    }
+}
+
+#[cfg(not(feature = "bcm2711"))]
+const INSTANCE_COUNT: usize = 2;
+#[cfg(feature = "bcm2711")]
+const INSTANCE_COUNT: usize = 6;
+
+static INSTANCES: [Instance; INSTANCE_COUNT] = [
+    Instance::new(UART_BASE, "UART0", &[UartPins::new([14, 15], gpio::PinFunction::Alt0, [16, 17], gpio::PinFunction::Alt3)]),
+    // UART0 keeps 14 and 15 for the console, the other pins are not on the header of every board
+    Instance::new(AUX_BASE, "UART1", &[
+        UartPins::new([14, 15], gpio::PinFunction::Alt5, [16, 17], gpio::PinFunction::Alt5),
+        UartPins::new([32, 33], gpio::PinFunction::Alt5, [30, 31], gpio::PinFunction::Alt5),
+        UartPins::new([40, 41], gpio::PinFunction::Alt5, [43, 42], gpio::PinFunction::Alt5),
+    ]),
+    #[cfg(feature = "bcm2711")]
+    Instance::new(0x201400, "UART2", &[UartPins::new([0, 1], gpio::PinFunction::Alt4, [2, 3], gpio::PinFunction::Alt4)]),
+    #[cfg(feature = "bcm2711")]
+    Instance::new(0x201600, "UART3", &[UartPins::new([4, 5], gpio::PinFunction::Alt4, [6, 7], gpio::PinFunction::Alt4)]),
+    #[cfg(feature = "bcm2711")]
+    Instance::new(0x201800, "UART4", &[UartPins::new([8, 9], gpio::PinFunction::Alt4, [10, 11], gpio::PinFunction::Alt4)]),
+    #[cfg(feature = "bcm2711")]
+    Instance::new(0x201a00, "UART5", &[UartPins::new([12, 13], gpio::PinFunction::Alt4, [14, 15], gpio::PinFunction::Alt4)]),
+];
+
+/// Set once an instance is configured, [Uart::init] leaves it alone then.
+static INITIALIZED: [AtomicBool; INSTANCE_COUNT] = [const { AtomicBool::new(false) }; INSTANCE_COUNT];
+/// The index of the [Instance::pins] each instance uses.
+static PINS: [AtomicU8; INSTANCE_COUNT] = [const { AtomicU8::new(0) }; INSTANCE_COUNT];
 
 pub type UartDataReg = PeripheralRegister<0x00, UartData>;
 pub type UartReceiveStatusErrorClearReg = PeripheralRegister<0x04, u32>;
@@ -79,16 +321,120 @@ pub type UartIntegrationTestInputReg = PeripheralRegister<0x84, u32>;
 pub type UartIntegrationTestOutputReg = PeripheralRegister<0x88, u32>;
 pub type UartTestDataReg = PeripheralRegister<0x8c, u32>;
 
+pub type AuxEnablesReg = PeripheralRegister<0x04, u32>;
+pub type MiniUartIoReg = PeripheralRegister<0x40, u32>;
+pub type MiniUartInterruptEnableReg = PeripheralRegister<0x44, u32>;
+pub type MiniUartInterruptIdentifyReg = PeripheralRegister<0x48, u32>;
+pub type MiniUartLineControlReg = PeripheralRegister<0x4c, u32>;
+pub type MiniUartModemControlReg = PeripheralRegister<0x50, u32>;
+pub type MiniUartLineStatusReg = PeripheralRegister<0x54, MiniUartLineStatus>;
+pub type MiniUartExtraControlReg = PeripheralRegister<0x60, MiniUartExtraControl>;
+pub type MiniUartBaudRateReg = PeripheralRegister<0x68, u32>;
+
+
+/// What the interrupt handler of UART0 shares with its readers and writers. The rings have one
+/// producer and one consumer each, the locks keep it that way: the handler and readers both fill
+/// `rx`, the handler and writers both drain `tx`.
+struct Buffers {
+    /// Characters with their status bits, as the data register has them.
+    rx: AtomicRing256<u16>,
+    tx: AtomicRing256<u8>,
+    /// Set by [enable_interrupts], until then reads and writes use the FIFOs directly.
+    enabled: AtomicBool,
+    filling_rx: Mutex<()>,
+    reading_rx: Mutex<()>,
+    writing_tx: Mutex<()>,
+    draining_tx: Mutex<()>,
+}
+
+static UART0_BUFFERS: Buffers = Buffers {
+    rx: AtomicRing256::filled(0),
+    tx: AtomicRing256::filled(0),
+    enabled: AtomicBool::new(false),
+    filling_rx: Mutex::new(()),
+    reading_rx: Mutex::new(()),
+    writing_tx: Mutex::new(()),
+    draining_tx: Mutex::new(()),
+};
+
+/// Runs `f` holding `lock`, with IRQs masked: the handler spins on the same locks.
+fn locked<R>(lock: &Mutex<()>, f: impl FnOnce() -> R) -> R {
+    without_irqs(|| {
+        let _guard = unsafe { lock.lock() };
+        f()
+    })
+}
+
+impl Buffers {
+    /// Moves what the receive FIFO has into `rx`. While that is full the receive interrupts are
+    /// masked and the FIFO fills up instead, so RTS holds off the sender if flow control is on.
+    fn receive(&self, base_address: usize) {
+        locked(&self.filling_rx, || {
+            while !self.rx.is_full() && UartFlagReg::at(base_address).read().rxfe().is_clear() {
+                // SAFETY: filling_rx makes this the only producer
+                let _ = unsafe { self.rx.put(UartDataReg::at(base_address).read().to_underlying() as u16) };
+            }
+            let full = self.rx.is_full();
+            UartInterruptMaskSetClearReg::at(base_address).update(|mask| if full {
+                mask.receive().clear().receive_timeout().clear()
+            } else {
+                mask.receive().set().receive_timeout().set()
+            });
+        });
+    }
+
+    /// Moves what `tx` has into the transmit FIFO, as far as it fits. The transmit interrupt asks
+    /// for the rest once the FIFO has drained.
+    fn transmit(&self, base_address: usize) {
+        locked(&self.draining_tx, || {
+            while UartFlagReg::at(base_address).read().txff().is_clear() {
+                // SAFETY: draining_tx makes this the only consumer
+                match unsafe { self.tx.pop_take() } {
+                    Some(byte) => UartDataReg::at(base_address).write(UartData::new(byte as u32)),
+                    None => break,
+                }
+            }
+        });
+    }
+}
+
+/// Lets the interrupts of UART0 fill its RX ring and drain its TX ring, so nothing is lost while
+/// the CPU does other work. Reads and writes go through the rings from then on.
+pub fn enable_interrupts() -> Result<(), InterruptError> {
+    if UART0_BUFFERS.enabled.load(Ordering::Acquire) {
+        return Ok(());
+    }
+    interrupts::register(Irq::UART, handle_interrupts)?;
+    UART0_BUFFERS.enabled.store(true, Ordering::Release);
+    locked(&UART0_BUFFERS.filling_rx, || {
+        UartInterruptClearReg::at(UART_BASE).write(UartInterrupts::all_set());
+        UartInterruptMaskSetClearReg::at(UART_BASE).write(
+            UartInterrupts::zero()
+                .receive()
+                    .set()
+                .receive_timeout()
+                    .set()
+                .transmit()
+                    .set()
+        );
+    });
+    interrupts::controller().enable(Irq::UART);
+    Ok(())
+}
 
-pub fn handle_interrupts() {
-    use mystd::io::Write;
-    let masked = UART_0.masked_interrupt_status_reg();
-    let interrupts = masked.read();
-    writeln!(UART_0, "{:#?}", interrupts).unwrap();
-    UART_0.interrupt_clear_reg().write(interrupts);
-    let interrupts = masked.read();
-    writeln!(UART_0, "{:#?}", interrupts).unwrap();
-    writeln!(UART_0, "MASK: {:#?}", UART_0.interrupt_mask_reg().read()).unwrap();
+/// Moves received characters into the RX ring and refills the transmit FIFO from the TX ring,
+/// then wakes up whoever waits for either with `sev`.
+pub fn handle_interrupts(_: Irq) {
+    let pending = UartMaskedInterruptStatusReg::at(UART_BASE).read();
+    // cleared first, so what arrives from now on raises them again
+    UartInterruptClearReg::at(UART_BASE).write(pending);
+    if pending.receive().is_set() || pending.receive_timeout().is_set() {
+        UART0_BUFFERS.receive(UART_BASE);
+    }
+    if pending.transmit().is_set() {
+        UART0_BUFFERS.transmit(UART_BASE);
+    }
+    arm_core::send_event();
 }
 
 impl Uart {
@@ -113,14 +459,111 @@ impl Uart {
         }
     }
 
+    /// The same UART, with reads blocking as `read_blocking` says.
+    pub const fn with_read_blocking(self, read_blocking: Blocking) -> Self {
+        match self {
+            Uart::Pl011Uart{ address, behavior } => Uart::Pl011Uart{ address, behavior: behavior.with_read_blocking(read_blocking) },
+            Uart::MiniUart{ address, behavior } => Uart::MiniUart{ address, behavior: behavior.with_read_blocking(read_blocking) },
+        }
+    }
+
+    /// The index and table entry of this UART.
+    fn instance(&self) -> Result<(usize, &'static Instance), UartError> {
+        let address = self.base_address();
+        INSTANCES
+            .iter()
+            .enumerate()
+            .find(|(_, instance)| instance.address == address)
+            .ok_or(UartError::NoSuchUart(address))
+    }
+
+    pub fn is_initialized(&self) -> bool {
+        self.instance().is_ok_and(|(index, _)| INITIALIZED[index].load(Ordering::Acquire))
+    }
+
+    /// Starts the UART with the [UartConfig::default] settings, unless it was configured before.
     pub fn init(&self) {
-        unsafe {
-            if UART0_IS_INIT {
-                return;
-            } else {
-                UART0_IS_INIT = true;
+        if !self.is_initialized() {
+            // the console can't do without it, and a driver that took its pins has a bug
+            self.configure(UartConfig::default()).expect("the UART can start");
+        }
+    }
+
+    /// Programs the line settings and routes the TXD and RXD pins, also of a running UART: that
+    /// finishes sending first, and drops what it received but wasn't read yet. Pins it moves
+    /// away from become inputs again and are released, flow control moves along.
+    pub fn configure(&self, config: UartConfig) -> Result<(), UartError> {
+        let (index, instance) = self.instance()?;
+        let initialized = INITIALIZED[index].load(Ordering::Acquire);
+        let current = PINS[index].load(Ordering::Acquire) as usize;
+        let choice = match config.txd_pin {
+            None => current,
+            Some(txd_pin) => instance
+                .pins
+                .iter()
+                .position(|pins| pins.data[0] == txd_pin)
+                .ok_or(UartError::NoSuchPins(txd_pin))?,
+        };
+        let uart_pins = &instance.pins[choice];
+        let old_pins = &instance.pins[current];
+        let pins = PinSet::select(&uart_pins.data);
+        let old_flow_control = PinSet::select(&old_pins.flow_control);
+        let has_flow_control = choice != current
+            && old_flow_control.into_iter().all(|pin| gpio::Gpio::owner(pin) == Some(instance.name));
+        // the new pins all or none, the data pins are new to the UART when flow control moves
+        let claim_pins = || {
+            gpio::Gpio::claim(pins, instance.name).map_err(UartError::Pins)?;
+            if has_flow_control {
+                gpio::Gpio::claim(PinSet::select(&uart_pins.flow_control), instance.name).map_err(|error| {
+                    gpio::Gpio::release(pins, instance.name);
+                    UartError::Pins(error)
+                })?;
+            }
+            Ok(())
+        };
+        match self {
+            Uart::Pl011Uart{..} => {
+                let clock_rate = Clock::Uart.rate().unwrap_or(3_000_000);
+                let divisors = pl011_divisors(clock_rate, config.baud_rate).ok_or(UartError::Unsupported)?;
+                claim_pins()?;
+                if let Some(buffers) = self.buffers() {
+                    self.drain(buffers);
+                }
+                self.configure_pl011(config, divisors, initialized);
+            }
+            Uart::MiniUart{..} => {
+                let clock_rate = Clock::Core.rate().unwrap_or(250_000_000);
+                let divisor = mini_uart_divisor(clock_rate, config.baud_rate).ok_or(UartError::Unsupported)?;
+                let line_control = match (config.data_bits, config.parity, config.stop_bits) {
+                    (UartWordLength::Bit7, Parity::None, StopBits::One) => 0b00,
+                    // the datasheet only mentions bit 0, but 8 bits need both
+                    (UartWordLength::Bit8, Parity::None, StopBits::One) => 0b11,
+                    _ => return Err(UartError::Unsupported),
+                };
+                claim_pins()?;
+                self.configure_mini_uart(divisor, line_control, initialized);
+            }
+        }
+        gpio::Gpio::set_functions(pins, uart_pins.data_function);
+        gpio::Gpio::set_pull_resistors(pins, gpio::Resistor::None);
+        if has_flow_control {
+            route_flow_control(uart_pins);
+        }
+        if choice != current {
+            let old_flow_control = if has_flow_control { old_flow_control } else { PinSet::empty() };
+            for old in [PinSet::select(&old_pins.data), old_flow_control] {
+                if initialized {
+                    gpio::Gpio::set_functions(old, gpio::PinFunction::Input);
+                }
+                gpio::Gpio::release(old, instance.name);
             }
         }
+        PINS[index].store(choice as u8, Ordering::Release);
+        INITIALIZED[index].store(true, Ordering::Release);
+        Ok(())
+    }
+
+    fn configure_pl011(&self, config: UartConfig, (brd_int, brd_frac): (u32, u32), initialized: bool) {
         // NOTE: The UART_LCRH, UART_IBRD, and UART_FBRD registers must not be changed:
         // when the UART is enabled
         // when completing a transmission or a reception when it has been programmed to become disabled.
@@ -130,154 +573,234 @@ impl Uart {
         // 3. Flush the transmit FIFO by setting the FEN bit to 0 in the Line Control Register, UART_LCRH.
         // 4. Reprogram the Control Register, UART_CR.
         // 5. Enable the UART.
-
-        // disable UART
         let base_address = self.base_address();
-        UartControlReg::at(base_address).write(UartControl::disabled());
+        // a running UART keeps its interrupts and flow control
+        let (mask, control) = if initialized {
+            (self.interrupt_mask_reg().read(), UartControlReg::at(base_address).read())
+        } else {
+            (UartInterrupts::zero(), UartControl::disabled())
+        };
 
+        // busy stays set while the transmit FIFO has something, so it's emptied first
         while UartFlagReg::at(base_address).read().busy().is_set() {
             core::hint::spin_loop();
         }
 
+        // disable UART
+        UartControlReg::at(base_address).write(UartControl::disabled());
+
         // flush transmit fifo
         UartLineControlReg::at(base_address).update(|u| u.fifo_enabled().clear());
 
-        // todo figure out how to select pins / functions for each uart on pi4
-        let pins = PinSet::select(&[14, 15]);
-        gpio::Gpio::set_functions(pins, gpio::PinFunction::Alt0);
-        gpio::Gpio::set_pull_resistors(pins, gpio::Resistor::None);
-
         // Clear all pending UART interrupts
         UartInterruptClearReg::at(base_address).write(UartInterrupts::all_set());
 
-        let clock_rate = Clock::Uart.rate().unwrap_or(3_000_000);
-        let (brd_int, brd_frac) = UartBitrate::Baud115200.to_int_frac(clock_rate);
         UartIntegerBaudRateDivisorReg::at(base_address).write(brd_int);
         UartFractionalBaudRateDivisorReg::at(base_address).write(brd_frac);
+        UartLineControlReg::at(base_address).write(config.line_control());
+        UartInterruptFIFOLevelSelectReg::at(base_address).write(config.fifo_levels());
 
-        UartLineControlReg::at(base_address).write(
-            UartLineControl::zero()
-                .word_length()
-                    .set_value(UartWordLength::Bit8)
-                // .parity_enabled()
-                //     .clear()
-                // .stick_parity_select()
-                //     .clear()
-                // .even_parity_select()
-                //     .clear()
-                // .send_break()
-                //     .set()
-                // .two_stop_bits()
-                //     .clear()
-                .fifo_enabled()
-                    .set(),
-        );
-        // mask (disable) all interrupts
-        self.interrupt_mask_reg().write(UartInterrupts::zero());
+        self.interrupt_mask_reg().write(mask);
         UartInterruptClearReg::at(base_address).write(UartInterrupts::all_set());
 
         // enable UART
-        UartControlReg::at(base_address).write(UartControl::enabled());
+        UartControlReg::at(base_address).write(
+            UartControl::enabled()
+                .cts_hardware_flow_control()
+                    .set_value(control.cts_hardware_flow_control().is_set())
+                .rts_hardware_flow_control()
+                    .set_value(control.rts_hardware_flow_control().is_set())
+        );
     }
 
-    pub fn try_put_byte(&self, data: u8) -> Result<(), UartWriteError> {
-        if self.flags().txff().is_set() {
-            Err(UartWriteError::TransmitFifoFull)
+    fn configure_mini_uart(&self, divisor: u32, line_control: u32, initialized: bool) {
+        let base_address = self.base_address();
+        // a running UART keeps its flow control
+        let control = if initialized {
+            while MiniUartLineStatusReg::at(base_address).read().transmitter_idle().is_clear() {
+                core::hint::spin_loop();
+            }
+            MiniUartExtraControlReg::at(base_address).read()
         } else {
-            UartDataReg::at(self.base_address()).write(UartData::new(data as u32));
-            Ok(())
-        }
+            MiniUartExtraControl::zero()
+        };
+        // its registers only respond while it's enabled
+        AuxEnablesReg::at(base_address).update(|enables| enables | 1);
+        MiniUartExtraControlReg::at(base_address).write(MiniUartExtraControl::zero());
+        MiniUartInterruptEnableReg::at(base_address).write(0);
+        MiniUartLineControlReg::at(base_address).write(line_control);
+        MiniUartModemControlReg::at(base_address).write(0);
+        // clears both FIFOs
+        MiniUartInterruptIdentifyReg::at(base_address).write(0b110);
+        MiniUartBaudRateReg::at(base_address).write(divisor);
+        MiniUartExtraControlReg::at(base_address).write(
+            control
+                .receiver_enable()
+                    .set()
+                .transmitter_enable()
+                    .set()
+        );
     }
 
-    pub fn put_byte(&self, data: u8) -> Result<(), mystd::io::Error> {
-        match self.write_blocking() {
-            Blocking::Never => {
-                if let Err(UartWriteError::TransmitFifoFull) = self.try_put_byte(data) {
-                    return Err(mystd::io::Error::WouldBlock)
-                }
-            },
-            Blocking::TimeoutAfter(timeout_duration) => {
-                let timeout = PointInTime::now() + timeout_duration;
-                while let Err(UartWriteError::TransmitFifoFull) = self.try_put_byte(data) {
-                    if !timeout.is_in_the_future() {
-                        return Err(mystd::io::Error::TimedOut)
-                    }
+    /// Turns on RTS/CTS flow control, on the CTS and RTS pins of the UART. It then only sends
+    /// while CTS is asserted, and deasserts RTS while its receive FIFO is full.
+    pub fn enable_flow_control(&self) -> Result<(), UartError> {
+        let (index, instance) = self.instance()?;
+        let uart_pins = &instance.pins[PINS[index].load(Ordering::Acquire) as usize];
+        gpio::Gpio::claim(PinSet::select(&uart_pins.flow_control), instance.name).map_err(UartError::Pins)?;
+        route_flow_control(uart_pins);
+
+        let base_address = self.base_address();
+        match self {
+            Uart::Pl011Uart{..} => {
+                // the control register is reprogrammed with the UART disabled, after the current character
+                while self.flags().busy().is_set() {
                     core::hint::spin_loop();
                 }
+                UartControlReg::at(base_address).write(UartControl::disabled());
+                UartControlReg::at(base_address).write(
+                    UartControl::enabled()
+                        .cts_hardware_flow_control()
+                            .set()
+                        .rts_hardware_flow_control()
+                            .set()
+                );
             }
-            Blocking::Indefinetly => {
-                while let Err(UartWriteError::TransmitFifoFull) = self.try_put_byte(data) {
-                    core::hint::spin_loop();
-                }
-            },
-        } 
-        
+            Uart::MiniUart{..} => MiniUartExtraControlReg::at(base_address).update(|control| {
+                control
+                    .cts_flow_control()
+                        .set()
+                    .rts_flow_control()
+                        .set()
+            }),
+        }
         Ok(())
     }
 
-    pub fn try_get_byte(&self) -> Result<u8, UartReadError> {
-        if self.flags().rxfe().is_set() {
-            Err(UartReadError::ReceiveFifoEmpty)
+    /// The rings, if interrupts move the characters of this UART. A core with its MMU still off
+    /// can't take their locks, it uses the FIFOs directly.
+    fn buffers(&self) -> Option<&'static Buffers> {
+        match self {
+            Uart::Pl011Uart{ address: UART_BASE, .. }
+                if UART0_BUFFERS.enabled.load(Ordering::Acquire) && mmu::is_enabled() => Some(&UART0_BUFFERS),
+            _ => None,
+        }
+    }
+
+    /// Waits a little for the UART to move on. With interrupts the handler's `sev`, or any other
+    /// interrupt, ends the `wfe`; without them, or with IRQs masked, the FIFOs are polled.
+    fn wait(&self) {
+        if self.buffers().is_some() && irq_enabled() {
+            arm_core::wait_for_event();
         } else {
-            let read = UartDataReg::at(self.base_address()).read();
-            let (status, data): (UartStatus, u8) =
-                (read.status().into(), read.data().value().unwrap());
-            if status.overrun_error().clear().is_all_clear() { 
-                // don't propagate overrun errors, because reading clears the flag.
-                Ok(data)
-            } else {
-                Err(UartReadError::Status(status))
-            }
+            core::hint::spin_loop();
         }
     }
 
-    pub fn get_byte(&self) -> Result<u8, mystd::io::Error> {
-        let err_status = match self.read_blocking() {
-            Blocking::Never => {
-                match self.try_get_byte() {
-                    Ok(byte) => return Ok(byte),
-                    Err(UartReadError::ReceiveFifoEmpty) => return Err(mystd::io::Error::WouldBlock),
-                    Err(UartReadError::Status(error_status)) => error_status,
-                }
+    /// Waits until the TX ring is empty, moving it into the FIFO as that drains.
+    fn drain(&self, buffers: &Buffers) {
+        loop {
+            buffers.transmit(self.base_address());
+            if buffers.tx.is_empty() {
+                break;
             }
-            Blocking::TimeoutAfter(timeout_duration) => {
-                let timeout = PointInTime::now() + timeout_duration;
-                loop {
-                    match self.try_get_byte() {
-                        Ok(byte) => return Ok(byte),
-                        Err(UartReadError::ReceiveFifoEmpty) => {
-                            if !timeout.is_in_the_future() {
-                                return Err(mystd::io::Error::TimedOut);
-                            } else {
-                                core::hint::spin_loop();
-                            }
-                        },
-                        Err(UartReadError::Status(error_status)) => break error_status,
-                    }
-                }    
+            self.wait();
+        }
+    }
+
+    pub fn try_put_byte(&self, data: u8) -> Result<(), UartWriteError> {
+        if let Some(buffers) = self.buffers() {
+            // SAFETY: writing_tx makes this the only producer
+            let put = locked(&buffers.writing_tx, || unsafe { buffers.tx.put(data) });
+            buffers.transmit(self.base_address());
+            return put.map(|_| ()).map_err(|_| UartWriteError::TransmitFifoFull);
+        }
+        let base_address = self.base_address();
+        let full = match self {
+            Uart::Pl011Uart{..} => self.flags().txff().is_set(),
+            Uart::MiniUart{..} => MiniUartLineStatusReg::at(base_address).read().transmitter_empty().is_clear(),
+        };
+        if full {
+            return Err(UartWriteError::TransmitFifoFull);
+        }
+        match self {
+            Uart::Pl011Uart{..} => UartDataReg::at(base_address).write(UartData::new(data as u32)),
+            Uart::MiniUart{..} => MiniUartIoReg::at(base_address).write(data as u32),
+        }
+        Ok(())
+    }
+
+    pub fn put_byte(&self, data: u8) -> Result<(), mystd::io::Error> {
+        let timeout = match self.write_blocking() {
+            Blocking::TimeoutAfter(timeout_duration) => Some(PointInTime::now() + timeout_duration),
+            _ => None,
+        };
+        while let Err(UartWriteError::TransmitFifoFull) = self.try_put_byte(data) {
+            match (self.write_blocking(), timeout) {
+                (Blocking::Never, _) => return Err(mystd::io::Error::WouldBlock),
+                (_, Some(timeout)) if !timeout.is_in_the_future() => return Err(mystd::io::Error::TimedOut),
+                _ => self.wait(),
             }
-            Blocking::Indefinetly => {
-                loop {
-                    match self.try_get_byte() {
-                        Ok(byte) => return Ok(byte),
-                        Err(UartReadError::ReceiveFifoEmpty) => {
-                            core::hint::spin_loop();    
-                        },
-                        Err(UartReadError::Status(error_status)) => break error_status,
-                    }
-                }    
+        }
+        Ok(())
+    }
+
+    /// The next character, from the RX ring with interrupts or from the FIFO without them.
+    pub fn try_get_byte(&self) -> Result<u8, UartReadError> {
+        if let Some(buffers) = self.buffers() {
+            let entry = locked(&buffers.reading_rx, || {
+                // SAFETY: reading_rx makes this the only consumer
+                let entry = unsafe { buffers.rx.pop_take() };
+                // takes what the FIFO kept while the ring was full, and unmasks the interrupts
+                buffers.receive(self.base_address());
+                entry.or_else(|| unsafe { buffers.rx.pop_take() })
+            });
+            return match entry {
+                Some(entry) => UartData::new(entry as u32).received(),
+                None => Err(UartReadError::ReceiveFifoEmpty),
+            };
+        }
+        let base_address = self.base_address();
+        match self {
+            Uart::Pl011Uart{..} if self.flags().rxfe().is_set() => Err(UartReadError::ReceiveFifoEmpty),
+            Uart::Pl011Uart{..} => UartDataReg::at(base_address).read().received(),
+            Uart::MiniUart{..} => {
+                // reading the status clears the overrun flag, it belongs to the character read next
+                let status = MiniUartLineStatusReg::at(base_address).read();
+                if status.data_ready().is_clear() {
+                    return Err(UartReadError::ReceiveFifoEmpty);
+                }
+                let data = MiniUartIoReg::at(base_address).read() as u8;
+                if status.receiver_overrun().is_set() {
+                    Err(UartReadError::Overrun(data))
+                } else {
+                    Ok(data)
+                }
             }
+        }
+    }
+
+    pub fn get_byte(&self) -> Result<u8, mystd::io::Error> {
+        let timeout = match self.read_blocking() {
+            Blocking::TimeoutAfter(timeout_duration) => Some(PointInTime::now() + timeout_duration),
+            _ => None,
         };
-            
-        if err_status.parity_error().is_set() || err_status.framing_error().is_set(){
-            Err(mystd::io::Error::InvalidData)
-        } else if err_status.break_error().is_set() {
-            Err(mystd::io::Error::UnexpectedEof)
-        } else {
-            Err(mystd::io::Error::Unknown { err_code: err_status.to_underlying() as i32 })
+        loop {
+            match self.try_get_byte() {
+                // the character itself is fine, the ones lost after it can't be helped by now
+                Ok(byte) | Err(UartReadError::Overrun(byte)) => return Ok(byte),
+                Err(UartReadError::ReceiveFifoEmpty) => match (self.read_blocking(), timeout) {
+                    (Blocking::Never, _) => return Err(mystd::io::Error::WouldBlock),
+                    (_, Some(timeout)) if !timeout.is_in_the_future() => return Err(mystd::io::Error::TimedOut),
+                    _ => self.wait(),
+                },
+                Err(UartReadError::Framing | UartReadError::Parity) => return Err(mystd::io::Error::InvalidData),
+                Err(UartReadError::Break) => return Err(mystd::io::Error::UnexpectedEof),
+            }
         }
     }
 
+    /// The flags of a PL011.
     pub fn flags(&self) -> UartFlags {
         UartFlagReg::at(self.base_address()).read()
     }
@@ -317,11 +840,24 @@ impl mystd::io::Write for Uart {
                 },
             }
         }
+        if let Some(buffers) = self.buffers() {
+            if !irq_enabled() {
+                // e.g. panicking in a handler, the interrupt might not come before IRQs are back
+                self.drain(buffers);
+            }
+        }
         Ok(mystd::io::Size::from_usize(count))
     }
 
     fn flush(&mut self) -> mystd::io::Result<()> {
-        while !self.flags().txfe().is_set() {
+        if let Some(buffers) = self.buffers() {
+            self.drain(buffers);
+        }
+        let base_address = self.base_address();
+        while !match self {
+            Uart::Pl011Uart{..} => self.flags().txfe().is_set(),
+            Uart::MiniUart{..} => MiniUartLineStatusReg::at(base_address).read().transmitter_idle().is_set(),
+        } {
             core::hint::spin_loop();
         }
         Ok(())
@@ -370,6 +906,24 @@ bit_field!(pub UartData(u32){
     0:7 => data: u8
 });
 
+impl UartData {
+    /// The character, or what went wrong receiving it.
+    fn received(self) -> Result<u8, UartReadError> {
+        let data = self.data().value().unwrap();
+        if self.break_error().is_set() {
+            Err(UartReadError::Break)
+        } else if self.framing_error().is_set() {
+            Err(UartReadError::Framing)
+        } else if self.parity_error().is_set() {
+            Err(UartReadError::Parity)
+        } else if self.overrun_error().is_set() {
+            Err(UartReadError::Overrun(data))
+        } else {
+            Ok(data)
+        }
+    }
+}
+
 bit_field!(pub UartStatus(u32){
     /// Overrun error. This bit is set to 1 if data is received and the receive FIFO is already full.
     ///
@@ -571,3 +1125,23 @@ bit_field!(pub UartInterrupts(u32){
     4 => receive,
     1 => n_uartcts_modem
 });
+
+bit_field!(pub MiniUartLineStatus(u32){
+    /// Transmitter idle. The transmit FIFO is empty and the transmitter has sent the last bit.
+    6 => transmitter_idle,
+    /// Transmitter empty. The transmit FIFO can accept at least one more character.
+    5 => transmitter_empty,
+    /// Receiver overrun. A character was lost because the receive FIFO was full. Cleared by reading this register.
+    1 => receiver_overrun,
+    /// Data ready. The receive FIFO holds at least one character.
+    0 => data_ready
+});
+
+bit_field!(pub MiniUartExtraControl(u32){
+    /// Enable transmit auto flow control using CTS. The transmitter stops while CTS is deasserted.
+    3 => cts_flow_control,
+    /// Enable receive auto flow control using RTS. RTS is deasserted while the receive FIFO is nearly full.
+    2 => rts_flow_control,
+    1 => transmitter_enable,
+    0 => receiver_enable
+});
@@ -3,7 +3,7 @@ pub const USB_CORE_BASE: usize = USB_BASE;
 pub const USB_HOST_BASE: usize = USB_BASE + 0x400;
 pub const USB_POWER_BASE: usize = USB_BASE + 0xe00;
 
-use super::mmio::TypedMMIO;
+use super::mmio::{PeripheralRegister, TypedMMIO};
 use mystd::bit_field;
 
 type DwhciCoreAhbCfgReg = TypedMMIO<DwHciCoreAhbCfg, USB_CORE_BASE, 0x008>;
@@ -17,6 +17,28 @@ type DwhciCoreHwCfg1Reg = TypedMMIO<DwHciCoreHwCfg1, USB_CORE_BASE, 0x044>;
 type DwhciCoreHwCfg2Reg = TypedMMIO<DwHciCoreHwCfg2, USB_CORE_BASE, 0x048>;
 type DwhciCoreHwCfg3Reg = TypedMMIO<DwHciCoreHwCfg3, USB_CORE_BASE, 0x04c>;
 type DwhciCoreHwCfg4Reg = TypedMMIO<DwHciCoreHwCfg4, USB_CORE_BASE, 0x050>;
+type DwhciCoreRxFifoSizeReg = TypedMMIO<u32, USB_CORE_BASE, 0x024>;
+type DwhciCoreNonPeriodicTxFifoSizeReg = TypedMMIO<DwHciFifoSize, USB_CORE_BASE, 0x028>;
+type DwhciCorePeriodicTxFifoSizeReg = TypedMMIO<DwHciFifoSize, USB_CORE_BASE, 0x100>;
+
+type DwhciHostCfgReg = TypedMMIO<DwHciHostCfg, USB_HOST_BASE, 0x000>;
+type DwhciHostFrameNumberReg = TypedMMIO<u32, USB_HOST_BASE, 0x008>;
+type DwhciHostAllChannelsIntReg = TypedMMIO<u32, USB_HOST_BASE, 0x014>;
+type DwhciHostAllChannelsIntMaskReg = TypedMMIO<u32, USB_HOST_BASE, 0x018>;
+type DwhciHostPortReg = TypedMMIO<DwHciHostPort, USB_HOST_BASE, 0x040>;
+
+type DwhciPowerReg = TypedMMIO<u32, USB_POWER_BASE, 0x000>;
+
+/// The registers of host channel `n` start at `USB_HOST_CHANNEL_BASE + n * USB_HOST_CHANNEL_SIZE`.
+pub const USB_HOST_CHANNEL_BASE: usize = USB_HOST_BASE + 0x100;
+pub const USB_HOST_CHANNEL_SIZE: usize = 0x20;
+
+pub type DwhciChannelCharReg = PeripheralRegister<0x00, DwHciChannelChar>;
+pub type DwhciChannelSplitReg = PeripheralRegister<0x04, DwHciChannelSplit>;
+pub type DwhciChannelIntReg = PeripheralRegister<0x08, DwHciChannelInterrupts>;
+pub type DwhciChannelIntMaskReg = PeripheralRegister<0x0c, DwHciChannelInterrupts>;
+pub type DwhciChannelTransferSizeReg = PeripheralRegister<0x10, DwHciChannelTransferSize>;
+pub type DwhciChannelDmaAddressReg = PeripheralRegister<0x14, u32>;
 
 #[derive(Clone, Copy)]
 pub struct DwHciCore {}
@@ -71,6 +93,131 @@ impl DwHciCore {
     pub fn set_interrupt_state(state: DwHciCoreInterrupts) {
         DwhciCoreIntStatReg::write(state)
     }
+
+    pub fn set_interrupt_mask(mask: DwHciCoreInterrupts) {
+        DwhciCoreIntMaskReg::write(mask)
+    }
+
+    /// Sets the sizes of the receive, non-periodic and periodic transmit FIFOs, in words.
+    pub fn set_fifo_sizes(rx: u32, non_periodic_tx: u32, periodic_tx: u32) {
+        DwhciCoreRxFifoSizeReg::write(rx);
+        DwhciCoreNonPeriodicTxFifoSizeReg::write(
+            DwHciFifoSize::zero()
+                .start_address()
+                .set_value(rx)
+                .depth()
+                .set_value(non_periodic_tx),
+        );
+        DwhciCorePeriodicTxFifoSizeReg::write(
+            DwHciFifoSize::zero()
+                .start_address()
+                .set_value(rx + non_periodic_tx)
+                .depth()
+                .set_value(periodic_tx),
+        );
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct DwHciHost {}
+
+impl DwHciHost {
+    pub fn config() -> DwHciHostCfg {
+        DwhciHostCfgReg::read()
+    }
+
+    pub fn set_config(config: DwHciHostCfg) {
+        DwhciHostCfgReg::write(config)
+    }
+
+    pub fn frame_number() -> u32 {
+        DwhciHostFrameNumberReg::read() & 0xffff
+    }
+
+    /// One bit per channel that has a pending interrupt.
+    pub fn channel_interrupts() -> u32 {
+        DwhciHostAllChannelsIntReg::read()
+    }
+
+    pub fn set_channel_interrupt_mask(mask: u32) {
+        DwhciHostAllChannelsIntMaskReg::write(mask)
+    }
+
+    pub fn port() -> DwHciHostPort {
+        DwhciHostPortReg::read()
+    }
+
+    /// Writes the port register. The change bits are cleared by writing 1 and writing 1 to the
+    /// enable bit disables the port, so these are masked out of `port` unless they are in
+    /// `clear_changes`.
+    pub fn set_port(port: DwHciHostPort, clear_changes: DwHciHostPort) {
+        let write_clear = DwHciHostPort::zero()
+            .connect_changed()
+            .set()
+            .enable()
+            .set()
+            .enable_changed()
+            .set()
+            .over_current_changed()
+            .set()
+            .to_underlying();
+        DwhciHostPortReg::write(DwHciHostPort::new(
+            port.to_underlying() & !write_clear | clear_changes.to_underlying() & write_clear,
+        ))
+    }
+
+    /// Ungates the clocks of the PHY and the controller.
+    pub fn power_on() {
+        DwhciPowerReg::write(0)
+    }
+
+    pub fn channel(index: usize) -> DwHciChannel {
+        DwHciChannel(USB_HOST_CHANNEL_BASE + index * USB_HOST_CHANNEL_SIZE)
+    }
+}
+
+/// The registers of a host channel.
+#[derive(Clone, Copy)]
+pub struct DwHciChannel(usize);
+
+impl DwHciChannel {
+    pub fn characteristics(self) -> DwHciChannelChar {
+        DwhciChannelCharReg::at(self.0).read()
+    }
+
+    pub fn set_characteristics(self, value: DwHciChannelChar) {
+        DwhciChannelCharReg::at(self.0).write(value)
+    }
+
+    pub fn set_split(self, value: DwHciChannelSplit) {
+        DwhciChannelSplitReg::at(self.0).write(value)
+    }
+
+    pub fn interrupts(self) -> DwHciChannelInterrupts {
+        DwhciChannelIntReg::at(self.0).read()
+    }
+
+    /// Clears the interrupts that are set in `value`.
+    pub fn clear_interrupts(self, value: DwHciChannelInterrupts) {
+        DwhciChannelIntReg::at(self.0).write(value)
+    }
+
+    pub fn set_interrupt_mask(self, value: DwHciChannelInterrupts) {
+        DwhciChannelIntMaskReg::at(self.0).write(value)
+    }
+
+    pub fn transfer_size(self) -> DwHciChannelTransferSize {
+        DwhciChannelTransferSizeReg::at(self.0).read()
+    }
+
+    pub fn set_transfer_size(self, value: DwHciChannelTransferSize) {
+        DwhciChannelTransferSizeReg::at(self.0).write(value)
+    }
+
+    /// Sets the bus address the channel's DMA reads from or writes to.
+    pub fn set_dma_address(self, bus_address: u32) {
+        DwhciChannelDmaAddressReg::at(self.0).write(bus_address)
+    }
 }
 
 bit_field!(pub DwHciCoreAhbCfg(u32){
@@ -141,3 +288,92 @@ bit_field!(pub DwHciCoreInterrupts(u32){
     30 => sess_req,
     31 => wkup
 });
+
+bit_field!(pub DwHciFifoSize(u32){
+    0:15 => start_address,
+    16:31 => depth
+});
+
+bit_field!(pub DwHciHostCfg(u32){
+    0:1 => fs_ls_phy_clock_select: enum PhyClock {
+        Clock30_60MHz = 0b00,
+        Clock48MHz = 0b01,
+    },
+    2 => fs_ls_support_only
+});
+
+bit_field!(pub DwHciHostPort(u32){
+    0 => connect,
+    /// Write 1 to clear.
+    1 => connect_changed,
+    /// Write 1 to disable the port, it is only enabled by a reset.
+    2 => enable,
+    /// Write 1 to clear.
+    3 => enable_changed,
+    4 => over_current,
+    /// Write 1 to clear.
+    5 => over_current_changed,
+    6 => resume,
+    7 => suspend,
+    8 => reset,
+    10:11 => line_status,
+    12 => power,
+    17:18 => speed: enum PortSpeed {
+        High = 0b00,
+        Full = 0b01,
+        Low = 0b10,
+    }
+});
+
+bit_field!(pub DwHciChannelChar(u32){
+    0:10 => max_packet_size,
+    11:14 => endpoint_number,
+    15 => endpoint_direction_in,
+    17 => low_speed,
+    18:19 => endpoint_type: enum EndpointType {
+        Control = 0b00,
+        Isochronous = 0b01,
+        Bulk = 0b10,
+        Interrupt = 0b11,
+    },
+    /// Number of transactions per frame for periodic endpoints.
+    20:21 => multi_count,
+    22:28 => device_address,
+    29 => odd_frame,
+    30 => disable,
+    31 => enable
+});
+
+bit_field!(pub DwHciChannelSplit(u32){
+    0:6 => port_address,
+    7:13 => hub_address,
+    14:15 => transaction_position,
+    16 => complete_split,
+    31 => split_enable
+});
+
+bit_field!(pub DwHciChannelInterrupts(u32){
+    0 => transfer_completed,
+    1 => halted,
+    2 => ahb_error,
+    3 => stall,
+    4 => nak,
+    5 => ack,
+    6 => nyet,
+    7 => transaction_error,
+    8 => babble_error,
+    9 => frame_overrun,
+    10 => data_toggle_error
+});
+
+bit_field!(pub DwHciChannelTransferSize(u32){
+    0:18 => size,
+    19:28 => packet_count,
+    29:30 => pid: enum Pid {
+        Data0 = 0b00,
+        Data2 = 0b01,
+        Data1 = 0b10,
+        Setup = 0b11,
+    },
+    31 => do_ping
+});
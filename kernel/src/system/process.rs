@@ -0,0 +1,360 @@
+//! Programs at EL0, each in an address space of its own.
+//!
+//! A process is a kernel thread that loads the TTBR0 tables of the process and drops to EL0 with
+//! `eret`. The root table of a process refers to the kernel's level 1 table in its first entry,
+//! which EL0 can't use (see `mmu_init`), and to tables of the process in its second entry, the
+//! [USER_START]..[USER_END] range. The ASID in the TTBR0 is the slot of the process plus one, it
+//! is also how the kernel finds the process of a system call.
+//!
+//! Programs call the kernel with `svc`, see [mystd::syscall]. Any other exception from EL0 ends
+//! the process, the kernel carries on.
+
+mod syscall;
+
+use alloc::vec::Vec;
+use core::arch::asm;
+
+use mystd::elf::{ElfError, ElfFile, MACHINE_AARCH64};
+use mystd::paging::{AddressSpace, PAGE_SIZE};
+use mystd::sync::mutex::Mutex;
+
+use crate::exception::{Abort, AuxExceptionData, ExceptionClass, ExceptionType, TrapFrame};
+use crate::peripherals::interrupts::with_locked;
+use crate::println_log;
+use crate::system::arm_core;
+use crate::system::arm_core::mmu::{self, mapping, mapping::MapAttributes};
+use crate::system::frames;
+use crate::system::hal::scheduler;
+use crate::system::hal::thread::{self, JoinHandle, SpawnError};
+
+pub const USER_START: u64 = 0x80_0000_0000;
+/// The end of the TTBR0 range with `T0SZ` = 24.
+pub const USER_END: u64 = 0x100_0000_0000;
+/// The stack ends at [USER_END].
+pub const USER_STACK_SIZE: u64 = 0x1_0000;
+pub const MAX_PROCESSES: usize = 16;
+
+/// The thread result of a process that was ended because of an exception.
+const KILLED: usize = usize::MAX;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProcessError {
+    /// Processes need their own translation tables.
+    MmuOff,
+    TooManyProcesses,
+    /// No frames left for the program, its stack or its tables.
+    OutOfMemory,
+    Elf(ElfError),
+    /// A segment is outside the user range, overlaps another one or the stack.
+    BadSegment,
+    Thread(SpawnError),
+}
+
+impl From<ElfError> for ProcessError {
+    fn from(value: ElfError) -> Self {
+        Self::Elf(value)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitStatus {
+    /// With the code the program passed to the exit call.
+    Exited(i32),
+    /// Ended by an exception, e.g. an access outside its memory.
+    Killed,
+}
+
+/// Frames of the process, mapped at `virtual_address`.
+#[derive(Debug, Clone, Copy)]
+struct Region {
+    virtual_address: u64,
+    physical_address: u64,
+    frames: usize,
+}
+
+impl Region {
+    fn size(&self) -> u64 {
+        self.frames as u64 * PAGE_SIZE
+    }
+
+    fn overlaps(&self, start: u64, end: u64) -> bool {
+        start < self.virtual_address + self.size() && self.virtual_address < end
+    }
+}
+
+struct Slot {
+    /// The root table, a frame.
+    root: u64,
+    entry: u64,
+    regions: Vec<Region>,
+}
+
+impl Slot {
+    fn space(&self) -> AddressSpace {
+        AddressSpace::new(self.root, 0)
+    }
+}
+
+const NO_PROCESS: Option<Slot> = None;
+static PROCESSES: Mutex<[Option<Slot>; MAX_PROCESSES]> = Mutex::new([NO_PROCESS; MAX_PROCESSES]);
+
+fn with_processes<R>(f: impl FnOnce(&mut [Option<Slot>; MAX_PROCESSES]) -> R) -> R {
+    with_locked(&PROCESSES, f)
+}
+
+fn asid(slot: usize) -> u64 {
+    slot as u64 + 1
+}
+
+fn ttbr0(slot: usize, root: u64) -> u64 {
+    root | asid(slot) << 48
+}
+
+/// The slot of the process whose tables are active, if any.
+fn current_slot() -> Option<usize> {
+    let slot = (mmu::ttbr0() >> 48).checked_sub(1)? as usize;
+    (slot < MAX_PROCESSES).then_some(slot)
+}
+
+/// A running program, [Process::wait] for it to end.
+pub struct Process {
+    slot: usize,
+    thread: JoinHandle,
+}
+
+impl Process {
+    /// The ASID of the process, unique while it runs.
+    pub fn id(&self) -> usize {
+        asid(self.slot) as usize
+    }
+
+    pub fn wait(self) -> ExitStatus {
+        match self.thread.join() {
+            KILLED => ExitStatus::Killed,
+            code => ExitStatus::Exited(code as i32),
+        }
+    }
+}
+
+/// Starts a process running `image`, either an AArch64 ELF executable linked for the user range,
+/// or a flat binary that is loaded to [USER_START] and starts there.
+pub fn spawn(image: &[u8]) -> Result<Process, ProcessError> {
+    if mmu::kernel_ttbr0() == 0 {
+        return Err(ProcessError::MmuOff);
+    }
+    let slot = with_processes(|processes| {
+        let slot = processes.iter().position(Option::is_none)?;
+        processes[slot] = Some(Slot {
+            root: 0,
+            entry: USER_START,
+            regions: Vec::new(),
+        });
+        Some(slot)
+    })
+    .ok_or(ProcessError::TooManyProcesses)?;
+    let started =
+        load(slot, image).and_then(|()| thread::spawn(run, slot).map_err(ProcessError::Thread));
+    match started {
+        Ok(thread) => Ok(Process { slot, thread }),
+        Err(error) => {
+            release(slot);
+            Err(error)
+        }
+    }
+}
+
+/// Sets up the tables, the program and the stack of the process in `slot`.
+fn load(slot: usize, image: &[u8]) -> Result<(), ProcessError> {
+    let root = allocate_zeroed(1)?;
+    let kernel_root = mmu::kernel_ttbr0() & mystd::paging::ADDRESS_MASK;
+    // SAFETY: both tables are identity mapped, and the new one isn't in use
+    unsafe { (root as *mut u64).write((kernel_root as *const u64).read()) };
+    with_processes(|processes| processes[slot].as_mut().expect("reserved").root = root);
+
+    if ElfFile::is_elf(image) {
+        let file = ElfFile::parse(image, MACHINE_AARCH64)?;
+        for segment in file.segments() {
+            let segment = segment?;
+            let attributes = MapAttributes {
+                writable: segment.flags.write,
+                executable: segment.flags.execute,
+                ..MapAttributes::USER_DATA
+            };
+            let data = file.segment_data(&segment)?;
+            add_region(
+                slot,
+                segment.virtual_address,
+                segment.memory_size,
+                data,
+                attributes,
+            )?;
+        }
+        with_processes(|processes| {
+            processes[slot].as_mut().expect("reserved").entry = file.entry()
+        });
+    } else {
+        let attributes = MapAttributes {
+            executable: true,
+            ..MapAttributes::USER_DATA
+        };
+        add_region(slot, USER_START, image.len() as u64, image, attributes)?;
+    }
+    add_region(
+        slot,
+        USER_END - USER_STACK_SIZE,
+        USER_STACK_SIZE,
+        &[],
+        MapAttributes::USER_DATA,
+    )
+}
+
+/// Frames that are zero, for `count` pages.
+fn allocate_zeroed(count: usize) -> Result<u64, ProcessError> {
+    let frames = frames::allocate_contiguous(count).ok_or(ProcessError::OutOfMemory)?;
+    // SAFETY: the frames are new and identity mapped
+    unsafe { core::ptr::write_bytes(frames as *mut u8, 0, count * PAGE_SIZE as usize) };
+    Ok(frames)
+}
+
+/// Maps `size` bytes at `virtual_address` to new frames that start with `data`.
+fn add_region(
+    slot: usize,
+    virtual_address: u64,
+    size: u64,
+    data: &[u8],
+    attributes: MapAttributes,
+) -> Result<(), ProcessError> {
+    let start = virtual_address & !(PAGE_SIZE - 1);
+    let end = virtual_address
+        .checked_add(size)
+        .ok_or(ProcessError::BadSegment)?
+        .next_multiple_of(PAGE_SIZE);
+    // the stack comes last, so this also keeps the segments away from it
+    let overlaps = with_processes(|processes| {
+        let process = processes[slot].as_ref().expect("reserved");
+        process
+            .regions
+            .iter()
+            .any(|region| region.overlaps(start, end))
+    });
+    if start < USER_START || end > USER_END || overlaps {
+        return Err(ProcessError::BadSegment);
+    }
+    if start == end {
+        return Ok(());
+    }
+
+    let count = ((end - start) / PAGE_SIZE) as usize;
+    let physical_address = allocate_zeroed(count)?;
+    let region = Region {
+        virtual_address: start,
+        physical_address,
+        frames: count,
+    };
+    let space = with_processes(|processes| {
+        let process = processes[slot].as_mut().expect("reserved");
+        process.regions.push(region);
+        process.space()
+    });
+    let offset = virtual_address - start;
+    // SAFETY: the frames belong to the process and are identity mapped
+    unsafe {
+        let destination = (physical_address + offset) as *mut u8;
+        core::ptr::copy_nonoverlapping(data.as_ptr(), destination, data.len());
+    }
+    if attributes.executable {
+        arm_core::sync_instruction_cache(physical_address as usize, region.size() as usize);
+    }
+    mapping::map_in(space, start, physical_address, region.size(), attributes).map_err(|_| {
+        // the only thing that can go wrong with aligned addresses
+        ProcessError::OutOfMemory
+    })
+}
+
+/// Frees everything of the process in `slot`, which mustn't run anymore.
+fn release(slot: usize) {
+    let Some(process) = with_processes(|processes| processes[slot].take()) else {
+        return;
+    };
+    if process.root != 0 {
+        // frees the tables of the user range, and drops its translations from the TLBs
+        let _ = mapping::unmap_in(process.space(), USER_START, USER_END - USER_START);
+        let _ = frames::free(process.root);
+    }
+    for region in process.regions {
+        let frames = region.physical_address..region.physical_address + region.size();
+        for frame in frames.step_by(PAGE_SIZE as usize) {
+            let _ = frames::free(frame);
+        }
+    }
+}
+
+/// The thread of the process in `slot`.
+fn run(slot: usize) -> usize {
+    let (root, entry) = with_processes(|processes| {
+        let process = processes[slot]
+            .as_ref()
+            .expect("spawned processes have a slot");
+        (process.root, process.entry)
+    });
+    // SAFETY: the root table refers to the kernel's tables for the kernel's addresses
+    unsafe {
+        scheduler::set_address_space(ttbr0(slot, root));
+        enter_user(entry, USER_END)
+    }
+}
+
+/// Continues at `entry` at EL0 with the stack at `stack_top`, and nothing of the kernel in the
+/// registers.
+unsafe fn enter_user(entry: u64, stack_top: u64) -> ! {
+    asm!(
+        // an IRQ would change the ELR and SPSR
+        "msr daifset, #0b0010",
+        "msr sp_el0, {stack_top}",
+        "msr elr_el1, {entry}",
+        // EL0 with all exceptions unmasked
+        "msr spsr_el1, xzr",
+        "msr fpsr, xzr",
+        ".irp n, 0,1,2,3,4,5,6,7,8,9,10,11,12,13,14,15,16,17,18,19,20,21,22,23,24,25,26,27,28,29,30,31",
+        "movi v\\n.2d, #0",
+        ".endr",
+        ".irp n, 0,1,2,3,4,5,6,7,8,9,10,11,12,13,14,15,16,17,18,19,20,21,22,23,24,25,26,27,28,29,30",
+        "mov x\\n, xzr",
+        ".endr",
+        "eret",
+        entry = in(reg) entry,
+        stack_top = in(reg) stack_top,
+        options(noreturn)
+    )
+}
+
+/// Ends the process of the calling thread with its thread result, doesn't return.
+fn exit_current(result: usize) -> ! {
+    let slot = current_slot().expect("only processes exit");
+    // SAFETY: the kernel's tables map everything the kernel uses
+    unsafe { scheduler::set_address_space(0) };
+    release(slot);
+    thread::exit(result)
+}
+
+/// Handles an exception from EL0: system calls are served, everything else ends the process.
+pub fn handle_exception(data: &AuxExceptionData, frame: &mut TrapFrame) {
+    let syndrome = frame.syndrome();
+    let class = syndrome.exception_class();
+    let synchronous = matches!(
+        data.exception_type().value(),
+        Ok(ExceptionType::Synchronous)
+    );
+    if synchronous && class == ExceptionClass::TrappedSVCInstructionAArch64 {
+        return syscall::handle(frame);
+    }
+    match Abort::from_syndrome(&syndrome) {
+        Some(abort) => println_log!(
+            "Process killed at {:#x}: {}",
+            frame.elr,
+            abort.report(frame.far)
+        ),
+        None => println_log!("Process killed at {:#x}: {:?}", frame.elr, class),
+    }
+    exit_current(KILLED)
+}
@@ -0,0 +1,100 @@
+//! The system calls of [mystd::syscall], served for the process of the calling thread.
+
+use core::time::Duration;
+
+use mystd::io::Write;
+use mystd::paging::PAGE_SIZE;
+use mystd::syscall::{encode_result, Syscall, SyscallError};
+
+use super::{current_slot, exit_current, with_processes, USER_END, USER_START};
+use crate::exception::TrapFrame;
+use crate::system::arm_core::mmu::descriptors::BlockDescriptor;
+use crate::system::arm_core::mmu::mapping::PhysicalTables;
+use crate::system::hal::{counter, thread};
+use crate::system::output;
+use crate::system::peripherals::uart::{UartReadError, UART_0};
+
+/// How long a read waits before looking for input again.
+const INPUT_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Serves the system call in `frame` and puts the result in `x0`.
+pub fn handle(frame: &mut TrapFrame) {
+    let [arg0, arg1] = [frame.x[0], frame.x[1]];
+    let result = match Syscall::try_from(frame.x[8]) {
+        Ok(Syscall::Exit) => exit_current(arg0 as i32 as u32 as usize),
+        Ok(Syscall::Write) => write(arg0, arg1),
+        Ok(Syscall::Read) => read(arg0, arg1),
+        Ok(Syscall::Sleep) => {
+            thread::sleep(Duration::from_nanos(arg0));
+            Ok(0)
+        }
+        Ok(Syscall::Time) => Ok(counter::uptime().as_nanos() as u64),
+        Err(error) => Err(error),
+    };
+    frame.x[0] = encode_result(result);
+}
+
+fn write(address: u64, len: u64) -> Result<u64, SyscallError> {
+    check_buffer(address, len, false)?;
+    // SAFETY: the program can read the buffer, and its tables are active
+    let buffer = unsafe { core::slice::from_raw_parts(address as *const u8, len as usize) };
+    let mut out = loop {
+        match output::std_out().try_lock() {
+            Some(out) => break out,
+            None => thread::yield_now(),
+        }
+    };
+    // the console has nowhere to report errors to either
+    let _ = out.write_all(buffer);
+    Ok(len)
+}
+
+fn read(address: u64, len: u64) -> Result<u64, SyscallError> {
+    check_buffer(address, len, true)?;
+    // SAFETY: the program can write the buffer, and its tables are active
+    let buffer = unsafe { core::slice::from_raw_parts_mut(address as *mut u8, len as usize) };
+    if buffer.is_empty() {
+        return Ok(0);
+    }
+    loop {
+        let mut count = 0;
+        while let Some(byte) = buffer.get_mut(count) {
+            match UART_0.try_get_byte() {
+                Ok(value) | Err(UartReadError::Overrun(value)) => *byte = value,
+                Err(_) => break,
+            }
+            count += 1;
+        }
+        if count > 0 {
+            return Ok(count as u64);
+        }
+        thread::sleep(INPUT_POLL_INTERVAL);
+    }
+}
+
+/// Checks that the program has the `len` bytes at `address` mapped for itself, and writable if
+/// `write` is set. The kernel could access more than that.
+fn check_buffer(address: u64, len: u64, write: bool) -> Result<(), SyscallError> {
+    let end = address.checked_add(len).ok_or(SyscallError::BadAddress)?;
+    if address < USER_START || end > USER_END {
+        return Err(SyscallError::BadAddress);
+    }
+    let slot = current_slot().ok_or(SyscallError::BadAddress)?;
+    let space = with_processes(|processes| processes[slot].as_ref().map(|process| process.space()))
+        .ok_or(SyscallError::BadAddress)?;
+    let tables = PhysicalTables::new();
+    let first_page = address & !(PAGE_SIZE - 1);
+    for page in (first_page..end).step_by(PAGE_SIZE as usize) {
+        let translation = space
+            .translate(&tables, page)
+            .map_err(|_| SyscallError::BadAddress)?;
+        // AP[1] lets EL0 in, AP[2] makes it read-only
+        let access = BlockDescriptor::from(translation.attributes)
+            .ap_s2ap()
+            .value();
+        if access & 0b01 == 0 || (write && access & 0b10 != 0) {
+            return Err(SyscallError::BadAddress);
+        }
+    }
+    Ok(())
+}
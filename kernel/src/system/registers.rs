@@ -0,0 +1,188 @@
+//! The system registers the monitor's `S` command can show and set.
+
+use core::fmt::{Debug, Write};
+
+use monitor::registers::{RegisterError, RegisterProvider};
+
+use super::arm_core::registers::aarch64::general_sys_ctrl::*;
+use super::arm_core::registers::aarch64::generic_timer::*;
+use super::arm_core::registers::aarch64::special_purpose::{self, elr_elx, spsel};
+use super::arm_core::{self, ExceptionLevel};
+
+pub static SYSTEM_REGISTERS: SystemRegisters = SystemRegisters;
+
+pub struct SystemRegisters;
+
+struct Register {
+    name: &'static str,
+    /// Registers of a higher exception level trap when accessed, so they are skipped.
+    level: ExceptionLevel,
+    read: fn(&mut dyn Write),
+    write: Option<fn(u64)>,
+}
+
+fn debug(out: &mut dyn Write, value: impl Debug) {
+    let _ = write!(out, "{value:#?}");
+}
+
+fn address(out: &mut dyn Write, value: u64) {
+    let _ = write!(out, "{value:#018x}");
+}
+
+const REGISTERS: &[Register] = &[
+    Register {
+        name: "CurrentEL",
+        level: ExceptionLevel::EL1,
+        read: |out| debug(out, special_purpose::current_el()),
+        write: None,
+    },
+    Register {
+        name: "DAIF",
+        level: ExceptionLevel::EL1,
+        read: |out| debug(out, special_purpose::Daif::read_register()),
+        write: Some(|value| special_purpose::Daif::new(value).write_register()),
+    },
+    Register {
+        name: "SPSel",
+        level: ExceptionLevel::EL1,
+        read: |out| debug(out, spsel::SpSel::read_register()),
+        write: None,
+    },
+    Register {
+        name: "MPIDR_EL1",
+        level: ExceptionLevel::EL1,
+        read: |out| debug(out, mpidr_el1::MpidrEl1::read_register()),
+        write: None,
+    },
+    Register {
+        name: "ID_AA64MMFR0_EL1",
+        level: ExceptionLevel::EL1,
+        read: |out| debug(out, id_aa64mmfr0_el1::IdAa64Mmfr0El1::read_register()),
+        write: None,
+    },
+    Register {
+        name: "SCTLR_EL1",
+        level: ExceptionLevel::EL1,
+        read: |out| debug(out, sctlr_el1::SctlrEl1::read_register_ordered_ish()),
+        write: Some(|value| sctlr_el1::SctlrEl1::new(value).write_register()),
+    },
+    Register {
+        name: "TCR_EL1",
+        level: ExceptionLevel::EL1,
+        read: |out| debug(out, tcr_el1::TcrEl1::read_register()),
+        write: Some(|value| tcr_el1::TcrEl1::new(value).write_register()),
+    },
+    Register {
+        name: "MAIR_EL1",
+        level: ExceptionLevel::EL1,
+        read: |out| debug(out, mair_el1::MairEl1::read_register()),
+        write: Some(|value| mair_el1::MairEl1::new(value).write_register()),
+    },
+    Register {
+        name: "CPACR_EL1",
+        level: ExceptionLevel::EL1,
+        read: |out| debug(out, cpacr_el1::CpAcrEl1::read_register()),
+        write: Some(|value| cpacr_el1::CpAcrEl1::new(value).write_register()),
+    },
+    Register {
+        name: "VBAR_EL1",
+        level: ExceptionLevel::EL1,
+        read: |out| address(out, vbar_elx::VbarEl1::read_register().value()),
+        write: Some(|value| vbar_elx::VbarEl1::new(value).write_register()),
+    },
+    Register {
+        name: "ELR_EL1",
+        level: ExceptionLevel::EL1,
+        read: |out| address(out, elr_elx::ElrEl1::read_register().value()),
+        write: Some(|value| elr_elx::ElrEl1::new(value).write_register()),
+    },
+    Register {
+        name: "CNTFRQ_EL0",
+        level: ExceptionLevel::EL1,
+        read: |out| debug(out, cntfrq_el0::CntFrqEl0::read_register()),
+        write: None,
+    },
+    Register {
+        name: "CNTPCT_EL0",
+        level: ExceptionLevel::EL1,
+        read: |out| debug(out, cntpct_el0::CntPCtEl0::read_register()),
+        write: None,
+    },
+    Register {
+        name: "CNTP_CTL_EL0",
+        level: ExceptionLevel::EL1,
+        read: |out| debug(out, cntp_ctl_el0::CntPCtlEl0::read_register()),
+        write: Some(|value| cntp_ctl_el0::CntPCtlEl0::new(value).write_register()),
+    },
+    Register {
+        name: "CNTP_CVAL_EL0",
+        level: ExceptionLevel::EL1,
+        read: |out| debug(out, cntp_cval_el0::CntPCValEl0::read_register()),
+        write: Some(|value| cntp_cval_el0::CntPCValEl0::new(value).write_register()),
+    },
+    Register {
+        name: "CNTP_TVAL_EL0",
+        level: ExceptionLevel::EL1,
+        read: |out| debug(out, cntp_tval_el0::CntPTValEl0::read_register()),
+        write: Some(|value| cntp_tval_el0::CntPTValEl0::new(value).write_register()),
+    },
+    Register {
+        name: "HCR_EL2",
+        level: ExceptionLevel::EL2,
+        read: |out| debug(out, hcr_el2::HcrEl2::read_register()),
+        write: Some(|value| hcr_el2::HcrEl2::new(value).write_register()),
+    },
+    Register {
+        name: "CNTHCTL_EL2",
+        level: ExceptionLevel::EL2,
+        read: |out| debug(out, cnthctl_el2::CntHCtlEl2::read_register()),
+        write: Some(|value| cnthctl_el2::CntHCtlEl2::new(value).write_register()),
+    },
+    Register {
+        name: "VBAR_EL2",
+        level: ExceptionLevel::EL2,
+        read: |out| address(out, vbar_elx::VbarEl2::read_register().value()),
+        write: Some(|value| vbar_elx::VbarEl2::new(value).write_register()),
+    },
+    Register {
+        name: "SCR_EL3",
+        level: ExceptionLevel::EL3,
+        read: |out| debug(out, scr_el3::ScrEl3::read_register()),
+        write: Some(|value| scr_el3::ScrEl3::new(value).write_register()),
+    },
+];
+
+impl SystemRegisters {
+    fn accessible(register: &Register) -> Result<(), RegisterError> {
+        if arm_core::current_exception_level() as u64 >= register.level as u64 {
+            Ok(())
+        } else {
+            Err(RegisterError::Inaccessible)
+        }
+    }
+}
+
+impl RegisterProvider for SystemRegisters {
+    fn count(&self) -> usize {
+        REGISTERS.len()
+    }
+
+    fn name(&self, index: usize) -> &str {
+        REGISTERS[index].name
+    }
+
+    fn read(&self, index: usize, out: &mut dyn Write) -> Result<(), RegisterError> {
+        let register = &REGISTERS[index];
+        Self::accessible(register)?;
+        (register.read)(out);
+        Ok(())
+    }
+
+    fn write(&self, index: usize, value: u64) -> Result<(), RegisterError> {
+        let register = &REGISTERS[index];
+        Self::accessible(register)?;
+        let write = register.write.ok_or(RegisterError::ReadOnly)?;
+        write(value);
+        Ok(())
+    }
+}
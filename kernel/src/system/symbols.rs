@@ -0,0 +1,31 @@
+//! The kernel's own symbol table, so the monitor can work with names instead of addresses.
+//!
+//! `link64.x` places the space reserved here between `__symbols_start` and `__symbols_end`. It is
+//! all zeroes after linking, the `symtab` tool fills in the table from the ELF symbols:
+//!
+//! ```sh
+//! cd ../symtab && cargo run --release -- ../kernel/target/aarch64-unknown-none/release/picrust
+//! ```
+
+/// Space reserved for the table in the kernel image.
+const CAPACITY: usize = 0x8000;
+
+#[used]
+#[link_section = ".symbols"]
+static RESERVED: [u8; CAPACITY] = [0; CAPACITY];
+
+extern "C" {
+    static __symbols_start: u8;
+    static __symbols_end: u8;
+}
+
+/// The symbol table, empty if the image was not processed by the `symtab` tool.
+pub fn table() -> monitor::symbols::SymbolTable<'static> {
+    // read through the linker symbols, the compiler would assume the reserved space is still zeroed
+    let data = unsafe {
+        let start = core::ptr::addr_of!(__symbols_start);
+        let end = core::ptr::addr_of!(__symbols_end);
+        core::slice::from_raw_parts(start, end as usize - start as usize)
+    };
+    monitor::symbols::SymbolTable::from_bytes(data)
+}
@@ -11,7 +11,6 @@ use crate::system::peripherals;
 use crate::system::peripherals::dma::DmaControlAndStatus;
 use crate::system::peripherals::dma::DmaControlBlock;
 use crate::system::peripherals::dma::DMA_0;
-use crate::system::peripherals::usb::DwHciCoreInterrupts;
 
 use mystd::arr2d;
 use mystd::byte_value::ByteValue;
@@ -209,9 +208,17 @@ pub fn test_screen() {
 
 pub fn test_irq0() {
     use crate::peripherals::interrupts;
-    interrupts::BasicIrqs::all_set().write_enable();
-    interrupts::GpuIrqs1::all_set().write_enable();
-    interrupts::GpuIrqs2::zero().uart_int().set().write_disable();
+    use crate::system::hal::interrupts::{self as irq, Irq};
+    use peripherals::system_timer::SystemTimer;
+    let controller = irq::controller();
+    for n in 0..4 {
+        let _ = irq::register(Irq::system_timer(n), |_| {
+            SystemTimer::clear_matches(SystemTimer::matches());
+            let _ = TEST_LATCH.set();
+        });
+        controller.enable(Irq::system_timer(n));
+    }
+    let _ = peripherals::uart::enable_interrupts();
     interrupts::irq_enable();
     let frequency = 1_000_000; // increments once every microsecond is this fixed??
     let start_lo = peripherals::system_timer::SystemTimer::counter_low();
@@ -236,6 +243,54 @@ pub fn test_irq1() {
 }
 
 
+pub fn test_exceptions() {
+    use crate::exception::{self, ExceptionClass};
+    // the breakpoint is printed and skipped
+    unsafe { core::arch::asm!("brk #0x42") };
+    // a system call that adds its immediate to x0
+    exception::set_handler(ExceptionClass::TrappedSVCInstructionAArch64, |frame| {
+        frame.x[0] += (frame.syndrome().instruction_specific_syndrome() & 0xffff) as u64;
+        true
+    });
+    let mut value: u64 = 1;
+    unsafe { core::arch::asm!("svc #41", inout("x0") value) };
+    exception::remove_handler(ExceptionClass::TrappedSVCInstructionAArch64);
+    println_log!("svc #41 returned {}", value);
+}
+
+pub fn test_threads() {
+    use crate::system::arm_core::get_core_num;
+    use crate::system::hal::thread::Priority;
+    fn work(n: usize) -> usize {
+        thread::sleep(core::time::Duration::from_millis(10 * n as u64));
+        // long enough to be preempted a few times
+        thread::spin_wait_for(core::time::Duration::from_millis(50));
+        println_log!("Thread {} ({}) on core {}", thread::id(), n, get_core_num().num());
+        n * n
+    }
+    let handles: [_; 8] = core::array::from_fn(|n| {
+        let priority = if n % 2 == 0 { Priority::Normal } else { Priority::High };
+        thread::spawn_with_priority(work, n, priority).expect("a free thread slot")
+    });
+    let sum: usize = handles.into_iter().map(|handle| handle.join()).sum();
+    assert_eq!(sum, (0..8).map(|n| n * n).sum());
+    println_log!("Threads joined, sum of squares {}", sum);
+}
+
+pub fn test_heap() {
+    use crate::system::heap;
+    use alloc::{boxed::Box, vec::Vec};
+    let before = heap::stats();
+    let boxed = Box::new([7_u64; 64]);
+    let numbers: Vec<usize> = (0..1000).collect();
+    println_log!("Heap: {}", heap::stats());
+    assert_eq!(numbers.iter().sum::<usize>(), 999 * 1000 / 2);
+    assert_eq!(boxed.iter().sum::<u64>(), 7 * 64);
+    drop(numbers);
+    drop(boxed);
+    assert_eq!(heap::stats().allocated_bytes, before.allocated_bytes);
+}
+
 pub fn test_dma() {
     println_log!("Testing DMA...");
     use super::peripherals::dma;
@@ -310,118 +365,31 @@ pub fn test_dma() {
 }
 
 pub fn test_usb() -> Option<()> {
-    use core::time::Duration;
-    use peripherals::power;
-    use peripherals::usb;
-
-    println_log!("USB Vendor-ID {:#x}", usb::DwHciCore::vendor_id());
-
-    let power_state = power::PowerDevice::UsbHcd.state()?;
-    println_log!("USB Exists: {}", power_state.exists());
-    println_log!("USB Power On: {}", power_state.is_on());
-    if !power_state.is_on() {
-        let timeout =
-            core::time::Duration::from_millis(power::PowerDevice::UsbHcd.timing_ms()? as u64);
-        println_log!("USB Power On Timeout: {} msec", timeout.as_millis());
-        let turned_on = power_state.with_on().with_wait_set();
-        power::PowerDevice::UsbHcd.set_state(turned_on);
-        thread::spin_wait_for(timeout);
-        let power_state = power::PowerDevice::UsbHcd.state()?;
-        println_log!("USB Power On: {}", power_state.is_on());
+    use crate::system::hal::usb;
+    use peripherals::usb::DwHciCore;
+
+    println_log!("USB Vendor-ID {:#x}", DwHciCore::vendor_id());
+    if let Err(error) = usb::init() {
+        println_log!("USB init failed: {:?}", error);
+        return None;
     }
+    usb::with_host(|host| println_log!("{}", host.tree()))
+}
 
-    let ahb_config = usb::DwHciCore::ahb_config().enable_global_interrupt().set();
-    usb::DwHciCore::set_ahb_config(ahb_config);
-
-    // todo! hook up irq 9
-
-    // DWHCIDeviceInitCore enter
-    let usb_config = usb::DwHciCore::usb_config()
-        .ulpi_ext_vbus_drv()
-        .clear()
-        .term_sel_dl_pulse()
-        .clear();
-    usb::DwHciCore::set_usb_config(usb_config);
-
-    // reset dwhci device
-    let mut reset = usb::DwHciCoreReset::zero();
-    usb::DwHciCore::set_reset(reset);
-
-    // wait for AHB master IDLE state
-    reset = poll_await(
-        usb::DwHciCore::get_reset,
-        |r| r.ahb_idle().is_set(),
-        100,
-        Duration::from_millis(1),
-    )
-    .expect("ahb should turn idle");
-
-    // soft reset
-    usb::DwHciCore::set_reset(reset.soft_reset().set());
-    let _ = poll_await(
-        usb::DwHciCore::get_reset,
-        |r| r.soft_reset().is_clear(),
-        100,
-        Duration::from_millis(1),
-    )
-    .expect("soft reset bit should clear");
-
-    thread::spin_wait_for(Duration::from_millis(100));
-    // reset finished
-
-    let usb_config = usb::DwHciCore::usb_config()
-        .ulpi_utmi_sel()
-        .clear()
-        .phyif()
-        .clear();
-    usb::DwHciCore::set_usb_config(usb_config);
-
-    // Internal DMA mode only
-    let (_, hw_cfg2, _, _) = usb::DwHciCore::hw_config();
-    let mut usb_config = usb::DwHciCore::usb_config();
-    usb_config = if let (Ok(usb::FsPhyType::Dedicated), Ok(usb::HsPhyType::Ulpi)) =
-        (hw_cfg2.fs_phy_type().value(), hw_cfg2.hs_phy_type().value())
-    {
-        usb_config.ulpi_clk_sus_m().set().ulpi_fsls().set()
-    } else {
-        usb_config.ulpi_clk_sus_m().clear().ulpi_fsls().clear()
-    };
-    usb::DwHciCore::set_usb_config(usb_config);
-
-    let num_host_channels = hw_cfg2.num_host_channels_actual();
-    assert!((4..=16).contains(&num_host_channels));
-
-    let ahb_config = usb::DwHciCore::ahb_config()
-        .enable_dma()
-        .set()
-        .wait_axi_writes()
-        .set()
-        .max_axi_burst()
-        .set_value(0);
-    usb::DwHciCore::set_ahb_config(ahb_config);
-
-    // HNP and SRP are not used
-    let usb_config = usb::DwHciCore::usb_config()
-        .srp_capable()
-        .clear()
-        .hnp_capable()
-        .clear();
-    usb::DwHciCore::set_usb_config(usb_config);
-
-    // DWHCIDeviceEnableCommonInterrupts
-    // Clear any pending interrupts
-    usb::DwHciCore::set_interrupt_state(DwHciCoreInterrupts::all_set());
-
-    // DWHCIDeviceInitCore finished
-
-    // DWHCIDeviceEnableGlobalInterrupts enter
-    let ahb_config = usb::DwHciCore::ahb_config().enable_global_interrupt().set();
-    usb::DwHciCore::set_ahb_config(ahb_config);
-    // DWHCIDeviceEnableGlobalInterrupts finished
-
-    // DWHCIDeviceInitHost enter
-    // DWHCIDeviceInitHost leave
+/// Echoes what is typed on a USB keyboard, until Escape.
+pub fn test_keyboard() -> Option<()> {
+    use crate::system::hal::keyboard;
+    use mystd::io::Read;
 
+    test_usb()?;
+    if let Err(error) = keyboard::init(&mystd::protocols::hid::US) {
+        println_log!("no keyboard: {:?}", error);
+        return None;
+    }
+    let mut byte = [0_u8];
+    while keyboard::Keyboard.read_exact(&mut byte).is_ok() && byte[0] != 0x1b {
+        println_log!("key {:#04x} {:?}", byte[0], byte[0] as char);
+    }
     Some(())
 }
 
@@ -446,3 +414,146 @@ fn poll_await<R: Copy, G: Fn() -> R, F: Fn(R) -> bool>(
         thread::spin_wait_for(timeout_interval);
     }
 }
+
+// Flat programs for test_processes, they run at USER_START.
+core::arch::global_asm!(
+    ".section .rodata.test_processes, \"a\"",
+    ".balign 4",
+    "test_process_exit:",
+    "adr x0, 1f",
+    "mov x1, #(2f - 1f)",
+    "mov x8, #1", // write
+    "svc #0",
+    "movz x0, #0x4240",
+    "movk x0, #0xf, lsl #16",
+    "mov x8, #3", // sleep for 1 ms
+    "svc #0",
+    "mov x0, #7",
+    "mov x8, #0", // exit
+    "svc #0",
+    "1: .ascii \"Hello from EL0\\n\"",
+    "2: .balign 4",
+    "test_process_kernel_access:",
+    // the kernel's code is mapped, but not for EL0
+    "mov x0, #0x80000",
+    "ldr x1, [x0]",
+    "brk #0",
+    "test_process_end:",
+    ".previous",
+);
+
+pub fn test_processes() {
+    use crate::system::process::{self, ExitStatus};
+    extern "C" {
+        static test_process_exit: u8;
+        static test_process_kernel_access: u8;
+        static test_process_end: u8;
+    }
+    // SAFETY: the symbols are in the same section, in this order
+    let (exit, kernel_access) = unsafe {
+        let exit = &raw const test_process_exit;
+        let kernel_access = &raw const test_process_kernel_access;
+        let end = &raw const test_process_end;
+        (
+            slice::from_raw_parts(exit, kernel_access.offset_from(exit) as usize),
+            slice::from_raw_parts(kernel_access, end.offset_from(kernel_access) as usize),
+        )
+    };
+    println_log!("Testing processes...");
+    let status = process::spawn(exit).expect("the process starts").wait();
+    assert_eq!(status, ExitStatus::Exited(7));
+    let status = process::spawn(kernel_access).expect("the process starts").wait();
+    assert_eq!(status, ExitStatus::Killed);
+    println_log!("Processes OK");
+}
+
+/// Needs a card, e.g. `-drive if=sd,format=raw,file=sd.img` in QEMU. Writes every block back
+/// unchanged.
+pub fn test_sd() {
+    use crate::system::hal::sd;
+    use mystd::block::{BlockDevice, BLOCK_SIZE};
+    println_log!("Testing the SD card...");
+    sd::init().expect("the card is identified");
+    sd::with_card(|card| {
+        println_log!("{}, {} blocks", card.cid(), card.block_count());
+        let mut blocks = [0_u8; 20 * BLOCK_SIZE];
+        card.read_blocks(0, &mut blocks).expect("multi block read");
+        for (i, block) in blocks.chunks(BLOCK_SIZE).enumerate() {
+            let mut single = [0_u8; BLOCK_SIZE];
+            card.read_blocks(i as u64, &mut single).expect("single block read");
+            assert_eq!(block, &single[..]);
+        }
+        card.write_blocks(0, &blocks).expect("multi block write");
+        card.write_blocks(3, &blocks[3 * BLOCK_SIZE..4 * BLOCK_SIZE]).expect("single block write");
+        let mut again = [0_u8; 20 * BLOCK_SIZE];
+        card.read_blocks(0, &mut again).expect("multi block read");
+        assert_eq!(blocks, again);
+    })
+    .expect("the card is available");
+    println_log!("SD card OK");
+}
+
+pub fn test_fat() {
+    use crate::system::hal::sd;
+    use mystd::fat;
+    use mystd::io::{Read, Seek, SeekFrom, Write};
+    println_log!("Testing the FAT file system on the SD card...");
+    sd::init().expect("the card is identified");
+    sd::with_card(|card| {
+        let mut fs = fat::mount(card).expect("a FAT partition");
+        println_log!("{:?}", fs.layout());
+        for entry in fs.read_dir("/").expect("the root directory") {
+            let entry = entry.expect("a directory entry");
+            println_log!("{:>10} {}", entry.size, entry.name());
+        }
+        let mut config = [0_u8; 512];
+        if let Ok(mut file) = fs.open("/config.txt") {
+            let len = file.read(&mut config).expect("config.txt is read").to_usize();
+            println_log!("{}", core::str::from_utf8(&config[..len]).unwrap_or("(not UTF-8)"));
+        }
+        let mut file = fs.create("/picrust test.txt").expect("the file is created");
+        file.write_all(b"written by picrust").expect("the file is written");
+        file.seek(SeekFrom::Start(11)).expect("seek");
+        let mut name = [0_u8; 7];
+        file.read_exact(&mut name).expect("the file is read back");
+        assert_eq!(&name, b"picrust");
+    })
+    .expect("the card is available");
+    println_log!("FAT file system OK");
+}
+
+pub fn test_gpio() {
+    use crate::peripherals::gpio::{Event, GpioError, Level, Pin, PinFunction};
+    use core::sync::atomic::{AtomicUsize, Ordering};
+    static RISING_EDGES: AtomicUsize = AtomicUsize::new(0);
+    println_log!("Testing GPIO...");
+    assert!(matches!(
+        Pin::claim(14, "test"),
+        Err(GpioError::Claimed { pin: 14, owner: "UART0" })
+    ));
+    // an output pin sees its own edges
+    let pin = Pin::claim(21, "test").expect("pin 21 is free");
+    assert!(matches!(Pin::claim(21, "other"), Err(GpioError::Claimed { .. })));
+    pin.set_function(PinFunction::Output);
+    pin.set_low();
+    pin.clear_event();
+    pin.enable_event(Event::RisingEdge);
+    pin.on_event(|_| {
+        RISING_EDGES.fetch_add(1, Ordering::Relaxed);
+    })
+    .expect("the callback is registered");
+    for _ in 0..3 {
+        pin.set_high();
+        thread::sleep(core::time::Duration::from_millis(1));
+        assert_eq!(pin.level(), Level::High);
+        pin.set_low();
+        thread::sleep(core::time::Duration::from_millis(1));
+        assert_eq!(pin.level(), Level::Low);
+    }
+    println_log!("{} rising edges", RISING_EDGES.load(Ordering::Relaxed));
+    pin.set_function(PinFunction::Input);
+    drop(pin);
+    let pin = Pin::claim(21, "other").expect("pin 21 was given back");
+    assert!(!pin.event_detected());
+    println_log!("GPIO OK");
+}
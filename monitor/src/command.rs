@@ -1,149 +1,1121 @@
+use crate::disasm;
+use crate::extension::{self, ExtensionValues, MonitorExtension};
+use crate::registers::{RegisterError, RegisterProvider};
+use crate::symbols::SymbolTable;
 use crate::token;
 use crate::writer;
 use mystd::format;
+use mystd::protocols::xmodem;
+
+pub const MORE_PROMPT: &[u8] = b"-- more -- (Q to quit)";
+
+/// Copies `src` to `dst` with the DMA engine.
+pub type DmaCopy = fn(&[u8], &mut [u8]);
 
 pub struct CommandContext {
     pub last_address: usize,
     pub length: usize,
     pub cursor_type: CursorType,
+    /// Number of rows a range dump prints before pausing.
+    pub page_rows: usize,
+    /// The remainder of a range dump that is waiting at the "more" prompt.
+    pub more: Option<PendingDump>,
+    /// Symbols that can be used in place of addresses, and annotate printed addresses.
+    pub symbols: SymbolTable<'static>,
+    /// Copies with the DMA engine, used by the `M` command.
+    pub dma_copy: Option<DmaCopy>,
+    /// Registers the `S` command can show and set.
+    pub registers: Option<&'static dyn RegisterProvider>,
+    /// Commands added by the kernel, started by their name.
+    pub extensions: &'static [&'static dyn MonitorExtension],
 }
 
 impl Default for CommandContext {
     fn default() -> Self {
-        Self { last_address: 0, length: 8, cursor_type: CursorType::U64 }
+        Self {
+            last_address: 0,
+            length: 8,
+            cursor_type: CursorType::U64,
+            page_rows: 16,
+            more: None,
+            symbols: SymbolTable::empty(),
+            dma_copy: None,
+            registers: None,
+            extensions: &[],
+        }
     }
 }
 
+#[derive(Clone, Copy)]
+pub struct PendingDump {
+    /// Address of the next row to print.
+    pub row: usize,
+    /// Last address of the range (inclusive).
+    pub end: usize,
+    /// Address the cursor is rendered at.
+    pub cursor: usize,
+}
+
+/// The forms of the commands that start with an address, with their arguments and what they do.
+pub const ADDRESS_FORMS: &[(&[u8], &[u8])] = &[
+    (b"ADDR", b"show memory"),
+    (b"[START].END", b"show a range"),
+    (b"[ADDR]: VALUES", b"store values at the cursor width"),
+    (b"+ -", b"change the cursor width, on an empty line"),
+];
+
+/// The letters that start a command, their arguments and what they do. Tab on an empty line lists
+/// them, `?` lists them with their arguments.
+pub const COMMAND_LETTERS: &[(u8, &[u8], &[u8])] = &[
+    (b'L', b"[ADDR]", b"list instructions"),
+    (b'R', b"ADDR [X0 X1 X2 X3]", b"run a function"),
+    (b'G', b"ADDR [X0 X1 X2 X3]", b"go, same as R"),
+    (b'P', b"START.END VALUES", b"fill a range with a pattern"),
+    (b'T', b"START.END DEST", b"transfer (copy) a range"),
+    (b'M', b"START.END DEST", b"move (copy) a range with the DMA engine"),
+    (b'V', b"START.END OTHER", b"verify (compare) two ranges"),
+    (b'H', b"START.END VALUES", b"hunt (search) a range for a pattern"),
+    (b'S', b"[NAME[=VALUE]]", b"show or set a system register"),
+    (b'X', b"ADDR [G]", b"receive an XMODEM upload, and go"),
+];
+
+/// Width of the arguments column of the `?` listing.
+const HELP_COLUMN: usize = 22;
+
+/// Largest upload the `X` command accepts.
+pub const MAX_UPLOAD_LEN: usize = 0x100_0000;
+
+/// Maximum number of values a single store command (`ADDR: BB BB ...`) can hold.
+pub const MAX_STORE_VALUES: usize = 16;
+
 pub enum Command {
     DoNothing,
+    /// Calls the function at `start` with `params` in x0..x3 and prints the registers when it returns.
     ExecuteMemory {
         start: usize,
-        params: (usize, usize, usize, usize),
+        params: [usize; 4],
     },
     PrintMemory {
         start: usize,
     },
     PrintMemoryContinue,
+    /// Prints all rows from `start` to `end` (inclusive).
+    ///
+    /// If `start` is `None` the range begins at the last address.
+    PrintRange {
+        start: Option<usize>,
+        end: usize,
+    },
+    /// Writes `values` to consecutive memory locations, each value at the width of the current cursor.
+    ///
+    /// If `start` is `None` the store continues at the last address.
+    StoreMemory {
+        start: Option<usize>,
+        values: [usize; MAX_STORE_VALUES],
+        count: usize,
+    },
+    /// Disassembles `context.page_rows` instructions.
+    ///
+    /// If `start` is `None` the listing continues at the last address.
+    Disassemble {
+        start: Option<usize>,
+    },
+    /// Fills `start..=end` with the repeated `pattern`, each value at the width of the current cursor.
+    FillMemory {
+        start: usize,
+        end: usize,
+        pattern: [usize; MAX_STORE_VALUES],
+        count: usize,
+    },
+    /// Copies `start..=end` to `dest`, with the DMA engine if `dma` is set.
+    CopyMemory {
+        start: usize,
+        end: usize,
+        dest: usize,
+        dma: bool,
+    },
+    /// Lists the bytes that differ between `start..=end` and the range of the same length at `other`.
+    CompareMemory {
+        start: usize,
+        end: usize,
+        other: usize,
+    },
+    /// Lists the addresses in `start..=end` where `pattern` occurs, each value at the width of the
+    /// current cursor.
+    SearchMemory {
+        start: usize,
+        end: usize,
+        pattern: [usize; MAX_STORE_VALUES],
+        count: usize,
+    },
+    /// Lists the names of the registers `S` can show.
+    ListRegisters,
+    /// Prints register `index` of the register provider, after writing `value` to it if set.
+    SystemRegister {
+        index: usize,
+        value: Option<usize>,
+    },
+    /// Receives an XMODEM upload to `start`, and calls it if `go` is set.
+    Receive {
+        start: usize,
+        go: bool,
+    },
+    /// Lists the commands with their arguments.
+    Help,
+    /// Runs an extension command with the values its parser returned.
+    Extension {
+        extension: &'static dyn MonitorExtension,
+        values: ExtensionValues,
+    },
 }
 
 pub enum CommandParseError {
     IllegalToken { position: usize },
+    MissingValue { position: usize },
+    TooManyValues { position: usize },
+    InvalidRange { position: usize },
+    UnknownSymbol { position: usize },
+    UnknownRegister { position: usize },
+    /// A value an extension command doesn't accept, e.g. a pin number that doesn't exist.
+    InvalidArgument { position: usize },
 }
 
 
 impl Command {
-    pub fn parse(c_str: &[u8]) -> Result<Command, CommandParseError> {
-        let tokenizer = token::Tokenizer::new(c_str, true);
-        for (token_nr, token) in tokenizer.enumerate() {
-            match (token_nr, token) {
-                (
-                    0,
-                    token::Token {
+    pub fn parse(c_str: &[u8], context: &CommandContext) -> Result<Command, CommandParseError> {
+        if let Some(result) = extension::parse(c_str, &context.symbols, context.extensions) {
+            return result.map(|(extension, values)| Command::Extension { extension, values });
+        }
+        let mut tokenizer =
+            token::Resolver::new(token::Tokenizer::new(c_str, true), &context.symbols);
+        let Some(first) = tokenizer.next() else {
+            return Ok(Command::PrintMemoryContinue);
+        };
+        match first.token_type {
+            token::TokenType::USize(start) => match tokenizer.next() {
+                None => Ok(Command::PrintMemory { start }),
+                Some(token::Token {
+                    token_type: token::TokenType::Colon,
+                    ..
+                }) => Self::parse_store(Some(start), tokenizer, c_str.len()),
+                Some(token::Token {
+                    token_type: token::TokenType::Dot,
+                    ..
+                }) => Self::parse_range(Some(start), tokenizer, c_str.len()),
+                Some(t) => Err(Self::unexpected(t)),
+            },
+            token::TokenType::Colon => Self::parse_store(None, tokenizer, c_str.len()),
+            token::TokenType::Dot => Self::parse_range(None, tokenizer, c_str.len()),
+            token::TokenType::Question => match tokenizer.next() {
+                None => Ok(Command::Help),
+                Some(t) => Err(Self::unexpected(t)),
+            },
+            token::TokenType::SingleLetter(b'L') => Self::parse_disassemble(tokenizer),
+            token::TokenType::SingleLetter(b'R' | b'G') => {
+                Self::parse_execute(tokenizer, c_str.len())
+            }
+            token::TokenType::SingleLetter(b'X') => Self::parse_receive(tokenizer, c_str.len()),
+            token::TokenType::SingleLetter(b'S') => {
+                Self::parse_register(tokenizer, c_str, context.registers)
+            }
+            token::TokenType::SingleLetter(letter @ (b'P' | b'H')) => {
+                let (start, end) = Self::parse_range_argument(&mut tokenizer, first.end)?;
+                let (pattern, count) = Self::parse_values(tokenizer, c_str.len())?;
+                Ok(if letter == b'P' {
+                    Command::FillMemory {
+                        start,
+                        end,
+                        pattern,
+                        count,
+                    }
+                } else {
+                    Command::SearchMemory {
+                        start,
+                        end,
+                        pattern,
+                        count,
+                    }
+                })
+            }
+            token::TokenType::SingleLetter(letter @ (b'T' | b'M' | b'V')) => {
+                let (start, end) = Self::parse_range_argument(&mut tokenizer, first.end)?;
+                let target = match tokenizer.next() {
+                    Some(token::Token {
                         token_type: token::TokenType::USize(value),
                         ..
-                    },
-                ) => return Ok(Command::PrintMemory { start: value }),
-                (_, t) => return Err(CommandParseError::IllegalToken { position: t.start }),
-                _ => return Ok(Command::DoNothing),
+                    }) => value,
+                    Some(t) => return Err(Self::unexpected(t)),
+                    None => return Err(CommandParseError::MissingValue { position: c_str.len() }),
+                };
+                if let Some(t) = tokenizer.next() {
+                    return Err(Self::unexpected(t));
+                }
+                Ok(if letter == b'V' {
+                    Command::CompareMemory {
+                        start,
+                        end,
+                        other: target,
+                    }
+                } else {
+                    Command::CopyMemory {
+                        start,
+                        end,
+                        dest: target,
+                        dma: letter == b'M',
+                    }
+                })
+            }
+            _ => Err(Self::unexpected(first)),
+        }
+    }
+
+    fn unexpected(token: token::Token) -> CommandParseError {
+        match token.token_type {
+            token::TokenType::Symbol => CommandParseError::UnknownSymbol {
+                position: token.start,
+            },
+            _ => CommandParseError::IllegalToken {
+                position: token.start,
+            },
+        }
+    }
+
+    fn parse_range(
+        start: Option<usize>,
+        mut tokenizer: token::Resolver,
+        end: usize,
+    ) -> Result<Command, CommandParseError> {
+        let range_end = match tokenizer.next() {
+            Some(token::Token {
+                token_type: token::TokenType::USize(value),
+                start: position,
+                ..
+            }) => {
+                if start.is_some_and(|start| start > value) {
+                    return Err(CommandParseError::InvalidRange { position });
+                }
+                value
+            }
+            Some(t) => return Err(Self::unexpected(t)),
+            None => return Err(CommandParseError::MissingValue { position: end }),
+        };
+        if let Some(t) = tokenizer.next() {
+            return Err(Self::unexpected(t));
+        }
+        Ok(Command::PrintRange {
+            start,
+            end: range_end,
+        })
+    }
+
+    /// Parses `X ADDR` or `X ADDR G`.
+    fn parse_receive(mut tokenizer: token::Resolver, end: usize) -> Result<Command, CommandParseError> {
+        let start = match tokenizer.next() {
+            Some(token::Token {
+                token_type: token::TokenType::USize(value),
+                ..
+            }) => value,
+            Some(t) => return Err(Self::unexpected(t)),
+            None => return Err(CommandParseError::MissingValue { position: end }),
+        };
+        let go = match tokenizer.next() {
+            None => false,
+            Some(token::Token {
+                token_type: token::TokenType::SingleLetter(b'G'),
+                ..
+            }) => true,
+            Some(t) => return Err(Self::unexpected(t)),
+        };
+        if let Some(t) = tokenizer.next() {
+            return Err(Self::unexpected(t));
+        }
+        Ok(Command::Receive { start, go })
+    }
+
+    /// Parses `S`, `S NAME` or `S NAME=VALUE`.
+    fn parse_register(
+        mut tokenizer: token::Resolver,
+        c_str: &[u8],
+        registers: Option<&dyn RegisterProvider>,
+    ) -> Result<Command, CommandParseError> {
+        let Some(name) = tokenizer.next() else {
+            return Ok(Command::ListRegisters);
+        };
+        // take the name as typed, even if it resolved to a symbol
+        let index = registers
+            .and_then(|registers| registers.find(&c_str[name.start..name.end]))
+            .ok_or(CommandParseError::UnknownRegister {
+                position: name.start,
+            })?;
+        let value = match tokenizer.next() {
+            None => None,
+            Some(token::Token {
+                token_type: token::TokenType::Equals,
+                end,
+                ..
+            }) => match tokenizer.next() {
+                Some(token::Token {
+                    token_type: token::TokenType::USize(value),
+                    ..
+                }) => Some(value),
+                Some(t) => return Err(Self::unexpected(t)),
+                None => return Err(CommandParseError::MissingValue { position: end }),
+            },
+            Some(t) => return Err(Self::unexpected(t)),
+        };
+        if let Some(t) = tokenizer.next() {
+            return Err(Self::unexpected(t));
+        }
+        Ok(Command::SystemRegister { index, value })
+    }
+
+    /// Parses the `START.END` argument of the block commands, `position` is where it should begin.
+    fn parse_range_argument(
+        tokenizer: &mut token::Resolver,
+        position: usize,
+    ) -> Result<(usize, usize), CommandParseError> {
+        let (start, position) = match tokenizer.next() {
+            Some(token::Token {
+                token_type: token::TokenType::USize(value),
+                end,
+                ..
+            }) => (value, end),
+            Some(t) => return Err(Self::unexpected(t)),
+            None => return Err(CommandParseError::MissingValue { position }),
+        };
+        match tokenizer.next() {
+            Some(token::Token {
+                token_type: token::TokenType::Dot,
+                ..
+            }) => {}
+            Some(t) => return Err(Self::unexpected(t)),
+            None => return Err(CommandParseError::MissingValue { position }),
+        }
+        let end = match tokenizer.next() {
+            Some(token::Token {
+                token_type: token::TokenType::USize(value),
+                start: position,
+                ..
+            }) if value < start => return Err(CommandParseError::InvalidRange { position }),
+            Some(token::Token {
+                token_type: token::TokenType::USize(value),
+                ..
+            }) => value,
+            Some(t) => return Err(Self::unexpected(t)),
+            None => return Err(CommandParseError::MissingValue { position }),
+        };
+        Ok((start, end))
+    }
+
+    fn parse_disassemble(mut tokenizer: token::Resolver) -> Result<Command, CommandParseError> {
+        let start = match tokenizer.next() {
+            None => return Ok(Command::Disassemble { start: None }),
+            Some(token::Token {
+                token_type: token::TokenType::USize(value),
+                ..
+            }) => value,
+            Some(t) => return Err(Self::unexpected(t)),
+        };
+        if let Some(t) = tokenizer.next() {
+            return Err(Self::unexpected(t));
+        }
+        Ok(Command::Disassemble { start: Some(start) })
+    }
+
+    fn parse_execute(
+        tokenizer: token::Resolver,
+        end: usize,
+    ) -> Result<Command, CommandParseError> {
+        let mut start = None;
+        let mut params = [0; 4];
+        let mut count = 0;
+        for t in tokenizer {
+            let token::TokenType::USize(value) = t.token_type else {
+                return Err(Self::unexpected(t));
+            };
+            if start.is_none() {
+                start = Some(value);
+                continue;
             }
+            let Some(slot) = params.get_mut(count) else {
+                return Err(CommandParseError::TooManyValues { position: t.start });
+            };
+            *slot = value;
+            count += 1;
+        }
+        match start {
+            Some(start) => Ok(Command::ExecuteMemory { start, params }),
+            None => Err(CommandParseError::MissingValue { position: end }),
         }
-        return Ok(Command::PrintMemoryContinue);
     }
 
-    pub fn run<Out: mystd::io::Write>(&self, out: &mut writer::Writer<Out>, context: &mut CommandContext) {
+    fn parse_store(
+        start: Option<usize>,
+        tokenizer: token::Resolver,
+        end: usize,
+    ) -> Result<Command, CommandParseError> {
+        let (values, count) = Self::parse_values(tokenizer, end)?;
+        Ok(Command::StoreMemory {
+            start,
+            values,
+            count,
+        })
+    }
+
+    /// Parses the remaining tokens as at least one and at most `MAX_STORE_VALUES` values.
+    fn parse_values(
+        tokenizer: token::Resolver,
+        end: usize,
+    ) -> Result<([usize; MAX_STORE_VALUES], usize), CommandParseError> {
+        let mut values = [0; MAX_STORE_VALUES];
+        let mut count = 0;
+        for t in tokenizer {
+            match t.token_type {
+                token::TokenType::USize(value) => {
+                    let Some(slot) = values.get_mut(count) else {
+                        return Err(CommandParseError::TooManyValues { position: t.start });
+                    };
+                    *slot = value;
+                    count += 1;
+                }
+                _ => return Err(Self::unexpected(t)),
+            }
+        }
+        if count == 0 {
+            return Err(CommandParseError::MissingValue { position: end });
+        }
+        Ok((values, count))
+    }
+
+    /// Runs the command, `input` is only read by commands that receive data, like `X`.
+    pub fn run<In: mystd::io::Read, Out: mystd::io::Write>(
+        &self,
+        input: &mut In,
+        out: &mut writer::Writer<Out>,
+        context: &mut CommandContext,
+    ) {
         match self {
             Command::DoNothing => {}
-            Command::ExecuteMemory { start, params } => unsafe {
-                core::arch::asm!(
-                    "mov x0, {1}",
-                    "blr {0}",
-                    in(reg) start,
-                    in(reg) params.0,
-                );
-            },
+            #[cfg(target_arch = "aarch64")]
+            Command::ExecuteMemory { start, params } => {
+                let registers = unsafe { call(*start, *params) };
+                registers.print(out);
+            }
+            #[cfg(not(target_arch = "aarch64"))]
+            Command::ExecuteMemory { .. } => out.puts(b"! Not Supported"),
             Command::PrintMemory { start } => {
-                context.last_address =
-                    self.print_memory(out, *start, context.length, context.cursor_type);
+                context.last_address = self.print_memory(
+                    out,
+                    *start,
+                    1,
+                    context.length,
+                    context.cursor_type,
+                    &context.symbols,
+                );
             }
             Command::PrintMemoryContinue => {
                 context.last_address = self.print_memory(
                     out,
                     context.last_address,
+                    1,
+                    context.length,
+                    context.cursor_type,
+                    &context.symbols,
+                );
+            }
+            Command::PrintRange { start, end } => {
+                let start = start.unwrap_or(context.last_address);
+                if start > *end {
+                    out.puts(b"! Invalid Range");
+                    return;
+                }
+                let column_align = context.cursor_type.align_of().max(context.length);
+                context.more = Some(PendingDump {
+                    row: start & !(column_align - 1),
+                    end: *end,
+                    cursor: start,
+                });
+                Self::print_more(out, context);
+            }
+            Command::StoreMemory {
+                start,
+                values,
+                count,
+            } => {
+                let start = start.unwrap_or(context.last_address);
+                let cursor = context.cursor_type;
+                if start % cursor.align_of() != 0 {
+                    out.puts(b"! Unaligned");
+                    return;
+                }
+                for (i, value) in values[..*count].iter().enumerate() {
+                    unsafe { cursor.write_volatile(start + i * cursor.byte_len(), *value) };
+                }
+                // echo what is in memory now, so the change can be confirmed
+                context.last_address = self.print_memory(
+                    out,
+                    start,
+                    *count,
+                    context.length,
+                    cursor,
+                    &context.symbols,
+                );
+            }
+            Command::Disassemble { start } => {
+                // instructions are always word aligned
+                let start = start.unwrap_or(context.last_address) & !3;
+                context.last_address = Self::print_instructions(
+                    out,
+                    start,
+                    context.page_rows.max(1),
+                    &context.symbols,
+                );
+            }
+            Command::FillMemory {
+                start,
+                end,
+                pattern,
+                count,
+            } => {
+                let cursor = context.cursor_type;
+                if start % cursor.align_of() != 0 {
+                    out.puts(b"! Unaligned");
+                    return;
+                }
+                let len = cursor.byte_len();
+                let mut address = *start;
+                for value in pattern[..*count].iter().cycle() {
+                    // only write values that fit into the range completely
+                    if address > *end || end - address < len - 1 {
+                        break;
+                    }
+                    unsafe { cursor.write_volatile(address, *value) };
+                    address += len;
+                }
+                context.last_address =
+                    self.print_memory(out, *start, 1, context.length, cursor, &context.symbols);
+            }
+            Command::CopyMemory {
+                start,
+                end,
+                dest,
+                dma,
+            } => {
+                // the ranges may end at the top of the address space, but not beyond
+                let Some((len, dest_end)) = (end.checked_sub(*start))
+                    .and_then(|last| Some((last.checked_add(1)?, dest.checked_add(last)?)))
+                else {
+                    out.puts(b"! Invalid Range");
+                    return;
+                };
+                if *dma {
+                    let Some(dma_copy) = context.dma_copy else {
+                        out.puts(b"! Not Supported");
+                        return;
+                    };
+                    if *start <= dest_end && *dest <= *end {
+                        out.puts(b"! Overlapping");
+                        return;
+                    }
+                    let (src, dst) = unsafe {
+                        (
+                            core::slice::from_raw_parts(*start as *const u8, len),
+                            core::slice::from_raw_parts_mut(*dest as *mut u8, len),
+                        )
+                    };
+                    dma_copy(src, dst);
+                } else {
+                    unsafe { core::ptr::copy(*start as *const u8, *dest as *mut u8, len) };
+                }
+                context.last_address = self.print_memory(
+                    out,
+                    *dest,
+                    1,
                     context.length,
                     context.cursor_type,
+                    &context.symbols,
                 );
             }
+            Command::CompareMemory { start, end, other } => {
+                // the other range may end at the top of the address space, but not beyond
+                if other.checked_add(end - start).is_none() {
+                    out.puts(b"! Invalid Range");
+                    return;
+                }
+                let mut differences = 0;
+                for offset in 0..=end - start {
+                    let (a, b) = unsafe {
+                        (
+                            ((start + offset) as *const u8).read_volatile(),
+                            ((other + offset) as *const u8).read_volatile(),
+                        )
+                    };
+                    if a == b {
+                        continue;
+                    }
+                    differences += 1;
+                    if differences <= context.page_rows {
+                        Self::print_address(out, start + offset);
+                        out.hex(a, None);
+                        out.putc(b' ');
+                        Self::print_address(out, other + offset);
+                        out.hex(b, None);
+                        out.newline();
+                    }
+                }
+                Self::print_count(out, b"differences: ", differences);
+            }
+            Command::SearchMemory {
+                start,
+                end,
+                pattern,
+                count,
+            } => {
+                let cursor = context.cursor_type;
+                if start % cursor.align_of() != 0 {
+                    out.puts(b"! Unaligned");
+                    return;
+                }
+                let mut bytes = [0_u8; MAX_STORE_VALUES * 16];
+                let len = cursor.byte_len();
+                for (value, chunk) in pattern[..*count].iter().zip(bytes.chunks_mut(len)) {
+                    let value = (*value as u128).to_le_bytes();
+                    chunk.copy_from_slice(&value[..len]);
+                }
+                let bytes = &bytes[..count * len];
+
+                let mut matches = 0;
+                let mut address = *start;
+                while address <= *end && end - address >= bytes.len() - 1 {
+                    let found = bytes.iter().enumerate().all(|(i, byte)| {
+                        unsafe { ((address + i) as *const u8).read_volatile() == *byte }
+                    });
+                    if found {
+                        matches += 1;
+                        if matches <= context.page_rows {
+                            out.hex_usize(
+                                address,
+                                Some(format::Formatting {
+                                    leading_zeros: format::LeadingZeros::Space,
+                                    ..format::Formatting::default()
+                                }),
+                            );
+                            Self::print_symbol(out, &context.symbols, address);
+                            out.newline();
+                        }
+                    }
+                    address += cursor.align_of();
+                }
+                Self::print_count(out, b"matches: ", matches);
+            }
+            Command::ListRegisters => {
+                let Some(registers) = context.registers else {
+                    out.puts(b"! Not Supported");
+                    return;
+                };
+                for i in 0..registers.count() {
+                    if i != 0 {
+                        out.putc(if i % 6 == 0 { b'\n' } else { b' ' });
+                    }
+                    out.puts(registers.name(i).as_bytes());
+                }
+            }
+            Command::SystemRegister { index, value } => {
+                let Some(registers) = context.registers else {
+                    out.puts(b"! Not Supported");
+                    return;
+                };
+                let result = match value {
+                    Some(value) => registers.write(*index, *value as u64),
+                    None => Ok(()),
+                }
+                .and_then(|_| registers.read(*index, out));
+                match result {
+                    Ok(()) => {}
+                    Err(RegisterError::ReadOnly) => out.puts(b"! Read Only"),
+                    Err(RegisterError::Inaccessible) => out.puts(b"! Not Accessible"),
+                }
+            }
+            Command::Receive { start, go } => {
+                out.puts(b"waiting for XMODEM upload, Ctrl-X to cancel");
+                out.newline();
+                let dest =
+                    unsafe { core::slice::from_raw_parts_mut(*start as *mut u8, MAX_UPLOAD_LEN) };
+                let len = match xmodem::receive(input, out.output(), dest) {
+                    Ok(len) => len,
+                    Err(error) => {
+                        out.puts(match error {
+                            xmodem::XmodemError::Cancelled => b"! Cancelled",
+                            xmodem::XmodemError::TooManyErrors => b"! Too Many Errors",
+                            xmodem::XmodemError::OutOfSequence => b"! Out Of Sequence",
+                            xmodem::XmodemError::TooLarge => b"! Too Large",
+                            xmodem::XmodemError::Io(_) => b"! I/O Error",
+                        });
+                        return;
+                    }
+                };
+                #[cfg(target_arch = "aarch64")]
+                unsafe {
+                    sync_instructions(*start, len)
+                };
+                context.last_address = *start;
+                Self::print_count(out, b"received: ", len);
+                if *go {
+                    out.newline();
+                    Command::ExecuteMemory {
+                        start: *start,
+                        params: [0; 4],
+                    }
+                    .run(input, out, context);
+                }
+            }
+            Command::Help => Self::print_help(out, context.extensions),
+            Command::Extension { extension, values } => extension.run(values, out),
+        }
+    }
+
+    /// Prints the commands with their arguments, one per line.
+    fn print_help<Out: mystd::io::Write>(
+        out: &mut writer::Writer<Out>,
+        extensions: &[&dyn MonitorExtension],
+    ) {
+        for (arguments, description) in ADDRESS_FORMS {
+            Self::print_help_line(out, &[], arguments, description);
+        }
+        for (letter, arguments, description) in COMMAND_LETTERS {
+            Self::print_help_line(out, &[*letter, b' '], arguments, description);
+        }
+        Self::print_help_line(out, &[], b"?", b"list the commands");
+        for extension in extensions {
+            // the help text is `ARGUMENTS  description`, the description goes into its column
+            let help = extension.help().as_bytes();
+            let (arguments, description) = help
+                .windows(2)
+                .position(|pair| pair == b"  ")
+                .map_or((&[][..], help), |i| (&help[..i], help[i..].trim_ascii_start()));
+            out.newline();
+            out.puts(extension.name().as_bytes());
+            out.putc(b' ');
+            Self::print_help_columns(out, extension.name().len() + 1, arguments, description);
+        }
+    }
+
+    fn print_help_line<Out: mystd::io::Write>(
+        out: &mut writer::Writer<Out>,
+        command: &[u8],
+        arguments: &[u8],
+        description: &[u8],
+    ) {
+        out.newline();
+        out.puts(command);
+        Self::print_help_columns(out, command.len(), arguments, description);
+    }
+
+    /// Prints `arguments` and `description` aligned to the column after `indent` characters.
+    fn print_help_columns<Out: mystd::io::Write>(
+        out: &mut writer::Writer<Out>,
+        indent: usize,
+        arguments: &[u8],
+        description: &[u8],
+    ) {
+        out.puts(arguments);
+        let width = indent + arguments.len();
+        out.putc_repeat(b' ', HELP_COLUMN.saturating_sub(width).max(1));
+        out.puts(description);
+    }
+
+    /// Prints `count` instructions starting at `address`, returns the address following the last one.
+    fn print_instructions<Out: mystd::io::Write>(
+        out: &mut writer::Writer<Out>,
+        mut address: usize,
+        count: usize,
+        symbols: &SymbolTable,
+    ) -> usize {
+        use core::fmt::Write;
+        for i in 0..count {
+            if i != 0 {
+                out.newline();
+            }
+            // label the first instruction of a symbol
+            if let Some((symbol, 0)) = symbols.lookup(address) {
+                out.puts(symbol.name);
+                out.putc(b':');
+                out.newline();
+            }
+            let instruction = unsafe { disasm::Instruction::read(address) };
+            out.hex_usize(
+                address,
+                Some(format::Formatting {
+                    leading_zeros: format::LeadingZeros::Space,
+                    ..format::Formatting::default()
+                }),
+            );
+            out.puts(b": ");
+            for byte in instruction.word.to_be_bytes() {
+                out.hex(byte, None);
+            }
+            out.puts(b"  ");
+            // the writer itself never fails
+            let _ = write!(out, "{}", instruction);
+            if let Some(target) = instruction.target() {
+                Self::print_symbol(out, symbols, target);
+            }
+            address += 4;
         }
+        address
     }
 
     fn print_memory<Out: mystd::io::Write>(
         &self,
         out: &mut writer::Writer<Out>,
         address: usize,
+        count: usize,
         length: usize,
         cursor: CursorType,
+        symbols: &SymbolTable,
     ) -> usize {
         // calculate start and end of the column we print
         let cur_start = address;
-        let cur_end = address + cursor.byte_len();
+        let cur_end = address + cursor.byte_len() * count;
         let column_align = cursor.align_of().max(length);
         let mut start = address & !(column_align - 1);
-        let mut end = start + length;
         loop {
-            // print the address of the column first
-            out.hex_usize(
-                start,
-                Some(format::Formatting {
-                    leading_zeros: format::LeadingZeros::Space,
-                    ..format::Formatting::default()
-                }),
-            );
-            out.putc(b':');
+            Self::print_row(out, start, length, (cur_start, cur_end), symbols);
+            start += length;
+            if cur_end <= start {
+                break;
+            } else {
+                out.newline();
+            }
+        }
+        cur_end
+    }
 
-            for addr in start..end {
-                if addr == cur_start {
-                    out.putc(b'(');
-                } else if addr == cur_end {
-                    out.putc(b')');
-                } else {
-                    out.putc(b' ');
+    /// Prints the rows of a pending range dump, pausing with a "more" prompt after `context.page_rows` rows.
+    pub fn print_more<Out: mystd::io::Write>(
+        out: &mut writer::Writer<Out>,
+        context: &mut CommandContext,
+    ) {
+        let Some(mut dump) = context.more.take() else {
+            return;
+        };
+        let cur_end = dump.cursor.saturating_add(context.cursor_type.byte_len());
+        for _ in 0..context.page_rows.max(1) {
+            Self::print_row(
+                out,
+                dump.row,
+                context.length,
+                (dump.cursor, cur_end),
+                &context.symbols,
+            );
+            // the last row can end at the top of the address space
+            match dump.row.checked_add(context.length) {
+                Some(row) if row <= dump.end => dump.row = row,
+                _ => {
+                    context.last_address = dump.end.saturating_add(1);
+                    return;
                 }
-                let memvalue = unsafe { (addr as *const u8).read_volatile() };
-                out.hex(memvalue, None);
             }
-            if cur_end == end {
+            out.newline();
+        }
+        out.puts(MORE_PROMPT);
+        context.more = Some(dump);
+    }
+
+    fn print_row<Out: mystd::io::Write>(
+        out: &mut writer::Writer<Out>,
+        start: usize,
+        length: usize,
+        (cur_start, cur_end): (usize, usize),
+        symbols: &SymbolTable,
+    ) {
+        let end = start.saturating_add(length);
+        // print the address of the column first
+        out.hex_usize(
+            start,
+            Some(format::Formatting {
+                leading_zeros: format::LeadingZeros::Space,
+                ..format::Formatting::default()
+            }),
+        );
+        out.putc(b':');
+
+        for addr in start..end {
+            if addr == cur_start {
+                out.putc(b'(');
+            } else if addr == cur_end {
                 out.putc(b')');
             } else {
                 out.putc(b' ');
             }
+            let memvalue = unsafe { (addr as *const u8).read_volatile() };
+            out.hex(memvalue, None);
+        }
+        if cur_end == end {
+            out.putc(b')');
+        } else {
+            out.putc(b' ');
+        }
 
-            for addr in start..end {
-                let memvalue = unsafe { (addr as *const u8).read_volatile() };
-                out.putc(if memvalue.is_ascii_graphic() {
-                    memvalue
-                } else {
-                    b'.'
-                });
-            }
+        for addr in start..end {
+            let memvalue = unsafe { (addr as *const u8).read_volatile() };
+            out.putc(if memvalue.is_ascii_graphic() {
+                memvalue
+            } else {
+                b'.'
+            });
+        }
 
-            for addr in cur_start.max(start)..cur_end.min(end) {
-                let memvalue = unsafe { (addr as *const u8).read_volatile() };
-                out.putc(b' ');
-                out.binary(memvalue, None);
-            }
+        for addr in cur_start.max(start)..cur_end.min(end) {
+            let memvalue = unsafe { (addr as *const u8).read_volatile() };
+            out.putc(b' ');
+            out.binary(memvalue, None);
+        }
 
-            if cur_end <= end {
-                break;
-            } else {
-                end += length;
-                start += length;
+        Self::print_symbol(out, symbols, start);
+    }
+
+    /// Prints `address: ` as a column.
+    fn print_address<Out: mystd::io::Write>(out: &mut writer::Writer<Out>, address: usize) {
+        out.hex_usize(
+            address,
+            Some(format::Formatting {
+                leading_zeros: format::LeadingZeros::Space,
+                ..format::Formatting::default()
+            }),
+        );
+        out.puts(b": ");
+    }
+
+    fn print_count<Out: mystd::io::Write>(out: &mut writer::Writer<Out>, label: &[u8], count: usize) {
+        out.puts(label);
+        out.decimal_usize(
+            count,
+            Some(format::Formatting {
+                leading_zeros: format::LeadingZeros::Skip,
+                ..format::Formatting::default()
+            }),
+        );
+    }
+
+    /// Prints ` <symbol+offset>` if `address` belongs to a symbol.
+    fn print_symbol<Out: mystd::io::Write>(
+        out: &mut writer::Writer<Out>,
+        symbols: &SymbolTable,
+        address: usize,
+    ) {
+        let Some((symbol, offset)) = symbols.lookup(address) else {
+            return;
+        };
+        out.puts(b" <");
+        out.puts(symbol.name);
+        if offset != 0 {
+            out.puts(b"+0x");
+            out.hex_usize(
+                offset,
+                Some(format::Formatting {
+                    leading_zeros: format::LeadingZeros::Skip,
+                    ..format::Formatting::default()
+                }),
+            );
+        }
+        out.putc(b'>');
+    }
+}
+
+/// The registers after a function called by the monitor returned.
+pub struct CallRegisters {
+    /// x0..x3
+    pub results: [usize; 4],
+    /// x19..x29, which the callee must have preserved.
+    pub callee_saved: [usize; 11],
+}
+
+impl CallRegisters {
+    pub fn print<Out: mystd::io::Write>(&self, out: &mut writer::Writer<Out>) {
+        for (i, value) in self.results.iter().enumerate() {
+            Self::print_register(out, i, *value);
+        }
+        for (i, value) in self.callee_saved.iter().enumerate() {
+            if i % 4 == 0 {
                 out.newline();
             }
+            Self::print_register(out, 19 + i, *value);
         }
-        cur_end
     }
+
+    fn print_register<Out: mystd::io::Write>(
+        out: &mut writer::Writer<Out>,
+        number: usize,
+        value: usize,
+    ) {
+        out.puts(if number < 10 { b"  x" } else { b" x" });
+        out.decimal_usize(
+            number,
+            Some(format::Formatting {
+                leading_zeros: format::LeadingZeros::Skip,
+                ..format::Formatting::default()
+            }),
+        );
+        out.putc(b'=');
+        out.hex_usize(value, None);
+    }
+}
+
+/// Makes the instructions written to `start..start + len` visible to instruction fetches.
+///
+/// # Safety
+/// `start..start + len` has to be mapped memory.
+#[cfg(target_arch = "aarch64")]
+unsafe fn sync_instructions(start: usize, len: usize) {
+    let ctr: usize;
+    core::arch::asm!("mrs {}, ctr_el0", out(reg) ctr);
+    // CTR_EL0 has the line sizes as log2 of the number of words
+    let data_line = 4 << ((ctr >> 16) & 0xf);
+    let instruction_line = 4 << (ctr & 0xf);
+    let end = start + len;
+    for address in (start & !(data_line - 1)..end).step_by(data_line) {
+        core::arch::asm!("dc cvau, {}", in(reg) address);
+    }
+    core::arch::asm!("dsb ish");
+    for address in (start & !(instruction_line - 1)..end).step_by(instruction_line) {
+        core::arch::asm!("ic ivau, {}", in(reg) address);
+    }
+    core::arch::asm!("dsb ish", "isb");
 }
 
+/// Calls the function at `entry` with `params` in x0..x3 following the AAPCS64 calling convention.
+///
+/// # Safety
+/// `entry` has to be the address of a function that returns, otherwise the monitor is lost.
+#[cfg(target_arch = "aarch64")]
+unsafe fn call(entry: usize, params: [usize; 4]) -> CallRegisters {
+    let [mut x0, mut x1, mut x2, mut x3] = params;
+    let mut callee_saved = [0_usize; 11];
+    core::arch::asm!(
+        // keep the buffer pointer on the stack, the callee may use any caller-saved register
+        "str {buffer}, [sp, #-16]!",
+        "blr {entry}",
+        "ldr x9, [sp], #16",
+        "stp x19, x20, [x9, #0]",
+        "stp x21, x22, [x9, #16]",
+        "stp x23, x24, [x9, #32]",
+        "stp x25, x26, [x9, #48]",
+        "stp x27, x28, [x9, #64]",
+        "str x29, [x9, #80]",
+        entry = in(reg) entry,
+        buffer = in(reg) callee_saved.as_mut_ptr(),
+        inout("x0") x0,
+        inout("x1") x1,
+        inout("x2") x2,
+        inout("x3") x3,
+        clobber_abi("C"),
+    );
+    CallRegisters {
+        results: [x0, x1, x2, x3],
+        callee_saved,
+    }
+}
 
 #[derive(Clone, Copy)]
 pub enum CursorType {
@@ -175,6 +1147,20 @@ impl CursorType {
         }
     }
 
+    /// Writes `value` to `address`, truncated to the width of the cursor.
+    ///
+    /// # Safety
+    /// `address` must be valid for a write of `self.byte_len()` bytes and aligned to `self.align_of()`.
+    pub unsafe fn write_volatile(&self, address: usize, value: usize) {
+        match self {
+            CursorType::U8 => (address as *mut u8).write_volatile(value as u8),
+            CursorType::U16 => (address as *mut u16).write_volatile(value as u16),
+            CursorType::U32 => (address as *mut u32).write_volatile(value as u32),
+            CursorType::U64 => (address as *mut u64).write_volatile(value as u64),
+            CursorType::U128 => (address as *mut u128).write_volatile(value as u128),
+        }
+    }
+
     pub const fn wider(&self) -> Self {
         match self {
             CursorType::U8 => CursorType::U16,
@@ -195,3 +1181,179 @@ impl CursorType {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    extern crate std;
+    use std::format;
+
+    fn parse(line: &[u8]) -> Result<Command, CommandParseError> {
+        Command::parse(line, &CommandContext::default())
+    }
+
+    /// Parses and runs `line`, returns what the command printed.
+    fn run(line: &str, context: &mut CommandContext) -> std::string::String {
+        let mut output = [0; 256];
+        let mut rest = &mut output[..];
+        let command = Command::parse(line.as_bytes(), context)
+            .unwrap_or_else(|_| panic!("{line} should parse"));
+        command.run(&mut &[][..], &mut writer::Writer::new(&mut rest), context);
+        let written = 256 - rest.len();
+        std::string::String::from_utf8_lossy(&output[..written]).into_owned()
+    }
+
+    fn byte_context() -> CommandContext {
+        CommandContext {
+            cursor_type: CursorType::U8,
+            ..CommandContext::default()
+        }
+    }
+
+    #[test]
+    fn parses_block_commands() {
+        assert!(matches!(
+            parse(b"P 10.1F AA 55"),
+            Ok(Command::FillMemory { start: 0x10, end: 0x1f, count: 2, pattern: [0xaa, 0x55, ..] })
+        ));
+        assert!(matches!(
+            parse(b"T 10.1F 100"),
+            Ok(Command::CopyMemory { start: 0x10, end: 0x1f, dest: 0x100, dma: false })
+        ));
+        assert!(matches!(parse(b"m 10.1F 100"), Ok(Command::CopyMemory { dma: true, .. })));
+        assert!(matches!(
+            parse(b"V 10.1F 100"),
+            Ok(Command::CompareMemory { start: 0x10, end: 0x1f, other: 0x100 })
+        ));
+        assert!(matches!(
+            parse(b"H 0.FFFF 1234"),
+            Ok(Command::SearchMemory { end: 0xffff, count: 1, .. })
+        ));
+
+        assert!(matches!(parse(b"P 10.1F"), Err(CommandParseError::MissingValue { position: 7 })));
+        assert!(matches!(parse(b"T 10"), Err(CommandParseError::MissingValue { position: 4 })));
+        assert!(matches!(parse(b"T 10.1F"), Err(CommandParseError::MissingValue { position: 7 })));
+        assert!(matches!(parse(b"V 10.1F 3 4"), Err(CommandParseError::IllegalToken { position: 10 })));
+        assert!(matches!(parse(b"H 20.10 0"), Err(CommandParseError::InvalidRange { position: 5 })));
+        assert!(matches!(parse(b"T 10:1F 0"), Err(CommandParseError::IllegalToken { position: 4 })));
+    }
+
+    #[test]
+    fn parses_receive() {
+        assert!(matches!(parse(b"X 80000"), Ok(Command::Receive { start: 0x80000, go: false })));
+        assert!(matches!(parse(b"x 80000 g"), Ok(Command::Receive { start: 0x80000, go: true })));
+        assert!(matches!(parse(b"X"), Err(CommandParseError::MissingValue { position: 1 })));
+        assert!(matches!(parse(b"X 80000 R"), Err(CommandParseError::IllegalToken { position: 8 })));
+    }
+
+    #[test]
+    fn parses_help_and_extensions() {
+        assert!(matches!(parse(b" ?"), Ok(Command::Help)));
+        assert!(matches!(parse(b"? L"), Err(CommandParseError::IllegalToken { position: 2 })));
+        // without the extension `add` is a hex value
+        assert!(matches!(parse(b"add 1 2"), Err(CommandParseError::IllegalToken { position: 4 })));
+
+        let mut context = CommandContext {
+            extensions: crate::extension::tests::EXTENSIONS,
+            ..CommandContext::default()
+        };
+        assert!(matches!(
+            Command::parse(b"add 1 2", &context),
+            Ok(Command::Extension { values: [3, ..], .. })
+        ));
+        assert_eq!("7", run("Add 3 4", &mut context));
+    }
+
+    #[test]
+    fn fills_and_copies() {
+        // u64 for the alignment of the word sized fill
+        let mut memory = [0_u64; 2];
+        let base = memory.as_mut_ptr() as usize;
+        let mut context = byte_context();
+
+        run(&format!("P {:x}.{:x} 1 2 3 4", base + 1, base + 6), &mut context);
+        assert_eq!([0, 1, 2, 3, 4, 1, 2, 0], unsafe { (base as *const [u8; 8]).read_volatile() });
+
+        // only whole values are written
+        context.cursor_type = CursorType::U16;
+        run(&format!("P {:x}.{:x} BEEF", base + 8, base + 14), &mut context);
+        assert_eq!([0xef, 0xbe, 0xef, 0xbe, 0xef, 0xbe, 0, 0], unsafe {
+            ((base + 8) as *const [u8; 8]).read_volatile()
+        });
+
+        // overlapping copies work like memmove
+        run(&format!("T {:x}.{:x} {:x}", base, base + 5, base + 2), &mut context);
+        assert_eq!([0, 1, 0, 1, 2, 3, 4, 1], unsafe { (base as *const [u8; 8]).read_volatile() });
+
+        let printed = run(&format!("M {:x}.{:x} 0", base, base + 3), &mut context);
+        assert_eq!("! Not Supported", printed);
+    }
+
+    #[test]
+    fn compares_and_searches() {
+        let memory = [
+            u64::from_le_bytes([1, 2, 3, 4, 1, 2, 3, 4]),
+            u64::from_le_bytes([1, 9, 3, 4, 1, 2, 3, 0]),
+        ];
+        let base = memory.as_ptr() as usize;
+        let mut context = byte_context();
+
+        let printed = run(&format!("V {:x}.{:x} {:x}", base, base + 7, base + 8), &mut context);
+        assert_eq!(
+            format!(
+                "{:16x}: 02 {:16x}: 09\n{:16x}: 04 {:16x}: 00\ndifferences: 2",
+                base + 1,
+                base + 9,
+                base + 7,
+                base + 15
+            ),
+            printed
+        );
+        assert_eq!("! Invalid Range", run("V 0.FF FFFFFFFFFFFFFF80", &mut context));
+
+        let printed = run(&format!("H {:x}.{:x} 1 2 3", base, base + 15), &mut context);
+        assert_eq!(
+            format!("{:16x}\n{:16x}\n{:16x}\nmatches: 3", base, base + 4, base + 12),
+            printed
+        );
+
+        // word sized patterns only match at aligned addresses
+        context.cursor_type = CursorType::U16;
+        let printed = run(&format!("H {:x}.{:x} 403", base, base + 15), &mut context);
+        assert_eq!(
+            format!("{:16x}\n{:16x}\n{:16x}\nmatches: 3", base + 2, base + 6, base + 10),
+            printed
+        );
+    }
+
+    #[test]
+    fn shows_and_sets_registers() {
+        use crate::registers::tests::TestRegisters;
+        let registers = std::boxed::Box::leak(std::boxed::Box::new(TestRegisters {
+            counter: core::cell::Cell::new(7),
+        }));
+        let mut context = CommandContext {
+            registers: Some(registers),
+            ..CommandContext::default()
+        };
+
+        assert!(matches!(
+            Command::parse(b"S count", &context),
+            Err(CommandParseError::UnknownRegister { position: 2 })
+        ));
+        assert!(matches!(
+            Command::parse(b"S counter=", &context),
+            Err(CommandParseError::MissingValue { position: 10 })
+        ));
+        assert!(matches!(
+            Command::parse(b"S id 5", &context),
+            Err(CommandParseError::IllegalToken { position: 5 })
+        ));
+
+        assert_eq!("COUNTER ID", run("S", &mut context));
+        assert_eq!("0x7", run("S counter", &mut context));
+        assert_eq!("0xbeef", run("S COUNTER=BEEF", &mut context));
+        assert_eq!(0xbeef, registers.counter.get());
+        assert_eq!("! Read Only", run("S ID = 1", &mut context));
+    }
+}
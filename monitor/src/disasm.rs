@@ -0,0 +1,1310 @@
+//! A decoder for the A64 instruction set.
+//!
+//! It covers the integer part of the ISA that a kernel like this one is mostly made of: branches,
+//! loads and stores, data processing, system register moves, barriers, hints and the exception
+//! generating instructions. Anything else (SIMD and floating point arithmetic, ...) is printed
+//! as `.inst 0x...`.
+
+use core::fmt;
+
+/// A single A64 instruction. Its `Display` implementation renders it in assembler syntax.
+///
+/// PC-relative operands (branch targets, `adr`, literal loads) are printed as absolute addresses,
+/// so the instruction needs to know where it was read from.
+#[derive(Clone, Copy)]
+pub struct Instruction {
+    pub address: usize,
+    pub word: u32,
+}
+
+impl Instruction {
+    pub const fn new(address: usize, word: u32) -> Self {
+        Self { address, word }
+    }
+
+    /// Reads the instruction located at `address`.
+    ///
+    /// # Safety
+    /// `address` must be valid for a read of 4 bytes and 4 byte aligned.
+    pub unsafe fn read(address: usize) -> Self {
+        Self::new(address, (address as *const u32).read_volatile())
+    }
+
+    /// The address a direct branch, `adr`, `adrp` or literal load refers to.
+    pub fn target(&self) -> Option<usize> {
+        let w = self.word;
+        let offset = if w & 0x7c00_0000 == 0x1400_0000 {
+            sign_extend(bits(w, 25, 0), 26) << 2
+        } else if w & 0x7e00_0000 == 0x3600_0000 {
+            sign_extend(bits(w, 18, 5), 14) << 2
+        } else if w & 0x7e00_0000 == 0x3400_0000
+            || w & 0xff00_0010 == 0x5400_0000
+            || w & 0x3b00_0000 == 0x1800_0000
+        {
+            sign_extend(bits(w, 23, 5), 19) << 2
+        } else if w & 0x9f00_0000 == 0x1000_0000 {
+            sign_extend((bits(w, 23, 5) << 2) | bits(w, 30, 29), 21)
+        } else if w & 0x9f00_0000 == 0x9000_0000 {
+            let offset = sign_extend((bits(w, 23, 5) << 2) | bits(w, 30, 29), 21);
+            return Some((self.address & !0xfff).wrapping_add((offset << 12) as usize));
+        } else {
+            return None;
+        };
+        Some(self.relative(offset))
+    }
+
+    fn relative(&self, offset: i64) -> usize {
+        self.address.wrapping_add(offset as usize)
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let decoded = match bits(self.word, 28, 25) {
+            0b1000 | 0b1001 => self.data_processing_immediate(f),
+            0b1010 | 0b1011 => self.branch_exception_system(f),
+            0b0100 | 0b0110 | 0b1100 | 0b1110 => self.load_store(f),
+            0b0101 | 0b1101 => self.data_processing_register(f),
+            0b0000 if bits(self.word, 31, 16) == 0 => {
+                Some(write!(f, "udf {}", Unsigned(bits(self.word, 15, 0) as u64)))
+            }
+            _ => None,
+        };
+        // the decoders return None before they write anything
+        decoded.unwrap_or_else(|| write!(f, ".inst {:#010x}", self.word))
+    }
+}
+
+/// Result of a decoder, `None` if the encoding is not handled.
+type Decoded = Option<fmt::Result>;
+
+const fn bits(word: u32, high: u32, low: u32) -> u32 {
+    (word >> low) & ((1 << (high - low + 1)) - 1)
+}
+
+const fn bit(word: u32, n: u32) -> bool {
+    word & (1 << n) != 0
+}
+
+const fn sign_extend(value: u32, width: u32) -> i64 {
+    ((value as i64) << (64 - width)) >> (64 - width)
+}
+
+const CONDITIONS: [&str; 16] = [
+    "eq", "ne", "hs", "lo", "mi", "pl", "vs", "vc", "hi", "ls", "ge", "lt", "gt", "le", "al", "nv",
+];
+
+const SHIFTS: [&str; 4] = ["lsl", "lsr", "asr", "ror"];
+
+const EXTENDS: [&str; 8] = [
+    "uxtb", "uxth", "uxtw", "uxtx", "sxtb", "sxth", "sxtw", "sxtx",
+];
+
+const BARRIER_OPTIONS: [&str; 16] = [
+    "#0", "oshld", "oshst", "osh", "#4", "nshld", "nshst", "nsh", "#8", "ishld", "ishst", "ish",
+    "#12", "ld", "st", "sy",
+];
+
+/// A register operand. Number 31 is the zero register, or the stack pointer if `sp` is set.
+#[derive(Clone, Copy)]
+struct Reg {
+    prefix: char,
+    number: u32,
+    sp: bool,
+}
+
+impl fmt::Display for Reg {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.prefix, self.number, self.sp) {
+            ('x', 31, true) => f.write_str("sp"),
+            ('w', 31, true) => f.write_str("wsp"),
+            ('x', 31, false) => f.write_str("xzr"),
+            ('w', 31, false) => f.write_str("wzr"),
+            (prefix, number, _) => write!(f, "{}{}", prefix, number),
+        }
+    }
+}
+
+const fn reg(wide: bool, number: u32) -> Reg {
+    Reg {
+        prefix: if wide { 'x' } else { 'w' },
+        number,
+        sp: false,
+    }
+}
+
+const fn reg_sp(wide: bool, number: u32) -> Reg {
+    Reg {
+        prefix: if wide { 'x' } else { 'w' },
+        number,
+        sp: true,
+    }
+}
+
+/// An unsigned immediate, small values are printed in decimal, everything else in hex.
+struct Unsigned(u64);
+
+impl fmt::Display for Unsigned {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.0 < 10 {
+            write!(f, "#{}", self.0)
+        } else {
+            write!(f, "#{:#x}", self.0)
+        }
+    }
+}
+
+/// A signed immediate, e.g. an offset in a load or store.
+struct Signed(i64);
+
+impl fmt::Display for Signed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.0 < 0 {
+            f.write_str("#-")?;
+        } else {
+            f.write_str("#")?;
+        }
+        let magnitude = self.0.unsigned_abs();
+        if magnitude < 10 {
+            write!(f, "{}", magnitude)
+        } else {
+            write!(f, "{:#x}", magnitude)
+        }
+    }
+}
+
+/// Writes `, <shift> #amount` unless it is a no-op `lsl #0`.
+fn write_shift(f: &mut fmt::Formatter<'_>, shift: u32, amount: u32) -> fmt::Result {
+    if shift == 0 && amount == 0 {
+        Ok(())
+    } else {
+        write!(f, ", {} #{}", SHIFTS[shift as usize], amount)
+    }
+}
+
+/// Decodes the `imms`/`immr` pair of the logical immediate instructions.
+///
+/// Returns `None` for the reserved encodings.
+fn decode_bit_mask(n: bool, imms: u32, immr: u32, wide: bool) -> Option<u64> {
+    let combined = ((n as u32) << 6) | (!imms & 0x3f);
+    if combined == 0 || (!wide && n) {
+        return None;
+    }
+    let len = 31 - combined.leading_zeros();
+    if len == 0 {
+        return None;
+    }
+    let size = 1_u32 << len;
+    let levels = size - 1;
+    let s = imms & levels;
+    let r = immr & levels;
+    if s == levels {
+        return None;
+    }
+    let element_mask = if size == 64 {
+        u64::MAX
+    } else {
+        (1 << size) - 1
+    };
+    let ones = (1_u64 << (s + 1)) - 1;
+    let mut element = if r == 0 {
+        ones
+    } else {
+        ((ones >> r) | (ones << (size - r))) & element_mask
+    };
+    let mut width = size;
+    while width < 64 {
+        element |= element << width;
+        width *= 2;
+    }
+    Some(if wide { element } else { element & 0xffff_ffff })
+}
+
+/// Addressing modes of the loads and stores with an immediate offset.
+#[derive(Clone, Copy, PartialEq)]
+enum Index {
+    Offset,
+    PreIndexed,
+    PostIndexed,
+}
+
+fn write_address(f: &mut fmt::Formatter<'_>, base: u32, offset: i64, index: Index) -> fmt::Result {
+    let base = reg_sp(true, base);
+    match index {
+        Index::Offset if offset == 0 => write!(f, "[{}]", base),
+        Index::Offset => write!(f, "[{}, {}]", base, Signed(offset)),
+        Index::PreIndexed => write!(f, "[{}, {}]!", base, Signed(offset)),
+        Index::PostIndexed => write!(f, "[{}], {}", base, Signed(offset)),
+    }
+}
+
+/// What a single register load or store transfers, decoded from its `size`, `V` and `opc` fields.
+enum Access {
+    Register {
+        load: bool,
+        suffix: &'static str,
+        reg: char,
+        scale: u32,
+    },
+    Prefetch,
+}
+
+impl Access {
+    fn decode(size: u32, vector: bool, opc: u32) -> Option<Self> {
+        let access = |load, suffix, reg, scale| {
+            Some(Access::Register {
+                load,
+                suffix,
+                reg,
+                scale,
+            })
+        };
+        if vector {
+            return match (size, opc) {
+                (_, 0b00 | 0b01) => {
+                    access(opc == 0b01, "", ['b', 'h', 's', 'd'][size as usize], size)
+                }
+                (0b00, 0b10 | 0b11) => access(opc == 0b11, "", 'q', 4),
+                _ => None,
+            };
+        }
+        match (size, opc) {
+            (_, 0b00 | 0b01) => {
+                let suffix = ["b", "h", "", ""][size as usize];
+                access(opc == 0b01, suffix, if size == 3 { 'x' } else { 'w' }, size)
+            }
+            (0b11, 0b10) => Some(Access::Prefetch),
+            (0b00..=0b10, 0b10) => access(true, ["sb", "sh", "sw"][size as usize], 'x', size),
+            (0b00 | 0b01, 0b11) => access(true, ["sb", "sh"][size as usize], 'w', size),
+            _ => None,
+        }
+    }
+
+    /// Writes the mnemonic and the transfer register, `infix` distinguishes `ldr`, `ldur` and `ldtr`.
+    fn write(&self, f: &mut fmt::Formatter<'_>, infix: &str, rt: u32) -> fmt::Result {
+        match self {
+            Access::Register {
+                load, suffix, reg, ..
+            } => {
+                let op = if *load { "ld" } else { "st" };
+                let rt = Reg {
+                    prefix: *reg,
+                    number: rt,
+                    sp: false,
+                };
+                write!(f, "{}{}{} {}, ", op, infix, suffix, rt)
+            }
+            Access::Prefetch => {
+                let infix = if infix == "ur" { "u" } else { "" };
+                write!(f, "prf{}m ", infix)?;
+                write_prefetch_op(f, rt)?;
+                f.write_str(", ")
+            }
+        }
+    }
+
+    fn scale(&self) -> u32 {
+        match self {
+            Access::Register { scale, .. } => *scale,
+            Access::Prefetch => 3,
+        }
+    }
+}
+
+fn write_prefetch_op(f: &mut fmt::Formatter<'_>, op: u32) -> fmt::Result {
+    let kind = bits(op, 4, 3);
+    if kind == 0b11 || bits(op, 2, 1) == 0b11 {
+        return write!(f, "{}", Unsigned(op as u64));
+    }
+    let kind = ["pld", "pli", "pst"][kind as usize];
+    let policy = if bit(op, 0) { "strm" } else { "keep" };
+    write!(f, "{}l{}{}", kind, bits(op, 2, 1) + 1, policy)
+}
+
+/// A system register operand of `mrs` and `msr`, in the packed `op0:op1:CRn:CRm:op2` form.
+struct SystemRegister(u32);
+
+const fn sysreg(op0: u32, op1: u32, crn: u32, crm: u32, op2: u32) -> u32 {
+    (op0 << 14) | (op1 << 11) | (crn << 7) | (crm << 3) | op2
+}
+
+/// Names of the system registers this kernel touches, others are printed in the generic
+/// `s<op0>_<op1>_c<n>_c<m>_<op2>` form.
+const SYSTEM_REGISTERS: &[(u32, &str)] = &[
+    (sysreg(3, 0, 0, 0, 0), "midr_el1"),
+    (sysreg(3, 0, 0, 0, 5), "mpidr_el1"),
+    (sysreg(3, 0, 0, 4, 0), "id_aa64pfr0_el1"),
+    (sysreg(3, 0, 0, 6, 0), "id_aa64isar0_el1"),
+    (sysreg(3, 0, 0, 7, 0), "id_aa64mmfr0_el1"),
+    (sysreg(3, 0, 1, 0, 0), "sctlr_el1"),
+    (sysreg(3, 0, 1, 0, 1), "actlr_el1"),
+    (sysreg(3, 0, 1, 0, 2), "cpacr_el1"),
+    (sysreg(3, 0, 2, 0, 0), "ttbr0_el1"),
+    (sysreg(3, 0, 2, 0, 1), "ttbr1_el1"),
+    (sysreg(3, 0, 2, 0, 2), "tcr_el1"),
+    (sysreg(3, 0, 4, 0, 0), "spsr_el1"),
+    (sysreg(3, 0, 4, 0, 1), "elr_el1"),
+    (sysreg(3, 0, 4, 1, 0), "sp_el0"),
+    (sysreg(3, 0, 4, 2, 0), "spsel"),
+    (sysreg(3, 0, 4, 2, 2), "currentel"),
+    (sysreg(3, 0, 5, 2, 0), "esr_el1"),
+    (sysreg(3, 0, 6, 0, 0), "far_el1"),
+    (sysreg(3, 0, 7, 4, 0), "par_el1"),
+    (sysreg(3, 0, 10, 2, 0), "mair_el1"),
+    (sysreg(3, 0, 12, 0, 0), "vbar_el1"),
+    (sysreg(3, 0, 12, 0, 2), "rmr_el1"),
+    (sysreg(3, 0, 12, 1, 0), "isr_el1"),
+    (sysreg(3, 0, 13, 0, 1), "contextidr_el1"),
+    (sysreg(3, 0, 13, 0, 4), "tpidr_el1"),
+    (sysreg(3, 0, 14, 1, 0), "cntkctl_el1"),
+    (sysreg(3, 1, 15, 2, 1), "cpuectlr_el1"),
+    (sysreg(3, 3, 4, 2, 0), "nzcv"),
+    (sysreg(3, 3, 4, 2, 1), "daif"),
+    (sysreg(3, 3, 4, 4, 0), "fpcr"),
+    (sysreg(3, 3, 4, 4, 1), "fpsr"),
+    (sysreg(3, 3, 13, 0, 2), "tpidr_el0"),
+    (sysreg(3, 3, 13, 0, 3), "tpidrro_el0"),
+    (sysreg(3, 3, 14, 0, 0), "cntfrq_el0"),
+    (sysreg(3, 3, 14, 0, 1), "cntpct_el0"),
+    (sysreg(3, 3, 14, 0, 2), "cntvct_el0"),
+    (sysreg(3, 3, 14, 2, 0), "cntp_tval_el0"),
+    (sysreg(3, 3, 14, 2, 1), "cntp_ctl_el0"),
+    (sysreg(3, 3, 14, 2, 2), "cntp_cval_el0"),
+    (sysreg(3, 3, 14, 3, 0), "cntv_tval_el0"),
+    (sysreg(3, 3, 14, 3, 1), "cntv_ctl_el0"),
+    (sysreg(3, 3, 14, 3, 2), "cntv_cval_el0"),
+    (sysreg(3, 4, 0, 0, 0), "vpidr_el2"),
+    (sysreg(3, 4, 0, 0, 5), "vmpidr_el2"),
+    (sysreg(3, 4, 1, 0, 0), "sctlr_el2"),
+    (sysreg(3, 4, 1, 1, 0), "hcr_el2"),
+    (sysreg(3, 4, 1, 1, 2), "cptr_el2"),
+    (sysreg(3, 4, 4, 0, 0), "spsr_el2"),
+    (sysreg(3, 4, 4, 0, 1), "elr_el2"),
+    (sysreg(3, 4, 4, 1, 0), "sp_el1"),
+    (sysreg(3, 4, 5, 2, 0), "esr_el2"),
+    (sysreg(3, 4, 6, 0, 0), "far_el2"),
+    (sysreg(3, 4, 12, 0, 0), "vbar_el2"),
+    (sysreg(3, 4, 12, 0, 2), "rmr_el2"),
+    (sysreg(3, 4, 13, 0, 2), "tpidr_el2"),
+    (sysreg(3, 4, 14, 0, 3), "cntvoff_el2"),
+    (sysreg(3, 4, 14, 1, 0), "cnthctl_el2"),
+    (sysreg(3, 6, 1, 0, 0), "sctlr_el3"),
+    (sysreg(3, 6, 1, 1, 0), "scr_el3"),
+    (sysreg(3, 6, 4, 0, 0), "spsr_el3"),
+    (sysreg(3, 6, 4, 0, 1), "elr_el3"),
+    (sysreg(3, 6, 4, 1, 0), "sp_el2"),
+    (sysreg(3, 6, 5, 2, 0), "esr_el3"),
+    (sysreg(3, 6, 6, 0, 0), "far_el3"),
+    (sysreg(3, 6, 12, 0, 0), "vbar_el3"),
+    (sysreg(3, 6, 12, 0, 2), "rmr_el3"),
+    (sysreg(3, 6, 13, 0, 2), "tpidr_el3"),
+];
+
+impl fmt::Display for SystemRegister {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some((_, name)) = SYSTEM_REGISTERS.iter().find(|(key, _)| *key == self.0) {
+            return f.write_str(name);
+        }
+        write!(
+            f,
+            "s{}_{}_c{}_c{}_{}",
+            bits(self.0, 15, 14),
+            bits(self.0, 13, 11),
+            bits(self.0, 10, 7),
+            bits(self.0, 6, 3),
+            bits(self.0, 2, 0)
+        )
+    }
+}
+
+/// Cache and TLB maintenance aliases of `sys`, keyed by `op1:CRn:CRm:op2`.
+const SYS_ALIASES: &[(u32, &str)] = &[
+    (sysreg(0, 0, 7, 1, 0), "ic ialluis"),
+    (sysreg(0, 0, 7, 5, 0), "ic iallu"),
+    (sysreg(0, 3, 7, 5, 1), "ic ivau"),
+    (sysreg(0, 0, 7, 6, 1), "dc ivac"),
+    (sysreg(0, 0, 7, 6, 2), "dc isw"),
+    (sysreg(0, 3, 7, 4, 1), "dc zva"),
+    (sysreg(0, 3, 7, 10, 1), "dc cvac"),
+    (sysreg(0, 0, 7, 10, 2), "dc csw"),
+    (sysreg(0, 3, 7, 11, 1), "dc cvau"),
+    (sysreg(0, 3, 7, 14, 1), "dc civac"),
+    (sysreg(0, 0, 7, 14, 2), "dc cisw"),
+    (sysreg(0, 0, 8, 3, 0), "tlbi vmalle1is"),
+    (sysreg(0, 0, 8, 7, 0), "tlbi vmalle1"),
+    (sysreg(0, 0, 8, 7, 1), "tlbi vae1"),
+    (sysreg(0, 4, 8, 7, 0), "tlbi alle2"),
+    (sysreg(0, 4, 8, 7, 4), "tlbi alle1"),
+    (sysreg(0, 6, 8, 7, 0), "tlbi alle3"),
+];
+
+impl Instruction {
+    fn data_processing_immediate(&self, f: &mut fmt::Formatter<'_>) -> Decoded {
+        let w = self.word;
+        let wide = bit(w, 31);
+        let rd = bits(w, 4, 0);
+        let rn = bits(w, 9, 5);
+        match bits(w, 25, 23) {
+            0b000 | 0b001 => {
+                let offset = sign_extend((bits(w, 23, 5) << 2) | bits(w, 30, 29), 21);
+                Some(if bit(w, 31) {
+                    let page = self.address & !0xfff;
+                    let target = page.wrapping_add((offset << 12) as usize);
+                    write!(f, "adrp {}, {:#x}", reg(true, rd), target)
+                } else {
+                    write!(f, "adr {}, {:#x}", reg(true, rd), self.relative(offset))
+                })
+            }
+            0b010 => {
+                let sub = bit(w, 30);
+                let set_flags = bit(w, 29);
+                let imm = Unsigned(bits(w, 21, 10) as u64);
+                let shifted = bit(w, 22);
+                let rn = reg_sp(wide, rn);
+                if !sub && !set_flags && !shifted && imm.0 == 0 && (rd == 31 || rn.number == 31) {
+                    return Some(write!(f, "mov {}, {}", reg_sp(wide, rd), rn));
+                }
+                let result = if set_flags && rd == 31 {
+                    write!(f, "{} {}, {}", if sub { "cmp" } else { "cmn" }, rn, imm)
+                } else {
+                    let mnemonic = ["add", "adds", "sub", "subs"][bits(w, 30, 29) as usize];
+                    let rd = if set_flags {
+                        reg(wide, rd)
+                    } else {
+                        reg_sp(wide, rd)
+                    };
+                    write!(f, "{} {}, {}, {}", mnemonic, rd, rn, imm)
+                };
+                Some(result.and_then(|_| {
+                    if shifted {
+                        f.write_str(", lsl #12")
+                    } else {
+                        Ok(())
+                    }
+                }))
+            }
+            0b100 => {
+                let imm = decode_bit_mask(bit(w, 22), bits(w, 15, 10), bits(w, 21, 16), wide)?;
+                let imm = Unsigned(imm);
+                let opc = bits(w, 30, 29);
+                Some(match opc {
+                    0b01 if rn == 31 => write!(f, "mov {}, {}", reg_sp(wide, rd), imm),
+                    0b11 if rd == 31 => write!(f, "tst {}, {}", reg(wide, rn), imm),
+                    _ => {
+                        let mnemonic = ["and", "orr", "eor", "ands"][opc as usize];
+                        let rd = if opc == 0b11 {
+                            reg(wide, rd)
+                        } else {
+                            reg_sp(wide, rd)
+                        };
+                        write!(f, "{} {}, {}, {}", mnemonic, rd, reg(wide, rn), imm)
+                    }
+                })
+            }
+            0b101 => {
+                let opc = bits(w, 30, 29);
+                let hw = bits(w, 22, 21);
+                if opc == 0b01 || (!wide && hw >= 2) {
+                    return None;
+                }
+                let imm16 = bits(w, 20, 5) as u64;
+                let shift = hw * 16;
+                let rd = reg(wide, rd);
+                // the mov alias is only used where it is unambiguous
+                let zero_shifted = imm16 == 0 && hw != 0;
+                let alias = match opc {
+                    0b00 if !zero_shifted && (wide || imm16 != 0xffff) => {
+                        let value = !(imm16 << shift);
+                        Some(if wide {
+                            value as i64
+                        } else {
+                            value as u32 as i32 as i64
+                        })
+                    }
+                    0b10 if !zero_shifted => Some((imm16 << shift) as i64),
+                    _ => None,
+                };
+                Some(match alias {
+                    Some(value) if value < 0 => write!(f, "mov {}, {}", rd, Signed(value)),
+                    Some(value) => write!(f, "mov {}, {}", rd, Unsigned(value as u64)),
+                    None => {
+                        let mnemonic = ["movn", "", "movz", "movk"][opc as usize];
+                        write!(f, "{} {}, {}", mnemonic, rd, Unsigned(imm16))
+                            .and_then(|_| write_shift(f, 0, shift))
+                    }
+                })
+            }
+            0b110 => self.bitfield(f),
+            0b111 => {
+                let imms = bits(w, 15, 10);
+                if bits(w, 30, 29) != 0 || bit(w, 21) || bit(w, 22) != wide || (!wide && imms >= 32)
+                {
+                    return None;
+                }
+                let rm = bits(w, 20, 16);
+                Some(if rm == rn {
+                    write!(f, "ror {}, {}, #{}", reg(wide, rd), reg(wide, rn), imms)
+                } else {
+                    write!(
+                        f,
+                        "extr {}, {}, {}, #{}",
+                        reg(wide, rd),
+                        reg(wide, rn),
+                        reg(wide, rm),
+                        imms
+                    )
+                })
+            }
+            _ => None,
+        }
+    }
+
+    fn bitfield(&self, f: &mut fmt::Formatter<'_>) -> Decoded {
+        let w = self.word;
+        let wide = bit(w, 31);
+        let opc = bits(w, 30, 29);
+        if opc == 0b11 || bit(w, 22) != wide {
+            return None;
+        }
+        let immr = bits(w, 21, 16);
+        let imms = bits(w, 15, 10);
+        let size = if wide { 64 } else { 32 };
+        if immr >= size || imms >= size {
+            return None;
+        }
+        let rd = reg(wide, bits(w, 4, 0));
+        let rn = reg(wide, bits(w, 9, 5));
+        let (insert, extract) = match opc {
+            0b00 => {
+                if imms == size - 1 {
+                    return Some(write!(f, "asr {}, {}, #{}", rd, rn, immr));
+                }
+                if immr == 0 && (imms == 7 || imms == 15 || imms == 31) {
+                    let mnemonic = match imms {
+                        7 => "sxtb",
+                        15 => "sxth",
+                        _ => "sxtw",
+                    };
+                    return Some(write!(f, "{} {}, {}", mnemonic, rd, reg(false, rn.number)));
+                }
+                ("sbfiz", "sbfx")
+            }
+            0b01 => ("bfi", "bfxil"),
+            _ => {
+                if imms == size - 1 {
+                    return Some(write!(f, "lsr {}, {}, #{}", rd, rn, immr));
+                }
+                if imms + 1 == immr {
+                    return Some(write!(f, "lsl {}, {}, #{}", rd, rn, size - 1 - imms));
+                }
+                if !wide && immr == 0 && (imms == 7 || imms == 15) {
+                    let mnemonic = if imms == 7 { "uxtb" } else { "uxth" };
+                    return Some(write!(f, "{} {}, {}", mnemonic, rd, rn));
+                }
+                ("ubfiz", "ubfx")
+            }
+        };
+        Some(if imms < immr {
+            write!(
+                f,
+                "{} {}, {}, #{}, #{}",
+                insert,
+                rd,
+                rn,
+                size - immr,
+                imms + 1
+            )
+        } else {
+            write!(
+                f,
+                "{} {}, {}, #{}, #{}",
+                extract,
+                rd,
+                rn,
+                immr,
+                imms - immr + 1
+            )
+        })
+    }
+
+    fn branch_exception_system(&self, f: &mut fmt::Formatter<'_>) -> Decoded {
+        let w = self.word;
+        let rt = bits(w, 4, 0);
+        if w & 0x7c00_0000 == 0x1400_0000 {
+            let target = self.relative(sign_extend(bits(w, 25, 0), 26) << 2);
+            let mnemonic = if bit(w, 31) { "bl" } else { "b" };
+            return Some(write!(f, "{} {:#x}", mnemonic, target));
+        }
+        if w & 0x7e00_0000 == 0x3400_0000 {
+            let target = self.relative(sign_extend(bits(w, 23, 5), 19) << 2);
+            let mnemonic = if bit(w, 24) { "cbnz" } else { "cbz" };
+            return Some(write!(
+                f,
+                "{} {}, {:#x}",
+                mnemonic,
+                reg(bit(w, 31), rt),
+                target
+            ));
+        }
+        if w & 0x7e00_0000 == 0x3600_0000 {
+            let target = self.relative(sign_extend(bits(w, 18, 5), 14) << 2);
+            let mnemonic = if bit(w, 24) { "tbnz" } else { "tbz" };
+            let bit_number = ((w >> 31) << 5) | bits(w, 23, 19);
+            let rt = reg(bit(w, 31), rt);
+            return Some(write!(
+                f,
+                "{} {}, #{}, {:#x}",
+                mnemonic, rt, bit_number, target
+            ));
+        }
+        if w & 0xff00_0010 == 0x5400_0000 {
+            let target = self.relative(sign_extend(bits(w, 23, 5), 19) << 2);
+            return Some(write!(
+                f,
+                "b.{} {:#x}",
+                CONDITIONS[bits(w, 3, 0) as usize],
+                target
+            ));
+        }
+        if w & 0xff00_0000 == 0xd400_0000 {
+            let imm = Unsigned(bits(w, 20, 5) as u64);
+            let mnemonic = match (bits(w, 23, 21), bits(w, 4, 0)) {
+                (0b000, 0b00001) => "svc",
+                (0b000, 0b00010) => "hvc",
+                (0b000, 0b00011) => "smc",
+                (0b001, 0b00000) => "brk",
+                (0b010, 0b00000) => "hlt",
+                _ => return None,
+            };
+            return Some(write!(f, "{} {}", mnemonic, imm));
+        }
+        if w & 0xffc0_0000 == 0xd500_0000 {
+            return self.system(f);
+        }
+        if w & 0xfe1f_fc1f == 0xd61f_0000 {
+            let rn = bits(w, 9, 5);
+            return Some(match (bits(w, 24, 21), rn) {
+                (0b0000, _) => write!(f, "br {}", reg(true, rn)),
+                (0b0001, _) => write!(f, "blr {}", reg(true, rn)),
+                (0b0010, 30) => f.write_str("ret"),
+                (0b0010, _) => write!(f, "ret {}", reg(true, rn)),
+                (0b0100, 31) => f.write_str("eret"),
+                (0b0101, 31) => f.write_str("drps"),
+                _ => return None,
+            });
+        }
+        None
+    }
+
+    fn system(&self, f: &mut fmt::Formatter<'_>) -> Decoded {
+        let w = self.word;
+        let read = bit(w, 21);
+        let op0 = bits(w, 20, 19);
+        let op1 = bits(w, 18, 16);
+        let crn = bits(w, 15, 12);
+        let crm = bits(w, 11, 8);
+        let op2 = bits(w, 7, 5);
+        let rt = bits(w, 4, 0);
+        match (read, op0) {
+            (false, 0b00) => {
+                if rt != 31 {
+                    return None;
+                }
+                match (crn, op1) {
+                    (0b0010, 0b011) => {
+                        let hint = (crm << 3) | op2;
+                        let names = ["nop", "yield", "wfe", "wfi", "sev", "sevl"];
+                        Some(match names.get(hint as usize) {
+                            Some(name) => f.write_str(name),
+                            None => write!(f, "hint {}", Unsigned(hint as u64)),
+                        })
+                    }
+                    (0b0011, 0b011) => Some(match op2 {
+                        0b010 if crm == 15 => f.write_str("clrex"),
+                        0b010 => write!(f, "clrex #{}", crm),
+                        0b100 => write!(f, "dsb {}", BARRIER_OPTIONS[crm as usize]),
+                        0b101 => write!(f, "dmb {}", BARRIER_OPTIONS[crm as usize]),
+                        0b110 if crm == 15 => f.write_str("isb"),
+                        0b110 => write!(f, "isb #{}", crm),
+                        _ => return None,
+                    }),
+                    (0b0100, _) => {
+                        let field = match (op1, op2) {
+                            (0b000, 0b101) => "spsel",
+                            (0b011, 0b110) => "daifset",
+                            (0b011, 0b111) => "daifclr",
+                            _ => return None,
+                        };
+                        Some(write!(f, "msr {}, #{}", field, crm))
+                    }
+                    _ => None,
+                }
+            }
+            (_, 0b01) => {
+                let key = sysreg(0, op1, crn, crm, op2);
+                if !read {
+                    if let Some((_, alias)) = SYS_ALIASES.iter().find(|(k, _)| *k == key) {
+                        return Some(if rt == 31 {
+                            f.write_str(alias)
+                        } else {
+                            write!(f, "{}, {}", alias, reg(true, rt))
+                        });
+                    }
+                }
+                Some(if read {
+                    write!(
+                        f,
+                        "sysl {}, #{}, c{}, c{}, #{}",
+                        reg(true, rt),
+                        op1,
+                        crn,
+                        crm,
+                        op2
+                    )
+                } else {
+                    write!(f, "sys #{}, c{}, c{}, #{}", op1, crn, crm, op2).and_then(|_| {
+                        if rt == 31 {
+                            Ok(())
+                        } else {
+                            write!(f, ", {}", reg(true, rt))
+                        }
+                    })
+                })
+            }
+            (true, _) => {
+                let register = SystemRegister(bits(w, 20, 5));
+                Some(write!(f, "mrs {}, {}", reg(true, rt), register))
+            }
+            (false, _) => {
+                let register = SystemRegister(bits(w, 20, 5));
+                Some(write!(f, "msr {}, {}", register, reg(true, rt)))
+            }
+        }
+    }
+
+    fn load_store(&self, f: &mut fmt::Formatter<'_>) -> Decoded {
+        let w = self.word;
+        let rt = bits(w, 4, 0);
+        let rn = bits(w, 9, 5);
+        let vector = bit(w, 26);
+        if w & 0x3f00_0000 == 0x0800_0000 {
+            return self.load_store_exclusive(f);
+        }
+        if w & 0x3b00_0000 == 0x1800_0000 {
+            let target = self.relative(sign_extend(bits(w, 23, 5), 19) << 2);
+            let rt = match (bits(w, 31, 30), vector) {
+                (0b11, false) => {
+                    return Some(f.write_str("prfm ").and_then(|_| {
+                        write_prefetch_op(f, rt)?;
+                        write!(f, ", {:#x}", target)
+                    }));
+                }
+                (0b10, false) => return Some(write!(f, "ldrsw {}, {:#x}", reg(true, rt), target)),
+                (0b11, true) => return None,
+                (opc, false) => reg(opc == 0b01, rt),
+                (opc, true) => Reg {
+                    prefix: ['s', 'd', 'q'][opc as usize],
+                    number: rt,
+                    sp: false,
+                },
+            };
+            return Some(write!(f, "ldr {}, {:#x}", rt, target));
+        }
+        if w & 0x3a00_0000 == 0x2800_0000 {
+            return self.load_store_pair(f);
+        }
+        let size = bits(w, 31, 30);
+        let opc = bits(w, 23, 22);
+        if w & 0x3b00_0000 == 0x3900_0000 {
+            let access = Access::decode(size, vector, opc)?;
+            let offset = (bits(w, 21, 10) as i64) << access.scale();
+            return Some(
+                access
+                    .write(f, "r", rt)
+                    .and_then(|_| write_address(f, rn, offset, Index::Offset)),
+            );
+        }
+        if w & 0x3b20_0000 == 0x3800_0000 {
+            let access = Access::decode(size, vector, opc)?;
+            let offset = sign_extend(bits(w, 20, 12), 9);
+            let (infix, index) = match (bits(w, 11, 10), &access) {
+                (0b00, _) => ("ur", Index::Offset),
+                (0b01, Access::Register { .. }) => ("r", Index::PostIndexed),
+                (0b10, Access::Register { .. }) if !vector => ("tr", Index::Offset),
+                (0b11, Access::Register { .. }) => ("r", Index::PreIndexed),
+                _ => return None,
+            };
+            return Some(
+                access
+                    .write(f, infix, rt)
+                    .and_then(|_| write_address(f, rn, offset, index)),
+            );
+        }
+        if w & 0x3b20_0c00 == 0x3820_0800 {
+            let access = Access::decode(size, vector, opc)?;
+            let option = bits(w, 15, 13);
+            if option & 0b010 == 0 {
+                return None;
+            }
+            let rm = reg(option & 1 == 1, bits(w, 20, 16));
+            let shift = bit(w, 12);
+            return Some((|| {
+                access.write(f, "r", rt)?;
+                write!(f, "[{}, {}", reg_sp(true, rn), rm)?;
+                match (option, shift) {
+                    (0b011, false) => {}
+                    (0b011, true) => write!(f, ", lsl #{}", access.scale())?,
+                    (_, false) => write!(f, ", {}", EXTENDS[option as usize])?,
+                    (_, true) => write!(f, ", {} #{}", EXTENDS[option as usize], access.scale())?,
+                }
+                f.write_str("]")
+            })());
+        }
+        None
+    }
+
+    fn load_store_pair(&self, f: &mut fmt::Formatter<'_>) -> Decoded {
+        let w = self.word;
+        let load = bit(w, 22);
+        let (prefix, scale, signed) = match (bits(w, 31, 30), bit(w, 26)) {
+            (0b00, false) => ('w', 2, false),
+            (0b01, false) if load => ('x', 2, true),
+            (0b10, false) => ('x', 3, false),
+            (0b00, true) => ('s', 2, false),
+            (0b01, true) => ('d', 3, false),
+            (0b10, true) => ('q', 4, false),
+            _ => return None,
+        };
+        let (non_temporal, index) = match bits(w, 24, 23) {
+            0b00 => (true, Index::Offset),
+            0b01 => (false, Index::PostIndexed),
+            0b10 => (false, Index::Offset),
+            _ => (false, Index::PreIndexed),
+        };
+        if non_temporal && signed {
+            return None;
+        }
+        let mnemonic = match (load, non_temporal, signed) {
+            (true, _, true) => "ldpsw",
+            (true, true, _) => "ldnp",
+            (true, false, _) => "ldp",
+            (false, true, _) => "stnp",
+            (false, false, _) => "stp",
+        };
+        let offset = sign_extend(bits(w, 21, 15), 7) << scale;
+        let register = |number| Reg {
+            prefix,
+            number,
+            sp: false,
+        };
+        let rt = register(bits(w, 4, 0));
+        let rt2 = register(bits(w, 14, 10));
+        Some(
+            write!(f, "{} {}, {}, ", mnemonic, rt, rt2)
+                .and_then(|_| write_address(f, bits(w, 9, 5), offset, index)),
+        )
+    }
+
+    fn load_store_exclusive(&self, f: &mut fmt::Formatter<'_>) -> Decoded {
+        let w = self.word;
+        let size = bits(w, 31, 30);
+        let wide = size == 0b11;
+        let load = bit(w, 22);
+        let ordered = bit(w, 15);
+        let rt = reg(wide, bits(w, 4, 0));
+        let rt2 = reg(wide, bits(w, 14, 10));
+        let rs = reg(false, bits(w, 20, 16));
+        let rn = reg_sp(true, bits(w, 9, 5));
+        let suffix = ["b", "h", "", ""][size as usize];
+        Some(match (bit(w, 23), bit(w, 21)) {
+            (false, false) if load => {
+                let mnemonic = if ordered { "ldaxr" } else { "ldxr" };
+                write!(f, "{}{} {}, [{}]", mnemonic, suffix, rt, rn)
+            }
+            (false, false) => {
+                let mnemonic = if ordered { "stlxr" } else { "stxr" };
+                write!(f, "{}{} {}, {}, [{}]", mnemonic, suffix, rs, rt, rn)
+            }
+            (false, true) if size < 0b10 => return None,
+            (false, true) if load => {
+                let mnemonic = if ordered { "ldaxp" } else { "ldxp" };
+                write!(f, "{} {}, {}, [{}]", mnemonic, rt, rt2, rn)
+            }
+            (false, true) => {
+                let mnemonic = if ordered { "stlxp" } else { "stxp" };
+                write!(f, "{} {}, {}, {}, [{}]", mnemonic, rs, rt, rt2, rn)
+            }
+            (true, false) => {
+                let mnemonic = match (load, ordered) {
+                    (true, true) => "ldar",
+                    (true, false) => "ldlar",
+                    (false, true) => "stlr",
+                    (false, false) => "stllr",
+                };
+                write!(f, "{}{} {}, [{}]", mnemonic, suffix, rt, rn)
+            }
+            (true, true) => return None,
+        })
+    }
+
+    fn data_processing_register(&self, f: &mut fmt::Formatter<'_>) -> Decoded {
+        let w = self.word;
+        let wide = bit(w, 31);
+        let set_flags = bit(w, 29);
+        let rd = bits(w, 4, 0);
+        let rn = bits(w, 9, 5);
+        let rm = bits(w, 20, 16);
+        let imm6 = bits(w, 15, 10);
+        let op2 = bits(w, 24, 21);
+        if !bit(w, 28) {
+            if !wide && imm6 >= 32 && op2 & 0b1001 != 0b1001 {
+                return None;
+            }
+            let shift = bits(w, 23, 22);
+            return Some(if op2 & 0b1000 == 0 {
+                // logical (shifted register)
+                let opc = bits(w, 30, 29);
+                let negate = bit(w, 21);
+                let (rd, rn, rm) = (reg(wide, rd), reg(wide, rn), reg(wide, rm));
+                match (opc, negate) {
+                    (0b01, false) if rn.number == 31 && shift == 0 && imm6 == 0 => {
+                        write!(f, "mov {}, {}", rd, rm)
+                    }
+                    (0b01, true) if rn.number == 31 => {
+                        write!(f, "mvn {}, {}", rd, rm).and_then(|_| write_shift(f, shift, imm6))
+                    }
+                    (0b11, false) if rd.number == 31 => {
+                        write!(f, "tst {}, {}", rn, rm).and_then(|_| write_shift(f, shift, imm6))
+                    }
+                    _ => {
+                        let mnemonics = ["and", "bic", "orr", "orn", "eor", "eon", "ands", "bics"];
+                        let mnemonic = mnemonics[((opc << 1) | negate as u32) as usize];
+                        write!(f, "{} {}, {}, {}", mnemonic, rd, rn, rm)
+                            .and_then(|_| write_shift(f, shift, imm6))
+                    }
+                }
+            } else if op2 & 1 == 0 {
+                // add/subtract (shifted register)
+                if shift == 0b11 {
+                    return None;
+                }
+                let sub = bit(w, 30);
+                let (rd, rn, rm) = (reg(wide, rd), reg(wide, rn), reg(wide, rm));
+                if set_flags && rd.number == 31 {
+                    write!(f, "{} {}, {}", if sub { "cmp" } else { "cmn" }, rn, rm)
+                } else if sub && rn.number == 31 {
+                    write!(
+                        f,
+                        "{} {}, {}",
+                        if set_flags { "negs" } else { "neg" },
+                        rd,
+                        rm
+                    )
+                } else {
+                    let mnemonic = ["add", "adds", "sub", "subs"][bits(w, 30, 29) as usize];
+                    write!(f, "{} {}, {}, {}", mnemonic, rd, rn, rm)
+                }
+                .and_then(|_| write_shift(f, shift, imm6))
+            } else {
+                // add/subtract (extended register)
+                let option = bits(w, 15, 13);
+                let amount = bits(w, 12, 10);
+                if bits(w, 23, 22) != 0 || amount > 4 {
+                    return None;
+                }
+                let sub = bit(w, 30);
+                let rm = reg(wide && option & 0b011 == 0b011, rm);
+                let rn = reg_sp(wide, rn);
+                let lsl =
+                    (rd == 31 || rn.number == 31) && option == if wide { 0b011 } else { 0b010 };
+                if set_flags && rd == 31 {
+                    write!(f, "{} {}, {}", if sub { "cmp" } else { "cmn" }, rn, rm)
+                } else {
+                    let mnemonic = ["add", "adds", "sub", "subs"][bits(w, 30, 29) as usize];
+                    let rd = if set_flags {
+                        reg(wide, rd)
+                    } else {
+                        reg_sp(wide, rd)
+                    };
+                    write!(f, "{} {}, {}, {}", mnemonic, rd, rn, rm)
+                }
+                .and_then(|_| match (lsl, amount) {
+                    (true, 0) => Ok(()),
+                    (true, _) => write!(f, ", lsl #{}", amount),
+                    (false, 0) => write!(f, ", {}", EXTENDS[option as usize]),
+                    (false, _) => write!(f, ", {} #{}", EXTENDS[option as usize], amount),
+                })
+            });
+        }
+
+        let (rd, rn, rm) = (reg(wide, rd), reg(wide, rn), reg(wide, rm));
+        let cond = bits(w, 15, 12);
+        match op2 {
+            0b0000 if imm6 == 0 => {
+                let mnemonic = ["adc", "adcs", "sbc", "sbcs"][bits(w, 30, 29) as usize];
+                Some(write!(f, "{} {}, {}, {}", mnemonic, rd, rn, rm))
+            }
+            0b0010 if set_flags && !bit(w, 10) && !bit(w, 4) => {
+                let mnemonic = if bit(w, 30) { "ccmp" } else { "ccmn" };
+                let nzcv = bits(w, 3, 0);
+                let condition = CONDITIONS[cond as usize];
+                Some(if bit(w, 11) {
+                    let imm = Unsigned(rm.number as u64);
+                    write!(f, "{} {}, {}, #{}, {}", mnemonic, rn, imm, nzcv, condition)
+                } else {
+                    write!(f, "{} {}, {}, #{}, {}", mnemonic, rn, rm, nzcv, condition)
+                })
+            }
+            0b0100 if !set_flags && !bit(w, 11) => {
+                let op = ((bit(w, 30) as u32) << 1) | bit(w, 10) as u32;
+                let inverted = CONDITIONS[(cond ^ 1) as usize];
+                Some(match op {
+                    0b01 | 0b10 if rm.number == 31 && rn.number == 31 && cond < 14 => {
+                        let mnemonic = if op == 0b01 { "cset" } else { "csetm" };
+                        write!(f, "{} {}, {}", mnemonic, rd, inverted)
+                    }
+                    0b01..=0b11 if rm.number == rn.number && cond < 14 => {
+                        let mnemonic = ["", "cinc", "cinv", "cneg"][op as usize];
+                        write!(f, "{} {}, {}, {}", mnemonic, rd, rn, inverted)
+                    }
+                    _ => {
+                        let mnemonic = ["csel", "csinc", "csinv", "csneg"][op as usize];
+                        let condition = CONDITIONS[cond as usize];
+                        write!(f, "{} {}, {}, {}, {}", mnemonic, rd, rn, rm, condition)
+                    }
+                })
+            }
+            0b0110 if !set_flags && !bit(w, 30) => {
+                let mnemonic = match imm6 {
+                    0b000010 => "udiv",
+                    0b000011 => "sdiv",
+                    0b001000 => "lsl",
+                    0b001001 => "lsr",
+                    0b001010 => "asr",
+                    0b001011 => "ror",
+                    _ => return None,
+                };
+                Some(write!(f, "{} {}, {}, {}", mnemonic, rd, rn, rm))
+            }
+            0b0110 if !set_flags && rm.number == 0 => {
+                let mnemonic = match (imm6, wide) {
+                    (0b000000, _) => "rbit",
+                    (0b000001, _) => "rev16",
+                    (0b000010, true) => "rev32",
+                    (0b000010, false) | (0b000011, true) => "rev",
+                    (0b000100, _) => "clz",
+                    (0b000101, _) => "cls",
+                    _ => return None,
+                };
+                Some(write!(f, "{} {}, {}", mnemonic, rd, rn))
+            }
+            0b1000..=0b1111 if bits(w, 30, 29) == 0 => {
+                let ra = reg(wide, bits(w, 14, 10));
+                let subtract = bit(w, 15);
+                let (long, mnemonic, alias) = match (bits(w, 23, 21), subtract) {
+                    (0b000, false) => (false, "madd", "mul"),
+                    (0b000, true) => (false, "msub", "mneg"),
+                    (0b001, false) if wide => (true, "smaddl", "smull"),
+                    (0b001, true) if wide => (true, "smsubl", "smnegl"),
+                    (0b101, false) if wide => (true, "umaddl", "umull"),
+                    (0b101, true) if wide => (true, "umsubl", "umnegl"),
+                    (0b010, false) if wide && ra.number == 31 => {
+                        return Some(write!(f, "smulh {}, {}, {}", rd, rn, rm))
+                    }
+                    (0b110, false) if wide && ra.number == 31 => {
+                        return Some(write!(f, "umulh {}, {}, {}", rd, rn, rm))
+                    }
+                    _ => return None,
+                };
+                let (rn, rm) = if long {
+                    (reg(false, rn.number), reg(false, rm.number))
+                } else {
+                    (rn, rm)
+                };
+                Some(if ra.number == 31 {
+                    write!(f, "{} {}, {}, {}", alias, rd, rn, rm)
+                } else {
+                    write!(f, "{} {}, {}, {}, {}", mnemonic, rd, rn, rm, ra)
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::fmt::Write;
+    use mystd::collections::ring::RingArray;
+
+    fn check(address: usize, word: u32, expected: &str) {
+        let mut buf: RingArray<u8, 64> = RingArray::new();
+        write!(buf, "{}", Instruction::new(address, word)).unwrap();
+        assert_eq!(expected, buf.to_str().unwrap(), "decoding {:#010x}", word);
+    }
+
+    #[test]
+    fn branches() {
+        check(0x80000, 0x14000004, "b 0x80010");
+        check(0x80010, 0x97fffffc, "bl 0x80000");
+        check(0x80000, 0x54000041, "b.ne 0x80008");
+        check(0x80000, 0x54ffffeb, "b.lt 0x7fffc");
+        check(0x80000, 0xb4000080, "cbz x0, 0x80010");
+        check(0x80000, 0x35000061, "cbnz w1, 0x8000c");
+        check(0x80000, 0x37080042, "tbnz w2, #1, 0x80008");
+        check(0x80000, 0xb6f80043, "tbz x3, #63, 0x80008");
+        check(0, 0xd61f0020, "br x1");
+        check(0, 0xd63f0100, "blr x8");
+        check(0, 0xd65f03c0, "ret");
+        check(0, 0xd65f0040, "ret x2");
+    }
+
+    #[test]
+    fn exceptions() {
+        check(0, 0xd69f03e0, "eret");
+        check(0, 0xd4000001, "svc #0");
+        check(0, 0xd4000022, "hvc #1");
+        check(0, 0xd4000203, "smc #0x10");
+        check(0, 0xd4200000, "brk #0");
+        check(0, 0x00000000, "udf #0");
+    }
+
+    #[test]
+    fn system() {
+        check(0, 0xd503201f, "nop");
+        check(0, 0xd503205f, "wfe");
+        check(0, 0xd503207f, "wfi");
+        check(0, 0xd503209f, "sev");
+        check(0, 0xd5033f9f, "dsb sy");
+        check(0, 0xd5033bbf, "dmb ish");
+        check(0, 0xd5033fdf, "isb");
+        check(0, 0xd50342df, "msr daifset, #2");
+        check(0, 0xd50343ff, "msr daifclr, #3");
+        check(0, 0xd5381000, "mrs x0, sctlr_el1");
+        check(0, 0xd51c1101, "msr hcr_el2, x1");
+        check(0, 0xd53800a0, "mrs x0, mpidr_el1");
+        check(0, 0xd5384240, "mrs x0, currentel");
+        check(0, 0xd51e1102, "msr scr_el3, x2");
+        check(0, 0xd518c003, "msr vbar_el1, x3");
+        check(0, 0xd53be021, "mrs x1, cntpct_el0");
+        check(0, 0xd53be041, "mrs x1, cntvct_el0");
+        check(0, 0xd5385201, "mrs x1, esr_el1");
+        check(0, 0xd539f220, "mrs x0, cpuectlr_el1");
+        check(0, 0xd53ff000, "mrs x0, s3_7_c15_c0_0");
+        check(0, 0xd508871f, "tlbi vmalle1");
+        check(0, 0xd50b7e20, "dc civac, x0");
+        check(0, 0xd508751f, "ic iallu");
+    }
+
+    #[test]
+    fn loads_and_stores() {
+        check(0, 0xa9bf7bfd, "stp x29, x30, [sp, #-0x10]!");
+        check(0, 0xa8c17bfd, "ldp x29, x30, [sp], #0x10");
+        check(0, 0xa9400440, "ldp x0, x1, [x2]");
+        check(0, 0xf9400020, "ldr x0, [x1]");
+        check(0, 0xf9000c20, "str x0, [x1, #0x18]");
+        check(0, 0xb9400441, "ldr w1, [x2, #4]");
+        check(0, 0x39400020, "ldrb w0, [x1]");
+        check(0, 0x79000420, "strh w0, [x1, #2]");
+        check(0, 0x39c00020, "ldrsb w0, [x1]");
+        check(0, 0xb9800020, "ldrsw x0, [x1]");
+        check(0, 0xf85f83e0, "ldur x0, [sp, #-8]");
+        check(0, 0xf8408420, "ldr x0, [x1], #8");
+        check(0, 0xf8010c20, "str x0, [x1, #0x10]!");
+        check(0, 0xf8627820, "ldr x0, [x1, x2, lsl #3]");
+        check(0, 0x38624820, "ldrb w0, [x1, w2, uxtw]");
+        check(0x80000, 0x58000040, "ldr x0, 0x80008");
+        check(0x80000, 0x18000040, "ldr w0, 0x80008");
+        check(0, 0x3dc00020, "ldr q0, [x1]");
+        check(0, 0xad000420, "stp q0, q1, [x1]");
+        check(0, 0xf9800020, "prfm pldl1keep, [x1]");
+        check(0, 0xc85ffc20, "ldaxr x0, [x1]");
+        check(0, 0xc802fc20, "stlxr w2, x0, [x1]");
+        check(0, 0x885f7c20, "ldxr w0, [x1]");
+        check(0, 0xc8dffc20, "ldar x0, [x1]");
+        check(0, 0x089ffc20, "stlrb w0, [x1]");
+    }
+
+    #[test]
+    fn data_processing_immediate() {
+        check(0x80004, 0x10000000, "adr x0, 0x80004");
+        check(0x80004, 0x90000000, "adrp x0, 0x80000");
+        check(0x80004, 0xb0000001, "adrp x1, 0x81000");
+        check(0, 0x91004020, "add x0, x1, #0x10");
+        check(0, 0xd1400420, "sub x0, x1, #1, lsl #12");
+        check(0, 0x910003fd, "mov x29, sp");
+        check(0, 0x9100001f, "mov sp, x0");
+        check(0, 0xf100041f, "cmp x0, #1");
+        check(0, 0x7100001f, "cmp w0, #0");
+        check(0, 0xd2800020, "mov x0, #1");
+        check(0, 0xd2a00020, "mov x0, #0x10000");
+        check(0, 0x92800000, "mov x0, #-1");
+        check(0, 0xf2a24680, "movk x0, #0x1234, lsl #16");
+        check(0, 0x12000c00, "and w0, w0, #0xf");
+        check(0, 0x927ef400, "and x0, x0, #0xfffffffffffffffc");
+        check(0, 0xb2400000, "orr x0, x0, #1");
+        check(0, 0x320003e0, "mov w0, #1");
+        check(0, 0xf240001f, "tst x0, #1");
+        check(0, 0xd3441c41, "ubfx x1, x2, #4, #4");
+        check(0, 0xd37cec20, "lsl x0, x1, #4");
+        check(0, 0xd344fc20, "lsr x0, x1, #4");
+        check(0, 0x9344fc20, "asr x0, x1, #4");
+        check(0, 0x53001c20, "uxtb w0, w1");
+        check(0, 0x93407c20, "sxtw x0, w1");
+        check(0, 0xb37c0c20, "bfi x0, x1, #4, #4");
+        check(0, 0x93c21020, "extr x0, x1, x2, #4");
+        check(0, 0x93c11020, "ror x0, x1, #4");
+    }
+
+    #[test]
+    fn data_processing_register() {
+        check(0, 0xaa0103e0, "mov x0, x1");
+        check(0, 0x2a0103e0, "mov w0, w1");
+        check(0, 0xaa020020, "orr x0, x1, x2");
+        check(0, 0x8a020c20, "and x0, x1, x2, lsl #3");
+        check(0, 0xaa2103e0, "mvn x0, x1");
+        check(0, 0xea01001f, "tst x0, x1");
+        check(0, 0x8b020020, "add x0, x1, x2");
+        check(0, 0xcb421020, "sub x0, x1, x2, lsr #4");
+        check(0, 0xeb01001f, "cmp x0, x1");
+        check(0, 0xcb0103e0, "neg x0, x1");
+        check(0, 0x8b22c020, "add x0, x1, w2, sxtw");
+        check(0, 0x8b226020, "add x0, x1, x2, uxtx");
+        check(0, 0x8b2263ff, "add sp, sp, x2");
+        check(0, 0x9a020020, "adc x0, x1, x2");
+        check(0, 0x9a821020, "csel x0, x1, x2, ne");
+        check(0, 0x9a9f17e0, "cset x0, eq");
+        check(0, 0x5a9f03e0, "csetm w0, ne");
+        check(0, 0x9a810420, "cinc x0, x1, ne");
+        check(0, 0xfa410804, "ccmp x0, #1, #4, eq");
+        check(0, 0x9ac20820, "udiv x0, x1, x2");
+        check(0, 0x9ac22020, "lsl x0, x1, x2");
+        check(0, 0xdac01020, "clz x0, x1");
+        check(0, 0xdac00c20, "rev x0, x1");
+        check(0, 0x9b027c20, "mul x0, x1, x2");
+        check(0, 0x9b020c20, "madd x0, x1, x2, x3");
+        check(0, 0x9ba27c20, "umull x0, w1, w2");
+        check(0, 0x9bc27c20, "umulh x0, x1, x2");
+    }
+
+    #[test]
+    fn targets() {
+        let target = |address, word| Instruction::new(address, word).target();
+        assert_eq!(Some(0x80010), target(0x80000, 0x14000004));
+        assert_eq!(Some(0x80008), target(0x80000, 0x54000041));
+        assert_eq!(Some(0x80010), target(0x80000, 0xb4000080));
+        assert_eq!(Some(0x80008), target(0x80000, 0xb6f80043));
+        assert_eq!(Some(0x80008), target(0x80000, 0x58000040));
+        assert_eq!(Some(0x80004), target(0x80004, 0x10000000));
+        assert_eq!(Some(0x81000), target(0x80004, 0xb0000001));
+        assert_eq!(None, target(0x80000, 0xd65f03c0));
+        assert_eq!(None, target(0x80000, 0xf9400020));
+    }
+
+    #[test]
+    fn unknown_encodings() {
+        check(0, 0x4e208400, ".inst 0x4e208400");
+        check(0, 0xffffffff, ".inst 0xffffffff");
+    }
+}
@@ -0,0 +1,179 @@
+//! Commands the kernel adds to the monitor, without the monitor depending on the kernel.
+//!
+//! An extension is started by its name, e.g. `LED ON`. Its [MonitorExtension::parse] gets the
+//! [Arguments] after the name and turns them into [ExtensionValues], which are handed to
+//! [MonitorExtension::run] when the command executes.
+
+use crate::command::CommandParseError;
+use crate::symbols::SymbolTable;
+use crate::token;
+
+/// Maximum number of values an extension command can carry from parse to run.
+pub const MAX_EXTENSION_VALUES: usize = 8;
+
+pub type ExtensionValues = [usize; MAX_EXTENSION_VALUES];
+
+pub trait MonitorExtension: Sync {
+    /// The word that starts the command, matched ignoring case. It should not be a hex number or
+    /// a built-in command letter, and it takes precedence over symbols of the same name.
+    fn name(&self) -> &str;
+
+    /// The arguments and what the command does, listed by `?`, e.g. `ON|OFF  switch the LED`.
+    fn help(&self) -> &str;
+
+    /// Parses the arguments following the name.
+    fn parse(&self, arguments: Arguments) -> Result<ExtensionValues, CommandParseError>;
+
+    /// Runs the command with the values returned by [MonitorExtension::parse].
+    fn run(&self, values: &ExtensionValues, out: &mut dyn core::fmt::Write);
+}
+
+pub enum ArgumentValue<'a> {
+    /// A hex value, or a symbol that resolved to its address.
+    Number(usize),
+    /// Anything else, e.g. `ON` or `=`.
+    Word(&'a [u8]),
+}
+
+pub struct Argument<'a> {
+    /// Position in the command line, for the errors of [MonitorExtension::parse].
+    pub position: usize,
+    pub value: ArgumentValue<'a>,
+}
+
+/// The arguments of an extension command.
+pub struct Arguments<'a, 's> {
+    c_str: &'a [u8],
+    tokens: token::Resolver<'a, 's>,
+}
+
+impl<'a, 's> Arguments<'a, 's> {
+    pub(crate) fn new(c_str: &'a [u8], tokens: token::Resolver<'a, 's>) -> Self {
+        Self { c_str, tokens }
+    }
+
+    /// Position of the end of the command line, for [CommandParseError::MissingValue].
+    pub fn end(&self) -> usize {
+        self.c_str.len()
+    }
+}
+
+impl<'a> Iterator for Arguments<'a, '_> {
+    type Item = Argument<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let token = self.tokens.next()?;
+        let value = match token.token_type {
+            token::TokenType::USize(value) => ArgumentValue::Number(value),
+            _ => ArgumentValue::Word(&self.c_str[token.start..token.end]),
+        };
+        Some(Argument {
+            position: token.start,
+            value,
+        })
+    }
+}
+
+/// Finds the extension whose name is the first word of `c_str`, and parses the command.
+pub(crate) fn parse<'e>(
+    c_str: &[u8],
+    symbols: &SymbolTable,
+    extensions: &[&'e dyn MonitorExtension],
+) -> Option<Result<(&'e dyn MonitorExtension, ExtensionValues), CommandParseError>> {
+    let mut tokenizer = token::Tokenizer::new(c_str, true);
+    let first = tokenizer.next()?;
+    let word = &c_str[first.start..first.end];
+    let extension = extensions
+        .iter()
+        .find(|extension| extension.name().as_bytes().eq_ignore_ascii_case(word))?;
+    let arguments = Arguments::new(c_str, token::Resolver::new(tokenizer, symbols));
+    Some(
+        extension
+            .parse(arguments)
+            .map(|values| (*extension, values)),
+    )
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// `ADD VALUE VALUE` prints the sum, `ADD ON` or `ADD OFF` print 1 or 0.
+    pub(crate) struct Add;
+
+    impl MonitorExtension for Add {
+        fn name(&self) -> &str {
+            "add"
+        }
+
+        fn help(&self) -> &str {
+            "A B | ON | OFF  print the sum"
+        }
+
+        fn parse(&self, mut arguments: Arguments) -> Result<ExtensionValues, CommandParseError> {
+            let mut values = [0; MAX_EXTENSION_VALUES];
+            let end = arguments.end();
+            match arguments.next() {
+                Some(Argument {
+                    value: ArgumentValue::Word(b"ON" | b"on"),
+                    ..
+                }) => values[0] = 1,
+                Some(Argument {
+                    value: ArgumentValue::Word(b"OFF" | b"off"),
+                    ..
+                }) => {}
+                Some(Argument {
+                    value: ArgumentValue::Number(a),
+                    ..
+                }) => match arguments.next() {
+                    Some(Argument {
+                        value: ArgumentValue::Number(b),
+                        ..
+                    }) => values[0] = a + b,
+                    Some(Argument { position, .. }) => {
+                        return Err(CommandParseError::IllegalToken { position })
+                    }
+                    None => return Err(CommandParseError::MissingValue { position: end }),
+                },
+                Some(Argument { position, .. }) => {
+                    return Err(CommandParseError::IllegalToken { position })
+                }
+                None => return Err(CommandParseError::MissingValue { position: end }),
+            }
+            match arguments.next() {
+                Some(Argument { position, .. }) => {
+                    Err(CommandParseError::TooManyValues { position })
+                }
+                None => Ok(values),
+            }
+        }
+
+        fn run(&self, values: &ExtensionValues, out: &mut dyn core::fmt::Write) {
+            let _ = write!(out, "{:x}", values[0]);
+        }
+    }
+
+    pub(crate) static EXTENSIONS: &[&dyn MonitorExtension] = &[&Add];
+
+    fn parse_add(c_str: &[u8]) -> Option<Result<usize, CommandParseError>> {
+        let symbols = SymbolTable::from_bytes(crate::symbols::tests::TABLE);
+        parse(c_str, &symbols, EXTENSIONS).map(|result| result.map(|(_, values)| values[0]))
+    }
+
+    #[test]
+    fn parses_by_name() {
+        assert!(matches!(parse_add(b"ADD 1 2"), Some(Ok(3))));
+        assert!(matches!(parse_add(b"  add on"), Some(Ok(1))));
+        assert!(matches!(parse_add(b"add _start+2 1"), Some(Ok(0x80003))));
+        assert!(matches!(
+            parse_add(b"add 1"),
+            Some(Err(CommandParseError::MissingValue { position: 5 }))
+        ));
+        assert!(matches!(
+            parse_add(b"add 1 2 3"),
+            Some(Err(CommandParseError::TooManyValues { position: 8 }))
+        ));
+        assert!(parse_add(b"adder 1 2").is_none());
+        assert!(parse_add(b"").is_none());
+    }
+}
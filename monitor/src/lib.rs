@@ -4,65 +4,337 @@ use mystd::format;
 mod writer;
 mod token;
 mod command;
+pub mod disasm;
+pub mod extension;
+pub mod registers;
+pub mod symbols;
 use writer::Writer;
+pub use command::CommandParseError;
 
 const LINE_LEN: usize = 256;
 type Buffer = mystd::collections::line::LineArray<u8, LINE_LEN>;
+/// Number of slots in the history ring, it keeps one less line than that.
+const HISTORY_LEN: usize = 16;
+type History = mystd::collections::ring::Ring<Buffer, [Buffer; HISTORY_LEN], HISTORY_LEN>;
+
+const BEL: u8 = 0x07;
+const BACKSPACE: u8 = 0x08;
+const ESC: u8 = 0x1B;
+
+/// Progress through an ANSI escape sequence, e.g. `ESC [ A` for cursor up.
+#[derive(Clone, Copy)]
+enum Escape {
+    None,
+    Started,
+    /// Control sequence (`ESC [`) with its numeric parameter so far.
+    Csi(u8),
+    /// Single shift (`ESC O`), sent for cursor keys in application mode.
+    Ss3,
+}
 
 pub struct Monitor<In: mystd::io::Read, Out: mystd::io::Write> {
     input: In,
     writer: Writer<Out>,
     line_buffer: Buffer,
+    history: History,
+    /// The history entry shown while browsing with up and down, `None` while editing a new line.
+    history_index: Option<usize>,
+    /// The new line, kept while browsing the history.
+    draft: Buffer,
+    escape: Escape,
     context: command::CommandContext,
 }
 
 impl<In: mystd::io::Read, Out: mystd::io::Write> Monitor<In, Out> {
-    pub fn new(input: In, output: Out) -> Self {
+    /// Creates a monitor that reads commands from `input` and answers on `output`.
+    ///
+    /// The `extensions` are commands the kernel adds, `?` lists them with the built-in commands.
+    pub fn new(
+        input: In,
+        output: Out,
+        extensions: &'static [&'static dyn extension::MonitorExtension],
+    ) -> Self {
         Self {
             input,
             writer: Writer::new(output),
             line_buffer: Buffer::new(),
-            context: command::CommandContext::default(),
+            history: History::adapting(core::array::from_fn(|_| Buffer::new())),
+            history_index: None,
+            draft: Buffer::new(),
+            escape: Escape::None,
+            context: command::CommandContext {
+                extensions,
+                ..command::CommandContext::default()
+            },
         }
     }
 
+    /// Lets commands take symbol names as addresses, and annotates printed addresses.
+    pub fn with_symbols(mut self, symbols: symbols::SymbolTable<'static>) -> Self {
+        self.context.symbols = symbols;
+        self
+    }
+
+    /// Lets the `M` command copy with the DMA engine.
+    pub fn with_dma_copy(mut self, dma_copy: command::DmaCopy) -> Self {
+        self.context.dma_copy = Some(dma_copy);
+        self
+    }
+
+    /// Lets the `S` command show and set the registers of `registers`.
+    pub fn with_registers(mut self, registers: &'static dyn registers::RegisterProvider) -> Self {
+        self.context.registers = Some(registers);
+        self
+    }
+
     pub fn run(&mut self) -> ! {
+        self.start();
+        loop {
+            let _ = self.process();
+        }
+    }
+
+    /// Clears the screen and shows the prompt.
+    pub fn start(&mut self) {
         self.writer.putc(0x0c);
         self.echo_prompt();
-        loop {
-            let mut buf = [0_u8];
-            if self.input.read_exact(buf.as_mut_slice()).is_err() {
-                continue;
-            }
-            let c = buf[0].to_ascii_uppercase();
-            match c {
-                0x7F | 0x08 => {
-                    if let Some(_) = self.line_buffer.pop_back() {
-                        self.echo_backspace();
-                    }
+    }
+
+    /// Reads one byte from the input and handles it.
+    pub fn process(&mut self) -> mystd::io::Result<()> {
+        let mut buf = [0_u8];
+        self.input.read_exact(buf.as_mut_slice())?;
+        let c = buf[0];
+        if self.context.more.is_some() {
+            self.continue_more(c);
+            return Ok(());
+        }
+        match core::mem::replace(&mut self.escape, Escape::None) {
+            Escape::None => self.handle_key(c),
+            Escape::Started => match c {
+                b'[' => self.escape = Escape::Csi(0),
+                b'O' => self.escape = Escape::Ss3,
+                _ => self.writer.putc(BEL),
+            },
+            Escape::Csi(parameter) => match c {
+                b'0'..=b'9' => {
+                    self.escape = Escape::Csi(parameter.saturating_mul(10).saturating_add(c - b'0'))
                 }
-                b'\n' | 0x0D => {
-                    self.writer.carriage_return();
-                    self.submit();
-                    self.line_buffer.clear();
+                // VT style keys, ESC [ 3 ~ is delete
+                b'~' => match parameter {
+                    1 | 7 => self.handle_cursor_key(b'H'),
+                    4 | 8 => self.handle_cursor_key(b'F'),
+                    3 => self.delete(),
+                    _ => self.writer.putc(BEL),
+                },
+                _ => self.handle_cursor_key(c),
+            },
+            Escape::Ss3 => self.handle_cursor_key(c),
+        }
+        Ok(())
+    }
+
+    fn handle_key(&mut self, c: u8) {
+        match c {
+            0x7F | BACKSPACE => self.backspace(),
+            ESC => self.escape = Escape::Started,
+            b'\t' => self.complete(),
+            b'\n' | 0x0D => {
+                self.writer.puts(self.line_buffer.after_cursor());
+                self.writer.carriage_return();
+                self.submit();
+                self.remember();
+                self.line_buffer.clear();
+                if self.context.more.is_none() {
                     self.echo_prompt();
                 }
-                b'+' => {
-                    self.context.cursor_type = self.context.cursor_type.wider();
-                }
-                b'-' => {
-                    self.context.cursor_type = self.context.cursor_type.slimmer();
-                }
-                c if c.is_ascii_hexdigit() => {
-                    if self.line_buffer.push_back(c).is_ok() {
-                        self.writer.putc(c);
-                    }
+            }
+            // on an empty line + and - change the cursor width, otherwise they are offsets
+            b'+' if self.line_buffer.is_empty() => {
+                self.context.cursor_type = self.context.cursor_type.wider();
+            }
+            b'-' if self.line_buffer.is_empty() => {
+                self.context.cursor_type = self.context.cursor_type.slimmer();
+            }
+            c if (c.is_ascii_alphanumeric() || b":. +-_$=?".contains(&c))
+                && self.line_buffer.insert(c).is_ok() =>
+            {
+                self.writer.putc(c);
+                self.echo_after_cursor(0);
+            }
+            _ => {
+                self.writer.putc(BEL);
+            }
+        }
+    }
+
+    /// Handles the final byte of a cursor key sequence.
+    fn handle_cursor_key(&mut self, key: u8) {
+        match key {
+            b'A' => self.history_previous(),
+            b'B' => self.history_next(),
+            b'C' => match self.line_buffer.after_cursor().first() {
+                Some(&c) => {
+                    self.line_buffer.move_right();
+                    self.writer.putc(c);
                 }
-                _ => {
-                    self.writer.putc(7); // BEL
-                                         // self.writer.hex(c);
+                None => self.writer.putc(BEL),
+            },
+            b'D' => {
+                if self.line_buffer.move_left() {
+                    self.writer.putc(BACKSPACE);
+                } else {
+                    self.writer.putc(BEL);
                 }
             }
+            b'H' => {
+                let moved = self.line_buffer.move_to_start();
+                self.writer.putc_repeat(BACKSPACE, moved);
+            }
+            b'F' => {
+                self.writer.puts(self.line_buffer.after_cursor());
+                self.line_buffer.move_to_end();
+            }
+            _ => self.writer.putc(BEL),
+        }
+    }
+
+    fn backspace(&mut self) {
+        if self.line_buffer.remove_before_cursor().is_some() {
+            self.writer.putc(BACKSPACE);
+            self.echo_after_cursor(1);
+        }
+    }
+
+    fn delete(&mut self) {
+        if self.line_buffer.remove_at_cursor().is_some() {
+            self.echo_after_cursor(1);
+        }
+    }
+
+    /// Completes a command letter or extension name to the command, or lists the commands on an
+    /// empty line.
+    fn complete(&mut self) {
+        let typed = self.line_buffer.before_cursor();
+        if typed.is_empty() {
+            for (letter, _, description) in command::COMMAND_LETTERS {
+                self.writer.newline();
+                self.writer.putc(*letter);
+                self.writer.putc(b' ');
+                self.writer.puts(description);
+            }
+            for extension in self.context.extensions {
+                self.writer.newline();
+                self.writer.puts(extension.name().as_bytes());
+            }
+            self.echo_prompt();
+            self.echo_line_buffer();
+            self.writer
+                .putc_repeat(BACKSPACE, self.line_buffer.after_cursor().len());
+            return;
+        }
+        if !self.line_buffer.after_cursor().is_empty() {
+            self.writer.putc(BEL);
+            return;
+        }
+        let is_letter = |c: &u8| {
+            command::COMMAND_LETTERS
+                .iter()
+                .any(|(letter, _, _)| *letter == c.to_ascii_uppercase())
+        };
+        let mut names = self.context.extensions.iter().map(|e| e.name().as_bytes()).filter(
+            |name| name.len() >= typed.len() && name[..typed.len()].eq_ignore_ascii_case(typed),
+        );
+        // the rest of the only matching name, and the space that follows a command
+        let rest = match (typed, names.next(), names.next()) {
+            ([c], _, _) if is_letter(c) => &[][..],
+            (_, Some(name), None) => &name[typed.len()..],
+            _ => {
+                self.writer.putc(BEL);
+                return;
+            }
+        };
+        for c in rest.iter().chain(b" ") {
+            if self.line_buffer.push_back(*c).is_err() {
+                break;
+            }
+            self.writer.putc(*c);
+        }
+    }
+
+    /// Adds the line to the history, unless it is empty or repeats the last entry.
+    fn remember(&mut self) {
+        self.history_index = None;
+        let line = self.line_buffer.as_slice();
+        let newest = self.history.len().checked_sub(1).and_then(|i| self.history.get(i));
+        if line.is_empty() || newest.is_some_and(|entry| entry.as_slice() == line) {
+            return;
+        }
+        if self.history.is_full() {
+            self.history.pop();
+        }
+        let mut entry = Buffer::new();
+        let _ = entry.copy_from_slice(line);
+        let _ = self.history.put(entry);
+    }
+
+    fn history_previous(&mut self) {
+        let index = match self.history_index {
+            None if !self.history.is_empty() => {
+                let _ = self.draft.copy_from_slice(self.line_buffer.as_slice());
+                self.history.len() - 1
+            }
+            Some(index) if index > 0 => index - 1,
+            _ => {
+                self.writer.putc(BEL);
+                return;
+            }
+        };
+        self.history_index = Some(index);
+        if let Some(entry) = self.history.get(index) {
+            Self::replace_line(&mut self.writer, &mut self.line_buffer, entry.as_slice());
+        }
+    }
+
+    fn history_next(&mut self) {
+        let Some(index) = self.history_index else {
+            self.writer.putc(BEL);
+            return;
+        };
+        let entry = if index + 1 < self.history.len() {
+            self.history_index = Some(index + 1);
+            self.history.get(index + 1)
+        } else {
+            self.history_index = None;
+            Some(&self.draft)
+        };
+        if let Some(entry) = entry {
+            Self::replace_line(&mut self.writer, &mut self.line_buffer, entry.as_slice());
+        }
+    }
+
+    /// Replaces the edited line and its echo with `content`.
+    fn replace_line(writer: &mut Writer<Out>, line: &mut Buffer, content: &[u8]) {
+        writer.putc_repeat(BACKSPACE, line.cursor());
+        writer.puts(content);
+        let erase = line.len().saturating_sub(content.len());
+        writer.putc_repeat(b' ', erase);
+        writer.putc_repeat(BACKSPACE, erase);
+        let _ = line.copy_from_slice(content);
+    }
+
+    /// Handles a key press while a range dump waits at the "more" prompt.
+    fn continue_more(&mut self, c: u8) {
+        // remove the prompt
+        self.writer.carriage_return();
+        self.writer.putc_repeat(b' ', command::MORE_PROMPT.len());
+        self.writer.carriage_return();
+        match c {
+            b'Q' | b'q' | ESC | 0x03 => self.context.more = None,
+            _ => command::Command::print_more(&mut self.writer, &mut self.context),
+        }
+        if self.context.more.is_none() {
+            self.echo_prompt();
         }
     }
 
@@ -79,45 +351,154 @@ impl<In: mystd::io::Read, Out: mystd::io::Write> Monitor<In, Out> {
         self.writer.puts(self.line_buffer.as_slice());
     }
 
-    fn echo_error(&mut self, position: usize) {
+    fn echo_error(&mut self, position: usize, message: &[u8]) {
         self.echo_line_buffer();
         self.writer.newline();
         for _ in 0..position {
             self.writer.putc(b' ');
         }
-        self.writer.puts(b"^! Error");
+        self.writer.puts(b"^! ");
+        self.writer.puts(message);
     }
 
-    fn echo_backspace(&mut self) {
-        self.writer.puts(&[0x08, 0x20, 0x08]);
-    }
-
-    fn execute(&self, address: usize) -> ! {
-        unsafe {
-            #[cfg(target_arch = "arm")]
-            core::arch::asm!(
-                "mov {0}, {1}",
-                "blx {0}",
-                out(reg) _,
-                in(reg) address
-            );
-
-            #[cfg(target_arch = "aarch64")]
-            core::arch::asm!(
-                "blr {0}",
-                in(reg) address
-            );
-        }
-        panic!()
+    /// Echoes the rest of the line after an edit, blanks `erase` characters after it, and moves
+    /// back to the cursor.
+    fn echo_after_cursor(&mut self, erase: usize) {
+        let tail = self.line_buffer.after_cursor();
+        self.writer.puts(tail);
+        self.writer.putc_repeat(b' ', erase);
+        self.writer.putc_repeat(BACKSPACE, tail.len() + erase);
     }
 
     fn submit(&mut self) {
-        match command::Command::parse(self.line_buffer.as_slice()) {
-            Ok(command) => command.run(&mut self.writer, &mut self.context),
+        match command::Command::parse(self.line_buffer.as_slice(), &self.context) {
+            Ok(command) => command.run(&mut self.input, &mut self.writer, &mut self.context),
             Err(err) => match err {
-                command::CommandParseError::IllegalToken { position } => self.echo_error(position),
+                command::CommandParseError::IllegalToken { position } => {
+                    self.echo_error(position, b"Error")
+                }
+                command::CommandParseError::MissingValue { position } => {
+                    self.echo_error(position, b"Missing Value")
+                }
+                command::CommandParseError::TooManyValues { position } => {
+                    self.echo_error(position, b"Too Many Values")
+                }
+                command::CommandParseError::InvalidRange { position } => {
+                    self.echo_error(position, b"Invalid Range")
+                }
+                command::CommandParseError::UnknownSymbol { position } => {
+                    self.echo_error(position, b"Unknown Symbol")
+                }
+                command::CommandParseError::UnknownRegister { position } => {
+                    self.echo_error(position, b"Unknown Register")
+                }
+                command::CommandParseError::InvalidArgument { position } => {
+                    self.echo_error(position, b"Invalid Argument")
+                }
             },
         }
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    extern crate std;
+
+    type TestMonitor<'a> = Monitor<&'a [u8], &'a mut [u8]>;
+
+    /// Feeds all of `input` to a fresh monitor and returns it for inspection.
+    fn session<'a>(input: &'a [u8], output: &'a mut [u8]) -> TestMonitor<'a> {
+        let mut monitor = Monitor::new(input, output, extension::tests::EXTENSIONS);
+        monitor.start();
+        while monitor.process().is_ok() {}
+        monitor
+    }
+
+    /// The bytes written by a session, the monitor never writes NUL.
+    fn written(output: &[u8]) -> &[u8] {
+        let len = output.iter().rposition(|c| *c != 0).map_or(0, |i| i + 1);
+        &output[..len]
+    }
+
+    #[test]
+    fn edits_in_line() {
+        let mut output = [0; 256];
+        let monitor = session(b"ac\x1b[Db", &mut output);
+        assert_eq!(b"abc", monitor.line_buffer.as_slice());
+        assert_eq!(2, monitor.line_buffer.cursor());
+        drop(monitor);
+        assert_eq!(b"\x0c\n0>ac\x08bc\x08", written(&output));
+
+        let mut output = [0; 256];
+        let monitor = session(b"8010\x1b[D\x1b[D\x7f5\x1bOH\x1b[3~\x1b[F", &mut output);
+        assert_eq!(b"510", monitor.line_buffer.as_slice());
+        assert_eq!(3, monitor.line_buffer.cursor());
+    }
+
+    #[test]
+    fn backspace_redraws_rest_of_line() {
+        let mut output = [0; 256];
+        let _ = session(b"abc\x1b[D\x7f", &mut output);
+        assert_eq!(b"\x0c\n0>abc\x08\x08c \x08\x08", written(&output));
+    }
+
+    #[test]
+    fn recalls_history() {
+        let mut output = [0; 1024];
+        let mut monitor = session(b"foo\rbar\rbar\rba", &mut output);
+        assert_eq!(2, monitor.history.len());
+        let mut press = |keys: &'static [u8]| {
+            monitor.input = keys;
+            while monitor.process().is_ok() {}
+            monitor.line_buffer.as_slice().to_vec()
+        };
+        assert_eq!(b"bar", press(b"\x1b[A").as_slice());
+        assert_eq!(b"foo", press(b"\x1b[A").as_slice());
+        assert_eq!(b"foo", press(b"\x1b[A").as_slice());
+        assert_eq!(b"bar", press(b"\x1b[B").as_slice());
+        assert_eq!(b"ba", press(b"\x1b[B").as_slice());
+        assert_eq!(b"ba", press(b"\x1b[B").as_slice());
+    }
+
+    #[test]
+    fn completes_command_letters() {
+        let mut output = [0; 256];
+        let monitor = session(b"l\t", &mut output);
+        assert_eq!(b"l ", monitor.line_buffer.as_slice());
+
+        let mut output = [0; 256];
+        let monitor = session(b"A\t", &mut output);
+        assert_eq!(b"Add ", monitor.line_buffer.as_slice());
+
+        let mut output = [0; 512];
+        let _ = session(b"\t", &mut output);
+        let output = written(&output);
+        assert!(output.starts_with(b"\x0c\n0>\nL list instructions\nR run a function\n"));
+        assert_eq!(
+            command::COMMAND_LETTERS.len() + extension::tests::EXTENSIONS.len(),
+            output.split(|c| *c == b'\n').count() - 3
+        );
+        assert!(output.ends_with(b"\nadd\n0>"));
+    }
+
+    #[test]
+    fn runs_extensions_and_help() {
+        let mut output = [0; 256];
+        let _ = session(b"ADD 1 F\radd x\r", &mut output);
+        assert_eq!(
+            b"\x0c\n0>ADD 1 F\r10\n0>add x\radd x\n    ^! Error\n0>",
+            written(&output)
+        );
+
+        let mut output = [0; 2048];
+        let _ = session(b"?\r", &mut output);
+        let output = std::str::from_utf8(written(&output)).unwrap();
+        let mut lines = output.lines().skip(2);
+        assert_eq!(Some("ADDR                  show memory"), lines.next());
+        assert!(output.contains("\nR ADDR [X0 X1 X2 X3]  run a function\n"));
+        assert!(output.contains("\n?                     list the commands\n"));
+        assert!(output.ends_with("\nadd A B | ON | OFF    print the sum\n0>"));
+    }
+}
@@ -0,0 +1,77 @@
+//! Named registers the monitor can read and write with the `S` command.
+//!
+//! The monitor knows nothing about the registers itself, the kernel plugs in a [RegisterProvider]
+//! with `Monitor::with_registers`.
+
+pub enum RegisterError {
+    /// The register can't be written.
+    ReadOnly,
+    /// The register is not accessible at the current exception level.
+    Inaccessible,
+}
+
+pub trait RegisterProvider {
+    /// Number of registers, they are addressed by index `0..count()`.
+    fn count(&self) -> usize;
+
+    /// The name of register `index`, e.g. `SCTLR_EL1`.
+    fn name(&self, index: usize) -> &str;
+
+    /// Reads register `index` and prints it decoded, field by field.
+    fn read(&self, index: usize, out: &mut dyn core::fmt::Write) -> Result<(), RegisterError>;
+
+    /// Writes `value` to register `index`.
+    fn write(&self, index: usize, value: u64) -> Result<(), RegisterError>;
+
+    /// Finds a register by its name, ignoring case.
+    fn find(&self, name: &[u8]) -> Option<usize> {
+        (0..self.count()).find(|i| self.name(*i).as_bytes().eq_ignore_ascii_case(name))
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use core::cell::Cell;
+
+    /// Two fake registers, `COUNTER` can be written, `ID` is read only.
+    pub(crate) struct TestRegisters {
+        pub(crate) counter: Cell<u64>,
+    }
+
+    impl RegisterProvider for TestRegisters {
+        fn count(&self) -> usize {
+            2
+        }
+
+        fn name(&self, index: usize) -> &str {
+            ["COUNTER", "ID"][index]
+        }
+
+        fn read(&self, index: usize, out: &mut dyn core::fmt::Write) -> Result<(), RegisterError> {
+            let value = if index == 0 { self.counter.get() } else { 0x42 };
+            let _ = write!(out, "{value:#x}");
+            Ok(())
+        }
+
+        fn write(&self, index: usize, value: u64) -> Result<(), RegisterError> {
+            match index {
+                0 => {
+                    self.counter.set(value);
+                    Ok(())
+                }
+                _ => Err(RegisterError::ReadOnly),
+            }
+        }
+    }
+
+    #[test]
+    fn find_ignores_case() {
+        let registers = TestRegisters {
+            counter: Cell::new(0),
+        };
+        assert_eq!(Some(0), registers.find(b"counter"));
+        assert_eq!(Some(1), registers.find(b"ID"));
+        assert_eq!(None, registers.find(b"IDX"));
+    }
+}
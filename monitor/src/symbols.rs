@@ -0,0 +1,185 @@
+//! A compact, address sorted symbol table, so the monitor can take and show names instead of
+//! raw addresses.
+//!
+//! The table is produced by the `symtab` tool from the kernel's ELF symbol table. All values are
+//! little endian:
+//!
+//! | offset             | size | content                                                 |
+//! |--------------------|------|---------------------------------------------------------|
+//! | 0                  | 4    | magic `SYMT`                                            |
+//! | 4                  | 4    | number of entries                                       |
+//! | 8 + 16 * i         | 8    | address of entry `i`                                    |
+//! | 16 + 16 * i        | 4    | size of entry `i` in bytes, 0 for labels                |
+//! | 20 + 16 * i        | 4    | offset of the NUL terminated name, from the table start |
+//!
+//! Entries are sorted by address, at equal addresses labels come before sized symbols.
+
+pub const MAGIC: &[u8; 4] = b"SYMT";
+const HEADER_LEN: usize = 8;
+const ENTRY_LEN: usize = 16;
+
+#[derive(Clone, Copy)]
+pub struct Symbol<'a> {
+    pub address: usize,
+    pub size: usize,
+    pub name: &'a [u8],
+}
+
+#[derive(Clone, Copy)]
+pub struct SymbolTable<'a> {
+    data: &'a [u8],
+    len: usize,
+}
+
+impl<'a> SymbolTable<'a> {
+    pub const fn empty() -> Self {
+        Self { data: &[], len: 0 }
+    }
+
+    /// Reads the table from `data`. An invalid or truncated table results in an empty one, e.g.
+    /// when the kernel image was not processed by the `symtab` tool.
+    pub fn from_bytes(data: &'a [u8]) -> Self {
+        let Some(header) = data.get(..HEADER_LEN) else {
+            return Self::empty();
+        };
+        let count = read_u32(header, 4) as usize;
+        if &header[..4] != MAGIC || data.len() < HEADER_LEN + count * ENTRY_LEN {
+            return Self::empty();
+        }
+        Self { data, len: count }
+    }
+
+    pub const fn len(&self) -> usize {
+        self.len
+    }
+
+    pub const fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn get(&self, index: usize) -> Option<Symbol<'a>> {
+        if index >= self.len {
+            return None;
+        }
+        let entry = HEADER_LEN + index * ENTRY_LEN;
+        let name_start = read_u32(self.data, entry + 12) as usize;
+        let name = self.data.get(name_start..).unwrap_or_default();
+        let name_len = name.iter().position(|c| *c == 0).unwrap_or(name.len());
+        Some(Symbol {
+            address: read_u64(self.data, entry) as usize,
+            size: read_u32(self.data, entry + 8) as usize,
+            name: &name[..name_len],
+        })
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = Symbol<'a>> + '_ {
+        (0..self.len).filter_map(|i| self.get(i))
+    }
+
+    /// Finds a symbol by its full name, e.g. `picrust::main`, or failing that by the last segment
+    /// of its path, e.g. `main`.
+    pub fn find(&self, name: &[u8]) -> Option<Symbol<'a>> {
+        self.iter().find(|symbol| symbol.name == name).or_else(|| {
+            self.iter().find(|symbol| {
+                symbol
+                    .name
+                    .strip_suffix(name)
+                    .is_some_and(|path| path.ends_with(b"::"))
+            })
+        })
+    }
+
+    /// Finds the symbol `address` belongs to, and the offset of `address` into it.
+    ///
+    /// Labels (symbols without a size) reach up to the next symbol, the last one only covers
+    /// its own address.
+    pub fn lookup(&self, address: usize) -> Option<(Symbol<'a>, usize)> {
+        // index of the first symbol after the address
+        let (mut low, mut high) = (0, self.len);
+        while low < high {
+            let mid = (low + high) / 2;
+            if self.get(mid)?.address <= address {
+                low = mid + 1;
+            } else {
+                high = mid;
+            }
+        }
+        let symbol = self.get(low.checked_sub(1)?)?;
+        let offset = address - symbol.address;
+        let covered = if symbol.size != 0 {
+            offset < symbol.size
+        } else {
+            low < self.len || offset == 0
+        };
+        covered.then_some((symbol, offset))
+    }
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    let mut bytes = [0; 4];
+    bytes.copy_from_slice(&data[offset..offset + 4]);
+    u32::from_le_bytes(bytes)
+}
+
+fn read_u64(data: &[u8], offset: usize) -> u64 {
+    let mut bytes = [0; 8];
+    bytes.copy_from_slice(&data[offset..offset + 8]);
+    u64::from_le_bytes(bytes)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// A table with `_start` (0x80000, 0x20 bytes), `picrust::main` (0x80020, 0x10 bytes)
+    /// and the label `__bss_start` at 0x90000.
+    pub(crate) const TABLE: &[u8] = &[
+        b'S', b'Y', b'M', b'T', 3, 0, 0, 0, //
+        0x00, 0x00, 0x08, 0, 0, 0, 0, 0, 0x20, 0, 0, 0, 56, 0, 0, 0, //
+        0x20, 0x00, 0x08, 0, 0, 0, 0, 0, 0x10, 0, 0, 0, 63, 0, 0, 0, //
+        0x00, 0x00, 0x09, 0, 0, 0, 0, 0, 0x00, 0, 0, 0, 77, 0, 0, 0, //
+        b'_', b's', b't', b'a', b'r', b't', 0, //
+        b'p', b'i', b'c', b'r', b'u', b's', b't', b':', b':', b'm', b'a', b'i', b'n', 0, //
+        b'_', b'_', b'b', b's', b's', b'_', b's', b't', b'a', b'r', b't', 0,
+    ];
+
+    #[test]
+    fn reads_entries() {
+        let table = SymbolTable::from_bytes(TABLE);
+        assert_eq!(3, table.len());
+        let main = table.get(1).unwrap();
+        assert_eq!(0x80020, main.address);
+        assert_eq!(0x10, main.size);
+        assert_eq!(b"picrust::main", main.name);
+        assert!(table.get(3).is_none());
+    }
+
+    #[test]
+    fn invalid_table_is_empty() {
+        assert!(SymbolTable::from_bytes(&[0; 64]).is_empty());
+        assert!(SymbolTable::from_bytes(&TABLE[..20]).is_empty());
+    }
+
+    #[test]
+    fn find_by_name() {
+        let table = SymbolTable::from_bytes(TABLE);
+        assert_eq!(0x80020, table.find(b"picrust::main").unwrap().address);
+        assert_eq!(0x80020, table.find(b"main").unwrap().address);
+        assert_eq!(0x90000, table.find(b"__bss_start").unwrap().address);
+        assert!(table.find(b"ain").is_none());
+        assert!(table.find(b"start").is_none());
+    }
+
+    #[test]
+    fn lookup_by_address() {
+        let table = SymbolTable::from_bytes(TABLE);
+        let name_and_offset = |address| table.lookup(address).map(|(s, offset)| (s.name, offset));
+        assert_eq!(Some((&b"_start"[..], 0)), name_and_offset(0x80000));
+        assert_eq!(Some((&b"_start"[..], 0x1c)), name_and_offset(0x8001c));
+        assert_eq!(Some((&b"picrust::main"[..], 4)), name_and_offset(0x80024));
+        assert_eq!(None, name_and_offset(0x80030));
+        assert_eq!(None, name_and_offset(0x7fffc));
+        assert_eq!(Some((&b"__bss_start"[..], 0)), name_and_offset(0x90000));
+        assert_eq!(None, name_and_offset(0x90004));
+    }
+}
@@ -1,3 +1,4 @@
+use crate::symbols::SymbolTable;
 use mystd::parse;
 
 pub struct Tokenizer<'a> {
@@ -49,9 +50,8 @@ impl Iterator for Tokenizer<'_> {
                 b'.' => Some(TokenType::Dot),
                 b'+' => Some(TokenType::Plus),
                 b'-' => Some(TokenType::Minus),
-                c if c.is_ascii_alphabetic() && !c.is_ascii_hexdigit() => {
-                    Some(TokenType::SingleLetter(*c))
-                }
+                b'=' => Some(TokenType::Equals),
+                b'?' => Some(TokenType::Question),
                 _ => None,
             };
 
@@ -60,24 +60,33 @@ impl Iterator for Tokenizer<'_> {
                 return symbol.map(|token_type| Token::new(token_type, start, end));
             }
 
-            // try to parse a hex value
-            while self
-                .c_str
-                .get(self.position)
-                .is_some_and(u8::is_ascii_hexdigit)
-            {
-                self.position += 1;
+            // words are hex values (optionally prefixed with 0x), single letters or symbol names
+            while let Some(c) = self.c_str.get(self.position) {
+                if is_word_char(*c) {
+                    self.position += 1;
+                } else if self.c_str[self.position..].starts_with(b"::") {
+                    self.position += 2;
+                } else {
+                    break;
+                }
             }
             if self.position != start {
-                if let Some(Ok(value)) = self
-                    .c_str
-                    .get(start..self.position)
-                    .map(parse::from_hex_be_usize)
-                {
-                    return Some(Token::new(TokenType::USize(value), start, self.position));
+                let word = &self.c_str[start..self.position];
+                let digits = match word {
+                    [b'0', b'x' | b'X', digits @ ..] if !digits.is_empty() => digits,
+                    _ => word,
+                };
+                let token_type = if digits.iter().all(u8::is_ascii_hexdigit) {
+                    match parse::from_hex_be_usize(digits) {
+                        Ok(value) => TokenType::USize(value),
+                        Err(_) => TokenType::Unknown,
+                    }
+                } else if let [letter] = word {
+                    TokenType::SingleLetter(letter.to_ascii_uppercase())
                 } else {
-                    return Some(Token::new(TokenType::Unknown, start, self.position));
-                }
+                    TokenType::Symbol
+                };
+                return Some(Token::new(token_type, start, self.position));
             }
 
             self.position += 1;
@@ -86,15 +95,85 @@ impl Iterator for Tokenizer<'_> {
     }
 }
 
+fn is_word_char(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || c == b'_' || c == b'$'
+}
+
+/// Resolves symbol names and `+`/`-` offsets in the tokens of a [Tokenizer], so the parser only
+/// has to deal with values, e.g. `exc_handler+0x40` becomes a single [TokenType::USize].
+///
+/// Symbols that are not in the table are passed on as [TokenType::Symbol].
+pub struct Resolver<'a, 's> {
+    c_str: &'a [u8],
+    tokens: core::iter::Peekable<Tokenizer<'a>>,
+    symbols: &'s SymbolTable<'s>,
+}
+
+impl<'a, 's> Resolver<'a, 's> {
+    pub fn new(tokenizer: Tokenizer<'a>, symbols: &'s SymbolTable<'s>) -> Self {
+        Self {
+            c_str: tokenizer.c_str,
+            tokens: tokenizer.peekable(),
+            symbols,
+        }
+    }
+}
+
+impl Iterator for Resolver<'_, '_> {
+    type Item = Token;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let token = self.tokens.next()?;
+        let base = match token.token_type {
+            TokenType::USize(value) => value,
+            TokenType::Symbol => {
+                let name = &self.c_str[token.start..token.end];
+                match self.symbols.find(name) {
+                    Some(symbol) => symbol.address,
+                    None => return Some(token),
+                }
+            }
+            _ => return Some(token),
+        };
+        let Some(Token {
+            token_type: TokenType::Plus | TokenType::Minus,
+            ..
+        }) = self.tokens.peek()
+        else {
+            return Some(Token::new(TokenType::USize(base), token.start, token.end));
+        };
+        let operator = self.tokens.next()?;
+        match self.tokens.next() {
+            Some(Token {
+                token_type: TokenType::USize(offset),
+                end,
+                ..
+            }) => {
+                let value = if matches!(operator.token_type, TokenType::Plus) {
+                    base.wrapping_add(offset)
+                } else {
+                    base.wrapping_sub(offset)
+                };
+                Some(Token::new(TokenType::USize(value), token.start, end))
+            }
+            _ => Some(Token::new(TokenType::Unknown, operator.start, operator.end)),
+        }
+    }
+}
+
 pub enum TokenType {
     Unknown,
     WhiteSpace,
     USize(usize),
     SingleLetter(u8),
+    /// A word that is neither a hex value nor a single letter.
+    Symbol,
     Dot,
     Colon,
     Minus,
     Plus,
+    Equals,
+    Question,
 }
 
 pub struct Token {
@@ -111,4 +190,73 @@ impl Token {
             end,
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::symbols;
+
+    fn token_types(c_str: &[u8]) -> impl Iterator<Item = TokenType> + '_ {
+        Tokenizer::new(c_str, true).map(|t| t.token_type)
+    }
+
+    #[test]
+    fn words() {
+        let mut tokens = token_types(b"l 8000 0x1f ab main $x0 picrust::main:");
+        assert!(matches!(tokens.next(), Some(TokenType::SingleLetter(b'L'))));
+        assert!(matches!(tokens.next(), Some(TokenType::USize(0x8000))));
+        assert!(matches!(tokens.next(), Some(TokenType::USize(0x1f))));
+        assert!(matches!(tokens.next(), Some(TokenType::USize(0xab))));
+        assert!(matches!(tokens.next(), Some(TokenType::Symbol)));
+        assert!(matches!(tokens.next(), Some(TokenType::Symbol)));
+        assert!(matches!(tokens.next(), Some(TokenType::Symbol)));
+        assert!(matches!(tokens.next(), Some(TokenType::Colon)));
+        assert!(tokens.next().is_none());
+    }
+
+    #[test]
+    fn resolves_symbols_and_offsets() {
+        let table = SymbolTable::from_bytes(symbols::tests::TABLE);
+        let c_str = b"main+0x10 _start-4 picrust::main nothing 10+";
+        let mut tokens = Resolver::new(Tokenizer::new(c_str, true), &table);
+        assert!(matches!(
+            tokens.next(),
+            Some(Token {
+                token_type: TokenType::USize(0x80030),
+                start: 0,
+                end: 9
+            })
+        ));
+        assert!(matches!(
+            tokens.next(),
+            Some(Token {
+                token_type: TokenType::USize(0x7fffc),
+                ..
+            })
+        ));
+        assert!(matches!(
+            tokens.next(),
+            Some(Token {
+                token_type: TokenType::USize(0x80020),
+                ..
+            })
+        ));
+        assert!(matches!(
+            tokens.next(),
+            Some(Token {
+                token_type: TokenType::Symbol,
+                start: 33,
+                end: 40
+            })
+        ));
+        assert!(matches!(
+            tokens.next(),
+            Some(Token {
+                token_type: TokenType::Unknown,
+                start: 43,
+                ..
+            })
+        ));
+    }
+}
@@ -8,6 +8,11 @@ impl<Out: mystd::io::Write> Writer<Out> {
         Self(output)
     }
 
+    /// The output, for protocols that send raw bytes.
+    pub fn output(&mut self) -> &mut Out {
+        &mut self.0
+    }
+
     pub fn putc(&mut self, char: u8) {
         self.0.write(&[char]).expect("putc should work");
     }
@@ -132,3 +137,10 @@ impl<Out: mystd::io::Write> Writer<Out> {
         self.putc(b'\r');
     }
 }
+
+impl<Out: mystd::io::Write> core::fmt::Write for Writer<Out> {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        self.puts(s.as_bytes());
+        Ok(())
+    }
+}
@@ -0,0 +1,127 @@
+//! Storage that is read and written in blocks of [BLOCK_SIZE] bytes, like SD cards.
+
+use core::fmt::Debug;
+
+/// The block size of every device, what SD cards use.
+pub const BLOCK_SIZE: usize = 512;
+
+pub trait BlockDevice {
+    type Error: Debug;
+
+    /// How many blocks the device has.
+    fn block_count(&self) -> u64;
+
+    /// Reads the blocks from `first` on into `buffer`, whose length is a multiple of
+    /// [BLOCK_SIZE].
+    fn read_blocks(&mut self, first: u64, buffer: &mut [u8]) -> Result<(), Self::Error>;
+
+    /// Writes `buffer`, a multiple of [BLOCK_SIZE] long, to the blocks from `first` on.
+    fn write_blocks(&mut self, first: u64, buffer: &[u8]) -> Result<(), Self::Error>;
+}
+
+impl<D: BlockDevice + ?Sized> BlockDevice for &mut D {
+    type Error = D::Error;
+
+    fn block_count(&self) -> u64 {
+        (**self).block_count()
+    }
+
+    fn read_blocks(&mut self, first: u64, buffer: &mut [u8]) -> Result<(), Self::Error> {
+        (**self).read_blocks(first, buffer)
+    }
+
+    fn write_blocks(&mut self, first: u64, buffer: &[u8]) -> Result<(), Self::Error> {
+        (**self).write_blocks(first, buffer)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockError {
+    /// The buffer isn't a multiple of [BLOCK_SIZE] long.
+    PartialBlock,
+    /// The blocks go past the end of the device.
+    OutOfRange,
+}
+
+/// Checks the arguments of a transfer of `len` bytes from `first` on, for a device with
+/// `block_count` blocks. Returns the number of blocks.
+pub fn check_transfer(first: u64, len: usize, block_count: u64) -> Result<u64, BlockError> {
+    if !len.is_multiple_of(BLOCK_SIZE) {
+        return Err(BlockError::PartialBlock);
+    }
+    let count = (len / BLOCK_SIZE) as u64;
+    match first.checked_add(count) {
+        Some(end) if end <= block_count => Ok(count),
+        _ => Err(BlockError::OutOfRange),
+    }
+}
+
+/// A block device in memory, e.g. a disk image.
+pub struct RamDisk<T> {
+    data: T,
+}
+
+impl<T: AsRef<[u8]> + AsMut<[u8]>> RamDisk<T> {
+    /// Whole blocks of `data`, a partial block at the end is left out.
+    pub fn new(data: T) -> Self {
+        Self { data }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.data
+    }
+}
+
+impl<T: AsRef<[u8]> + AsMut<[u8]>> BlockDevice for RamDisk<T> {
+    type Error = BlockError;
+
+    fn block_count(&self) -> u64 {
+        (self.data.as_ref().len() / BLOCK_SIZE) as u64
+    }
+
+    fn read_blocks(&mut self, first: u64, buffer: &mut [u8]) -> Result<(), Self::Error> {
+        check_transfer(first, buffer.len(), self.block_count())?;
+        let start = first as usize * BLOCK_SIZE;
+        buffer.copy_from_slice(&self.data.as_ref()[start..start + buffer.len()]);
+        Ok(())
+    }
+
+    fn write_blocks(&mut self, first: u64, buffer: &[u8]) -> Result<(), Self::Error> {
+        check_transfer(first, buffer.len(), self.block_count())?;
+        let start = first as usize * BLOCK_SIZE;
+        self.data.as_mut()[start..start + buffer.len()].copy_from_slice(buffer);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::vec;
+
+    use super::*;
+
+    #[test]
+    fn ram_disk_transfers_whole_blocks() {
+        let mut disk = RamDisk::new(vec![0_u8; 4 * BLOCK_SIZE + 100]);
+        assert_eq!(disk.block_count(), 4);
+        disk.write_blocks(1, &[0xaa; 2 * BLOCK_SIZE]).unwrap();
+        let mut buffer = [0; 3 * BLOCK_SIZE];
+        disk.read_blocks(0, &mut buffer).unwrap();
+        assert!(buffer[..BLOCK_SIZE].iter().all(|&b| b == 0));
+        assert!(buffer[BLOCK_SIZE..].iter().all(|&b| b == 0xaa));
+        assert_eq!(
+            disk.read_blocks(3, &mut buffer),
+            Err(BlockError::OutOfRange)
+        );
+        assert_eq!(
+            disk.write_blocks(0, &buffer[..100]),
+            Err(BlockError::PartialBlock)
+        );
+        assert_eq!(
+            check_transfer(u64::MAX, BLOCK_SIZE, 4),
+            Err(BlockError::OutOfRange)
+        );
+    }
+}
@@ -3,6 +3,8 @@ use super::MutSliceable;
 use super::Sliceable;
 use core::marker::PhantomData;
 
+/// A line of up to `N` values with an edit cursor, the cursor is the index where the next value is
+/// inserted (`0..=len`).
 pub struct Line<T, S: Sliceable<T>, const N: usize> {
     cursor: usize,
     end: usize,
@@ -28,9 +30,18 @@ impl<T, S: Sliceable<T>, const N: usize> Line<T, S, N> {
         0 == self.end
     }
 
+    pub fn len(&self) -> usize {
+        self.end
+    }
+
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
     pub fn pop_back(&mut self) -> Option<&T> {
         if !self.is_empty() {
             self.end -= 1;
+            self.cursor = self.cursor.min(self.end);
             let result = Some(&self.data.as_slice()[self.end]);
             result
         } else {
@@ -38,6 +49,38 @@ impl<T, S: Sliceable<T>, const N: usize> Line<T, S, N> {
         }
     }
 
+    /// Moves the cursor one position to the left, returns false if it already was at the start.
+    pub fn move_left(&mut self) -> bool {
+        if self.cursor > 0 {
+            self.cursor -= 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Moves the cursor one position to the right, returns false if it already was at the end.
+    pub fn move_right(&mut self) -> bool {
+        if self.cursor < self.end {
+            self.cursor += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Moves the cursor to the start, returns the number of positions moved.
+    pub fn move_to_start(&mut self) -> usize {
+        core::mem::take(&mut self.cursor)
+    }
+
+    /// Moves the cursor to the end, returns the number of positions moved.
+    pub fn move_to_end(&mut self) -> usize {
+        let moved = self.end - self.cursor;
+        self.cursor = self.end;
+        moved
+    }
+
     pub fn clear(&mut self) {
         self.end = 0;
         self.cursor = 0;
@@ -46,6 +89,14 @@ impl<T, S: Sliceable<T>, const N: usize> Line<T, S, N> {
     pub fn as_slice(&self) -> &[T] {
         unsafe { self.data.as_slice().get_unchecked(..self.end) }
     }
+
+    pub fn before_cursor(&self) -> &[T] {
+        &self.as_slice()[..self.cursor]
+    }
+
+    pub fn after_cursor(&self) -> &[T] {
+        &self.as_slice()[self.cursor..]
+    }
 }
 
 impl<T, S: MutSliceable<T>, const N: usize> Line<T, S, N> {
@@ -55,13 +106,71 @@ impl<T, S: MutSliceable<T>, const N: usize> Line<T, S, N> {
                 write_index: self.end,
             })
         } else {
-            self.cursor = self.end;
-            self.data.as_mut_slice()[self.cursor] = value;
+            self.data.as_mut_slice()[self.end] = value;
             self.end += 1;
+            self.cursor = self.end;
             Ok(())
         }
     }
 
+    /// Inserts `value` at the cursor and moves the cursor behind it.
+    pub fn insert(&mut self, value: T) -> Result<(), BufferError>
+    where
+        T: Copy,
+    {
+        if self.is_full() {
+            return Err(BufferError::Overflow {
+                write_index: self.cursor,
+            });
+        }
+        let data = self.data.as_mut_slice();
+        data.copy_within(self.cursor..self.end, self.cursor + 1);
+        data[self.cursor] = value;
+        self.cursor += 1;
+        self.end += 1;
+        Ok(())
+    }
+
+    /// Removes the value left of the cursor, like a backspace.
+    pub fn remove_before_cursor(&mut self) -> Option<T>
+    where
+        T: Copy,
+    {
+        if self.cursor == 0 {
+            return None;
+        }
+        self.cursor -= 1;
+        self.remove_at_cursor()
+    }
+
+    /// Removes the value under the cursor, like the delete key.
+    pub fn remove_at_cursor(&mut self) -> Option<T>
+    where
+        T: Copy,
+    {
+        if self.cursor == self.end {
+            return None;
+        }
+        let data = self.data.as_mut_slice();
+        let value = data[self.cursor];
+        data.copy_within(self.cursor + 1..self.end, self.cursor);
+        self.end -= 1;
+        Some(value)
+    }
+
+    /// Replaces the content with `src` and moves the cursor to the end.
+    pub fn copy_from_slice(&mut self, src: &[T]) -> Result<(), BufferError>
+    where
+        T: Copy,
+    {
+        if src.len() > N {
+            return Err(BufferError::Overflow { write_index: N });
+        }
+        self.data.as_mut_slice()[..src.len()].copy_from_slice(src);
+        self.end = src.len();
+        self.cursor = self.end;
+        Ok(())
+    }
 }
 
 pub type LineArray<T, const N: usize> = Line<T, [T; N], N>;
@@ -71,3 +180,60 @@ impl<T: Default + Copy, const N: usize> LineArray<T, N> {
         Self::adapting([T::default(); N])
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn line_push_and_pop_works() -> Result<(), BufferError> {
+        let mut line: LineArray<u8, 4> = LineArray::new();
+        for c in b"abcd" {
+            line.push_back(*c)?;
+        }
+        assert!(line.is_full());
+        assert!(line.push_back(b'e').is_err());
+        assert_eq!(4, line.cursor());
+        assert_eq!(Some(&b'd'), line.pop_back());
+        assert_eq!(b"abc", line.as_slice());
+        assert_eq!(3, line.cursor());
+        Ok(())
+    }
+
+    #[test]
+    fn line_cursor_movement_works() -> Result<(), BufferError> {
+        let mut line: LineArray<u8, 8> = LineArray::new();
+        line.copy_from_slice(b"abc")?;
+        assert!(!line.move_right());
+        assert!(line.move_left());
+        assert_eq!(b"ab", line.before_cursor());
+        assert_eq!(b"c", line.after_cursor());
+        assert_eq!(2, line.move_to_start());
+        assert!(!line.move_left());
+        assert_eq!(3, line.move_to_end());
+        Ok(())
+    }
+
+    #[test]
+    fn line_insert_and_remove_works() -> Result<(), BufferError> {
+        let mut line: LineArray<u8, 4> = LineArray::new();
+        line.copy_from_slice(b"ac")?;
+        line.move_left();
+        line.insert(b'b')?;
+        assert_eq!(b"abc", line.as_slice());
+        assert_eq!(2, line.cursor());
+        line.move_to_start();
+        line.insert(b'_')?;
+        assert!(line.insert(b'!').is_err());
+        assert_eq!(b"_abc", line.as_slice());
+
+        assert_eq!(Some(b'_'), line.remove_before_cursor());
+        assert_eq!(None, line.remove_before_cursor());
+        assert_eq!(Some(b'a'), line.remove_at_cursor());
+        assert_eq!(b"bc", line.as_slice());
+        line.move_to_end();
+        assert_eq!(None, line.remove_at_cursor());
+        assert!(line.copy_from_slice(b"too long").is_err());
+        Ok(())
+    }
+}
@@ -121,6 +121,16 @@ impl<T, S: Sliceable<T>, const N: usize> Ring<T, S, N> {
         }
     }
 
+    /// The element at `index`, counting from the oldest one.
+    pub fn get(&self, index: usize) -> Option<&T> {
+        if index < self.len() {
+            let i = self.read.add(index).1;
+            Some(&self.data.as_slice()[i.value()])
+        } else {
+            None
+        }
+    }
+
     pub fn clear(&mut self) {
         self.read = self.write;
     }
@@ -361,6 +371,20 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn ring_get_works() -> Result<(), BufferError> {
+        let mut ring: RingArray<u8, 4> = RingArray::new();
+        for i in 1..=3 {
+            ring.put(i)?;
+        }
+        ring.pop();
+        ring.put(4)?;
+        assert_eq!(Some(&2), ring.get(0));
+        assert_eq!(Some(&4), ring.get(2));
+        assert_eq!(None, ring.get(3));
+        Ok(())
+    }
+
     #[test]
     fn ring_make_continuous_works() -> Result<(), BufferError> {
         let mut ring: RingArray<u8, 8> = RingArray::new();
@@ -1,19 +1,37 @@
 use atomic::Ordering::*;
+use core::cell::UnsafeCell;
 use core::sync::atomic;
 
+use crate::io;
+
+/// A ring of 255 values for one producer and one consumer, e.g. an interrupt handler and a
+/// reader, that don't need a lock to share it. Keeping it to one of each is up to the users, which
+/// is why putting and taking are `unsafe`.
 pub struct AtomicRing256<T> {
     read_write_idx: atomic::AtomicU16,
-    data: [T; 256],
+    data: UnsafeCell<[T; 256]>,
 }
 
+// the producer only writes the slot at the write index, the consumer only reads the one at the
+// read index, and each only moves its own index
+unsafe impl<T: Send> Sync for AtomicRing256<T> {}
+
 impl<T> AtomicRing256<T> {
     pub fn new() -> Self
     where
         T: Default + Copy,
+    {
+        Self::filled(Default::default())
+    }
+
+    /// Creates an empty ring, `value` only fills the unused slots. Can initialize a `static`.
+    pub const fn filled(value: T) -> Self
+    where
+        T: Copy,
     {
         Self {
             read_write_idx: atomic::AtomicU16::new(0),
-            data: [Default::default(); 256],
+            data: UnsafeCell::new([value; 256]),
         }
     }
 
@@ -32,68 +50,60 @@ impl<T> AtomicRing256<T> {
         read.wrapping_sub(write).wrapping_sub(1) as usize
     }
 
-    pub fn put(&mut self, value: T) -> Result<T, ()> {
-        let index_update = self.read_write_idx.fetch_update(SeqCst, SeqCst, |current| {
-            let [read, mut write] = current.to_ne_bytes();
-            write = write.wrapping_add(1);
-            if read == write {
-                None
-            } else {
-                Some(u16::from_ne_bytes([read, write]))
-            }
-        });
-        match index_update {
-            Ok(old_index) => {
-                let [_, write] = old_index.to_ne_bytes();
-                let dest = unsafe { self.data.get_unchecked_mut(write as usize) };
-                Ok(core::mem::replace(dest, value))
-            }
-            Err(_) => Err(()),
+    /// Returns the value the slot held before, or an error when the ring is full.
+    ///
+    /// # Safety
+    /// Only one producer may put at a time, e.g. the interrupt handler, or whoever holds a lock.
+    pub unsafe fn put(&self, value: T) -> Result<T, ()> {
+        let [read, write] = self.read_write_idx.load(Acquire).to_ne_bytes();
+        if read == write.wrapping_add(1) {
+            return Err(());
         }
+        // the consumer doesn't touch this slot until the write index moves past it
+        let dest = unsafe { (*self.data.get()).get_unchecked_mut(write as usize) };
+        let old = core::mem::replace(dest, value);
+        let _ = self.read_write_idx.fetch_update(Release, Relaxed, |current| {
+            let [read, write] = current.to_ne_bytes();
+            Some(u16::from_ne_bytes([read, write.wrapping_add(1)]))
+        });
+        Ok(old)
     }
 
-    /// Returns None when the buffer is empty
-    pub fn pop_take(&mut self) -> Option<T>
+    /// Returns None when the buffer is empty.
+    ///
+    /// # Safety
+    /// Only one consumer may take or peek at a time.
+    pub unsafe fn pop_take(&self) -> Option<T>
     where
         T: Default,
     {
-        let index_update = self.read_write_idx.fetch_update(SeqCst, SeqCst, |current| {
+        let [read, write] = self.read_write_idx.load(Acquire).to_ne_bytes();
+        if read == write {
+            return None;
+        }
+        // the producer doesn't touch this slot until the read index moves past it
+        let src = unsafe { (*self.data.get()).get_unchecked_mut(read as usize) };
+        let value = core::mem::take(src);
+        let _ = self.read_write_idx.fetch_update(Release, Relaxed, |current| {
             let [read, write] = current.to_ne_bytes();
-            if read == write {
-                None
-            } else {
-                Some(u16::from_ne_bytes([read.wrapping_add(1), write]))
-            }
+            Some(u16::from_ne_bytes([read.wrapping_add(1), write]))
         });
-        match index_update {
-            Ok(old_index) => {
-                let [read, _] = old_index.to_ne_bytes();
-                let src = unsafe { self.data.get_unchecked_mut(read as usize) };
-                Some(core::mem::take(src))
-            }
-            Err(_) => None,
-        }
+        Some(value)
     }
 
-    /// Returns None when the buffer is empty
-    pub fn peek_copy(&mut self) -> Option<T>
+    /// Returns None when the buffer is empty.
+    ///
+    /// # Safety
+    /// Only one consumer may take or peek at a time.
+    pub unsafe fn peek_copy(&self) -> Option<T>
     where
         T: Copy,
     {
-        let mut latest_copy: Option<T> = None;
-        let index_update = self.read_write_idx.fetch_update(SeqCst, SeqCst, |current| {
-            let [read, write] = current.to_ne_bytes();
-            if read == write {
-                None
-            } else {
-                latest_copy = Some(*unsafe { self.data.get_unchecked(read as usize) });
-                Some(current)
-            }
-        });
-        match index_update {
-            Ok(_) => latest_copy,
-            Err(_) => None,
+        let [read, write] = self.read_write_idx.load(Acquire).to_ne_bytes();
+        if read == write {
+            return None;
         }
+        Some(unsafe { *(*self.data.get()).get_unchecked(read as usize) })
     }
 
     pub fn read_write_indexes(&self) -> (u8, u8) {
@@ -101,20 +111,72 @@ impl<T> AtomicRing256<T> {
     }
 }
 
+impl<T: Default + Copy> Default for AtomicRing256<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AtomicRing256<u8> {
+    /// Takes what the producer put so far, [io::Error::WouldBlock] if that's nothing.
+    ///
+    /// # Safety
+    /// Like [Self::pop_take], only one consumer may do this at a time.
+    pub unsafe fn read_available(&self, buf: &mut [u8]) -> io::Result<io::Size> {
+        let mut count = 0;
+        for byte in buf.iter_mut() {
+            let Some(value) = self.pop_take() else {
+                break;
+            };
+            *byte = value;
+            count += 1;
+        }
+        if count == 0 && !buf.is_empty() {
+            return Err(io::Error::WouldBlock);
+        }
+        Ok(io::Size::from_usize(count))
+    }
+}
+
+impl io::Read for AtomicRing256<u8> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<io::Size> {
+        // SAFETY: the exclusive borrow makes this the only consumer
+        unsafe { self.read_available(buf) }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
     #[test]
     fn capacity_empty_full_works() {
-        let mut buf = AtomicRing256::<u8>::new();
+        let buf = AtomicRing256::<u8>::new();
         assert_eq!(255, buf.capacity());
         assert!(buf.is_empty());
+        // SAFETY: the test is the only producer
         while buf.capacity() > 0 {
-            assert!(buf.put(1).is_ok());
+            assert!(unsafe { buf.put(1) }.is_ok());
         }
         assert!(buf.is_full());
         assert_eq!((0, 255), buf.read_write_indexes());
-        assert!(buf.put(2).is_err());
+        assert!(unsafe { buf.put(2) }.is_err());
+    }
+
+    #[test]
+    fn reads_in_order() {
+        static RING: AtomicRing256<u8> = AtomicRing256::filled(0);
+        let mut buf = [0; 4];
+        // SAFETY: the test is the only producer and consumer
+        let read = |buf: &mut [u8]| unsafe { RING.read_available(buf) };
+        assert!(matches!(read(&mut buf), Err(io::Error::WouldBlock)));
+        for round in 0..100_u8 {
+            for byte in b"abc" {
+                unsafe { RING.put(byte.wrapping_add(round)) }.unwrap();
+            }
+            assert_eq!(3, read(&mut buf).unwrap().to_usize());
+            assert_eq!([b'a', b'b', b'c'].map(|b| b + round), buf[..3]);
+        }
+        assert!(RING.is_empty());
     }
 }
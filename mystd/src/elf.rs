@@ -0,0 +1,260 @@
+//! Reads the loadable segments of 64 bit little endian ELF executables, enough to load a
+//! statically linked program.
+
+pub const MACHINE_AARCH64: u16 = 183;
+
+const MAGIC: &[u8; 4] = b"\x7fELF";
+const CLASS_64: u8 = 2;
+const LITTLE_ENDIAN: u8 = 1;
+const TYPE_EXECUTABLE: u16 = 2;
+const HEADER_SIZE: usize = 64;
+const PROGRAM_HEADER_SIZE: usize = 56;
+const PT_LOAD: u32 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ElfError {
+    TooShort,
+    NotElf,
+    Not64Bit,
+    NotLittleEndian,
+    /// Only static executables can be loaded, no shared objects or relocatable files.
+    NotExecutable,
+    WrongMachine(u16),
+    /// A program header or the data of a segment is outside the file, or a segment has less
+    /// memory than data.
+    BadSegment,
+}
+
+/// Whether a segment may be read, written and executed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SegmentFlags {
+    pub read: bool,
+    pub write: bool,
+    pub execute: bool,
+}
+
+/// A `PT_LOAD` segment: `file_size` bytes from `offset` in the file go to `virtual_address`, the
+/// rest of the `memory_size` bytes are zero.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Segment {
+    pub virtual_address: u64,
+    pub memory_size: u64,
+    pub offset: u64,
+    pub file_size: u64,
+    pub flags: SegmentFlags,
+}
+
+pub struct ElfFile<'a> {
+    data: &'a [u8],
+    entry: u64,
+    program_headers: u64,
+    program_header_size: u16,
+    program_header_count: u16,
+}
+
+fn read<const N: usize>(data: &[u8], offset: usize) -> Result<[u8; N], ElfError> {
+    let end = offset.checked_add(N).ok_or(ElfError::TooShort)?;
+    let bytes = data.get(offset..end).ok_or(ElfError::TooShort)?;
+    Ok(bytes.try_into().expect("the slice has N bytes"))
+}
+
+fn u16_at(data: &[u8], offset: usize) -> Result<u16, ElfError> {
+    read(data, offset).map(u16::from_le_bytes)
+}
+
+fn u32_at(data: &[u8], offset: usize) -> Result<u32, ElfError> {
+    read(data, offset).map(u32::from_le_bytes)
+}
+
+fn u64_at(data: &[u8], offset: usize) -> Result<u64, ElfError> {
+    read(data, offset).map(u64::from_le_bytes)
+}
+
+impl<'a> ElfFile<'a> {
+    /// Checks the header of `data` for an executable of `machine`.
+    pub fn parse(data: &'a [u8], machine: u16) -> Result<Self, ElfError> {
+        if data.len() < HEADER_SIZE {
+            return Err(ElfError::TooShort);
+        }
+        if &data[..4] != MAGIC {
+            return Err(ElfError::NotElf);
+        }
+        if data[4] != CLASS_64 {
+            return Err(ElfError::Not64Bit);
+        }
+        if data[5] != LITTLE_ENDIAN {
+            return Err(ElfError::NotLittleEndian);
+        }
+        if u16_at(data, 16)? != TYPE_EXECUTABLE {
+            return Err(ElfError::NotExecutable);
+        }
+        let file_machine = u16_at(data, 18)?;
+        if file_machine != machine {
+            return Err(ElfError::WrongMachine(file_machine));
+        }
+        let file = Self {
+            data,
+            entry: u64_at(data, 24)?,
+            program_headers: u64_at(data, 32)?,
+            program_header_size: u16_at(data, 54)?,
+            program_header_count: u16_at(data, 56)?,
+        };
+        if file.program_header_count > 0
+            && (file.program_header_size as usize) < PROGRAM_HEADER_SIZE
+        {
+            return Err(ElfError::BadSegment);
+        }
+        Ok(file)
+    }
+
+    /// Whether `data` starts like an ELF file, to tell it from a flat binary.
+    pub fn is_elf(data: &[u8]) -> bool {
+        data.starts_with(MAGIC)
+    }
+
+    pub fn entry(&self) -> u64 {
+        self.entry
+    }
+
+    /// The `PT_LOAD` segments in the order of the program headers.
+    pub fn segments(&self) -> impl Iterator<Item = Result<Segment, ElfError>> + '_ {
+        (0..self.program_header_count as u64).filter_map(|i| {
+            let offset = self.program_headers + i * self.program_header_size as u64;
+            self.segment_at(offset as usize).transpose()
+        })
+    }
+
+    /// The bytes of `segment` in the file.
+    pub fn segment_data(&self, segment: &Segment) -> Result<&'a [u8], ElfError> {
+        let start = segment.offset as usize;
+        let end = start
+            .checked_add(segment.file_size as usize)
+            .ok_or(ElfError::BadSegment)?;
+        self.data.get(start..end).ok_or(ElfError::BadSegment)
+    }
+
+    fn segment_at(&self, offset: usize) -> Result<Option<Segment>, ElfError> {
+        let header = offset
+            .checked_add(PROGRAM_HEADER_SIZE)
+            .and_then(|end| self.data.get(offset..end))
+            .ok_or(ElfError::BadSegment)?;
+        if u32_at(header, 0)? != PT_LOAD {
+            return Ok(None);
+        }
+        let flags = u32_at(header, 4)?;
+        let segment = Segment {
+            offset: u64_at(header, 8)?,
+            virtual_address: u64_at(header, 16)?,
+            file_size: u64_at(header, 32)?,
+            memory_size: u64_at(header, 40)?,
+            flags: SegmentFlags {
+                execute: flags & 1 != 0,
+                write: flags & 2 != 0,
+                read: flags & 4 != 0,
+            },
+        };
+        if segment.file_size > segment.memory_size {
+            return Err(ElfError::BadSegment);
+        }
+        self.segment_data(&segment)?;
+        Ok(Some(segment))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::vec::Vec;
+
+    use super::*;
+
+    /// An executable with a code segment, a note and a data segment with some bss.
+    fn executable() -> Vec<u8> {
+        let mut file = Vec::new();
+        file.extend_from_slice(b"\x7fELF\x02\x01\x01\0\0\0\0\0\0\0\0\0");
+        file.extend_from_slice(&TYPE_EXECUTABLE.to_le_bytes());
+        file.extend_from_slice(&MACHINE_AARCH64.to_le_bytes());
+        file.extend_from_slice(&1u32.to_le_bytes());
+        file.extend_from_slice(&0x8000_0010u64.to_le_bytes()); // entry
+        file.extend_from_slice(&64u64.to_le_bytes()); // program headers
+        file.extend_from_slice(&0u64.to_le_bytes()); // section headers
+        file.extend_from_slice(&0u32.to_le_bytes());
+        file.extend_from_slice(&64u16.to_le_bytes());
+        file.extend_from_slice(&56u16.to_le_bytes());
+        file.extend_from_slice(&3u16.to_le_bytes());
+        file.extend_from_slice(&[0; 6]);
+        assert_eq!(file.len(), HEADER_SIZE);
+        let headers: [(u32, u32, u64, u64, u64, u64); 3] = [
+            (PT_LOAD, 0b101, 232, 0x8000_0000, 8, 8),
+            (4, 0b100, 0, 0, 0, 0),
+            (PT_LOAD, 0b110, 240, 0x8000_1000, 4, 0x20),
+        ];
+        for (kind, flags, offset, address, file_size, memory_size) in headers {
+            file.extend_from_slice(&kind.to_le_bytes());
+            file.extend_from_slice(&flags.to_le_bytes());
+            file.extend_from_slice(&offset.to_le_bytes());
+            file.extend_from_slice(&address.to_le_bytes());
+            file.extend_from_slice(&address.to_le_bytes());
+            file.extend_from_slice(&file_size.to_le_bytes());
+            file.extend_from_slice(&memory_size.to_le_bytes());
+            file.extend_from_slice(&0x1000u64.to_le_bytes());
+        }
+        assert_eq!(file.len(), 232);
+        file.extend_from_slice(&[0xaa; 8]);
+        file.extend_from_slice(&[0xbb; 4]);
+        file
+    }
+
+    #[test]
+    fn reads_the_loadable_segments() {
+        let data = executable();
+        let file = ElfFile::parse(&data, MACHINE_AARCH64).unwrap();
+        assert_eq!(file.entry(), 0x8000_0010);
+        let segments: Vec<_> = file.segments().map(Result::unwrap).collect();
+        assert_eq!(segments.len(), 2);
+        assert_eq!(segments[0].virtual_address, 0x8000_0000);
+        assert_eq!(
+            segments[0].flags,
+            SegmentFlags {
+                read: true,
+                write: false,
+                execute: true
+            }
+        );
+        assert_eq!(file.segment_data(&segments[0]).unwrap(), &[0xaa; 8]);
+        assert_eq!(segments[1].memory_size, 0x20);
+        assert!(segments[1].flags.write && !segments[1].flags.execute);
+        assert_eq!(file.segment_data(&segments[1]).unwrap(), &[0xbb; 4]);
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        let mut data = executable();
+        assert_eq!(
+            ElfFile::parse(&data, 62).err(),
+            Some(ElfError::WrongMachine(MACHINE_AARCH64))
+        );
+        assert_eq!(
+            ElfFile::parse(&data[..40], MACHINE_AARCH64).err(),
+            Some(ElfError::TooShort)
+        );
+        assert!(!ElfFile::is_elf(b"\x00\x00\x80\xd2"));
+        data[16] = 3; // a shared object
+        assert_eq!(
+            ElfFile::parse(&data, MACHINE_AARCH64).err(),
+            Some(ElfError::NotExecutable)
+        );
+    }
+
+    #[test]
+    fn segments_stay_inside_the_file() {
+        let mut data = executable();
+        // the data of the last segment ends after the file
+        data.truncate(data.len() - 1);
+        let file = ElfFile::parse(&data, MACHINE_AARCH64).unwrap();
+        let segments: Vec<_> = file.segments().collect();
+        assert!(segments[0].is_ok());
+        assert_eq!(segments[1], Err(ElfError::BadSegment));
+    }
+}
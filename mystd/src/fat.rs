@@ -0,0 +1,968 @@
+//! FAT16 and FAT32 file systems on a [BlockDevice], with the long file names of VFAT.
+//!
+//! [mount] finds the file system on the first FAT partition of a device, or on all of it if it
+//! has no partition table, like SD cards formatted by cameras. Paths look like
+//! `/overlays/README`, their names are compared ignoring ASCII case. A [File] borrows the file
+//! system, so one is open at a time, and reads, seeks and writes through [io].
+//!
+//! Nothing is allocated: the file system keeps one sector to find its way around, and every
+//! change goes straight to the device.
+
+pub mod boot_sector;
+pub mod dir;
+pub mod file;
+pub mod mbr;
+
+use boot_sector::{BootSectorError, FatType, Layout};
+use dir::{Attributes, DirEntry, EntryPosition, LongName, ENTRY_SIZE, MAX_ENTRIES};
+pub use file::File;
+use mbr::Partition;
+
+use crate::block::{BlockDevice, BLOCK_SIZE};
+use crate::io;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FatError<E> {
+    Device(E),
+    /// Neither the device nor its partitions have a FAT file system.
+    NoFileSystem,
+    /// FAT12, or sectors that aren't [BLOCK_SIZE] long.
+    Unsupported,
+    /// The tables or directories point somewhere they can't.
+    Corrupt,
+    NotFound,
+    NotADirectory,
+    IsADirectory,
+    /// There is something at the path already, or no short name is left for a long one.
+    AlreadyExists,
+    InvalidName,
+    /// No free cluster left.
+    DiskFull,
+    /// The fixed size root directory of FAT16 has no room for another entry.
+    DirectoryFull,
+    /// Files end before 4 GiB.
+    FileTooLarge,
+}
+
+impl<E> From<FatError<E>> for io::Error {
+    fn from(error: FatError<E>) -> Self {
+        match error {
+            FatError::Device(_) => io::Error::Other,
+            FatError::Corrupt => io::Error::InvalidData,
+            FatError::DiskFull | FatError::DirectoryFull | FatError::FileTooLarge => {
+                io::Error::WriteZero
+            }
+            _ => io::Error::InvalidInput,
+        }
+    }
+}
+
+pub type FatResult<T, D> = Result<T, FatError<<D as BlockDevice>::Error>>;
+
+const FS_INFO_SIGNATURES: [(usize, u32); 3] =
+    [(0, 0x4161_5252), (484, 0x6141_7272), (508, 0xaa55_0000)];
+const FS_INFO_FREE_COUNT: usize = 488;
+const FS_INFO_NEXT_FREE: usize = 492;
+/// What a directory can have at most.
+const MAX_DIR_ENTRIES: u32 = 65536;
+
+/// The file system on the first FAT partition of `device`, or on all of it.
+pub fn mount<D: BlockDevice>(mut device: D) -> FatResult<FileSystem<Partition<D>>, D> {
+    let mut sector = [0; BLOCK_SIZE];
+    device
+        .read_blocks(0, &mut sector)
+        .map_err(FatError::Device)?;
+    match Layout::parse(&sector) {
+        Ok(_) => return FileSystem::new(Partition::whole(device)),
+        Err(BootSectorError::Unsupported) => return Err(FatError::Unsupported),
+        Err(BootSectorError::NotFat) => {}
+    }
+    let partition = mbr::partitions(&sector)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .find(|partition| partition.is_fat())
+        .ok_or(FatError::NoFileSystem)?;
+    FileSystem::new(Partition::new(
+        device,
+        partition.first_block as u64,
+        partition.block_count as u64,
+    ))
+}
+
+/// Where the entries of a directory are.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum DirStart {
+    /// The root directory of FAT16, between the tables and the clusters.
+    FixedRoot,
+    Cluster(u32),
+}
+
+/// Walks the entry positions of a directory.
+#[derive(Clone, Copy)]
+struct Cursor {
+    start: DirStart,
+    cluster: u32,
+    /// Of the next entry, in the cluster or in the fixed root directory.
+    index: u32,
+    count: u32,
+}
+
+impl Cursor {
+    fn new(start: DirStart) -> Self {
+        let cluster = match start {
+            DirStart::FixedRoot => 0,
+            DirStart::Cluster(cluster) => cluster,
+        };
+        Self {
+            start,
+            cluster,
+            index: 0,
+            count: 0,
+        }
+    }
+}
+
+pub struct FileSystem<D> {
+    device: D,
+    layout: Layout,
+    sector: [u8; BLOCK_SIZE],
+    /// The number of the sector in `sector`.
+    cached: Option<u64>,
+    /// Where the search for a free cluster starts.
+    next_free: u32,
+    /// Whether the free cluster count in the FSInfo sector is still to be marked unknown.
+    free_count_known: bool,
+}
+
+impl<D: BlockDevice> FileSystem<D> {
+    /// The file system on `device`, which starts with its boot sector.
+    pub fn new(mut device: D) -> FatResult<Self, D> {
+        let mut sector = [0; BLOCK_SIZE];
+        device
+            .read_blocks(0, &mut sector)
+            .map_err(FatError::Device)?;
+        let layout = Layout::parse(&sector).map_err(|error| match error {
+            BootSectorError::NotFat => FatError::NoFileSystem,
+            BootSectorError::Unsupported => FatError::Unsupported,
+        })?;
+        let mut fs = Self {
+            device,
+            layout,
+            sector,
+            cached: Some(0),
+            next_free: 2,
+            free_count_known: false,
+        };
+        let entries_per_fat = layout.sectors_per_fat * BLOCK_SIZE as u64
+            / match layout.fat_type {
+                FatType::Fat16 => 2,
+                FatType::Fat32 => 4,
+            };
+        let end = layout.cluster_sector(layout.last_cluster() + 1);
+        if end > fs.device.block_count() || entries_per_fat <= layout.last_cluster() as u64 {
+            return Err(FatError::Corrupt);
+        }
+        if let Some(sector) = layout.fs_info {
+            let info = *fs.read_sector(sector)?;
+            if has_fs_info_signatures(&info) {
+                let word = |offset: usize| {
+                    u32::from_le_bytes(info[offset..offset + 4].try_into().unwrap())
+                };
+                fs.free_count_known = word(FS_INFO_FREE_COUNT) != u32::MAX;
+                fs.next_free = word(FS_INFO_NEXT_FREE);
+            }
+        }
+        if !(2..=layout.last_cluster()).contains(&fs.next_free) {
+            fs.next_free = 2;
+        }
+        Ok(fs)
+    }
+
+    pub fn layout(&self) -> &Layout {
+        &self.layout
+    }
+
+    pub fn into_inner(self) -> D {
+        self.device
+    }
+
+    /// The entries of the directory at `path`.
+    pub fn read_dir(&mut self, path: &str) -> FatResult<ReadDir<'_, D>, D> {
+        let start = match self.find(path)? {
+            Some(entry) => self.dir_start(&entry)?,
+            None => self.root(),
+        };
+        Ok(ReadDir::new(self, start))
+    }
+
+    /// The file or directory at `path`, `/` has no entry.
+    pub fn entry(&mut self, path: &str) -> FatResult<DirEntry, D> {
+        self.find(path)?.ok_or(FatError::NotFound)
+    }
+
+    /// Opens the file at `path`, for reading and writing.
+    pub fn open(&mut self, path: &str) -> FatResult<File<'_, D>, D> {
+        match self.find(path)? {
+            Some(entry) if !entry.is_dir() => Ok(File::new(self, &entry)),
+            _ => Err(FatError::IsADirectory),
+        }
+    }
+
+    /// Opens the file at `path` empty, creating it if there is none.
+    pub fn create(&mut self, path: &str) -> FatResult<File<'_, D>, D> {
+        let (parent, name) = self.parent(path)?;
+        let entry = match self.find_in(parent, name)? {
+            Some(entry) if entry.is_dir() => return Err(FatError::IsADirectory),
+            Some(entry) => entry,
+            None => self.add_entry(parent, name, Attributes::zero().archive().set(), 0)?,
+        };
+        let mut file = File::new(self, &entry);
+        file.truncate()?;
+        Ok(file)
+    }
+
+    /// Creates the directory `path`, its parent has to be there.
+    pub fn create_dir(&mut self, path: &str) -> FatResult<(), D> {
+        let (parent, name) = self.parent(path)?;
+        if self.find_in(parent, name)?.is_some() {
+            return Err(FatError::AlreadyExists);
+        }
+        let cluster = self.allocate_cluster(None)?;
+        self.zero_cluster(cluster)?;
+        let parent_cluster = match parent {
+            DirStart::FixedRoot => 0,
+            DirStart::Cluster(cluster) if cluster == self.layout.root_cluster => 0,
+            DirStart::Cluster(cluster) => cluster,
+        };
+        let sector = self.layout.cluster_sector(cluster);
+        let dots = [
+            dir::dot_entry(1, cluster),
+            dir::dot_entry(2, parent_cluster),
+        ];
+        for (i, raw) in dots.iter().enumerate() {
+            self.write_entry(
+                EntryPosition {
+                    sector,
+                    offset: i * ENTRY_SIZE,
+                },
+                raw,
+            )?;
+        }
+        self.add_entry(parent, name, Attributes::zero().directory().set(), cluster)?;
+        Ok(())
+    }
+
+    fn root(&self) -> DirStart {
+        match self.layout.fat_type {
+            FatType::Fat16 => DirStart::FixedRoot,
+            FatType::Fat32 => DirStart::Cluster(self.layout.root_cluster),
+        }
+    }
+
+    fn dir_start(&self, entry: &DirEntry) -> FatResult<DirStart, D> {
+        match entry.first_cluster {
+            _ if !entry.is_dir() => Err(FatError::NotADirectory),
+            // what `..` says for the root directory
+            0 => Ok(self.root()),
+            cluster => Ok(DirStart::Cluster(cluster)),
+        }
+    }
+
+    /// The entry at `path`, None for the root directory.
+    fn find(&mut self, path: &str) -> FatResult<Option<DirEntry>, D> {
+        let mut found: Option<DirEntry> = None;
+        for name in path.split('/').filter(|name| !name.is_empty()) {
+            let dir = match &found {
+                Some(entry) => self.dir_start(entry)?,
+                None => self.root(),
+            };
+            found = Some(self.find_in(dir, name)?.ok_or(FatError::NotFound)?);
+        }
+        Ok(found)
+    }
+
+    fn find_in(&mut self, dir: DirStart, name: &str) -> FatResult<Option<DirEntry>, D> {
+        for entry in ReadDir::new(self, dir) {
+            let entry = entry?;
+            if entry.matches(name) {
+                return Ok(Some(entry));
+            }
+        }
+        Ok(None)
+    }
+
+    /// The directory that has to contain `path`, and the name in it.
+    fn parent<'p>(&mut self, path: &'p str) -> FatResult<(DirStart, &'p str), D> {
+        let path = path.trim_end_matches('/');
+        let (parent, name) = path.rsplit_once('/').unwrap_or(("", path));
+        if !dir::is_valid_name(name) {
+            return Err(FatError::InvalidName);
+        }
+        let start = match self.find(parent)? {
+            Some(entry) => self.dir_start(&entry)?,
+            None => self.root(),
+        };
+        Ok((start, name))
+    }
+
+    /// Writes the entries of `name` into `dir`, with a long name if it isn't an 8.3 one.
+    fn add_entry(
+        &mut self,
+        dir: DirStart,
+        name: &str,
+        attributes: Attributes,
+        first_cluster: u32,
+    ) -> FatResult<DirEntry, D> {
+        let mut entries = [[0; ENTRY_SIZE]; MAX_ENTRIES];
+        let (short_name, long_count) = match dir::short_name(name) {
+            // upper case 8.3 names need no long name
+            Some(short_name) if !name.bytes().any(|byte| byte.is_ascii_lowercase()) => {
+                (short_name, 0)
+            }
+            short_name => {
+                let short_name = match short_name {
+                    Some(short_name) if !self.short_name_taken(dir, &short_name)? => short_name,
+                    _ => self.unique_short_name(dir, name)?,
+                };
+                (
+                    short_name,
+                    dir::long_entries(name, &short_name, &mut entries),
+                )
+            }
+        };
+        entries[long_count] = dir::short_entry(&short_name, attributes, first_cluster);
+        let count = long_count + 1;
+        let positions = self.free_entries(dir, count)?;
+        for (position, raw) in positions.iter().zip(&entries[..count]) {
+            self.write_entry(*position, raw)?;
+        }
+        // the short name is enough to find the entry again
+        Ok(DirEntry::parse(
+            &entries[long_count],
+            positions[long_count],
+            None,
+        ))
+    }
+
+    fn unique_short_name(&mut self, dir: DirStart, name: &str) -> FatResult<[u8; 11], D> {
+        for number in 1..1000 {
+            let short_name = dir::numbered_short_name(name, number);
+            if !self.short_name_taken(dir, &short_name)? {
+                return Ok(short_name);
+            }
+        }
+        Err(FatError::AlreadyExists)
+    }
+
+    fn short_name_taken(&mut self, dir: DirStart, short_name: &[u8; 11]) -> FatResult<bool, D> {
+        for entry in ReadDir::new(self, dir) {
+            if entry?.short_name == *short_name {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// The positions of `count` free entries in a row in `dir`, which grows if it has to.
+    fn free_entries(
+        &mut self,
+        dir: DirStart,
+        count: usize,
+    ) -> FatResult<[EntryPosition; MAX_ENTRIES], D> {
+        let mut positions = [EntryPosition {
+            sector: 0,
+            offset: 0,
+        }; MAX_ENTRIES];
+        let mut found = 0;
+        let mut cursor = Cursor::new(dir);
+        while found < count {
+            match self.next_position(&mut cursor)? {
+                Some(position) => {
+                    if matches!(self.entry_at(position)?[0], dir::END | dir::FREE) {
+                        positions[found] = position;
+                        found += 1;
+                    } else {
+                        found = 0;
+                    }
+                }
+                None if dir == DirStart::FixedRoot => return Err(FatError::DirectoryFull),
+                None => {
+                    let cluster = self.allocate_cluster(Some(cursor.cluster))?;
+                    self.zero_cluster(cluster)?;
+                }
+            }
+        }
+        Ok(positions)
+    }
+
+    /// The position of the entry at `cursor`, which moves on. None after the last one.
+    fn next_position(&mut self, cursor: &mut Cursor) -> FatResult<Option<EntryPosition>, D> {
+        let per_sector = (BLOCK_SIZE / ENTRY_SIZE) as u32;
+        let (first_sector, index) = match cursor.start {
+            DirStart::FixedRoot if cursor.index >= self.layout.root_entries => return Ok(None),
+            DirStart::FixedRoot => (self.layout.root_start, cursor.index),
+            DirStart::Cluster(_) => {
+                if cursor.count >= MAX_DIR_ENTRIES {
+                    return Err(FatError::Corrupt);
+                }
+                if cursor.index == self.layout.sectors_per_cluster * per_sector {
+                    match self.next_cluster(cursor.cluster)? {
+                        Some(next) => {
+                            cursor.cluster = next;
+                            cursor.index = 0;
+                        }
+                        None => return Ok(None),
+                    }
+                }
+                (self.cluster_sector(cursor.cluster)?, cursor.index)
+            }
+        };
+        cursor.index += 1;
+        cursor.count += 1;
+        Ok(Some(EntryPosition {
+            sector: first_sector + (index / per_sector) as u64,
+            offset: (index % per_sector) as usize * ENTRY_SIZE,
+        }))
+    }
+
+    fn entry_at(&mut self, position: EntryPosition) -> FatResult<[u8; ENTRY_SIZE], D> {
+        let sector = self.read_sector(position.sector)?;
+        Ok(sector[position.offset..position.offset + ENTRY_SIZE]
+            .try_into()
+            .unwrap())
+    }
+
+    fn write_entry(&mut self, position: EntryPosition, raw: &[u8; ENTRY_SIZE]) -> FatResult<(), D> {
+        let sector = self.read_sector(position.sector)?;
+        sector[position.offset..position.offset + ENTRY_SIZE].copy_from_slice(raw);
+        self.write_back()
+    }
+
+    /// The sector `number`, from the cache if it's there.
+    fn read_sector(&mut self, number: u64) -> FatResult<&mut [u8; BLOCK_SIZE], D> {
+        if self.cached != Some(number) {
+            self.cached = None;
+            self.device
+                .read_blocks(number, &mut self.sector)
+                .map_err(FatError::Device)?;
+            self.cached = Some(number);
+        }
+        Ok(&mut self.sector)
+    }
+
+    /// Writes the cached sector after changing it.
+    fn write_back(&mut self) -> FatResult<(), D> {
+        match self.cached {
+            Some(number) => self
+                .device
+                .write_blocks(number, &self.sector)
+                .map_err(FatError::Device),
+            None => Ok(()),
+        }
+    }
+
+    /// Reads whole sectors from `first` on, past the cache.
+    fn read_sectors(&mut self, first: u64, buffer: &mut [u8]) -> FatResult<(), D> {
+        self.device
+            .read_blocks(first, buffer)
+            .map_err(FatError::Device)
+    }
+
+    /// Writes whole sectors from `first` on, past the cache, which is dropped if it has one.
+    fn write_sectors(&mut self, first: u64, buffer: &[u8]) -> FatResult<(), D> {
+        let end = first + (buffer.len() / BLOCK_SIZE) as u64;
+        if self
+            .cached
+            .is_some_and(|number| (first..end).contains(&number))
+        {
+            self.cached = None;
+        }
+        self.device
+            .write_blocks(first, buffer)
+            .map_err(FatError::Device)
+    }
+
+    fn cluster_sector(&self, cluster: u32) -> FatResult<u64, D> {
+        if !(2..=self.layout.last_cluster()).contains(&cluster) {
+            return Err(FatError::Corrupt);
+        }
+        Ok(self.layout.cluster_sector(cluster))
+    }
+
+    fn zero_cluster(&mut self, cluster: u32) -> FatResult<(), D> {
+        let first = self.cluster_sector(cluster)?;
+        for sector in first..first + self.layout.sectors_per_cluster as u64 {
+            self.write_sectors(sector, &[0; BLOCK_SIZE])?;
+        }
+        Ok(())
+    }
+
+    fn end_of_chain(&self) -> u32 {
+        match self.layout.fat_type {
+            FatType::Fat16 => 0xffff,
+            FatType::Fat32 => 0x0fff_ffff,
+        }
+    }
+
+    fn fat_entry(&mut self, cluster: u32) -> FatResult<u32, D> {
+        if !(2..=self.layout.last_cluster()).contains(&cluster) {
+            return Err(FatError::Corrupt);
+        }
+        let fat_type = self.layout.fat_type;
+        let (sector, offset) = self.layout.fat_entry(cluster);
+        let sector = self.read_sector(sector)?;
+        Ok(match fat_type {
+            FatType::Fat16 => u16::from_le_bytes([sector[offset], sector[offset + 1]]) as u32,
+            FatType::Fat32 => {
+                u32::from_le_bytes(sector[offset..offset + 4].try_into().unwrap()) & 0x0fff_ffff
+            }
+        })
+    }
+
+    /// Sets the entry of `cluster` in every FAT.
+    fn set_fat_entry(&mut self, cluster: u32, value: u32) -> FatResult<(), D> {
+        let layout = self.layout;
+        let (first_sector, offset) = layout.fat_entry(cluster);
+        for fat in 0..layout.fat_count as u64 {
+            let sector = self.read_sector(first_sector + fat * layout.sectors_per_fat)?;
+            match layout.fat_type {
+                FatType::Fat16 => {
+                    sector[offset..offset + 2].copy_from_slice(&(value as u16).to_le_bytes())
+                }
+                FatType::Fat32 => {
+                    // the top 4 bits are reserved
+                    let old = u32::from_le_bytes(sector[offset..offset + 4].try_into().unwrap());
+                    let value = old & 0xf000_0000 | value & 0x0fff_ffff;
+                    sector[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+                }
+            }
+            self.write_back()?;
+        }
+        Ok(())
+    }
+
+    /// The cluster after `cluster` in its chain, None at the end.
+    fn next_cluster(&mut self, cluster: u32) -> FatResult<Option<u32>, D> {
+        let next = self.fat_entry(cluster)?;
+        if next >= self.end_of_chain() & !0x7 {
+            Ok(None)
+        } else if (2..=self.layout.last_cluster()).contains(&next) {
+            Ok(Some(next))
+        } else {
+            // free or bad
+            Err(FatError::Corrupt)
+        }
+    }
+
+    /// Takes a free cluster and appends it to the chain that ends with `previous`.
+    fn allocate_cluster(&mut self, previous: Option<u32>) -> FatResult<u32, D> {
+        let count = self.layout.cluster_count;
+        for i in 0..count {
+            let cluster = 2 + (self.next_free - 2 + i) % count;
+            if self.fat_entry(cluster)? != 0 {
+                continue;
+            }
+            self.forget_free_count()?;
+            self.set_fat_entry(cluster, self.end_of_chain())?;
+            if let Some(previous) = previous {
+                self.set_fat_entry(previous, cluster)?;
+            }
+            self.next_free = 2 + (cluster - 1) % count;
+            return Ok(cluster);
+        }
+        Err(FatError::DiskFull)
+    }
+
+    /// Frees the chain from `first` on.
+    fn free_chain(&mut self, first: u32) -> FatResult<(), D> {
+        self.forget_free_count()?;
+        let mut cluster = Some(first);
+        for _ in 0..self.layout.cluster_count {
+            let Some(current) = cluster else {
+                return Ok(());
+            };
+            cluster = self.next_cluster(current)?;
+            self.set_fat_entry(current, 0)?;
+        }
+        // a chain longer than the disk goes around in circles
+        Err(FatError::Corrupt)
+    }
+
+    /// Marks the free cluster count of the FSInfo sector unknown, instead of keeping it up to
+    /// date. Whoever needs it counts again.
+    fn forget_free_count(&mut self) -> FatResult<(), D> {
+        let Some(number) = self.layout.fs_info.filter(|_| self.free_count_known) else {
+            return Ok(());
+        };
+        self.free_count_known = false;
+        let sector = self.read_sector(number)?;
+        sector[FS_INFO_FREE_COUNT..FS_INFO_FREE_COUNT + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        self.write_back()
+    }
+}
+
+fn has_fs_info_signatures(sector: &[u8; BLOCK_SIZE]) -> bool {
+    FS_INFO_SIGNATURES
+        .iter()
+        .all(|&(offset, signature)| sector[offset..offset + 4] == signature.to_le_bytes())
+}
+
+/// The entries of a directory, without `.` and `..`.
+pub struct ReadDir<'a, D: BlockDevice> {
+    fs: &'a mut FileSystem<D>,
+    cursor: Cursor,
+    long_name: LongName,
+    done: bool,
+}
+
+impl<'a, D: BlockDevice> ReadDir<'a, D> {
+    fn new(fs: &'a mut FileSystem<D>, start: DirStart) -> Self {
+        Self {
+            fs,
+            cursor: Cursor::new(start),
+            long_name: LongName::new(),
+            done: false,
+        }
+    }
+
+    fn next_entry(&mut self) -> FatResult<Option<DirEntry>, D> {
+        while let Some(position) = self.fs.next_position(&mut self.cursor)? {
+            let raw = self.fs.entry_at(position)?;
+            match raw[0] {
+                dir::END => return Ok(None),
+                dir::FREE => self.long_name.reset(),
+                _ if dir::is_long_name(&raw) => self.long_name.add(&raw),
+                _ => {
+                    let long_name = self.long_name.take(raw[..11].try_into().unwrap());
+                    let attributes = Attributes::new(raw[11]);
+                    if raw[0] != b'.' && !attributes.volume_id().is_set() {
+                        return Ok(Some(DirEntry::parse(&raw, position, long_name)));
+                    }
+                }
+            }
+        }
+        Ok(None)
+    }
+}
+
+impl<D: BlockDevice> Iterator for ReadDir<'_, D> {
+    type Item = FatResult<DirEntry, D>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let entry = self.next_entry().transpose();
+        self.done = !matches!(entry, Some(Ok(_)));
+        entry
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::vec;
+    use std::vec::Vec;
+
+    use super::*;
+    use crate::block::RamDisk;
+    use crate::io::{Read, Seek, SeekFrom, Size, Write};
+
+    const PARTITION_START: usize = 8;
+
+    /// A disk of `sectors` with one partition from sector 8 on, formatted the way mkfs.fat
+    /// would, with a fixed root directory of `root_entries` on FAT16.
+    fn disk(fat_type: FatType, sectors: usize, root_entries: u16) -> Vec<u8> {
+        let mut image = vec![0_u8; sectors * BLOCK_SIZE];
+        let total = sectors - PARTITION_START;
+        let entry = &mut image[0x1be..0x1ce];
+        entry[0] = 0x80;
+        entry[4] = match fat_type {
+            FatType::Fat16 => 0x06,
+            FatType::Fat32 => 0x0c,
+        };
+        entry[8..12].copy_from_slice(&(PARTITION_START as u32).to_le_bytes());
+        entry[12..16].copy_from_slice(&(total as u32).to_le_bytes());
+        image[0x1fe..0x200].copy_from_slice(&mbr::SIGNATURE);
+
+        let (sectors_per_cluster, reserved, root_entries, entry_size) = match fat_type {
+            FatType::Fat16 => (1, 1, root_entries, 2),
+            FatType::Fat32 => (2, 8, 0, 4),
+        };
+        let root_sectors = (root_entries as usize * ENTRY_SIZE).div_ceil(BLOCK_SIZE);
+        let clusters = (total - reserved - root_sectors) / sectors_per_cluster;
+        let sectors_per_fat = ((clusters + 2) * entry_size).div_ceil(BLOCK_SIZE);
+        let volume = &mut image[PARTITION_START * BLOCK_SIZE..];
+        let boot = &mut volume[..BLOCK_SIZE];
+        boot[..3].copy_from_slice(&[0xeb, 0x3c, 0x90]);
+        boot[3..11].copy_from_slice(b"MSWIN4.1");
+        boot[11..13].copy_from_slice(&(BLOCK_SIZE as u16).to_le_bytes());
+        boot[13] = sectors_per_cluster as u8;
+        boot[14..16].copy_from_slice(&(reserved as u16).to_le_bytes());
+        boot[16] = 2;
+        boot[17..19].copy_from_slice(&root_entries.to_le_bytes());
+        boot[21] = 0xf8;
+        boot[32..36].copy_from_slice(&(total as u32).to_le_bytes());
+        boot[510..].copy_from_slice(&mbr::SIGNATURE);
+        let mut fat = vec![0xf8, 0xff, 0xff, 0xff];
+        match fat_type {
+            FatType::Fat16 => boot[22..24].copy_from_slice(&(sectors_per_fat as u16).to_le_bytes()),
+            FatType::Fat32 => {
+                boot[36..40].copy_from_slice(&(sectors_per_fat as u32).to_le_bytes());
+                boot[44..48].copy_from_slice(&2_u32.to_le_bytes());
+                boot[48..50].copy_from_slice(&1_u16.to_le_bytes());
+                let info = &mut volume[BLOCK_SIZE..2 * BLOCK_SIZE];
+                for (offset, signature) in FS_INFO_SIGNATURES {
+                    info[offset..offset + 4].copy_from_slice(&signature.to_le_bytes());
+                }
+                info[FS_INFO_FREE_COUNT..FS_INFO_FREE_COUNT + 4]
+                    .copy_from_slice(&(clusters as u32 - 1).to_le_bytes());
+                info[FS_INFO_NEXT_FREE..FS_INFO_NEXT_FREE + 4]
+                    .copy_from_slice(&3_u32.to_le_bytes());
+                // media, end of chain and the root directory
+                fat = vec![
+                    0xf8, 0xff, 0xff, 0x0f, 0xff, 0xff, 0xff, 0x0f, 0xff, 0xff, 0xff, 0x0f,
+                ];
+            }
+        }
+        for copy in 0..2 {
+            let start = (reserved + copy * sectors_per_fat) * BLOCK_SIZE;
+            volume[start..start + fat.len()].copy_from_slice(&fat);
+        }
+        image
+    }
+
+    fn names<D: BlockDevice>(fs: &mut FileSystem<D>, path: &str) -> Vec<std::string::String> {
+        fs.read_dir(path)
+            .unwrap()
+            .map(|entry| entry.unwrap().name().into())
+            .collect()
+    }
+
+    fn contents<D: BlockDevice>(fs: &mut FileSystem<D>, path: &str) -> Vec<u8> {
+        let mut file = fs.open(path).unwrap();
+        let mut data = vec![0; file.len() as usize];
+        file.read_exact(&mut data).unwrap();
+        data
+    }
+
+    fn pattern(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 7 + i / 256) as u8).collect()
+    }
+
+    #[test]
+    fn finds_the_partition() {
+        let image = disk(FatType::Fat32, 2048, 0);
+        let entries = mbr::partitions(image[..BLOCK_SIZE].try_into().unwrap()).unwrap();
+        assert_eq!(
+            entries,
+            [
+                Some(mbr::PartitionEntry {
+                    bootable: true,
+                    kind: 0x0c,
+                    first_block: 8,
+                    block_count: 2040
+                }),
+                None,
+                None,
+                None
+            ]
+        );
+        let fs = mount(RamDisk::new(image.clone())).unwrap();
+        assert_eq!(fs.layout().fat_type, FatType::Fat32);
+        assert_eq!(fs.layout().cluster_count, (2040 - 8 - 2 * 8) / 2);
+        // without a partition table
+        let fs = mount(RamDisk::new(image[PARTITION_START * BLOCK_SIZE..].to_vec())).unwrap();
+        assert_eq!(fs.layout().root_cluster, 2);
+        assert!(matches!(
+            mount(RamDisk::new(vec![0_u8; 4 * BLOCK_SIZE])),
+            Err(FatError::NoFileSystem)
+        ));
+    }
+
+    #[test]
+    fn writes_and_reads_files() {
+        let mut fs = mount(RamDisk::new(disk(FatType::Fat32, 2048, 0))).unwrap();
+        let data = pattern(3000);
+        fs.create("/config.txt").unwrap().write_all(&data).unwrap();
+        fs.create_dir("/overlays").unwrap();
+        fs.create("/overlays/A long name, for a file.dtbo")
+            .unwrap()
+            .write_all(b"overlay")
+            .unwrap();
+        fs.create("KERNEL8.IMG").unwrap();
+
+        assert_eq!(
+            names(&mut fs, "/"),
+            ["config.txt", "overlays", "KERNEL8.IMG"]
+        );
+        assert_eq!(
+            names(&mut fs, "/overlays/"),
+            ["A long name, for a file.dtbo"]
+        );
+        assert_eq!(contents(&mut fs, "/CONFIG.TXT"), data);
+        assert_eq!(
+            contents(&mut fs, "overlays/a long NAME, for a file.dtbo"),
+            b"overlay"
+        );
+        assert_eq!(contents(&mut fs, "/overlays/ALONGN~1.DTB"), b"overlay");
+        assert_eq!(fs.open("/kernel8.img").unwrap().len(), 0);
+
+        let entry = fs.entry("/config.txt").unwrap();
+        assert_eq!(&entry.short_name, b"CONFIG  TXT");
+        assert_eq!(entry.size, 3000);
+        // 3 clusters of 1024 bytes, in a chain after the root directory's
+        assert_eq!(entry.first_cluster, 3);
+        assert_eq!(fs.next_cluster(3), Ok(Some(4)));
+        assert_eq!(fs.next_cluster(4), Ok(Some(5)));
+        assert_eq!(fs.next_cluster(5), Ok(None));
+        assert!(fs.entry("/overlays").unwrap().is_dir());
+        assert_eq!(fs.open("/overlays").err(), Some(FatError::IsADirectory));
+        assert_eq!(
+            fs.open("/config.txt/x").err(),
+            Some(FatError::NotADirectory)
+        );
+        assert_eq!(fs.open("/cmdline.txt").err(), Some(FatError::NotFound));
+        assert_eq!(fs.create("/a:b").err(), Some(FatError::InvalidName));
+        assert_eq!(
+            fs.create_dir("/overlays").err(),
+            Some(FatError::AlreadyExists)
+        );
+
+        // the second FAT is a copy, and the free count is no longer known
+        let image = fs.into_inner().into_inner().into_inner();
+        let volume = &image[PARTITION_START * BLOCK_SIZE..];
+        let sectors_per_fat = u32::from_le_bytes(volume[36..40].try_into().unwrap()) as usize;
+        let first = &volume[8 * BLOCK_SIZE..][..sectors_per_fat * BLOCK_SIZE];
+        let second = &volume[(8 + sectors_per_fat) * BLOCK_SIZE..][..sectors_per_fat * BLOCK_SIZE];
+        assert_eq!(first, second);
+        assert_eq!(volume[BLOCK_SIZE + FS_INFO_FREE_COUNT..][..4], [0xff; 4]);
+    }
+
+    #[test]
+    fn long_names_need_matching_checksums() {
+        let mut fs = mount(RamDisk::new(disk(FatType::Fat32, 2048, 0))).unwrap();
+        fs.create("/Grüße.txt").unwrap().write_all(b"hi").unwrap();
+        fs.create("/Grüße.text").unwrap();
+        assert_eq!(names(&mut fs, "/"), ["Grüße.txt", "Grüße.text"]);
+        let entry = fs.entry("/Grüße.txt").unwrap();
+        assert_eq!(&entry.short_name, b"GR__E~1 TXT");
+        assert_eq!(&fs.entry("/Grüße.text").unwrap().short_name, b"GR__E~1 TEX");
+
+        // the long name entry before it belongs to another short name now
+        let mut long_entry = fs.entry_at(entry.position).unwrap();
+        long_entry[..11].copy_from_slice(b"GR__E~2 TXT");
+        fs.write_entry(entry.position, &long_entry).unwrap();
+        assert_eq!(names(&mut fs, "/"), ["GR__E~2.TXT", "Grüße.text"]);
+        assert_eq!(contents(&mut fs, "/gr__e~2.txt"), b"hi");
+    }
+
+    #[test]
+    fn seeks_and_truncates() {
+        let mut fs = mount(RamDisk::new(disk(FatType::Fat32, 2048, 0))).unwrap();
+        let data = pattern(3000);
+        let mut file = fs.create("/data.bin").unwrap();
+        file.write_all(&data).unwrap();
+        let mut buf = [0; 10];
+        assert_eq!(file.seek(SeekFrom::Start(1500)), Ok(1500));
+        file.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, data[1500..1510]);
+        assert_eq!(file.seek(SeekFrom::Current(-1010)), Ok(500));
+        file.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, data[500..510]);
+        assert_eq!(file.seek(SeekFrom::End(-1)), Ok(2999));
+        assert_eq!(file.read(&mut buf).map(|size| size.to_usize()), Ok(1));
+        assert_eq!(buf[0], data[2999]);
+        assert!(matches!(file.read(&mut buf), Ok(Size::Eof)));
+        assert_eq!(file.seek(SeekFrom::End(1)), Err(io::Error::InvalidInput));
+        assert_eq!(
+            file.seek(SeekFrom::Current(-3001)),
+            Err(io::Error::InvalidInput)
+        );
+        // offsets far out of range neither overflow nor wrap around
+        assert_eq!(
+            file.seek(SeekFrom::Start(u64::MAX)),
+            Err(io::Error::InvalidInput)
+        );
+        assert_eq!(
+            file.seek(SeekFrom::End(i64::MAX)),
+            Err(io::Error::InvalidInput)
+        );
+        assert_eq!(
+            file.seek(SeekFrom::Current(i64::MAX)),
+            Err(io::Error::InvalidInput)
+        );
+
+        // overwriting across a sector and a cluster boundary
+        file.seek(SeekFrom::Start(1020)).unwrap();
+        file.write_all(&[0xaa; 8]).unwrap();
+        assert_eq!(file.len(), 3000);
+        file.rewind().unwrap();
+        let mut again = vec![0; 3000];
+        file.read_exact(&mut again).unwrap();
+        assert_eq!(again[..1020], data[..1020]);
+        assert_eq!(again[1020..1028], [0xaa; 8]);
+        assert_eq!(again[1028..], data[1028..]);
+
+        file.seek(SeekFrom::Start(1000)).unwrap();
+        file.truncate().unwrap();
+        file.write_all(b"end").unwrap();
+        assert_eq!(file.len(), 1003);
+        assert_eq!(fs.next_cluster(3), Ok(None));
+        assert_eq!(fs.fat_entry(4), Ok(0));
+        assert_eq!(
+            contents(&mut fs, "/data.bin")[998..],
+            [data[998], data[999], b'e', b'n', b'd']
+        );
+    }
+
+    #[test]
+    fn fat16_has_a_fixed_root_directory() {
+        let mut fs = mount(RamDisk::new(disk(FatType::Fat16, 4400, 16))).unwrap();
+        assert_eq!(fs.layout().fat_type, FatType::Fat16);
+        fs.create_dir("/DIR").unwrap();
+        for i in 0..15 {
+            fs.create(&std::format!("/FILE{i}.TXT")).unwrap();
+        }
+        assert_eq!(fs.create("/FULL.TXT").err(), Some(FatError::DirectoryFull));
+
+        // a subdirectory grows with clusters of one sector
+        for i in 0..20 {
+            let mut file = fs.create(&std::format!("/dir/file number {i}")).unwrap();
+            file.write_all(&pattern(i * 100)).unwrap();
+        }
+        let entries = names(&mut fs, "/DIR");
+        assert_eq!(entries.len(), 20);
+        assert_eq!(entries[19], "file number 19");
+        assert_eq!(contents(&mut fs, "/dir/file number 19"), pattern(1900));
+        let dir = fs.entry("/DIR").unwrap().first_cluster;
+        assert!(fs.next_cluster(dir).unwrap().is_some());
+    }
+
+    #[test]
+    fn runs_out_of_clusters() {
+        let mut fs = mount(RamDisk::new(disk(FatType::Fat32, 64, 0))).unwrap();
+        let free = fs.layout().cluster_count as usize - 1;
+        let mut file = fs.create("/big").unwrap();
+        let data = pattern(free * 1024 + 1);
+        assert_eq!(
+            file.write(&data).map(|size| size.to_usize()),
+            Ok(free * 1024)
+        );
+        assert_eq!(
+            file.write(&data).map(|size| size.to_usize()),
+            Err(io::Error::WriteZero)
+        );
+        assert_eq!(file.len() as usize, free * 1024);
+        file.rewind().unwrap();
+        file.truncate().unwrap();
+        assert!(file.is_empty());
+        fs.create("/small")
+            .unwrap()
+            .write_all(&data[..2048])
+            .unwrap();
+        assert_eq!(contents(&mut fs, "/small"), data[..2048]);
+    }
+}
@@ -0,0 +1,136 @@
+//! The BIOS parameter block at the start of a FAT volume, and where it puts things.
+
+use super::mbr::SIGNATURE;
+use crate::block::BLOCK_SIZE;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FatType {
+    Fat16,
+    Fat32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BootSectorError {
+    /// The sector doesn't start a FAT volume.
+    NotFat,
+    /// FAT12, or sectors that aren't [BLOCK_SIZE] long.
+    Unsupported,
+}
+
+/// Where a volume keeps its tables, directories and data, in sectors from its start.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Layout {
+    pub fat_type: FatType,
+    pub sectors_per_cluster: u32,
+    pub fat_start: u64,
+    pub sectors_per_fat: u64,
+    pub fat_count: u32,
+    /// The fixed root directory of FAT16, empty on FAT32.
+    pub root_start: u64,
+    pub root_entries: u32,
+    /// The first cluster of the root directory on FAT32.
+    pub root_cluster: u32,
+    /// The sector of cluster 2, the first one.
+    pub data_start: u64,
+    pub cluster_count: u32,
+    pub fs_info: Option<u64>,
+}
+
+impl Layout {
+    pub fn parse(sector: &[u8; BLOCK_SIZE]) -> Result<Self, BootSectorError> {
+        let half = |offset: usize| u16::from_le_bytes([sector[offset], sector[offset + 1]]) as u64;
+        let word = |offset: usize| {
+            u32::from_le_bytes(sector[offset..offset + 4].try_into().unwrap()) as u64
+        };
+        if !matches!(sector[0], 0xeb | 0xe9) || sector[510..] != SIGNATURE {
+            return Err(BootSectorError::NotFat);
+        }
+        let bytes_per_sector = half(11);
+        let sectors_per_cluster = sector[13] as u32;
+        let reserved = half(14);
+        let fat_count = sector[16] as u32;
+        let root_entries = half(17) as u32;
+        let total = match half(19) {
+            0 => word(32),
+            total => total,
+        };
+        if !sectors_per_cluster.is_power_of_two() || reserved == 0 || fat_count == 0 {
+            return Err(BootSectorError::NotFat);
+        }
+        if bytes_per_sector != BLOCK_SIZE as u64 {
+            return Err(BootSectorError::Unsupported);
+        }
+        // like Linux, FAT32 is what has no 16 bit FAT size, not what has enough clusters
+        let (fat_type, sectors_per_fat) = match half(22) {
+            0 => (FatType::Fat32, word(36)),
+            sectors => (FatType::Fat16, sectors),
+        };
+        let fat_start = reserved;
+        let root_start = fat_start + fat_count as u64 * sectors_per_fat;
+        let root_sectors = (root_entries as u64 * 32).div_ceil(BLOCK_SIZE as u64);
+        let data_start = root_start + root_sectors;
+        if sectors_per_fat == 0 || data_start >= total {
+            return Err(BootSectorError::NotFat);
+        }
+        let cluster_count = ((total - data_start) / sectors_per_cluster as u64) as u32;
+        let layout = Self {
+            fat_type,
+            sectors_per_cluster,
+            fat_start,
+            sectors_per_fat,
+            fat_count,
+            root_start,
+            root_entries,
+            root_cluster: 0,
+            data_start,
+            cluster_count,
+            fs_info: None,
+        };
+        match fat_type {
+            FatType::Fat16 if cluster_count < 4085 => Err(BootSectorError::Unsupported),
+            FatType::Fat16 if root_entries == 0 => Err(BootSectorError::NotFat),
+            FatType::Fat16 => Ok(layout),
+            FatType::Fat32 => {
+                let root_cluster = word(44) as u32;
+                if root_entries != 0 || root_cluster < 2 {
+                    return Err(BootSectorError::NotFat);
+                }
+                let fs_info = match half(48) {
+                    0 | 0xffff => None,
+                    sector => Some(sector),
+                };
+                Ok(Self {
+                    root_cluster,
+                    fs_info,
+                    ..layout
+                })
+            }
+        }
+    }
+
+    /// Clusters are numbered from 2 to this.
+    pub fn last_cluster(&self) -> u32 {
+        self.cluster_count + 1
+    }
+
+    pub fn cluster_size(&self) -> u32 {
+        self.sectors_per_cluster * BLOCK_SIZE as u32
+    }
+
+    /// The first sector of `cluster`.
+    pub fn cluster_sector(&self, cluster: u32) -> u64 {
+        self.data_start + (cluster - 2) as u64 * self.sectors_per_cluster as u64
+    }
+
+    /// The sector and offset of the FAT entry of `cluster` in the first FAT.
+    pub fn fat_entry(&self, cluster: u32) -> (u64, usize) {
+        let offset = match self.fat_type {
+            FatType::Fat16 => cluster as u64 * 2,
+            FatType::Fat32 => cluster as u64 * 4,
+        };
+        (
+            self.fat_start + offset / BLOCK_SIZE as u64,
+            (offset % BLOCK_SIZE as u64) as usize,
+        )
+    }
+}
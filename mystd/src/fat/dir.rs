@@ -0,0 +1,368 @@
+//! Directory entries: 8.3 names, and the long names of VFAT in the entries before them.
+
+use core::fmt;
+
+use crate::bit_field;
+
+pub const ENTRY_SIZE: usize = 32;
+/// The first name byte of an entry that was deleted.
+pub const FREE: u8 = 0xe5;
+/// The first name byte of the entry after the last one.
+pub const END: u8 = 0x00;
+/// The attributes of a long name entry: read-only, hidden, system and volume id.
+const LONG_NAME: u8 = 0x0f;
+/// Marks the long name entry with the highest sequence number, the first on the disk.
+const LAST_LONG_ENTRY: u8 = 0x40;
+const LONG_ENTRY_CHARS: [usize; 13] = [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30];
+/// The most UTF-16 code units a long name may have.
+pub const MAX_NAME_LEN: usize = 255;
+const MAX_LONG_ENTRIES: usize = MAX_NAME_LEN.div_ceil(LONG_ENTRY_CHARS.len());
+/// The most entries a name takes, the long ones and the short one.
+pub const MAX_ENTRIES: usize = MAX_LONG_ENTRIES + 1;
+/// 1980-01-01, the earliest date there is. Without a clock, that's when files were written.
+const DATE: u16 = 1 << 5 | 1;
+
+bit_field!(pub Attributes(u8){
+    5 => archive,
+    4 => directory,
+    3 => volume_id,
+    2 => system,
+    1 => hidden,
+    0 => read_only
+});
+
+/// Where an entry is, to update it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct EntryPosition {
+    pub sector: u64,
+    pub offset: usize,
+}
+
+/// A name as UTF-8, the long one or the short one.
+#[derive(Clone)]
+pub struct Name {
+    bytes: [u8; MAX_LONG_ENTRIES * 13 * 3],
+    len: usize,
+}
+
+impl Name {
+    fn new() -> Self {
+        Self {
+            bytes: [0; MAX_LONG_ENTRIES * 13 * 3],
+            len: 0,
+        }
+    }
+
+    fn push(&mut self, c: char) {
+        self.len += c.encode_utf8(&mut self.bytes[self.len..]).len();
+    }
+
+    pub fn as_str(&self) -> &str {
+        // only whole characters are pushed
+        core::str::from_utf8(&self.bytes[..self.len]).unwrap_or_default()
+    }
+}
+
+impl fmt::Display for Name {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl fmt::Debug for Name {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+/// A file or directory.
+#[derive(Clone, Debug)]
+pub struct DirEntry {
+    name: Name,
+    pub short_name: [u8; 11],
+    pub attributes: Attributes,
+    /// 0 for an empty file.
+    pub first_cluster: u32,
+    pub size: u32,
+    /// Of the short entry.
+    pub position: EntryPosition,
+}
+
+impl DirEntry {
+    /// The short entry `raw`, with the long name of the entries before it if there is one.
+    pub fn parse(raw: &[u8; ENTRY_SIZE], position: EntryPosition, long_name: Option<Name>) -> Self {
+        let mut short_name: [u8; 11] = raw[..11].try_into().unwrap();
+        if short_name[0] == 0x05 {
+            // a name that really starts with the FREE byte
+            short_name[0] = FREE;
+        }
+        let name = long_name.unwrap_or_else(|| display_short_name(&short_name, raw[12]));
+        Self {
+            name,
+            short_name,
+            attributes: Attributes::new(raw[11]),
+            first_cluster: first_cluster(raw),
+            size: u32::from_le_bytes(raw[28..32].try_into().unwrap()),
+            position,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        self.name.as_str()
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.attributes.directory().is_set()
+    }
+
+    /// Whether `name` is the long or the short name, ignoring ASCII case.
+    pub fn matches(&self, name: &str) -> bool {
+        self.name().eq_ignore_ascii_case(name)
+            || display_short_name(&self.short_name, 0)
+                .as_str()
+                .eq_ignore_ascii_case(name)
+    }
+}
+
+/// `NAME    EXT` as `NAME.EXT`, in lower case where Windows NT's `case` flags say so.
+fn display_short_name(short_name: &[u8; 11], case: u8) -> Name {
+    let mut name = Name::new();
+    let mut push = |part: &[u8], lower: bool| {
+        for &byte in part.iter().take_while(|&&byte| byte != b' ') {
+            let c = if byte.is_ascii() { byte as char } else { '?' };
+            name.push(if lower { c.to_ascii_lowercase() } else { c });
+        }
+    };
+    push(&short_name[..8], case & 0x08 != 0);
+    if short_name[8] != b' ' {
+        push(b".", false);
+        push(&short_name[8..], case & 0x10 != 0);
+    }
+    name
+}
+
+pub fn is_long_name(raw: &[u8; ENTRY_SIZE]) -> bool {
+    raw[11] & 0x3f == LONG_NAME
+}
+
+pub fn first_cluster(raw: &[u8; ENTRY_SIZE]) -> u32 {
+    let high = u16::from_le_bytes([raw[20], raw[21]]) as u32;
+    let low = u16::from_le_bytes([raw[26], raw[27]]) as u32;
+    high << 16 | low
+}
+
+pub fn set_first_cluster(raw: &mut [u8; ENTRY_SIZE], cluster: u32) {
+    raw[20..22].copy_from_slice(&((cluster >> 16) as u16).to_le_bytes());
+    raw[26..28].copy_from_slice(&(cluster as u16).to_le_bytes());
+}
+
+pub fn set_size(raw: &mut [u8; ENTRY_SIZE], size: u32) {
+    raw[28..32].copy_from_slice(&size.to_le_bytes());
+}
+
+/// The checksum of the short name that each of its long name entries has.
+pub fn checksum(short_name: &[u8; 11]) -> u8 {
+    short_name
+        .iter()
+        .fold(0_u8, |sum, &byte| sum.rotate_right(1).wrapping_add(byte))
+}
+
+/// Puts a long name together from its entries, which come in reverse order before the short one.
+pub struct LongName {
+    units: [u16; MAX_LONG_ENTRIES * 13],
+    /// The entries of the name, 0 while there is none.
+    count: u8,
+    /// The sequence number the next entry must have.
+    next: u8,
+    checksum: u8,
+}
+
+impl LongName {
+    pub fn new() -> Self {
+        Self {
+            units: [0; MAX_LONG_ENTRIES * 13],
+            count: 0,
+            next: 0,
+            checksum: 0,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.count = 0;
+    }
+
+    /// Adds the long name entry `raw`, one out of order starts over.
+    pub fn add(&mut self, raw: &[u8; ENTRY_SIZE]) {
+        let sequence = raw[0] & !LAST_LONG_ENTRY;
+        if raw[0] & LAST_LONG_ENTRY != 0 {
+            if sequence == 0 || sequence as usize > MAX_LONG_ENTRIES {
+                self.reset();
+                return;
+            }
+            self.count = sequence;
+            self.checksum = raw[13];
+        } else if self.count == 0
+            || sequence == 0
+            || sequence != self.next
+            || raw[13] != self.checksum
+        {
+            self.reset();
+            return;
+        }
+        let units = &mut self.units[(sequence as usize - 1) * 13..][..13];
+        for (unit, &offset) in units.iter_mut().zip(&LONG_ENTRY_CHARS) {
+            *unit = u16::from_le_bytes([raw[offset], raw[offset + 1]]);
+        }
+        self.next = sequence - 1;
+    }
+
+    /// The long name of `short_name`, if the entries before it spelled one out completely.
+    pub fn take(&mut self, short_name: &[u8; 11]) -> Option<Name> {
+        let complete = self.count != 0 && self.next == 0 && self.checksum == checksum(short_name);
+        let count = self.count as usize;
+        self.reset();
+        if !complete {
+            return None;
+        }
+        let mut name = Name::new();
+        let units = self.units[..count * 13]
+            .iter()
+            .copied()
+            .take_while(|&unit| unit != 0);
+        for c in char::decode_utf16(units) {
+            name.push(c.unwrap_or(char::REPLACEMENT_CHARACTER));
+        }
+        Some(name)
+    }
+}
+
+impl Default for LongName {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Whether `name` can be the name of a file or directory.
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.encode_utf16().count() <= MAX_NAME_LEN
+        && name != "."
+        && name != ".."
+        && !name.ends_with(['.', ' '])
+        && !name
+            .chars()
+            .any(|c| c < ' ' || matches!(c, '"' | '*' | '/' | ':' | '<' | '>' | '?' | '\\' | '|'))
+}
+
+fn is_short_name_char(c: char) -> bool {
+    c.is_ascii_uppercase() || c.is_ascii_digit() || "!#$%&'()-@^_`{}~".contains(c)
+}
+
+/// The 8.3 name `name` is, in upper case, if it is one.
+pub fn short_name(name: &str) -> Option<[u8; 11]> {
+    let (base, extension) = name.split_once('.').unwrap_or((name, ""));
+    let fits = (1..=8).contains(&base.len()) && extension.len() <= 3;
+    if !fits
+        || !base
+            .chars()
+            .chain(extension.chars())
+            .all(|c| is_short_name_char(c.to_ascii_uppercase()))
+    {
+        return None;
+    }
+    let mut short_name = [b' '; 11];
+    short_name[..base.len()].copy_from_slice(base.as_bytes());
+    short_name[8..8 + extension.len()].copy_from_slice(extension.as_bytes());
+    short_name.make_ascii_uppercase();
+    Some(short_name)
+}
+
+/// The short name `BASE~N.EXT` for the long name `name`, the `number`th one tried.
+pub fn numbered_short_name(name: &str, number: u32) -> [u8; 11] {
+    let (base, extension) = match name.rsplit_once('.') {
+        Some((base, extension)) if !base.is_empty() => (base, extension),
+        _ => (name, ""),
+    };
+    let convert = |c: char| match c.to_ascii_uppercase() {
+        c if is_short_name_char(c) => Some(c as u8),
+        ' ' | '.' => None,
+        _ => Some(b'_'),
+    };
+    let mut short_name = [b' '; 11];
+    let mut tail = [0_u8; 8];
+    let mut tail_len = 0;
+    let mut n = number;
+    while n > 0 || tail_len == 0 {
+        tail[tail_len] = b'0' + (n % 10) as u8;
+        tail_len += 1;
+        n /= 10;
+    }
+    let base_len = base
+        .chars()
+        .filter_map(convert)
+        .take(7 - tail_len)
+        .enumerate()
+        .map(|(i, byte)| short_name[i] = byte)
+        .count();
+    short_name[base_len] = b'~';
+    for (i, &digit) in tail[..tail_len].iter().rev().enumerate() {
+        short_name[base_len + 1 + i] = digit;
+    }
+    for (i, byte) in extension.chars().filter_map(convert).take(3).enumerate() {
+        short_name[8 + i] = byte;
+    }
+    short_name
+}
+
+/// The entry of `short_name`.
+pub fn short_entry(
+    short_name: &[u8; 11],
+    attributes: Attributes,
+    first_cluster: u32,
+) -> [u8; ENTRY_SIZE] {
+    let mut raw = [0; ENTRY_SIZE];
+    raw[..11].copy_from_slice(short_name);
+    if raw[0] == FREE {
+        raw[0] = 0x05;
+    }
+    raw[11] = attributes.to_underlying();
+    for offset in [16, 18, 24] {
+        raw[offset..offset + 2].copy_from_slice(&DATE.to_le_bytes());
+    }
+    set_first_cluster(&mut raw, first_cluster);
+    raw
+}
+
+/// Puts the long name entries of `name` into `entries`, in the order they go on the disk, and
+/// returns how many there are.
+pub fn long_entries(name: &str, short_name: &[u8; 11], entries: &mut [[u8; ENTRY_SIZE]]) -> usize {
+    let mut units = [0xffff_u16; MAX_LONG_ENTRIES * 13];
+    let mut len = 0;
+    for (unit, value) in units.iter_mut().zip(name.encode_utf16()) {
+        *unit = value;
+        len += 1;
+    }
+    if len % 13 != 0 {
+        units[len] = 0;
+    }
+    let count = len.div_ceil(13);
+    let checksum = checksum(short_name);
+    for (i, raw) in entries[..count].iter_mut().enumerate() {
+        let sequence = count - i;
+        *raw = [0; ENTRY_SIZE];
+        raw[0] = sequence as u8 | if i == 0 { LAST_LONG_ENTRY } else { 0 };
+        raw[11] = LONG_NAME;
+        raw[13] = checksum;
+        for (&unit, &offset) in units[(sequence - 1) * 13..].iter().zip(&LONG_ENTRY_CHARS) {
+            raw[offset..offset + 2].copy_from_slice(&unit.to_le_bytes());
+        }
+    }
+    count
+}
+
+/// The `.` or `..` entry of a new directory, pointing at `cluster`.
+pub fn dot_entry(dots: usize, cluster: u32) -> [u8; ENTRY_SIZE] {
+    let mut short_name = [b' '; 11];
+    short_name[..dots].fill(b'.');
+    short_entry(&short_name, Attributes::zero().directory().set(), cluster)
+}
@@ -0,0 +1,215 @@
+//! An open file: read, written and moved around in through [io].
+
+use super::dir::{self, DirEntry, EntryPosition};
+use super::{FatError, FatResult, FileSystem};
+use crate::block::{BlockDevice, BLOCK_SIZE};
+use crate::io::{self, Read, Seek, SeekFrom, Size, Write};
+
+pub struct File<'a, D: BlockDevice> {
+    fs: &'a mut FileSystem<D>,
+    /// The short entry, which has the size and the first cluster.
+    entry: EntryPosition,
+    first_cluster: u32,
+    size: u32,
+    position: u32,
+    /// The index in the chain and the number of the cluster used last, so going on from there
+    /// doesn't walk the chain from the start.
+    current: Option<(u32, u32)>,
+}
+
+impl<'a, D: BlockDevice> File<'a, D> {
+    pub(super) fn new(fs: &'a mut FileSystem<D>, entry: &DirEntry) -> Self {
+        Self {
+            fs,
+            entry: entry.position,
+            first_cluster: entry.first_cluster,
+            size: entry.size,
+            position: 0,
+            current: None,
+        }
+    }
+
+    pub fn len(&self) -> u32 {
+        self.size
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.size == 0
+    }
+
+    pub fn position(&self) -> u32 {
+        self.position
+    }
+
+    /// Cuts the file off at the position, and frees the clusters after it.
+    pub fn truncate(&mut self) -> FatResult<(), D> {
+        if self.position == 0 {
+            if self.first_cluster != 0 {
+                self.fs.free_chain(self.first_cluster)?;
+            }
+            self.first_cluster = 0;
+        } else {
+            let cluster_size = self.fs.layout.cluster_size();
+            let last = self.cluster((self.position - 1) / cluster_size, false)?;
+            if let Some(next) = self.fs.next_cluster(last)? {
+                let end_of_chain = self.fs.end_of_chain();
+                self.fs.set_fat_entry(last, end_of_chain)?;
+                self.fs.free_chain(next)?;
+            }
+        }
+        self.current = None;
+        self.size = self.position;
+        self.update_entry()
+    }
+
+    /// The cluster `index` in the chain. A chain that is too short is extended if `allocate` is
+    /// set, and corrupt if not: the size says there's more.
+    fn cluster(&mut self, index: u32, allocate: bool) -> FatResult<u32, D> {
+        if self.first_cluster == 0 {
+            if !allocate {
+                return Err(FatError::Corrupt);
+            }
+            self.first_cluster = self.fs.allocate_cluster(None)?;
+            self.current = None;
+        }
+        let (mut i, mut cluster) = match self.current {
+            Some((i, cluster)) if i <= index => (i, cluster),
+            _ => (0, self.first_cluster),
+        };
+        while i < index {
+            cluster = match self.fs.next_cluster(cluster)? {
+                Some(next) => next,
+                None if allocate => self.fs.allocate_cluster(Some(cluster))?,
+                None => return Err(FatError::Corrupt),
+            };
+            i += 1;
+        }
+        self.current = Some((index, cluster));
+        Ok(cluster)
+    }
+
+    /// The sector at the position, and the offset in it. At most `len` bytes from there are in
+    /// the same cluster.
+    fn locate(&mut self, allocate: bool) -> FatResult<(u64, usize, usize), D> {
+        let cluster_size = self.fs.layout.cluster_size();
+        let cluster = self.cluster(self.position / cluster_size, allocate)?;
+        let offset = self.position % cluster_size;
+        let sector = self.fs.cluster_sector(cluster)? + (offset as usize / BLOCK_SIZE) as u64;
+        Ok((
+            sector,
+            offset as usize % BLOCK_SIZE,
+            (cluster_size - offset) as usize,
+        ))
+    }
+
+    /// Reads what's at the position, up to the end of the cluster.
+    fn read_some(&mut self, buf: &mut [u8]) -> FatResult<usize, D> {
+        let len = buf.len().min((self.size - self.position) as usize);
+        if len == 0 {
+            return Ok(0);
+        }
+        let (sector, offset, in_cluster) = self.locate(false)?;
+        let len = len.min(in_cluster);
+        let len = if offset == 0 && len >= BLOCK_SIZE {
+            let len = len / BLOCK_SIZE * BLOCK_SIZE;
+            self.fs.read_sectors(sector, &mut buf[..len])?;
+            len
+        } else {
+            let len = len.min(BLOCK_SIZE - offset);
+            let data = self.fs.read_sector(sector)?;
+            buf[..len].copy_from_slice(&data[offset..offset + len]);
+            len
+        };
+        self.position += len as u32;
+        Ok(len)
+    }
+
+    /// Writes at the position, up to the end of the cluster, which is allocated if it isn't yet.
+    fn write_some(&mut self, buf: &[u8]) -> FatResult<usize, D> {
+        let len = buf.len().min((u32::MAX - self.position) as usize);
+        if len == 0 {
+            return Err(FatError::FileTooLarge);
+        }
+        let (sector, offset, in_cluster) = self.locate(true)?;
+        let len = len.min(in_cluster);
+        let len = if offset == 0 && len >= BLOCK_SIZE {
+            let len = len / BLOCK_SIZE * BLOCK_SIZE;
+            self.fs.write_sectors(sector, &buf[..len])?;
+            len
+        } else {
+            let len = len.min(BLOCK_SIZE - offset);
+            let data = self.fs.read_sector(sector)?;
+            data[offset..offset + len].copy_from_slice(&buf[..len]);
+            self.fs.write_back()?;
+            len
+        };
+        self.position += len as u32;
+        self.size = self.size.max(self.position);
+        Ok(len)
+    }
+
+    fn update_entry(&mut self) -> FatResult<(), D> {
+        let mut raw = self.fs.entry_at(self.entry)?;
+        dir::set_first_cluster(&mut raw, self.first_cluster);
+        dir::set_size(&mut raw, self.size);
+        self.fs.write_entry(self.entry, &raw)
+    }
+}
+
+impl<D: BlockDevice> Read for File<'_, D> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<Size> {
+        if self.position == self.size {
+            return Ok(Size::Eof);
+        }
+        if buf.is_empty() {
+            return Err(io::Error::ReadBufferZeroLength);
+        }
+        let mut count = 0;
+        while count < buf.len() && self.position < self.size {
+            count += self.read_some(&mut buf[count..])?;
+        }
+        Ok(Size::from_usize(count))
+    }
+}
+
+impl<D: BlockDevice> Write for File<'_, D> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<Size> {
+        let mut count = 0;
+        let mut result = Ok(());
+        while count < buf.len() {
+            match self.write_some(&buf[count..]) {
+                Ok(written) => count += written,
+                Err(error) => {
+                    result = Err(error);
+                    break;
+                }
+            }
+        }
+        // what was written is kept even if the rest didn't fit
+        self.update_entry()?;
+        match result {
+            Err(error) if count == 0 => Err(error.into()),
+            _ => Ok(Size::from_usize(count)),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<D: BlockDevice> Seek for File<'_, D> {
+    /// Positions go up to the end of the file, not beyond.
+    fn seek(&mut self, position: SeekFrom) -> io::Result<u64> {
+        let position = match position {
+            SeekFrom::Start(offset) => i64::try_from(offset).ok(),
+            SeekFrom::End(offset) => (self.size as i64).checked_add(offset),
+            SeekFrom::Current(offset) => (self.position as i64).checked_add(offset),
+        };
+        let position = position
+            .filter(|position| (0..=self.size as i64).contains(position))
+            .ok_or(io::Error::InvalidInput)?;
+        self.position = position as u32;
+        Ok(position as u64)
+    }
+}
@@ -0,0 +1,99 @@
+//! The partition table of the master boot record, and a partition as a device of its own.
+
+use crate::block::{BlockDevice, BLOCK_SIZE};
+
+const TABLE_OFFSET: usize = 0x1be;
+const SIGNATURE_OFFSET: usize = 0x1fe;
+/// The bytes that end a master boot record, and a FAT boot sector.
+pub const SIGNATURE: [u8; 2] = [0x55, 0xaa];
+
+/// A primary partition.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PartitionEntry {
+    pub bootable: bool,
+    pub kind: u8,
+    pub first_block: u32,
+    pub block_count: u32,
+}
+
+impl PartitionEntry {
+    /// FAT16 and FAT32, with CHS or LBA addressing.
+    pub fn is_fat(&self) -> bool {
+        matches!(self.kind, 0x04 | 0x06 | 0x0b | 0x0c | 0x0e)
+    }
+}
+
+/// The four primary partitions in `sector`, the first of the device. None if the sector isn't a
+/// master boot record, entries without a type are left out.
+pub fn partitions(sector: &[u8; BLOCK_SIZE]) -> Option<[Option<PartitionEntry>; 4]> {
+    if sector[SIGNATURE_OFFSET..] != SIGNATURE {
+        return None;
+    }
+    let mut entries = [None; 4];
+    for (i, entry) in entries.iter_mut().enumerate() {
+        let raw = &sector[TABLE_OFFSET + 16 * i..][..16];
+        if raw[0] & 0x7f != 0 {
+            // the status is 0x00 or 0x80, anything else is no partition table
+            return None;
+        }
+        let word = |offset: usize| u32::from_le_bytes(raw[offset..offset + 4].try_into().unwrap());
+        *entry = Some(PartitionEntry {
+            bootable: raw[0] == 0x80,
+            kind: raw[4],
+            first_block: word(8),
+            block_count: word(12),
+        })
+        .filter(|entry| entry.kind != 0 && entry.block_count != 0);
+    }
+    Some(entries)
+}
+
+/// The blocks of a partition, numbered from its start. The range isn't checked, that's up to the
+/// device.
+pub struct Partition<D> {
+    device: D,
+    first_block: u64,
+    block_count: u64,
+}
+
+impl<D: BlockDevice> Partition<D> {
+    pub fn new(device: D, first_block: u64, block_count: u64) -> Self {
+        Self {
+            device,
+            first_block,
+            block_count,
+        }
+    }
+
+    /// All of `device`, for file systems without a partition table.
+    pub fn whole(device: D) -> Self {
+        let block_count = device.block_count();
+        Self::new(device, 0, block_count)
+    }
+
+    pub fn first_block(&self) -> u64 {
+        self.first_block
+    }
+
+    pub fn into_inner(self) -> D {
+        self.device
+    }
+}
+
+impl<D: BlockDevice> BlockDevice for Partition<D> {
+    type Error = D::Error;
+
+    fn block_count(&self) -> u64 {
+        self.block_count
+    }
+
+    fn read_blocks(&mut self, first: u64, buffer: &mut [u8]) -> Result<(), Self::Error> {
+        debug_assert!(first + (buffer.len() / BLOCK_SIZE) as u64 <= self.block_count);
+        self.device.read_blocks(self.first_block + first, buffer)
+    }
+
+    fn write_blocks(&mut self, first: u64, buffer: &[u8]) -> Result<(), Self::Error> {
+        debug_assert!(first + (buffer.len() / BLOCK_SIZE) as u64 <= self.block_count);
+        self.device.write_blocks(self.first_block + first, buffer)
+    }
+}
@@ -0,0 +1,203 @@
+//! An allocator for physical page frames, one bit per frame.
+
+/// The size of a frame, the 4KB translation granule.
+pub const FRAME_SIZE: u64 = 4096;
+
+/// Hands out the frames of one contiguous region, tracked in `WORDS * 64` bits. A set bit is a
+/// frame in use.
+pub struct FrameAllocator<const WORDS: usize> {
+    start: u64,
+    frames: usize,
+    used: [u64; WORDS],
+    free_count: usize,
+    /// Where the search for a free frame begins, every frame below is in use.
+    next_candidate: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameError {
+    /// The address isn't the start of a frame of the region.
+    NotAFrame,
+    /// Freeing a frame that wasn't allocated.
+    NotAllocated,
+}
+
+impl<const WORDS: usize> Default for FrameAllocator<WORDS> {
+    fn default() -> Self {
+        Self::empty()
+    }
+}
+
+impl<const WORDS: usize> FrameAllocator<WORDS> {
+    pub const CAPACITY: usize = WORDS * 64;
+
+    /// An allocator without frames, see [Self::new].
+    pub const fn empty() -> Self {
+        Self {
+            start: 0,
+            frames: 0,
+            used: [0; WORDS],
+            free_count: 0,
+            next_candidate: 0,
+        }
+    }
+
+    /// Manages the frames from `start` (rounded up to a frame) to `end`, at most [Self::CAPACITY].
+    pub fn new(start: u64, end: u64) -> Self {
+        let start = start.next_multiple_of(FRAME_SIZE);
+        let frames = (end.saturating_sub(start) / FRAME_SIZE).min(Self::CAPACITY as u64) as usize;
+        Self {
+            start,
+            frames,
+            free_count: frames,
+            ..Self::empty()
+        }
+    }
+
+    /// The start of the first frame and the end of the last one.
+    pub fn region(&self) -> (u64, u64) {
+        (self.start, self.start + self.frames as u64 * FRAME_SIZE)
+    }
+
+    pub fn contains(&self, address: u64) -> bool {
+        let (start, end) = self.region();
+        (start..end).contains(&address)
+    }
+
+    pub fn frame_count(&self) -> usize {
+        self.frames
+    }
+
+    pub fn free_count(&self) -> usize {
+        self.free_count
+    }
+
+    /// The address of a free frame, which is now in use.
+    pub fn allocate(&mut self) -> Option<u64> {
+        self.allocate_contiguous(1)
+    }
+
+    /// The address of the first of `count` adjacent free frames, which are now in use.
+    pub fn allocate_contiguous(&mut self, count: usize) -> Option<u64> {
+        if count == 0 || count > self.free_count {
+            return None;
+        }
+        let mut first = self.next_candidate;
+        while first + count <= self.frames {
+            match (first..first + count).rfind(|&frame| self.is_used(frame)) {
+                // nothing before the used frame can start a long enough run
+                Some(used) => first = used + 1,
+                None => {
+                    (first..first + count).for_each(|frame| self.set_used(frame, true));
+                    self.free_count -= count;
+                    if first == self.next_candidate {
+                        self.next_candidate = first + count;
+                    }
+                    return Some(self.start + first as u64 * FRAME_SIZE);
+                }
+            }
+        }
+        None
+    }
+
+    pub fn free(&mut self, address: u64) -> Result<(), FrameError> {
+        let frame = self.frame(address)?;
+        if !self.is_used(frame) {
+            return Err(FrameError::NotAllocated);
+        }
+        self.set_used(frame, false);
+        self.free_count += 1;
+        self.next_candidate = self.next_candidate.min(frame);
+        Ok(())
+    }
+
+    /// Marks the frames overlapping `start..end` as used, so they are never handed out.
+    pub fn reserve(&mut self, start: u64, end: u64) {
+        let (region_start, region_end) = self.region();
+        let start = start.max(region_start);
+        let end = end.min(region_end);
+        if start >= end {
+            return;
+        }
+        let first = ((start - region_start) / FRAME_SIZE) as usize;
+        let last = ((end - region_start).div_ceil(FRAME_SIZE)) as usize;
+        for frame in first..last {
+            if !self.is_used(frame) {
+                self.set_used(frame, true);
+                self.free_count -= 1;
+            }
+        }
+    }
+
+    fn frame(&self, address: u64) -> Result<usize, FrameError> {
+        if !self.contains(address) || !address.is_multiple_of(FRAME_SIZE) {
+            return Err(FrameError::NotAFrame);
+        }
+        Ok(((address - self.start) / FRAME_SIZE) as usize)
+    }
+
+    fn is_used(&self, frame: usize) -> bool {
+        self.used[frame / 64] & (1 << (frame % 64)) != 0
+    }
+
+    fn set_used(&mut self, frame: usize, used: bool) {
+        if used {
+            self.used[frame / 64] |= 1 << (frame % 64);
+        } else {
+            self.used[frame / 64] &= !(1 << (frame % 64));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const START: u64 = 0x10_0000;
+
+    #[test]
+    fn frames_are_handed_out_once() {
+        let mut frames = FrameAllocator::<1>::new(START, START + 10 * FRAME_SIZE);
+        assert_eq!(frames.frame_count(), 10);
+        let a = frames.allocate().unwrap();
+        let b = frames.allocate().unwrap();
+        assert_eq!((a, b), (START, START + FRAME_SIZE));
+        assert_eq!(frames.free_count(), 8);
+        frames.free(a).unwrap();
+        assert_eq!(frames.free(a), Err(FrameError::NotAllocated));
+        assert_eq!(frames.free(START + 1), Err(FrameError::NotAFrame));
+        assert_eq!(
+            frames.free(START + 10 * FRAME_SIZE),
+            Err(FrameError::NotAFrame)
+        );
+        // the lowest free frame comes first
+        assert_eq!(frames.allocate(), Some(a));
+        while frames.allocate().is_some() {}
+        assert_eq!(frames.free_count(), 0);
+    }
+
+    #[test]
+    fn region_is_aligned_and_capped() {
+        let frames = FrameAllocator::<1>::new(START + 1, START + 1000 * FRAME_SIZE);
+        assert_eq!(
+            frames.region(),
+            (START + FRAME_SIZE, START + 65 * FRAME_SIZE)
+        );
+        assert_eq!(frames.frame_count(), 64);
+    }
+
+    #[test]
+    fn contiguous_frames_skip_used_ones() {
+        let mut frames = FrameAllocator::<2>::new(START, START + 100 * FRAME_SIZE);
+        frames.reserve(START + 2 * FRAME_SIZE, START + 3 * FRAME_SIZE + 1);
+        assert_eq!(frames.free_count(), 98);
+        assert_eq!(frames.allocate(), Some(START));
+        // frame 1 is too short a gap
+        assert_eq!(frames.allocate_contiguous(3), Some(START + 4 * FRAME_SIZE));
+        assert_eq!(frames.allocate(), Some(START + FRAME_SIZE));
+        assert_eq!(frames.allocate(), Some(START + 7 * FRAME_SIZE));
+        assert_eq!(frames.allocate_contiguous(100), None);
+        assert_eq!(frames.allocate_contiguous(92), Some(START + 8 * FRAME_SIZE));
+        assert_eq!(frames.free_count(), 0);
+    }
+}
@@ -0,0 +1,409 @@
+//! A buddy allocator for memory regions handed to it at runtime.
+//!
+//! Every block has a power of two size of at least [MIN_BLOCK_SIZE] and is aligned to its size, so
+//! a block's buddy, the other half of the block it was split from, is at `address ^ size`. Free
+//! blocks are kept in one list per size, linked through their first bytes. Freeing a block merges
+//! it with its buddy for as long as the buddy is free too.
+//!
+//! [LockedHeap] puts the allocator behind a spinning [Mutex] and implements [GlobalAlloc].
+
+use core::alloc::{GlobalAlloc, Layout};
+use core::ptr::NonNull;
+
+use crate::byte_value::ByteValue;
+use crate::sync::mutex::Mutex;
+
+/// Smaller allocations are rounded up, a free block has to hold the link to the next one.
+pub const MIN_BLOCK_SIZE: usize = 16;
+
+const ORDERS: usize = usize::BITS as usize;
+
+struct FreeBlock {
+    next: Option<NonNull<FreeBlock>>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct HeapStats {
+    /// The bytes of all regions added, without what was cut off to align them.
+    pub total_bytes: usize,
+    /// The bytes in allocated blocks, including what rounding up to the block size adds.
+    pub allocated_bytes: usize,
+    /// The bytes the current allocations asked for.
+    pub requested_bytes: usize,
+    /// The most bytes that were ever allocated at once.
+    pub peak_allocated_bytes: usize,
+    pub allocations: usize,
+    pub deallocations: usize,
+    /// Allocations that didn't find a large enough block.
+    pub failed_allocations: usize,
+}
+
+impl HeapStats {
+    pub fn free_bytes(&self) -> usize {
+        self.total_bytes - self.allocated_bytes
+    }
+
+    pub fn live_allocations(&self) -> usize {
+        self.allocations - self.deallocations
+    }
+}
+
+impl core::fmt::Display for HeapStats {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "{} of {} allocated ({} requested, peak {}), {} live allocations, {} failed",
+            ByteValue(self.allocated_bytes as u64),
+            ByteValue(self.total_bytes as u64),
+            ByteValue(self.requested_bytes as u64),
+            ByteValue(self.peak_allocated_bytes as u64),
+            self.live_allocations(),
+            self.failed_allocations
+        )
+    }
+}
+
+pub struct BuddyAllocator {
+    /// The free blocks of size `1 << n` at index `n`.
+    free_lists: [Option<NonNull<FreeBlock>>; ORDERS],
+    stats: HeapStats,
+}
+
+// SAFETY: the allocator owns the free blocks it points to
+unsafe impl Send for BuddyAllocator {}
+
+impl Default for BuddyAllocator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl BuddyAllocator {
+    pub const fn new() -> Self {
+        Self {
+            free_lists: [None; ORDERS],
+            stats: HeapStats {
+                total_bytes: 0,
+                allocated_bytes: 0,
+                requested_bytes: 0,
+                peak_allocated_bytes: 0,
+                allocations: 0,
+                deallocations: 0,
+                failed_allocations: 0,
+            },
+        }
+    }
+
+    /// Adds the memory from `start` to `end` (exclusive), split into the largest aligned blocks.
+    ///
+    /// # Safety
+    /// The memory has to be unused, writable, stay valid as long as the allocator is used, and
+    /// mustn't overlap a region added before.
+    pub unsafe fn add_region(&mut self, start: usize, end: usize) {
+        // address 0 can't be handed out, it's the null pointer
+        let mut start = start.max(MIN_BLOCK_SIZE).next_multiple_of(MIN_BLOCK_SIZE);
+        let end = end & !(MIN_BLOCK_SIZE - 1);
+        while start < end {
+            let alignment = 1 << start.trailing_zeros();
+            let fitting = 1 << (usize::BITS - 1 - (end - start).leading_zeros());
+            let size: usize = alignment.min(fitting);
+            self.push(size.trailing_zeros() as usize, start);
+            self.stats.total_bytes += size;
+            start += size;
+        }
+    }
+
+    /// The size of the block that holds an allocation for `layout`.
+    fn block_size(layout: Layout) -> Option<usize> {
+        layout
+            .size()
+            .max(layout.align())
+            .max(MIN_BLOCK_SIZE)
+            .checked_next_power_of_two()
+    }
+
+    pub fn allocate(&mut self, layout: Layout) -> Option<NonNull<u8>> {
+        let block = Self::block_size(layout).and_then(|size| {
+            let order = size.trailing_zeros() as usize;
+            let available = (order..ORDERS).find(|&order| self.free_lists[order].is_some())?;
+            let block = self.pop(available)?;
+            // keep the lower half, the upper halves become free blocks of the sizes in between
+            for order in (order..available).rev() {
+                // SAFETY: the upper half is part of the block that was just taken
+                unsafe { self.push(order, block + (1 << order)) };
+            }
+            Some((block, size))
+        });
+        let Some((block, size)) = block else {
+            self.stats.failed_allocations += 1;
+            return None;
+        };
+        self.stats.allocations += 1;
+        self.stats.allocated_bytes += size;
+        self.stats.requested_bytes += layout.size();
+        self.stats.peak_allocated_bytes = self
+            .stats
+            .peak_allocated_bytes
+            .max(self.stats.allocated_bytes);
+        NonNull::new(block as *mut u8)
+    }
+
+    /// Frees the block of an allocation, merging it with its free buddies.
+    ///
+    /// # Safety
+    /// `ptr` has to come from [Self::allocate] with the same `layout`, and mustn't be used after.
+    pub unsafe fn deallocate(&mut self, ptr: NonNull<u8>, layout: Layout) {
+        let Some(size) = Self::block_size(layout) else {
+            return;
+        };
+        self.stats.deallocations += 1;
+        self.stats.allocated_bytes -= size;
+        self.stats.requested_bytes -= layout.size();
+        let mut block = ptr.as_ptr() as usize;
+        let mut order = size.trailing_zeros() as usize;
+        while order < ORDERS - 1 && self.remove(order, block ^ (1 << order)) {
+            block &= !(1 << order);
+            order += 1;
+        }
+        self.push(order, block);
+    }
+
+    /// Whether an allocation for `old` can grow or shrink to `new` without moving.
+    pub fn fits_in_place(old: Layout, new: Layout) -> bool {
+        Self::block_size(old) == Self::block_size(new)
+    }
+
+    pub fn stats(&self) -> HeapStats {
+        self.stats
+    }
+
+    /// The size of the largest free block, the largest allocation that can succeed.
+    pub fn largest_free_block(&self) -> usize {
+        (0..ORDERS)
+            .rev()
+            .find(|&order| self.free_lists[order].is_some())
+            .map_or(0, |order| 1 << order)
+    }
+
+    /// # Safety
+    /// The block at `address` has to be free and `1 << order` bytes large.
+    unsafe fn push(&mut self, order: usize, address: usize) {
+        let block = address as *mut FreeBlock;
+        block.write(FreeBlock {
+            next: self.free_lists[order],
+        });
+        self.free_lists[order] = NonNull::new(block);
+    }
+
+    fn pop(&mut self, order: usize) -> Option<usize> {
+        let block = self.free_lists[order]?;
+        // SAFETY: the list only holds free blocks
+        self.free_lists[order] = unsafe { block.as_ref().next };
+        Some(block.as_ptr() as usize)
+    }
+
+    /// Takes the block at `address` out of the free list, returns `false` if it isn't free.
+    fn remove(&mut self, order: usize, address: usize) -> bool {
+        let mut link = &mut self.free_lists[order];
+        // SAFETY: the list only holds free blocks
+        while let Some(mut block) = *link {
+            if block.as_ptr() as usize == address {
+                *link = unsafe { block.as_ref().next };
+                return true;
+            }
+            link = unsafe { &mut block.as_mut().next };
+        }
+        false
+    }
+}
+
+/// A [BuddyAllocator] that can be shared between cores.
+pub struct LockedHeap(Mutex<BuddyAllocator>);
+
+impl Default for LockedHeap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LockedHeap {
+    pub const fn new() -> Self {
+        Self(Mutex::new(BuddyAllocator::new()))
+    }
+
+    /// See [BuddyAllocator::add_region].
+    ///
+    /// # Safety
+    /// As for [BuddyAllocator::add_region].
+    pub unsafe fn add_region(&self, start: usize, end: usize) {
+        self.0.lock().add_region(start, end)
+    }
+
+    pub fn stats(&self) -> HeapStats {
+        unsafe { self.0.lock() }.stats()
+    }
+
+    pub fn largest_free_block(&self) -> usize {
+        unsafe { self.0.lock() }.largest_free_block()
+    }
+}
+
+unsafe impl GlobalAlloc for LockedHeap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.0
+            .lock()
+            .allocate(layout)
+            .map_or(core::ptr::null_mut(), NonNull::as_ptr)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        if let Some(ptr) = NonNull::new(ptr) {
+            self.0.lock().deallocate(ptr, layout)
+        }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_layout = Layout::from_size_align_unchecked(new_size, layout.align());
+        if BuddyAllocator::fits_in_place(layout, new_layout) {
+            let mut heap = self.0.lock();
+            heap.stats.requested_bytes = heap.stats.requested_bytes - layout.size() + new_size;
+            return ptr;
+        }
+        let new_ptr = self.alloc(new_layout);
+        if !new_ptr.is_null() {
+            core::ptr::copy_nonoverlapping(ptr, new_ptr, layout.size().min(new_size));
+            self.dealloc(ptr, layout);
+        }
+        new_ptr
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::vec::Vec;
+
+    use super::*;
+
+    const REGION_SIZE: usize = 1 << 16;
+
+    /// A region aligned to its size, so it is a single block.
+    fn region() -> (usize, usize) {
+        let layout = Layout::from_size_align(REGION_SIZE, REGION_SIZE).unwrap();
+        let start = unsafe { std::alloc::alloc(layout) } as usize;
+        assert_ne!(start, 0);
+        (start, start + REGION_SIZE)
+    }
+
+    fn heap() -> BuddyAllocator {
+        let (start, end) = region();
+        let mut heap = BuddyAllocator::new();
+        unsafe { heap.add_region(start, end) };
+        heap
+    }
+
+    #[test]
+    fn allocations_are_aligned_and_disjoint() {
+        let mut heap = heap();
+        let layouts = [(1, 1), (24, 8), (100, 4), (16, 64), (4096, 4096), (3, 2)]
+            .map(|(size, align)| Layout::from_size_align(size, align).unwrap());
+        let mut blocks: Vec<(usize, usize)> = Vec::new();
+        for layout in layouts {
+            let ptr = heap.allocate(layout).unwrap().as_ptr() as usize;
+            assert_eq!(ptr % layout.align(), 0);
+            for &(other, size) in &blocks {
+                assert!(ptr + layout.size() <= other || other + size <= ptr);
+            }
+            blocks.push((ptr, layout.size()));
+        }
+        assert_eq!(heap.stats().live_allocations(), layouts.len());
+        assert_eq!(heap.stats().requested_bytes, 1 + 24 + 100 + 16 + 4096 + 3);
+    }
+
+    #[test]
+    fn freeing_merges_the_buddies() {
+        let mut heap = heap();
+        assert_eq!(heap.largest_free_block(), REGION_SIZE);
+        let layout = Layout::from_size_align(100, 8).unwrap();
+        let ptrs: Vec<_> = (0..50).map(|_| heap.allocate(layout).unwrap()).collect();
+        assert!(heap.largest_free_block() < REGION_SIZE);
+        assert_eq!(heap.stats().allocated_bytes, 50 * 128);
+        // in an order that frees buddies apart from each other
+        for ptr in ptrs.iter().step_by(2).chain(ptrs.iter().skip(1).step_by(2)) {
+            unsafe { heap.deallocate(*ptr, layout) };
+        }
+        assert_eq!(heap.largest_free_block(), REGION_SIZE);
+        let stats = heap.stats();
+        assert_eq!(stats.allocated_bytes, 0);
+        assert_eq!(stats.requested_bytes, 0);
+        assert_eq!(stats.peak_allocated_bytes, 50 * 128);
+        assert_eq!(stats.live_allocations(), 0);
+    }
+
+    #[test]
+    fn running_out_fails() {
+        let mut heap = heap();
+        let half = Layout::from_size_align(REGION_SIZE / 2, 1).unwrap();
+        assert!(heap.allocate(half).is_some());
+        assert!(heap.allocate(half).is_some());
+        assert!(heap.allocate(Layout::new::<u8>()).is_none());
+        assert!(heap
+            .allocate(Layout::from_size_align(REGION_SIZE * 2, 1).unwrap())
+            .is_none());
+        assert_eq!(heap.stats().failed_allocations, 2);
+        assert_eq!(heap.stats().free_bytes(), 0);
+    }
+
+    #[test]
+    fn unaligned_regions_are_split_into_aligned_blocks() {
+        let (start, end) = region();
+        let mut heap = BuddyAllocator::new();
+        unsafe { heap.add_region(start + 8, end - 100) };
+        // 16 cut off at the start to align, 100 rounded up to 112 at the end
+        assert_eq!(heap.stats().total_bytes, REGION_SIZE - 16 - 112);
+        assert_eq!(heap.largest_free_block(), REGION_SIZE / 4);
+        let layout = Layout::from_size_align(16, 16).unwrap();
+        while let Some(ptr) = heap.allocate(layout) {
+            let ptr = ptr.as_ptr() as usize;
+            assert!(ptr >= start + 16 && ptr + 16 <= end - 100);
+        }
+        assert_eq!(heap.stats().allocated_bytes, heap.stats().total_bytes);
+    }
+
+    #[test]
+    fn locked_heap_is_shared_between_threads() {
+        let (start, end) = region();
+        let heap: &'static LockedHeap =
+            std::boxed::Box::leak(std::boxed::Box::new(LockedHeap::new()));
+        unsafe { heap.add_region(start, end) };
+        let threads: Vec<_> = (0..4)
+            .map(|n| {
+                std::thread::spawn(move || {
+                    for i in 0..200 {
+                        let layout = Layout::from_size_align(8 + (n * 8 + i) % 200, 8).unwrap();
+                        unsafe {
+                            let ptr = heap.alloc(layout);
+                            assert!(!ptr.is_null());
+                            ptr.write_bytes(n as u8, layout.size());
+                            let ptr = heap.realloc(ptr, layout, layout.size() * 2);
+                            assert!(!ptr.is_null());
+                            assert!((0..layout.size()).all(|i| *ptr.add(i) == n as u8));
+                            heap.dealloc(
+                                ptr,
+                                Layout::from_size_align(layout.size() * 2, 8).unwrap(),
+                            );
+                        }
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        let stats = heap.stats();
+        assert_eq!(stats.live_allocations(), 0);
+        assert_eq!(stats.allocated_bytes, 0);
+        assert_eq!(heap.largest_free_block(), REGION_SIZE);
+    }
+}
@@ -2,7 +2,7 @@ use core::num::NonZeroUsize;
 
 pub mod buffered_writer;
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Error {
     NoReceiver,
     InvalidData,
@@ -14,6 +14,10 @@ pub enum Error {
     WouldBlock,
     TimedOut,
     ConnectionAborted,
+    /// An argument that makes no sense, like a position before the start.
+    InvalidInput,
+    /// Something below failed, e.g. the storage device.
+    Other,
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -238,6 +242,27 @@ pub trait Read {
     }
 }
 
+/// Where [Seek::seek] goes from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SeekFrom {
+    Start(u64),
+    End(i64),
+    Current(i64),
+}
+
+pub trait Seek {
+    /// Moves to `position` and returns the new position from the start.
+    fn seek(&mut self, position: SeekFrom) -> Result<u64>;
+
+    fn stream_position(&mut self) -> Result<u64> {
+        self.seek(SeekFrom::Current(0))
+    }
+
+    fn rewind(&mut self) -> Result<()> {
+        self.seek(SeekFrom::Start(0)).map(|_| ())
+    }
+}
+
 pub struct Bytes<T: Read> {
     reader: T,
 }
@@ -3,15 +3,22 @@
 pub mod bcd;
 pub mod bitfield;
 pub mod bitfield2;
+pub mod block;
 pub mod byte_value;
 pub mod collections;
 pub mod drawing;
+pub mod elf;
+pub mod fat;
 pub mod fixed_point;
 pub mod format;
 pub mod io;
 pub mod sync;
+pub mod syscall;
+pub mod paging;
 pub mod parse;
 pub mod slice;
 pub mod fractions;
+pub mod frames;
+pub mod heap;
 pub mod protocols;
 pub mod morse;
@@ -0,0 +1,797 @@
+//! Translation tables of the VMSAv8-64 with the 4KB granule and 48 bit addresses, worked on in
+//! software: walking them like the MMU does, and changing the mappings in them.
+//!
+//! The tables are reached through [TableMemory], so the same code runs on the tables the MMU uses
+//! and on tables in a test. Descriptors are plain `u64`s here. The attributes of a mapping are
+//! the descriptor bits outside the output address and the type bits, the caller builds them.
+//!
+//! Valid descriptors are replaced break-before-make, as the architecture requires for tables in
+//! use: the old one is made invalid and its translations dropped before the new one is written.
+
+/// Bits 1:0 of a descriptor.
+const VALID: u64 = 0b01;
+const TABLE_OR_PAGE: u64 = 0b11;
+const BLOCK: u64 = 0b01;
+/// The output address, or the address of the next table, bits 47:12.
+pub const ADDRESS_MASK: u64 = 0x0000_ffff_ffff_f000;
+/// The contiguous hint, it has to be the same for all 16 descriptors of an aligned group.
+const CONTIGUOUS: u64 = 1 << 52;
+const CONTIGUOUS_GROUP: usize = 16;
+
+const ENTRIES: u64 = 512;
+pub const PAGE_SIZE: u64 = 4096;
+
+/// The memory that holds the tables, addressed physically.
+pub trait TableMemory {
+    fn read(&self, address: u64) -> u64;
+    fn write(&mut self, address: u64, descriptor: u64);
+    /// A new table with all descriptors invalid, or `None` if there is no memory left.
+    fn allocate_table(&mut self) -> Option<u64>;
+    /// Called for tables that aren't referred to anymore, including ones not from
+    /// [Self::allocate_table].
+    fn free_table(&mut self, address: u64);
+    /// Whether the valid `descriptor` of a table at `level`, which maps the bytes from
+    /// `input_address`, may be invalid for a moment while it is replaced.
+    fn may_break(&self, input_address: u64, level: u8, descriptor: u64) -> bool;
+    /// Drops the translations of the `size` bytes from `input_address` from the TLBs, between
+    /// making a descriptor invalid and writing its replacement.
+    fn invalidate(&mut self, input_address: u64, size: u64);
+}
+
+/// The bytes a descriptor at `level` maps.
+pub const fn level_size(level: u8) -> u64 {
+    1 << (12 + 9 * (3 - level as u32))
+}
+
+/// The attribute bits of a descriptor, without the address and type.
+pub const fn attributes(descriptor: u64) -> u64 {
+    descriptor & !(ADDRESS_MASK | 0b11)
+}
+
+fn index(address: u64, level: u8) -> u64 {
+    (address / level_size(level)) % ENTRIES
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Descriptor {
+    Invalid,
+    /// With the address of the next level table.
+    Table(u64),
+    /// A block or a page, with its output address.
+    Leaf(u64),
+}
+
+impl Descriptor {
+    pub fn decode(descriptor: u64, level: u8) -> Self {
+        match (level, descriptor & 0b11) {
+            (0..=2, TABLE_OR_PAGE) => Self::Table(descriptor & ADDRESS_MASK),
+            (3, TABLE_OR_PAGE) => Self::Leaf(descriptor & ADDRESS_MASK),
+            (1 | 2, BLOCK) => Self::Leaf(descriptor & ADDRESS_MASK & !(level_size(level) - 1)),
+            _ => Self::Invalid,
+        }
+    }
+}
+
+/// One descriptor read during a walk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Step {
+    pub level: u8,
+    /// Where the descriptor was read from.
+    pub address: u64,
+    pub descriptor: u64,
+}
+
+/// The descriptors read to translate an address, up to the first leaf or invalid one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Walk {
+    pub input_address: u64,
+    pub steps: [Option<Step>; 4],
+}
+
+impl Walk {
+    pub fn last(&self) -> Option<&Step> {
+        self.steps.iter().rev().flatten().next()
+    }
+
+    pub fn translation(&self) -> Result<Translation, TranslationFault> {
+        let last = self.last().ok_or(TranslationFault { level: 0 })?;
+        match Descriptor::decode(last.descriptor, last.level) {
+            Descriptor::Leaf(output) => Ok(Translation {
+                output_address: output | (self.input_address & (level_size(last.level) - 1)),
+                level: last.level,
+                attributes: attributes(last.descriptor),
+            }),
+            _ => Err(TranslationFault { level: last.level }),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Translation {
+    pub output_address: u64,
+    /// The level of the block or page.
+    pub level: u8,
+    pub attributes: u64,
+}
+
+/// The level of the invalid descriptor the walk ended at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TranslationFault {
+    pub level: u8,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MapError {
+    /// Addresses and sizes have to be multiples of [PAGE_SIZE].
+    Unaligned,
+    /// [TableMemory::allocate_table] failed, the range is only partly changed.
+    OutOfTables,
+    /// There is no mapping at the address, the range before it is already changed.
+    NotMapped(u64),
+    /// [TableMemory::may_break] refused to replace the mapping at the address, the range before
+    /// it is already changed.
+    InUse(u64),
+}
+
+/// The tables reachable from one root table, like the ones of TTBR0 or TTBR1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AddressSpace {
+    pub root: u64,
+    /// The level of the root table, it depends on the size of the address range.
+    pub start_level: u8,
+}
+
+impl AddressSpace {
+    pub const fn new(root: u64, start_level: u8) -> Self {
+        Self { root, start_level }
+    }
+
+    /// Reads the descriptors for `input_address` the way the MMU does.
+    pub fn walk(&self, memory: &impl TableMemory, input_address: u64) -> Walk {
+        let mut walk = Walk {
+            input_address,
+            steps: [None; 4],
+        };
+        let mut table = self.root;
+        for level in self.start_level..=3 {
+            let address = table + index(input_address, level) * 8;
+            let descriptor = memory.read(address);
+            walk.steps[level as usize] = Some(Step {
+                level,
+                address,
+                descriptor,
+            });
+            match Descriptor::decode(descriptor, level) {
+                Descriptor::Table(next) => table = next,
+                _ => break,
+            }
+        }
+        walk
+    }
+
+    pub fn translate(
+        &self,
+        memory: &impl TableMemory,
+        input_address: u64,
+    ) -> Result<Translation, TranslationFault> {
+        self.walk(memory, input_address).translation()
+    }
+
+    /// Maps `size` bytes from `virtual_address` to `physical_address`, replacing what was mapped
+    /// there. Uses the largest blocks the alignment of both addresses allows.
+    pub fn map(
+        &self,
+        memory: &mut impl TableMemory,
+        virtual_address: u64,
+        physical_address: u64,
+        size: u64,
+        attributes: u64,
+    ) -> Result<(), MapError> {
+        check_aligned(&[virtual_address, physical_address, size])?;
+        let physical = Some(physical_address);
+        self.change(
+            memory,
+            virtual_address,
+            size,
+            Change::Map(physical, attributes),
+        )
+    }
+
+    /// Removes the mappings of `size` bytes from `virtual_address`, holes are fine.
+    pub fn unmap(
+        &self,
+        memory: &mut impl TableMemory,
+        virtual_address: u64,
+        size: u64,
+    ) -> Result<(), MapError> {
+        check_aligned(&[virtual_address, size])?;
+        self.change(memory, virtual_address, size, Change::Unmap)
+    }
+
+    /// Changes the attributes of the mapped range of `size` bytes from `virtual_address`.
+    pub fn protect(
+        &self,
+        memory: &mut impl TableMemory,
+        virtual_address: u64,
+        size: u64,
+        attributes: u64,
+    ) -> Result<(), MapError> {
+        check_aligned(&[virtual_address, size])?;
+        self.change(memory, virtual_address, size, Change::Map(None, attributes))
+    }
+
+    fn change(
+        &self,
+        memory: &mut impl TableMemory,
+        virtual_address: u64,
+        size: u64,
+        change: Change,
+    ) -> Result<(), MapError> {
+        let end = virtual_address
+            .checked_add(size)
+            .ok_or(MapError::Unaligned)?;
+        change_table(
+            memory,
+            self.root,
+            self.start_level,
+            virtual_address,
+            end,
+            change,
+        )
+    }
+}
+
+fn check_aligned(values: &[u64]) -> Result<(), MapError> {
+    match values.iter().all(|value| value % PAGE_SIZE == 0) {
+        true => Ok(()),
+        false => Err(MapError::Unaligned),
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Change {
+    /// Maps to the physical address given for the start of the range, or keeps the output
+    /// addresses of the existing mappings.
+    Map(Option<u64>, u64),
+    Unmap,
+}
+
+impl Change {
+    /// The change for the part of the range that starts `offset` bytes in.
+    fn skip(self, offset: u64) -> Self {
+        match self {
+            Self::Map(Some(physical), attributes) => Self::Map(Some(physical + offset), attributes),
+            other => other,
+        }
+    }
+}
+
+/// Applies `change` to `start..end` in the table at `level`, `start` and `end` within the range
+/// the table covers.
+fn change_table(
+    memory: &mut impl TableMemory,
+    table: u64,
+    level: u8,
+    start: u64,
+    end: u64,
+    change: Change,
+) -> Result<(), MapError> {
+    let size = level_size(level);
+    let mut address = start;
+    while address < end {
+        let entry_start = address & !(size - 1);
+        let entry_end = (entry_start + size).min(end);
+        let descriptor_address = table + index(address, level) * 8;
+        let descriptor = memory.read(descriptor_address);
+        let covers_entry = address == entry_start && entry_end - entry_start == size;
+        let current = Descriptor::decode(descriptor, level);
+        let change_here = change.skip(address - start);
+
+        // a whole entry is replaced by a leaf or made invalid, without looking further down
+        let leaf = match change_here {
+            Change::Map(Some(physical), attributes)
+                if covers_entry && can_be_leaf(level, physical) =>
+            {
+                Some(leaf_descriptor(level, physical, attributes))
+            }
+            Change::Unmap if covers_entry => Some(0),
+            _ => None,
+        };
+        if let Some(leaf) = leaf {
+            write_entry(memory, descriptor_address, entry_start, level, leaf)?;
+            if let Descriptor::Table(next) = current {
+                free_tables(memory, next, level + 1);
+            }
+            address = entry_end;
+            continue;
+        }
+
+        let next = match (current, change_here) {
+            (Descriptor::Table(next), _) => next,
+            (Descriptor::Invalid, Change::Unmap) => {
+                address = entry_end;
+                continue;
+            }
+            (Descriptor::Invalid, Change::Map(None, _)) => {
+                return Err(MapError::NotMapped(address))
+            }
+            (Descriptor::Leaf(output), Change::Map(None, attributes)) if covers_entry => {
+                write_entry(
+                    memory,
+                    descriptor_address,
+                    entry_start,
+                    level,
+                    leaf_descriptor(level, output, attributes),
+                )?;
+                address = entry_end;
+                continue;
+            }
+            (Descriptor::Invalid, Change::Map(Some(_), _)) => {
+                let next = memory.allocate_table().ok_or(MapError::OutOfTables)?;
+                write_entry(
+                    memory,
+                    descriptor_address,
+                    entry_start,
+                    level,
+                    next | TABLE_OR_PAGE,
+                )?;
+                next
+            }
+            // a block only partly changed is split into a table with the same mappings
+            (Descriptor::Leaf(output), _) => {
+                let next = memory.allocate_table().ok_or(MapError::OutOfTables)?;
+                let next_size = level_size(level + 1);
+                for i in 0..ENTRIES {
+                    let entry =
+                        leaf_descriptor(level + 1, output + i * next_size, attributes(descriptor));
+                    memory.write(next + i * 8, entry & !CONTIGUOUS);
+                }
+                write_entry(
+                    memory,
+                    descriptor_address,
+                    entry_start,
+                    level,
+                    next | TABLE_OR_PAGE,
+                )?;
+                next
+            }
+        };
+        change_table(memory, next, level + 1, address, entry_end, change_here)?;
+        address = entry_end;
+    }
+    Ok(())
+}
+
+/// Blocks exist at levels 1 and 2, pages at level 3.
+fn can_be_leaf(level: u8, physical_address: u64) -> bool {
+    level >= 1 && physical_address.is_multiple_of(level_size(level))
+}
+
+fn leaf_descriptor(level: u8, output_address: u64, attributes: u64) -> u64 {
+    let kind = if level == 3 { TABLE_OR_PAGE } else { BLOCK };
+    output_address | self::attributes(attributes) | kind
+}
+
+/// Writes the descriptor at `address` of a table at `level`, which maps the bytes from
+/// `input_address`. First clears the contiguous hint of its group if it has one.
+fn write_entry(
+    memory: &mut impl TableMemory,
+    address: u64,
+    input_address: u64,
+    level: u8,
+    descriptor: u64,
+) -> Result<(), MapError> {
+    let group_size = CONTIGUOUS_GROUP as u64 * 8;
+    let group = address & !(group_size - 1);
+    let group_input = input_address - (address - group) / 8 * level_size(level);
+    let hinted = |old: u64| old & VALID != 0 && old & CONTIGUOUS != 0;
+    let entries = || (group..group + group_size).step_by(8).zip(0..);
+    if entries().any(|(entry, _)| hinted(memory.read(entry))) {
+        // the hint is the same for the whole group, so the whole group is broken at once
+        for (entry, i) in entries() {
+            let old = memory.read(entry);
+            let entry_input = group_input + i * level_size(level);
+            if hinted(old) && !memory.may_break(entry_input, level, old) {
+                return Err(MapError::InUse(entry_input));
+            }
+        }
+        let hinted_entries: [u64; CONTIGUOUS_GROUP] = core::array::from_fn(|i| {
+            let entry = group + i as u64 * 8;
+            let old = memory.read(entry);
+            if hinted(old) {
+                memory.write(entry, 0);
+            }
+            old
+        });
+        memory.invalidate(group_input, CONTIGUOUS_GROUP as u64 * level_size(level));
+        for (old, (entry, _)) in hinted_entries.into_iter().zip(entries()) {
+            if hinted(old) {
+                memory.write(entry, old & !CONTIGUOUS);
+            }
+        }
+    }
+    replace(
+        memory,
+        address,
+        input_address,
+        level,
+        descriptor & !CONTIGUOUS,
+    )
+}
+
+/// Writes `descriptor` at `address` break-before-make if it replaces a different valid one.
+fn replace(
+    memory: &mut impl TableMemory,
+    address: u64,
+    input_address: u64,
+    level: u8,
+    descriptor: u64,
+) -> Result<(), MapError> {
+    let old = memory.read(address);
+    if old & VALID != 0 && descriptor & VALID != 0 && old != descriptor {
+        if !memory.may_break(input_address, level, old) {
+            return Err(MapError::InUse(input_address));
+        }
+        memory.write(address, 0);
+        memory.invalidate(input_address, level_size(level));
+    }
+    memory.write(address, descriptor);
+    Ok(())
+}
+
+/// Frees the table at `level` and all tables below it.
+fn free_tables(memory: &mut impl TableMemory, table: u64, level: u8) {
+    if level < 3 {
+        for i in 0..ENTRIES {
+            if let Descriptor::Table(next) = Descriptor::decode(memory.read(table + i * 8), level) {
+                free_tables(memory, next, level + 1);
+            }
+        }
+    }
+    memory.free_table(table);
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use core::ops::Range;
+    use std::collections::{BTreeMap, BTreeSet};
+    use std::vec::Vec;
+
+    use super::*;
+
+    /// Tables at made up addresses, starting at 1 MiB.
+    #[derive(Default)]
+    struct TestMemory {
+        descriptors: BTreeMap<u64, u64>,
+        tables: BTreeSet<u64>,
+        next_table: u64,
+        table_limit: Option<usize>,
+        /// Input addresses whose mappings may not be broken.
+        pinned: Option<Range<u64>>,
+        /// Descriptors made invalid and not yet invalidated.
+        broken: BTreeSet<u64>,
+        invalidated: Vec<(u64, u64)>,
+    }
+
+    impl TableMemory for TestMemory {
+        fn read(&self, address: u64) -> u64 {
+            assert!(
+                self.tables.contains(&(address & !(PAGE_SIZE - 1))),
+                "read outside a table"
+            );
+            self.descriptors.get(&address).copied().unwrap_or(0)
+        }
+
+        fn write(&mut self, address: u64, descriptor: u64) {
+            assert!(
+                self.tables.contains(&(address & !(PAGE_SIZE - 1))),
+                "write outside a table"
+            );
+            let old = self.descriptors.get(&address).copied().unwrap_or(0);
+            assert!(
+                old & VALID == 0 || descriptor & VALID == 0 || old == descriptor,
+                "replaced without break-before-make"
+            );
+            if old & VALID != 0 && descriptor == 0 {
+                self.broken.insert(address);
+            } else if descriptor & VALID != 0 {
+                assert!(
+                    !self.broken.remove(&address),
+                    "made before the TLBs were invalidated"
+                );
+            }
+            self.descriptors.insert(address, descriptor);
+        }
+
+        fn allocate_table(&mut self) -> Option<u64> {
+            if self
+                .table_limit
+                .is_some_and(|limit| self.tables.len() >= limit)
+            {
+                return None;
+            }
+            self.next_table += PAGE_SIZE;
+            let table = 0x10_0000 + self.next_table;
+            self.tables.insert(table);
+            Some(table)
+        }
+
+        fn free_table(&mut self, address: u64) {
+            assert!(self.tables.remove(&address), "freed twice");
+            self.descriptors
+                .retain(|&entry, _| entry & !(PAGE_SIZE - 1) != address);
+        }
+
+        fn may_break(&self, input_address: u64, level: u8, _descriptor: u64) -> bool {
+            let end = input_address + level_size(level);
+            self.pinned
+                .as_ref()
+                .is_none_or(|pinned| end <= pinned.start || pinned.end <= input_address)
+        }
+
+        fn invalidate(&mut self, input_address: u64, size: u64) {
+            self.broken.clear();
+            self.invalidated.push((input_address, size));
+        }
+    }
+
+    const ATTRIBUTES: u64 = 1 << 10 | 0b11 << 8; // AF, inner shareable
+    const READ_ONLY: u64 = ATTRIBUTES | 0b10 << 6;
+    const MIB: u64 = 1 << 20;
+    const GIB: u64 = 1 << 30;
+
+    fn space() -> (TestMemory, AddressSpace) {
+        let mut memory = TestMemory::default();
+        let root = memory.allocate_table().unwrap();
+        (memory, AddressSpace::new(root, 0))
+    }
+
+    fn translate(
+        memory: &TestMemory,
+        space: &AddressSpace,
+        address: u64,
+    ) -> Option<(u64, u8, u64)> {
+        let translation = space.translate(memory, address).ok()?;
+        Some((
+            translation.output_address,
+            translation.level,
+            translation.attributes,
+        ))
+    }
+
+    #[test]
+    fn pages_and_blocks_are_chosen_by_alignment() {
+        let (mut memory, space) = space();
+        space
+            .map(&mut memory, 0x1000, 0x8000_1000, 4 * MIB, ATTRIBUTES)
+            .unwrap();
+        // up to the first 2 MiB boundary pages, then one block, then pages again
+        assert_eq!(
+            translate(&memory, &space, 0x1000),
+            Some((0x8000_1000, 3, ATTRIBUTES))
+        );
+        assert_eq!(
+            translate(&memory, &space, 0x1f_fabc),
+            Some((0x801f_fabc, 3, ATTRIBUTES))
+        );
+        assert_eq!(
+            translate(&memory, &space, 0x20_0010),
+            Some((0x8020_0010, 2, ATTRIBUTES))
+        );
+        assert_eq!(
+            translate(&memory, &space, 0x40_0000),
+            Some((0x8040_0000, 3, ATTRIBUTES))
+        );
+        assert_eq!(
+            space.translate(&memory, 0x40_1000),
+            Err(TranslationFault { level: 3 })
+        );
+        assert_eq!(
+            space.translate(&memory, 0),
+            Err(TranslationFault { level: 3 })
+        );
+        assert_eq!(
+            space.translate(&memory, GIB),
+            Err(TranslationFault { level: 1 })
+        );
+        // root, level 1, level 2 and two level 3 tables
+        assert_eq!(memory.tables.len(), 5);
+
+        space
+            .map(&mut memory, 2 * GIB, 3 * GIB, GIB, ATTRIBUTES)
+            .unwrap();
+        assert_eq!(
+            translate(&memory, &space, 2 * GIB + 5),
+            Some((3 * GIB + 5, 1, ATTRIBUTES))
+        );
+    }
+
+    #[test]
+    fn unaligned_ranges_are_rejected() {
+        let (mut memory, space) = space();
+        assert_eq!(
+            space.map(&mut memory, 0x1001, 0, PAGE_SIZE, ATTRIBUTES),
+            Err(MapError::Unaligned)
+        );
+        assert_eq!(
+            space.map(&mut memory, 0, 0x10, PAGE_SIZE, ATTRIBUTES),
+            Err(MapError::Unaligned)
+        );
+        assert_eq!(space.unmap(&mut memory, 0, 100), Err(MapError::Unaligned));
+    }
+
+    #[test]
+    fn unmapping_part_of_a_block_splits_it() {
+        let (mut memory, space) = space();
+        space.map(&mut memory, 0, 0, GIB, ATTRIBUTES).unwrap();
+        assert_eq!(memory.tables.len(), 2);
+        space.unmap(&mut memory, 3 * MIB, PAGE_SIZE).unwrap();
+        assert_eq!(
+            space.translate(&memory, 3 * MIB + 8),
+            Err(TranslationFault { level: 3 })
+        );
+        assert_eq!(
+            translate(&memory, &space, 3 * MIB - 1),
+            Some((3 * MIB - 1, 3, ATTRIBUTES))
+        );
+        assert_eq!(
+            translate(&memory, &space, 2 * MIB - 1),
+            Some((2 * MIB - 1, 2, ATTRIBUTES))
+        );
+        assert_eq!(
+            translate(&memory, &space, 3 * MIB + PAGE_SIZE),
+            Some((3 * MIB + PAGE_SIZE, 3, ATTRIBUTES))
+        );
+        assert_eq!(
+            translate(&memory, &space, GIB - 1),
+            Some((GIB - 1, 2, ATTRIBUTES))
+        );
+        assert_eq!(memory.tables.len(), 4);
+
+        // unmapping everything frees the tables below the level 1 entry
+        space.unmap(&mut memory, 0, GIB).unwrap();
+        assert_eq!(
+            space.translate(&memory, 3 * MIB + PAGE_SIZE),
+            Err(TranslationFault { level: 1 })
+        );
+        assert_eq!(memory.tables.len(), 2);
+        // holes are fine
+        space.unmap(&mut memory, 0, 4 * GIB).unwrap();
+    }
+
+    #[test]
+    fn protect_keeps_the_output_addresses() {
+        let (mut memory, space) = space();
+        space
+            .map(&mut memory, 0, 0x4000_0000, 4 * MIB, ATTRIBUTES)
+            .unwrap();
+        space
+            .protect(&mut memory, 0x10_0000, 0x20_0000, READ_ONLY)
+            .unwrap();
+        assert_eq!(
+            translate(&memory, &space, 0xf_f000),
+            Some((0x400f_f000, 3, ATTRIBUTES))
+        );
+        assert_eq!(
+            translate(&memory, &space, 0x10_0000),
+            Some((0x4010_0000, 3, READ_ONLY))
+        );
+        assert_eq!(
+            translate(&memory, &space, 0x2f_f000),
+            Some((0x402f_f000, 3, READ_ONLY))
+        );
+        assert_eq!(
+            translate(&memory, &space, 0x30_0000),
+            Some((0x4030_0000, 3, ATTRIBUTES))
+        );
+        // the rest of the split block
+        space
+            .protect(&mut memory, 0x20_0000, 0x20_0000, READ_ONLY)
+            .unwrap();
+        assert_eq!(
+            translate(&memory, &space, 0x30_0000),
+            Some((0x4030_0000, 3, READ_ONLY))
+        );
+        assert_eq!(
+            space.protect(&mut memory, 0x3f_f000, 0x2000, READ_ONLY),
+            Err(MapError::NotMapped(0x40_0000))
+        );
+    }
+
+    #[test]
+    fn remapping_replaces_tables() {
+        let (mut memory, space) = space();
+        space
+            .map(&mut memory, 0, 0, 64 * PAGE_SIZE, ATTRIBUTES)
+            .unwrap();
+        assert_eq!(memory.tables.len(), 4);
+        space
+            .map(&mut memory, 0, 0x8000_0000, 2 * MIB, READ_ONLY)
+            .unwrap();
+        assert_eq!(memory.tables.len(), 3);
+        assert_eq!(
+            translate(&memory, &space, 0x1234),
+            Some((0x8000_1234, 2, READ_ONLY))
+        );
+    }
+
+    #[test]
+    fn contiguous_hints_are_cleared_for_the_whole_group() {
+        let (mut memory, space) = space();
+        space.map(&mut memory, 0, 0, 32 * MIB, ATTRIBUTES).unwrap();
+        // pretend the first 16 blocks were mapped with the hint
+        let level2 = space.walk(&memory, 0).steps[2].unwrap().address;
+        for i in 0..16 {
+            let descriptor = memory.read(level2 + i * 8);
+            memory
+                .descriptors
+                .insert(level2 + i * 8, descriptor | CONTIGUOUS);
+        }
+        space
+            .protect(&mut memory, 4 * MIB, 2 * MIB, READ_ONLY)
+            .unwrap();
+        let hints: Vec<_> = (0..16)
+            .map(|i| memory.read(level2 + i * 8) & CONTIGUOUS)
+            .collect();
+        assert!(hints.iter().all(|&hint| hint == 0));
+        assert_eq!(memory.invalidated[0], (0, 32 * MIB));
+    }
+
+    #[test]
+    fn live_mappings_are_broken_before_they_are_made() {
+        let (mut memory, space) = space();
+        space.map(&mut memory, 0, 0, 2 * MIB, ATTRIBUTES).unwrap();
+        assert!(memory.invalidated.is_empty());
+        // splitting the block and then changing a page both replace a valid descriptor
+        space
+            .map(&mut memory, 0x3000, 0x8000_0000, PAGE_SIZE, ATTRIBUTES)
+            .unwrap();
+        assert_eq!(memory.invalidated, [(0, 2 * MIB), (0x3000, PAGE_SIZE)]);
+        assert!(memory.broken.is_empty());
+    }
+
+    #[test]
+    fn pinned_mappings_are_not_broken() {
+        let (mut memory, space) = space();
+        space.map(&mut memory, 0, 0, 4 * MIB, ATTRIBUTES).unwrap();
+        memory.pinned = Some(2 * MIB..2 * MIB + PAGE_SIZE);
+        assert_eq!(
+            space.protect(&mut memory, 0, 4 * MIB, READ_ONLY),
+            Err(MapError::InUse(2 * MIB))
+        );
+        // mapping into nothing and unmapping break nothing
+        assert!(space
+            .map(&mut memory, 8 * MIB, 0, 2 * MIB, ATTRIBUTES)
+            .is_ok());
+        assert!(space.unmap(&mut memory, 2 * MIB, 2 * MIB).is_ok());
+    }
+
+    #[test]
+    fn running_out_of_tables_is_reported() {
+        let (mut memory, space) = space();
+        memory.table_limit = Some(3);
+        assert_eq!(
+            space.map(&mut memory, 0, 0, PAGE_SIZE, ATTRIBUTES),
+            Err(MapError::OutOfTables)
+        );
+        assert!(space.map(&mut memory, 0, 0, 2 * MIB, ATTRIBUTES).is_ok());
+    }
+
+    #[test]
+    fn walks_record_every_level() {
+        let (mut memory, space) = space();
+        space
+            .map(&mut memory, 0x20_0000, 0x20_0000, 2 * MIB, ATTRIBUTES)
+            .unwrap();
+        let walk = space.walk(&memory, 0x20_1000);
+        let levels: Vec<_> = walk.steps.iter().flatten().map(|step| step.level).collect();
+        assert_eq!(levels, [0, 1, 2]);
+        assert_eq!(walk.steps[0].unwrap().address, space.root);
+        assert_eq!(
+            Descriptor::decode(walk.last().unwrap().descriptor, 2),
+            Descriptor::Leaf(0x20_0000)
+        );
+    }
+}
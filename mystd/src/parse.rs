@@ -6,16 +6,11 @@ pub enum ParseError {
 pub type ParseResult<T> = Result<T, ParseError>;
 
 pub const fn from_hex(digit: u8) -> ParseResult<u8> {
-    let mut value = digit.wrapping_sub(b'0');
-    if value < 0xA {
-        Ok(value)
-    } else {
-        value -= 7;
-        if value < 0x10 {
-            Ok(value)
-        } else {
-            Err(ParseError::OutOfRange)
-        }
+    match digit {
+        b'0'..=b'9' => Ok(digit - b'0'),
+        b'A'..=b'F' => Ok(digit - b'A' + 0xA),
+        b'a'..=b'f' => Ok(digit - b'a' + 0xA),
+        _ => Err(ParseError::InvalidCharacter),
     }
 }
 
@@ -1 +1,5 @@
 pub mod edid;
+pub mod hid;
+pub mod sd;
+pub mod usb;
+pub mod xmodem;
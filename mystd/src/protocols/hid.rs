@@ -0,0 +1,442 @@
+//! USB HID keyboards in the boot protocol: the 8 byte report, keymaps and key repeat.
+//!
+//! [KeyboardState] turns the reports a keyboard sends into [Key]s, [Key::encode] turns those into
+//! the bytes a terminal would send, so a keyboard can stand in for a serial line.
+
+use core::time::Duration;
+
+use crate::bit_field;
+
+use super::usb::{request_type, SetupPacket};
+
+/// Subclass of HID interfaces that support the boot protocol.
+pub const SUBCLASS_BOOT: u8 = 1;
+pub const PROTOCOL_KEYBOARD: u8 = 1;
+pub const PROTOCOL_MOUSE: u8 = 2;
+
+/// Class specific requests.
+pub mod request {
+    pub const SET_REPORT: u8 = 0x09;
+    pub const SET_IDLE: u8 = 0x0a;
+    pub const SET_PROTOCOL: u8 = 0x0b;
+}
+
+/// Switches `interface` to the boot protocol, which every boot keyboard understands.
+pub const fn set_boot_protocol(interface: u8) -> SetupPacket {
+    SetupPacket {
+        request_type: request_type::HOST_TO_DEVICE | request_type::CLASS | request_type::INTERFACE,
+        request: request::SET_PROTOCOL,
+        value: 0,
+        index: interface as u16,
+        length: 0,
+    }
+}
+
+/// Makes `interface` report only on changes, key repeat is left to the host.
+pub const fn set_idle_infinite(interface: u8) -> SetupPacket {
+    SetupPacket {
+        request_type: request_type::HOST_TO_DEVICE | request_type::CLASS | request_type::INTERFACE,
+        request: request::SET_IDLE,
+        value: 0,
+        index: interface as u16,
+        length: 0,
+    }
+}
+
+bit_field!(pub Modifiers(u8){
+    0 => left_ctrl,
+    1 => left_shift,
+    2 => left_alt,
+    3 => left_gui,
+    4 => right_ctrl,
+    5 => right_shift,
+    /// AltGr on european layouts.
+    6 => right_alt,
+    7 => right_gui,
+});
+
+impl Modifiers {
+    pub fn ctrl(self) -> bool {
+        self.left_ctrl().is_set() || self.right_ctrl().is_set()
+    }
+
+    pub fn shift(self) -> bool {
+        self.left_shift().is_set() || self.right_shift().is_set()
+    }
+}
+
+/// The report of a keyboard in the boot protocol.
+#[derive(Clone, Copy, Debug)]
+pub struct BootReport {
+    pub modifiers: Modifiers,
+    /// Usage IDs of up to 6 pressed keys, 0 for none.
+    pub keys: [u8; 6],
+}
+
+/// Usage ID the keys report when too many are pressed at once.
+const ROLLOVER_ERROR: u8 = 0x01;
+
+impl BootReport {
+    pub const LEN: usize = 8;
+
+    pub const fn empty() -> Self {
+        Self {
+            modifiers: Modifiers::zero(),
+            keys: [0; 6],
+        }
+    }
+
+    /// `None` if `bytes` is too short or reports that too many keys are pressed.
+    pub fn parse(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < Self::LEN || bytes[2] == ROLLOVER_ERROR {
+            return None;
+        }
+        let mut keys = [0; 6];
+        keys.copy_from_slice(&bytes[2..8]);
+        Some(Self {
+            modifiers: Modifiers::new(bytes[0]),
+            keys,
+        })
+    }
+
+    pub fn is_pressed(&self, usage: u8) -> bool {
+        usage != 0 && self.keys.contains(&usage)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Key {
+    /// A character, including the control characters of Enter (`\r`), Tab, Backspace (`\x08`),
+    /// Escape and of Ctrl with a letter.
+    Char(char),
+    Up,
+    Down,
+    Left,
+    Right,
+    Home,
+    End,
+    Insert,
+    Delete,
+    PageUp,
+    PageDown,
+    /// F1 to F12.
+    Function(u8),
+}
+
+impl Key {
+    /// The bytes a VT100 style terminal sends for the key.
+    pub fn encode(self, buf: &mut [u8; 8]) -> &[u8] {
+        let sequence: &[u8] = match self {
+            Key::Char(c) => return c.encode_utf8(buf).as_bytes(),
+            Key::Up => b"\x1b[A",
+            Key::Down => b"\x1b[B",
+            Key::Right => b"\x1b[C",
+            Key::Left => b"\x1b[D",
+            Key::Home => b"\x1b[H",
+            Key::End => b"\x1b[F",
+            Key::Insert => b"\x1b[2~",
+            Key::Delete => b"\x1b[3~",
+            Key::PageUp => b"\x1b[5~",
+            Key::PageDown => b"\x1b[6~",
+            Key::Function(n @ 1..=4) => &[0x1b, b'O', b'P' + n - 1],
+            Key::Function(n @ 5..=12) => {
+                const CODES: [&[u8]; 8] = [b"15", b"17", b"18", b"19", b"20", b"21", b"23", b"24"];
+                let code = CODES[n as usize - 5];
+                buf[..2].copy_from_slice(b"\x1b[");
+                buf[2..4].copy_from_slice(code);
+                buf[4] = b'~';
+                return &buf[..5];
+            }
+            Key::Function(_) => b"",
+        };
+        buf[..sequence.len()].copy_from_slice(sequence);
+        &buf[..sequence.len()]
+    }
+}
+
+/// The characters of a keyboard layout.
+pub struct Keymap {
+    /// Keys 0x04 to 0x27 (letters and digits), 0x2d to 0x38 (punctuation) and 0x64 (the key
+    /// next to left shift on ISO keyboards), without and with shift.
+    normal: &'static str,
+    shift: &'static str,
+    /// Keys with AltGr.
+    alt_gr: &'static [(u8, char)],
+}
+
+pub const US: Keymap = Keymap {
+    normal: "abcdefghijklmnopqrstuvwxyz1234567890-=[]\\\\;'`,./\\",
+    shift: "ABCDEFGHIJKLMNOPQRSTUVWXYZ!@#$%^&*()_+{}||:\"~<>?|",
+    alt_gr: &[],
+};
+
+pub const DE: Keymap = Keymap {
+    normal: "abcdefghijklmnopqrstuvwxzy1234567890ß´ü+##öä^,.-<",
+    shift: "ABCDEFGHIJKLMNOPQRSTUVWXZY!\"§$%&/()=?`Ü*''ÖÄ°;:_>",
+    alt_gr: &[
+        (0x14, '@'),
+        (0x08, '€'),
+        (0x10, 'µ'),
+        (0x1f, '²'),
+        (0x20, '³'),
+        (0x24, '{'),
+        (0x25, '['),
+        (0x26, ']'),
+        (0x27, '}'),
+        (0x2d, '\\'),
+        (0x30, '~'),
+        (0x64, '|'),
+    ],
+};
+
+const CAPS_LOCK: u8 = 0x39;
+
+impl Keymap {
+    /// Index into [Keymap::normal] and [Keymap::shift].
+    fn index(usage: u8) -> Option<usize> {
+        match usage {
+            0x04..=0x27 => Some(usage as usize - 0x04),
+            0x2d..=0x38 => Some(usage as usize - 0x2d + 36),
+            0x64 => Some(48),
+            _ => None,
+        }
+    }
+
+    /// The key for `usage` with `modifiers` held, `None` for modifiers, Caps Lock and keys this
+    /// doesn't know.
+    pub fn translate(&self, usage: u8, modifiers: Modifiers, caps_lock: bool) -> Option<Key> {
+        let key = match usage {
+            0x28 | 0x58 => Key::Char('\r'),
+            0x29 => Key::Char('\x1b'),
+            0x2a => Key::Char('\x08'),
+            0x2b => Key::Char('\t'),
+            0x2c => Key::Char(' '),
+            0x3a..=0x45 => Key::Function(usage - 0x3a + 1),
+            0x49 => Key::Insert,
+            0x4a => Key::Home,
+            0x4b => Key::PageUp,
+            0x4c => Key::Delete,
+            0x4d => Key::End,
+            0x4e => Key::PageDown,
+            0x4f => Key::Right,
+            0x50 => Key::Left,
+            0x51 => Key::Down,
+            0x52 => Key::Up,
+            // the keypad, as if Num Lock was on
+            0x54..=0x63 => Key::Char(b"/*-+\r1234567890."[usage as usize - 0x54] as char),
+            _ => {
+                let index = Self::index(usage)?;
+                if modifiers.right_alt().is_set() {
+                    let (_, c) = self.alt_gr.iter().find(|(u, _)| *u == usage)?;
+                    return Some(Key::Char(*c));
+                }
+                let is_letter = (0x04..=0x1d).contains(&usage);
+                let shift = modifiers.shift() ^ (caps_lock && is_letter);
+                let map = if shift { self.shift } else { self.normal };
+                let c = map.chars().nth(index)?;
+                if modifiers.ctrl() && c.is_ascii_alphabetic() {
+                    Key::Char((c.to_ascii_uppercase() as u8 & 0x1f) as char)
+                } else {
+                    Key::Char(c)
+                }
+            }
+        };
+        Some(key)
+    }
+}
+
+/// Time a key has to be held before it repeats.
+pub const REPEAT_DELAY: Duration = Duration::from_millis(500);
+/// Time between repeats.
+pub const REPEAT_INTERVAL: Duration = Duration::from_millis(33);
+
+/// Follows the reports of a keyboard, and repeats the key pressed last while it is held.
+pub struct KeyboardState {
+    keymap: &'static Keymap,
+    previous: BootReport,
+    caps_lock: bool,
+    /// The key that repeats and when it repeats next.
+    repeat: Option<(u8, Duration)>,
+}
+
+impl KeyboardState {
+    pub const fn new(keymap: &'static Keymap) -> Self {
+        Self {
+            keymap,
+            previous: BootReport::empty(),
+            caps_lock: false,
+            repeat: None,
+        }
+    }
+
+    pub fn set_keymap(&mut self, keymap: &'static Keymap) {
+        self.keymap = keymap;
+    }
+
+    pub fn caps_lock(&self) -> bool {
+        self.caps_lock
+    }
+
+    /// Handles a report received at `now`, calls `f` with the keys that were pressed since the
+    /// previous report.
+    pub fn report<F: FnMut(Key)>(&mut self, report: &BootReport, now: Duration, mut f: F) {
+        for usage in report.keys {
+            if usage == 0 || self.previous.is_pressed(usage) {
+                continue;
+            }
+            if usage == CAPS_LOCK {
+                self.caps_lock = !self.caps_lock;
+                continue;
+            }
+            if let Some(key) = self
+                .keymap
+                .translate(usage, report.modifiers, self.caps_lock)
+            {
+                f(key);
+                self.repeat = Some((usage, now + REPEAT_DELAY));
+            }
+        }
+        if matches!(self.repeat, Some((usage, _)) if !report.is_pressed(usage)) {
+            self.repeat = None;
+        }
+        self.previous = *report;
+    }
+
+    /// The held key, if it is due to repeat at `now`.
+    pub fn repeat(&mut self, now: Duration) -> Option<Key> {
+        let (usage, at) = self.repeat?;
+        if now < at {
+            return None;
+        }
+        self.repeat = Some((
+            usage,
+            at.max(now.saturating_sub(REPEAT_INTERVAL)) + REPEAT_INTERVAL,
+        ));
+        self.keymap
+            .translate(usage, self.previous.modifiers, self.caps_lock)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    extern crate std;
+    use std::vec::Vec;
+
+    const SHIFT: u8 = 0b10;
+    const CTRL: u8 = 0b1;
+    const ALT_GR: u8 = 0b100_0000;
+
+    fn report(modifiers: u8, keys: &[u8]) -> BootReport {
+        let mut bytes = [0; 8];
+        bytes[0] = modifiers;
+        bytes[2..2 + keys.len()].copy_from_slice(keys);
+        BootReport::parse(&bytes).unwrap()
+    }
+
+    fn type_keys(keymap: &'static Keymap, reports: &[BootReport]) -> Vec<Key> {
+        let mut state = KeyboardState::new(keymap);
+        let mut keys = Vec::new();
+        for report in reports {
+            state.report(report, Duration::ZERO, |key| keys.push(key));
+        }
+        keys
+    }
+
+    fn chars(keys: &[Key]) -> std::string::String {
+        keys.iter()
+            .map(|key| match key {
+                Key::Char(c) => *c,
+                _ => '?',
+            })
+            .collect()
+    }
+
+    #[test]
+    fn keymaps_cover_all_keys() {
+        for keymap in [&US, &DE] {
+            assert_eq!(49, keymap.normal.chars().count());
+            assert_eq!(49, keymap.shift.chars().count());
+        }
+    }
+
+    #[test]
+    fn translates_with_modifiers() {
+        // h, i, shift 1, released in between
+        let reports = [
+            report(0, &[0x0b]),
+            report(0, &[]),
+            report(0, &[0x0c]),
+            report(SHIFT, &[0x0c, 0x1e]),
+            report(0, &[0x28]),
+        ];
+        assert_eq!("hi!\r", chars(&type_keys(&US, &reports)));
+        assert_eq!("hi!\r", chars(&type_keys(&DE, &reports)));
+
+        // z and y are swapped on DE, shift 2 is a quote, AltGr q is @, shift ü
+        let reports = [
+            report(0, &[0x1d]),
+            report(0, &[0x1c]),
+            report(SHIFT, &[0x1f]),
+            report(ALT_GR, &[0x14]),
+            report(SHIFT, &[0x2f]),
+        ];
+        assert_eq!("zy@", chars(&type_keys(&US, &reports[..4])));
+        assert_eq!("yz\"@Ü", chars(&type_keys(&DE, &reports)));
+
+        // Ctrl X cancels, caps lock only affects letters, arrows
+        let reports = [
+            report(CTRL, &[0x1b]),
+            report(0, &[0x39]),
+            report(0, &[0x04, 0x1e]),
+            report(0, &[0x52]),
+        ];
+        assert_eq!(
+            &[Key::Char('\x18'), Key::Char('A'), Key::Char('1'), Key::Up],
+            &type_keys(&US, &reports)[..]
+        );
+    }
+
+    #[test]
+    fn ignores_rollover_and_short_reports() {
+        assert!(BootReport::parse(&[0, 0, 1, 1, 1, 1, 1, 1]).is_none());
+        assert!(BootReport::parse(&[0, 0, 4]).is_none());
+    }
+
+    #[test]
+    fn repeats_held_key() {
+        let mut state = KeyboardState::new(&US);
+        let mut keys = Vec::new();
+        state.report(&report(0, &[0x04]), Duration::ZERO, |key| keys.push(key));
+        assert_eq!(&[Key::Char('a')], &keys[..]);
+        assert_eq!(None, state.repeat(Duration::from_millis(499)));
+        assert_eq!(Some(Key::Char('a')), state.repeat(REPEAT_DELAY));
+        assert_eq!(None, state.repeat(REPEAT_DELAY + Duration::from_millis(10)));
+        assert_eq!(
+            Some(Key::Char('a')),
+            state.repeat(REPEAT_DELAY + REPEAT_INTERVAL)
+        );
+        // the second key takes over, releasing it stops the repeat
+        state.report(&report(0, &[0x04, 0x05]), REPEAT_DELAY, |key| {
+            keys.push(key)
+        });
+        assert_eq!(&[Key::Char('a'), Key::Char('b')], &keys[..]);
+        assert_eq!(
+            None,
+            state.repeat(REPEAT_DELAY + Duration::from_millis(100))
+        );
+        state.report(&report(0, &[0x04]), REPEAT_DELAY, |key| keys.push(key));
+        assert_eq!(None, state.repeat(Duration::from_secs(10)));
+    }
+
+    #[test]
+    fn encodes_like_a_terminal() {
+        let mut buf = [0; 8];
+        assert_eq!(b"a", Key::Char('a').encode(&mut buf));
+        assert_eq!("ä".as_bytes(), Key::Char('ä').encode(&mut buf));
+        assert_eq!(b"\x1b[A", Key::Up.encode(&mut buf));
+        assert_eq!(b"\x1b[3~", Key::Delete.encode(&mut buf));
+        assert_eq!(b"\x1bOQ", Key::Function(2).encode(&mut buf));
+        assert_eq!(b"\x1b[24~", Key::Function(12).encode(&mut buf));
+    }
+}
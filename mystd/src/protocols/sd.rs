@@ -0,0 +1,267 @@
+//! SD memory card commands and registers (SD Physical Layer Simplified Specification).
+//!
+//! Only what a host needs to identify a card and transfer blocks in the default speed mode.
+//! Registers are read from 136 bit responses the way SDHCI controllers present them: without the
+//! CRC byte, so bit 8 of the register is bit 0 of the first response word.
+
+use core::fmt;
+
+use crate::bit_field;
+
+pub mod command {
+    pub const GO_IDLE_STATE: u8 = 0;
+    pub const ALL_SEND_CID: u8 = 2;
+    pub const SEND_RELATIVE_ADDR: u8 = 3;
+    pub const SELECT_CARD: u8 = 7;
+    pub const SEND_IF_COND: u8 = 8;
+    pub const SEND_CSD: u8 = 9;
+    pub const STOP_TRANSMISSION: u8 = 12;
+    pub const SEND_STATUS: u8 = 13;
+    pub const SET_BLOCKLEN: u8 = 16;
+    pub const READ_SINGLE_BLOCK: u8 = 17;
+    pub const READ_MULTIPLE_BLOCK: u8 = 18;
+    pub const WRITE_BLOCK: u8 = 24;
+    pub const WRITE_MULTIPLE_BLOCK: u8 = 25;
+    /// The next command is an application command.
+    pub const APP_CMD: u8 = 55;
+}
+
+/// Application commands, each sent after [command::APP_CMD].
+pub mod app_command {
+    pub const SET_BUS_WIDTH: u8 = 6;
+    pub const SD_SEND_OP_COND: u8 = 41;
+}
+
+/// The argument of SEND_IF_COND: 2.7-3.6V and a check pattern the card echoes.
+pub const IF_COND_CHECK: u32 = 0x1aa;
+/// The argument of SET_BUS_WIDTH for the 4 bit bus.
+pub const BUS_WIDTH_4: u32 = 0b10;
+
+bit_field!(pub Ocr(u32){
+    /// Clear while the card is still powering up.
+    31 => powered_up,
+    /// High or extended capacity: blocks are addressed by number, not by byte.
+    30 => card_capacity_status,
+    /// 2.7V to 3.6V in steps of 0.1V.
+    15:23 => voltage_window
+});
+
+impl Ocr {
+    /// The argument of SD_SEND_OP_COND, `high_capacity` if the host supports it.
+    pub fn host_request(high_capacity: bool) -> Self {
+        Self::zero()
+            .voltage_window()
+            .set_value(0x1ff)
+            .card_capacity_status()
+            .set_value(high_capacity)
+    }
+}
+
+bit_field!(pub CardStatus(u32){
+    31 => out_of_range,
+    30 => address_error,
+    29 => block_len_error,
+    26 => write_protect_violation,
+    25 => card_is_locked,
+    23 => command_crc_error,
+    22 => illegal_command,
+    21 => card_ecc_failed,
+    19 => error,
+    9:12 => current_state: enum CardState {
+        Idle = 0,
+        Ready = 1,
+        Identification = 2,
+        Standby = 3,
+        Transfer = 4,
+        SendingData = 5,
+        ReceivingData = 6,
+        Programming = 7,
+        Disconnect = 8
+    },
+    8 => ready_for_data,
+    5 => app_command
+});
+
+impl CardStatus {
+    /// The error bits of the status, the ones that aren't states.
+    pub const ERRORS: u32 = 0xfdf9_0000;
+
+    pub fn has_error(self) -> bool {
+        self.to_underlying() & Self::ERRORS != 0
+    }
+}
+
+/// Bits `lsb..=msb` of the register in `response`.
+fn register_bits(response: [u32; 4], msb: u32, lsb: u32) -> u32 {
+    let register = response
+        .iter()
+        .rev()
+        .fold(0_u128, |bits, &word| bits << 32 | word as u128)
+        << 8;
+    ((register >> lsb) & ((1 << (msb - lsb + 1)) - 1)) as u32
+}
+
+/// The card identification register.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Cid {
+    pub manufacturer: u8,
+    pub oem: [u8; 2],
+    pub product_name: [u8; 5],
+    /// Major and minor revision, a BCD digit each.
+    pub revision: u8,
+    pub serial_number: u32,
+    pub year: u16,
+    pub month: u8,
+}
+
+impl Cid {
+    pub fn from_response(response: [u32; 4]) -> Self {
+        let bits = |msb, lsb| register_bits(response, msb, lsb);
+        let name = [
+            bits(103, 96),
+            bits(95, 88),
+            bits(87, 80),
+            bits(79, 72),
+            bits(71, 64),
+        ];
+        Self {
+            manufacturer: bits(127, 120) as u8,
+            oem: [bits(119, 112) as u8, bits(111, 104) as u8],
+            product_name: name.map(|c| c as u8),
+            revision: bits(63, 56) as u8,
+            serial_number: bits(55, 24),
+            year: 2000 + bits(19, 12) as u16,
+            month: bits(11, 8) as u8,
+        }
+    }
+}
+
+/// Writes the ASCII characters of `text`, with `?` for anything else.
+fn write_ascii(f: &mut fmt::Formatter<'_>, text: &[u8]) -> fmt::Result {
+    for &byte in text {
+        let c = if byte.is_ascii_graphic() {
+            byte as char
+        } else {
+            '?'
+        };
+        fmt::Write::write_char(f, c)?;
+    }
+    Ok(())
+}
+
+impl fmt::Display for Cid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_ascii(f, &self.product_name)?;
+        write!(
+            f,
+            " rev {}.{} from {:02x}/",
+            self.revision >> 4,
+            self.revision & 0xf,
+            self.manufacturer
+        )?;
+        write_ascii(f, &self.oem)?;
+        write!(
+            f,
+            ", serial {:08x}, made {}-{:02}",
+            self.serial_number, self.year, self.month
+        )
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CsdError {
+    /// A CSD structure version this doesn't know.
+    UnknownStructure(u8),
+}
+
+/// The card specific data register, of which only the capacity is of interest.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Csd {
+    /// The capacity in blocks of 512 bytes.
+    pub block_count: u64,
+}
+
+impl Csd {
+    pub fn from_response(response: [u32; 4]) -> Result<Self, CsdError> {
+        let bits = |msb, lsb| register_bits(response, msb, lsb) as u64;
+        let block_count = match bits(127, 126) {
+            // standard capacity: (C_SIZE + 1) * 2^(C_SIZE_MULT + 2) blocks of 2^READ_BL_LEN bytes
+            0 => {
+                let blocks = (bits(73, 62) + 1) << (bits(49, 47) + 2);
+                (blocks << bits(83, 80)) / 512
+            }
+            // high and extended capacity: (C_SIZE + 1) * 512KB
+            1 => (bits(69, 48) + 1) * 1024,
+            structure => return Err(CsdError::UnknownStructure(structure as u8)),
+        };
+        Ok(Self { block_count })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    extern crate std;
+    use std::string::ToString;
+
+    /// A response with `fields` of (msb, lsb, value) set in the register.
+    fn response(fields: &[(u32, u32, u128)]) -> [u32; 4] {
+        let register = fields
+            .iter()
+            .fold(0_u128, |register, &(_, lsb, value)| register | value << lsb);
+        let bits = register >> 8;
+        [0, 1, 2, 3].map(|i| (bits >> (32 * i)) as u32)
+    }
+
+    #[test]
+    fn reads_the_capacity() {
+        let high_capacity = response(&[(127, 126, 1), (69, 48, 0x3b37)]);
+        assert_eq!(
+            Csd::from_response(high_capacity),
+            Ok(Csd {
+                block_count: 0x3b38 * 1024
+            })
+        );
+        // 1 GB: 4096 * 2^9 blocks of 2^9 bytes
+        let standard = response(&[(83, 80, 9), (73, 62, 4095), (49, 47, 7)]);
+        assert_eq!(
+            Csd::from_response(standard).map(|csd| csd.block_count),
+            Ok(2 * 1024 * 1024)
+        );
+        assert_eq!(
+            Csd::from_response(response(&[(127, 126, 3)])),
+            Err(CsdError::UnknownStructure(3))
+        );
+    }
+
+    #[test]
+    fn reads_the_identification() {
+        let cid = Cid::from_response(response(&[
+            (127, 120, 0x03),
+            (119, 104, u16::from_be_bytes(*b"SD") as u128),
+            (103, 64, u64::from_be_bytes(*b"\0\0\0SU08G") as u128),
+            (63, 56, 0x80),
+            (55, 24, 0x1234_5678),
+            (19, 12, 19),
+            (11, 8, 7),
+        ]));
+        assert_eq!(&cid.product_name, b"SU08G");
+        assert_eq!(cid.year, 2019);
+        assert_eq!(
+            cid.to_string(),
+            "SU08G rev 8.0 from 03/SD, serial 12345678, made 2019-07"
+        );
+    }
+
+    #[test]
+    fn card_status_errors() {
+        let status = CardStatus::zero()
+            .current_state()
+            .set_value(CardState::Transfer)
+            .ready_for_data()
+            .set();
+        assert!(!status.has_error());
+        assert!(status.address_error().set().has_error());
+        assert_eq!(Ocr::host_request(true).to_underlying(), 0x40ff_8000);
+    }
+}
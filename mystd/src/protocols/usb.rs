@@ -0,0 +1,557 @@
+//! USB 2.0 standard requests and descriptors (chapter 9), and the hub class (chapter 11).
+//!
+//! Only the parts a host needs to enumerate devices and hubs. Descriptors are parsed from the
+//! bytes a device returns, so they work for any host controller.
+
+use crate::bit_field;
+
+pub mod descriptor_type {
+    pub const DEVICE: u8 = 1;
+    pub const CONFIGURATION: u8 = 2;
+    pub const STRING: u8 = 3;
+    pub const INTERFACE: u8 = 4;
+    pub const ENDPOINT: u8 = 5;
+    pub const HID: u8 = 0x21;
+    pub const HUB: u8 = 0x29;
+}
+
+pub mod request {
+    pub const GET_STATUS: u8 = 0;
+    pub const CLEAR_FEATURE: u8 = 1;
+    pub const SET_FEATURE: u8 = 3;
+    pub const SET_ADDRESS: u8 = 5;
+    pub const GET_DESCRIPTOR: u8 = 6;
+    pub const GET_CONFIGURATION: u8 = 8;
+    pub const SET_CONFIGURATION: u8 = 9;
+    pub const SET_INTERFACE: u8 = 11;
+}
+
+pub mod class {
+    pub const HID: u8 = 3;
+    pub const HUB: u8 = 9;
+}
+
+/// Hub port features for [SetupPacket::set_port_feature] and [SetupPacket::clear_port_feature].
+pub mod port_feature {
+    pub const ENABLE: u16 = 1;
+    pub const RESET: u16 = 4;
+    pub const POWER: u16 = 8;
+    pub const C_CONNECTION: u16 = 16;
+    pub const C_ENABLE: u16 = 17;
+    pub const C_RESET: u16 = 20;
+}
+
+/// The first byte of a setup packet: direction, type and recipient.
+pub mod request_type {
+    pub const HOST_TO_DEVICE: u8 = 0x00;
+    pub const DEVICE_TO_HOST: u8 = 0x80;
+    pub const STANDARD: u8 = 0x00;
+    pub const CLASS: u8 = 0x20;
+    pub const DEVICE: u8 = 0x00;
+    pub const INTERFACE: u8 = 0x01;
+    pub const OTHER: u8 = 0x03;
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Speed {
+    Low,
+    Full,
+    High,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DescriptorError {
+    /// Fewer bytes than the descriptor needs.
+    TooShort,
+    /// The descriptor type doesn't match.
+    WrongType,
+}
+
+/// The 8 bytes that start a control transfer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SetupPacket {
+    pub request_type: u8,
+    pub request: u8,
+    pub value: u16,
+    pub index: u16,
+    /// Number of bytes in the data stage.
+    pub length: u16,
+}
+
+impl SetupPacket {
+    pub const fn get_descriptor(descriptor_type: u8, index: u8, length: u16) -> Self {
+        Self {
+            request_type: request_type::DEVICE_TO_HOST,
+            request: request::GET_DESCRIPTOR,
+            value: (descriptor_type as u16) << 8 | index as u16,
+            // the language ID for string descriptors, US English
+            index: if descriptor_type == descriptor_type::STRING { 0x0409 } else { 0 },
+            length,
+        }
+    }
+
+    pub const fn set_address(address: u8) -> Self {
+        Self {
+            request_type: request_type::HOST_TO_DEVICE,
+            request: request::SET_ADDRESS,
+            value: address as u16,
+            index: 0,
+            length: 0,
+        }
+    }
+
+    pub const fn set_configuration(value: u8) -> Self {
+        Self {
+            request_type: request_type::HOST_TO_DEVICE,
+            request: request::SET_CONFIGURATION,
+            value: value as u16,
+            index: 0,
+            length: 0,
+        }
+    }
+
+    pub const fn get_hub_descriptor(length: u16) -> Self {
+        Self {
+            request_type: request_type::DEVICE_TO_HOST | request_type::CLASS,
+            request: request::GET_DESCRIPTOR,
+            value: (descriptor_type::HUB as u16) << 8,
+            index: 0,
+            length,
+        }
+    }
+
+    /// Reads the [PortStatus] of hub port `port`, counting from 1.
+    pub const fn get_port_status(port: u8) -> Self {
+        Self {
+            request_type: request_type::DEVICE_TO_HOST | request_type::CLASS | request_type::OTHER,
+            request: request::GET_STATUS,
+            value: 0,
+            index: port as u16,
+            length: 4,
+        }
+    }
+
+    pub const fn set_port_feature(port: u8, feature: u16) -> Self {
+        Self {
+            request_type: request_type::CLASS | request_type::OTHER,
+            request: request::SET_FEATURE,
+            value: feature,
+            index: port as u16,
+            length: 0,
+        }
+    }
+
+    pub const fn clear_port_feature(port: u8, feature: u16) -> Self {
+        Self {
+            request_type: request_type::CLASS | request_type::OTHER,
+            request: request::CLEAR_FEATURE,
+            value: feature,
+            index: port as u16,
+            length: 0,
+        }
+    }
+
+    pub const fn is_device_to_host(&self) -> bool {
+        self.request_type & request_type::DEVICE_TO_HOST != 0
+    }
+
+    pub const fn to_bytes(self) -> [u8; 8] {
+        let [value_lo, value_hi] = self.value.to_le_bytes();
+        let [index_lo, index_hi] = self.index.to_le_bytes();
+        let [length_lo, length_hi] = self.length.to_le_bytes();
+        [
+            self.request_type,
+            self.request,
+            value_lo,
+            value_hi,
+            index_lo,
+            index_hi,
+            length_lo,
+            length_hi,
+        ]
+    }
+}
+
+fn u16_at(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+/// Checks length and type of the descriptor in `bytes`.
+fn check(bytes: &[u8], len: usize, descriptor_type: u8) -> Result<(), DescriptorError> {
+    if bytes.len() >= 2 && bytes[1] != descriptor_type {
+        Err(DescriptorError::WrongType)
+    } else if bytes.len() < len || (bytes[0] as usize) < len {
+        Err(DescriptorError::TooShort)
+    } else {
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DeviceDescriptor {
+    /// BCD, e.g. 0x0200 for USB 2.0.
+    pub usb_version: u16,
+    pub class: u8,
+    pub subclass: u8,
+    pub protocol: u8,
+    pub max_packet_size0: u8,
+    pub vendor_id: u16,
+    pub product_id: u16,
+    pub device_version: u16,
+    /// String descriptor indices, 0 if there is none.
+    pub manufacturer: u8,
+    pub product: u8,
+    pub serial_number: u8,
+    pub num_configurations: u8,
+}
+
+impl DeviceDescriptor {
+    pub const LEN: usize = 18;
+
+    pub fn parse(bytes: &[u8]) -> Result<Self, DescriptorError> {
+        check(bytes, Self::LEN, descriptor_type::DEVICE)?;
+        Ok(Self {
+            usb_version: u16_at(bytes, 2),
+            class: bytes[4],
+            subclass: bytes[5],
+            protocol: bytes[6],
+            max_packet_size0: bytes[7],
+            vendor_id: u16_at(bytes, 8),
+            product_id: u16_at(bytes, 10),
+            device_version: u16_at(bytes, 12),
+            manufacturer: bytes[14],
+            product: bytes[15],
+            serial_number: bytes[16],
+            num_configurations: bytes[17],
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ConfigurationDescriptor {
+    /// Length of the configuration with all its interface and endpoint descriptors.
+    pub total_length: u16,
+    pub num_interfaces: u8,
+    /// The value for [SetupPacket::set_configuration].
+    pub configuration_value: u8,
+    pub attributes: u8,
+    /// In units of 2 mA.
+    pub max_power: u8,
+}
+
+impl ConfigurationDescriptor {
+    pub const LEN: usize = 9;
+
+    pub fn parse(bytes: &[u8]) -> Result<Self, DescriptorError> {
+        check(bytes, Self::LEN, descriptor_type::CONFIGURATION)?;
+        Ok(Self {
+            total_length: u16_at(bytes, 2),
+            num_interfaces: bytes[4],
+            configuration_value: bytes[5],
+            attributes: bytes[7],
+            max_power: bytes[8],
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InterfaceDescriptor {
+    pub number: u8,
+    pub alternate_setting: u8,
+    pub num_endpoints: u8,
+    pub class: u8,
+    pub subclass: u8,
+    pub protocol: u8,
+}
+
+impl InterfaceDescriptor {
+    pub const LEN: usize = 9;
+
+    pub fn parse(bytes: &[u8]) -> Result<Self, DescriptorError> {
+        check(bytes, Self::LEN, descriptor_type::INTERFACE)?;
+        Ok(Self {
+            number: bytes[2],
+            alternate_setting: bytes[3],
+            num_endpoints: bytes[4],
+            class: bytes[5],
+            subclass: bytes[6],
+            protocol: bytes[7],
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TransferType {
+    Control = 0,
+    Isochronous = 1,
+    Bulk = 2,
+    Interrupt = 3,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct EndpointDescriptor {
+    /// Endpoint number in bits 0..3, bit 7 is set for IN endpoints.
+    pub address: u8,
+    pub attributes: u8,
+    pub max_packet_size: u16,
+    /// Polling interval, in frames for low and full speed interrupt endpoints.
+    pub interval: u8,
+}
+
+impl EndpointDescriptor {
+    pub const LEN: usize = 7;
+
+    pub fn parse(bytes: &[u8]) -> Result<Self, DescriptorError> {
+        check(bytes, Self::LEN, descriptor_type::ENDPOINT)?;
+        Ok(Self {
+            address: bytes[2],
+            attributes: bytes[3],
+            max_packet_size: u16_at(bytes, 4),
+            interval: bytes[6],
+        })
+    }
+
+    pub const fn number(&self) -> u8 {
+        self.address & 0xf
+    }
+
+    pub const fn is_in(&self) -> bool {
+        self.address & 0x80 != 0
+    }
+
+    pub const fn transfer_type(&self) -> TransferType {
+        match self.attributes & 0b11 {
+            0 => TransferType::Control,
+            1 => TransferType::Isochronous,
+            2 => TransferType::Bulk,
+            _ => TransferType::Interrupt,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HubDescriptor {
+    pub num_ports: u8,
+    pub characteristics: u16,
+    /// Time from powering a port until its power is good, in units of 2 ms.
+    pub power_on_to_good: u8,
+}
+
+impl HubDescriptor {
+    pub const LEN: usize = 7;
+
+    pub fn parse(bytes: &[u8]) -> Result<Self, DescriptorError> {
+        check(bytes, Self::LEN, descriptor_type::HUB)?;
+        Ok(Self {
+            num_ports: bytes[2],
+            characteristics: u16_at(bytes, 3),
+            power_on_to_good: bytes[5],
+        })
+    }
+}
+
+/// A string descriptor, displayed as its text.
+pub struct StringDescriptor<'a>(&'a [u8]);
+
+impl<'a> StringDescriptor<'a> {
+    pub fn parse(bytes: &'a [u8]) -> Result<Self, DescriptorError> {
+        check(bytes, 2, descriptor_type::STRING)?;
+        let len = (bytes[0] as usize).min(bytes.len());
+        Ok(Self(&bytes[2..len]))
+    }
+}
+
+impl core::fmt::Display for StringDescriptor<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let units = self.0.chunks_exact(2).map(|c| u16::from_le_bytes([c[0], c[1]]));
+        for c in char::decode_utf16(units) {
+            core::fmt::Write::write_char(f, c.unwrap_or(char::REPLACEMENT_CHARACTER))?;
+        }
+        Ok(())
+    }
+}
+
+/// A descriptor in the bytes returned for the configuration descriptor.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Descriptor<'a> {
+    Configuration(ConfigurationDescriptor),
+    Interface(InterfaceDescriptor),
+    Endpoint(EndpointDescriptor),
+    /// Class specific descriptors, like HID, with their type and all their bytes.
+    Other(u8, &'a [u8]),
+}
+
+/// Iterates over the descriptors of a configuration, stops at the first malformed one.
+pub struct Descriptors<'a>(&'a [u8]);
+
+impl<'a> Descriptors<'a> {
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Self(bytes)
+    }
+}
+
+impl<'a> Iterator for Descriptors<'a> {
+    type Item = Descriptor<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let len = *self.0.first()? as usize;
+        if len < 2 || len > self.0.len() {
+            return None;
+        }
+        let (bytes, rest) = self.0.split_at(len);
+        self.0 = rest;
+        let descriptor = match bytes[1] {
+            descriptor_type::CONFIGURATION => {
+                Descriptor::Configuration(ConfigurationDescriptor::parse(bytes).ok()?)
+            }
+            descriptor_type::INTERFACE => {
+                Descriptor::Interface(InterfaceDescriptor::parse(bytes).ok()?)
+            }
+            descriptor_type::ENDPOINT => {
+                Descriptor::Endpoint(EndpointDescriptor::parse(bytes).ok()?)
+            }
+            other => Descriptor::Other(other, bytes),
+        };
+        Some(descriptor)
+    }
+}
+
+bit_field!(
+    /// Status and change bits of a hub port, as returned by [SetupPacket::get_port_status].
+    pub PortStatus(u32){
+    0 => connection,
+    1 => enable,
+    2 => suspend,
+    3 => over_current,
+    4 => reset,
+    8 => power,
+    9 => low_speed,
+    10 => high_speed,
+    16 => connection_changed,
+    17 => enable_changed,
+    18 => suspend_changed,
+    19 => over_current_changed,
+    20 => reset_changed,
+});
+
+impl PortStatus {
+    pub fn from_bytes(bytes: [u8; 4]) -> Self {
+        Self::new(u32::from_le_bytes(bytes))
+    }
+
+    pub fn speed(self) -> Speed {
+        if self.low_speed().is_set() {
+            Speed::Low
+        } else if self.high_speed().is_set() {
+            Speed::High
+        } else {
+            Speed::Full
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    extern crate std;
+    use std::string::ToString;
+    use std::vec::Vec;
+
+    /// What QEMU's `usb-kbd` answers.
+    const KEYBOARD_DEVICE: [u8; 18] = [
+        0x12, 0x01, 0x00, 0x02, 0x00, 0x00, 0x00, 0x40, 0x27, 0x06, 0x01, 0x00, 0x00, 0x00, 0x01,
+        0x04, 0x0b, 0x01,
+    ];
+    const KEYBOARD_CONFIGURATION: [u8; 34] = [
+        0x09, 0x02, 0x22, 0x00, 0x01, 0x01, 0x07, 0xa0, 0x32, // configuration
+        0x09, 0x04, 0x00, 0x00, 0x01, 0x03, 0x01, 0x01, 0x00, // interface
+        0x09, 0x21, 0x11, 0x01, 0x00, 0x01, 0x22, 0x3f, 0x00, // HID
+        0x07, 0x05, 0x81, 0x03, 0x08, 0x00, 0x07, // endpoint
+    ];
+
+    #[test]
+    fn setup_packet_bytes() {
+        assert_eq!(
+            [0x80, 6, 0, 1, 0, 0, 18, 0],
+            SetupPacket::get_descriptor(descriptor_type::DEVICE, 0, 18).to_bytes()
+        );
+        assert_eq!(
+            [0x80, 6, 2, 3, 0x09, 0x04, 0xff, 0],
+            SetupPacket::get_descriptor(descriptor_type::STRING, 2, 255).to_bytes()
+        );
+        assert_eq!([0, 5, 3, 0, 0, 0, 0, 0], SetupPacket::set_address(3).to_bytes());
+        assert_eq!(
+            [0x23, 3, 4, 0, 2, 0, 0, 0],
+            SetupPacket::set_port_feature(2, port_feature::RESET).to_bytes()
+        );
+        assert_eq!([0xa3, 0, 0, 0, 1, 0, 4, 0], SetupPacket::get_port_status(1).to_bytes());
+        assert!(SetupPacket::get_hub_descriptor(8).is_device_to_host());
+    }
+
+    #[test]
+    fn parses_device_descriptor() {
+        let device = DeviceDescriptor::parse(&KEYBOARD_DEVICE).unwrap();
+        assert_eq!(0x0200, device.usb_version);
+        assert_eq!(64, device.max_packet_size0);
+        assert_eq!((0x0627, 0x0001), (device.vendor_id, device.product_id));
+        assert_eq!((1, 4, 11), (device.manufacturer, device.product, device.serial_number));
+        assert_eq!(1, device.num_configurations);
+
+        assert_eq!(
+            Err(DescriptorError::TooShort),
+            DeviceDescriptor::parse(&KEYBOARD_DEVICE[..8])
+        );
+        assert_eq!(
+            Err(DescriptorError::WrongType),
+            DeviceDescriptor::parse(&KEYBOARD_CONFIGURATION)
+        );
+    }
+
+    #[test]
+    fn iterates_configuration() {
+        let descriptors: Vec<_> = Descriptors::new(&KEYBOARD_CONFIGURATION).collect();
+        assert_eq!(4, descriptors.len());
+        assert!(matches!(
+            descriptors[0],
+            Descriptor::Configuration(ConfigurationDescriptor {
+                total_length: 34,
+                configuration_value: 1,
+                ..
+            })
+        ));
+        assert!(matches!(
+            descriptors[1],
+            Descriptor::Interface(InterfaceDescriptor {
+                class: class::HID,
+                protocol: 1,
+                ..
+            })
+        ));
+        assert!(matches!(descriptors[2], Descriptor::Other(descriptor_type::HID, [0x09, ..])));
+        let Descriptor::Endpoint(endpoint) = descriptors[3] else {
+            panic!("the last descriptor should be the endpoint");
+        };
+        assert!(endpoint.is_in());
+        assert_eq!(1, endpoint.number());
+        assert_eq!(TransferType::Interrupt, endpoint.transfer_type());
+
+        // a truncated configuration ends early
+        assert_eq!(2, Descriptors::new(&KEYBOARD_CONFIGURATION[..20]).count());
+    }
+
+    #[test]
+    fn hub_and_strings() {
+        let hub = HubDescriptor::parse(&[0x09, 0x29, 0x08, 0x09, 0x00, 0x32, 0x01, 0x00, 0xff])
+            .unwrap();
+        assert_eq!(8, hub.num_ports);
+        assert_eq!(0x32, hub.power_on_to_good);
+
+        let status = PortStatus::from_bytes([0x03, 0x02, 0x01, 0x00]);
+        assert!(status.connection().is_set());
+        assert!(status.connection_changed().is_set());
+        assert_eq!(Speed::Low, status.speed());
+
+        let string = [0x0a, 0x03, b'Q', 0, b'E', 0, b'M', 0, b'U', 0, 0xff];
+        assert_eq!("QEMU", StringDescriptor::parse(&string).unwrap().to_string());
+    }
+}
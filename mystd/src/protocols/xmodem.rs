@@ -0,0 +1,359 @@
+//! Receiving side of XMODEM-CRC, with 128 byte (`SOH`) and 1K (`STX`) blocks.
+//!
+//! The receiver starts the transfer by sending `C` until the sender answers with the first block.
+//! It relies on the input timing out ([io::Error::TimedOut]) when the line is quiet, that's how it
+//! knows to repeat the `C` or to ask for a block again. An input that blocks forever works too,
+//! as long as the sender is started first and the line has no errors.
+
+use crate::io::{self, Read, Write};
+
+const SOH: u8 = 0x01;
+const STX: u8 = 0x02;
+const EOT: u8 = 0x04;
+const ACK: u8 = 0x06;
+const NAK: u8 = 0x15;
+const CAN: u8 = 0x18;
+const ESC: u8 = 0x1b;
+/// Asks the sender to use a CRC instead of the 8 bit checksum.
+const CRC_START: u8 = b'C';
+
+/// The sender pads the last block with this.
+pub const PADDING: u8 = 0x1a;
+
+/// Number of timeouts or bad blocks in a row before the transfer is given up.
+pub const MAX_ERRORS: usize = 10;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum XmodemError {
+    /// The sender cancelled, or the user pressed Ctrl-X or ESC before the transfer started.
+    Cancelled,
+    /// Too many timeouts or bad blocks in a row.
+    TooManyErrors,
+    /// A block was out of sequence, the transfer can't recover from that.
+    OutOfSequence,
+    /// The data doesn't fit into the destination.
+    TooLarge,
+    Io(io::Error),
+}
+
+/// CRC-16/XMODEM (polynomial 0x1021, initial value 0) of `data`.
+pub fn crc16(data: &[u8]) -> u16 {
+    data.iter().fold(0, |crc, byte| {
+        (0..8).fold(crc ^ ((*byte as u16) << 8), |crc, _| {
+            if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            }
+        })
+    })
+}
+
+/// Receives a file from `input` into `dest`, answering the sender on `output`.
+///
+/// Returns the number of bytes received, which includes the [PADDING] of the last block.
+pub fn receive<In: Read, Out: Write>(
+    input: &mut In,
+    output: &mut Out,
+    dest: &mut [u8],
+) -> Result<usize, XmodemError> {
+    let mut buffer = [0_u8; 1024];
+    let mut received = 0;
+    let mut expected: u8 = 1;
+    let mut started = false;
+    let mut errors = 0;
+    let mut reply = CRC_START;
+    loop {
+        if errors > MAX_ERRORS {
+            cancel(output);
+            return Err(XmodemError::TooManyErrors);
+        }
+        send(output, reply)?;
+        let Some(header) = read_byte(input)? else {
+            // nothing arrived in time, ask again
+            errors += 1;
+            reply = if started { NAK } else { CRC_START };
+            continue;
+        };
+        let len = match header {
+            SOH => 128,
+            STX => 1024,
+            EOT if started => {
+                send(output, ACK)?;
+                return Ok(received);
+            }
+            CAN | ESC if !started => return Err(XmodemError::Cancelled),
+            CAN if read_byte(input)? == Some(CAN) => return Err(XmodemError::Cancelled),
+            _ => {
+                // noise, wait for the line to be quiet and ask again
+                purge(input)?;
+                errors += 1;
+                reply = if started { NAK } else { CRC_START };
+                continue;
+            }
+        };
+        started = true;
+        let block = &mut buffer[..len];
+        let Some((number, complement, crc)) = read_block(input, block)? else {
+            errors += 1;
+            reply = NAK;
+            continue;
+        };
+        if number != !complement || crc != crc16(block) {
+            purge(input)?;
+            errors += 1;
+            reply = NAK;
+            continue;
+        }
+        if number == expected.wrapping_sub(1) {
+            // our ACK got lost, the sender repeated the last block
+            reply = ACK;
+            continue;
+        }
+        if number != expected {
+            cancel(output);
+            return Err(XmodemError::OutOfSequence);
+        }
+        let Some(target) = dest.get_mut(received..received + len) else {
+            cancel(output);
+            return Err(XmodemError::TooLarge);
+        };
+        target.copy_from_slice(block);
+        received += len;
+        expected = expected.wrapping_add(1);
+        errors = 0;
+        reply = ACK;
+    }
+}
+
+/// Reads one byte, `None` if the input timed out.
+fn read_byte<In: Read>(input: &mut In) -> Result<Option<u8>, XmodemError> {
+    let mut byte = [0_u8];
+    match input.read_exact(&mut byte) {
+        Ok(()) => Ok(Some(byte[0])),
+        Err(io::Error::TimedOut | io::Error::WouldBlock) => Ok(None),
+        Err(e) => Err(XmodemError::Io(e)),
+    }
+}
+
+/// Reads the rest of a block after its header into `data`, returns the block number, its
+/// complement and the CRC, or `None` if the input timed out.
+fn read_block<In: Read>(
+    input: &mut In,
+    data: &mut [u8],
+) -> Result<Option<(u8, u8, u16)>, XmodemError> {
+    let mut number = [0_u8; 2];
+    let mut crc = [0_u8; 2];
+    for buf in [&mut number[..], data, &mut crc[..]] {
+        match input.read_exact(buf) {
+            Ok(()) => {}
+            Err(io::Error::TimedOut | io::Error::WouldBlock) => return Ok(None),
+            Err(e) => return Err(XmodemError::Io(e)),
+        }
+    }
+    Ok(Some((number[0], number[1], u16::from_be_bytes(crc))))
+}
+
+/// Discards the input until the line is quiet.
+fn purge<In: Read>(input: &mut In) -> Result<(), XmodemError> {
+    while read_byte(input)?.is_some() {}
+    Ok(())
+}
+
+fn send<Out: Write>(output: &mut Out, byte: u8) -> Result<(), XmodemError> {
+    output
+        .write_all(&[byte])
+        .and_then(|_| output.flush())
+        .map_err(XmodemError::Io)
+}
+
+fn cancel<Out: Write>(output: &mut Out) {
+    let _ = output.write_all(&[CAN, CAN]);
+    let _ = output.flush();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    extern crate std;
+    use core::cell::RefCell;
+    use std::collections::VecDeque;
+    use std::vec::Vec;
+
+    /// The sending side, answering what the receiver writes. Reading times out when the sender
+    /// has nothing to say.
+    struct Sender<'a> {
+        data: &'a [u8],
+        block_len: usize,
+        /// Index of the block that is sent next.
+        block: usize,
+        pending: VecDeque<u8>,
+        /// Number of `C` that are ignored, as if the sender was started late.
+        ignore_starts: usize,
+        /// Block whose CRC is corrupted the first time it is sent.
+        corrupt: Option<usize>,
+        /// Block that gets lost the first time it is sent.
+        lose: Option<usize>,
+        replies: Vec<u8>,
+    }
+
+    impl<'a> Sender<'a> {
+        fn new(data: &'a [u8], block_len: usize) -> Self {
+            Self {
+                data,
+                block_len,
+                block: 0,
+                pending: VecDeque::new(),
+                ignore_starts: 0,
+                corrupt: None,
+                lose: None,
+                replies: Vec::new(),
+            }
+        }
+
+        fn queue_block(&mut self) {
+            let start = self.block * self.block_len;
+            if start >= self.data.len() {
+                self.pending.push_back(EOT);
+                return;
+            }
+            let mut block = std::vec![PADDING; self.block_len];
+            let end = self.data.len().min(start + self.block_len);
+            block[..end - start].copy_from_slice(&self.data[start..end]);
+            if self.lose == Some(self.block) {
+                self.lose = None;
+                return;
+            }
+            let number = (self.block + 1) as u8;
+            let mut crc = crc16(&block);
+            if self.corrupt == Some(self.block) {
+                self.corrupt = None;
+                crc ^= 1;
+            }
+            self.pending
+                .push_back(if self.block_len == 128 { SOH } else { STX });
+            self.pending.extend([number, !number]);
+            self.pending.extend(block);
+            self.pending.extend(crc.to_be_bytes());
+        }
+
+        fn answer(&mut self, byte: u8) {
+            self.replies.push(byte);
+            match byte {
+                CRC_START if self.ignore_starts > 0 => self.ignore_starts -= 1,
+                CRC_START => self.queue_block(),
+                ACK => {
+                    self.block += 1;
+                    self.queue_block();
+                }
+                NAK => self.queue_block(),
+                _ => {}
+            }
+        }
+    }
+
+    /// Both ends of the line, the receiver reads from and writes to the same sender.
+    struct Line<'a>(RefCell<Sender<'a>>);
+
+    impl Read for &Line<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<io::Size> {
+            let byte = self.0.borrow_mut().pending.pop_front();
+            let Some(byte) = byte else {
+                return Err(io::Error::TimedOut);
+            };
+            buf[0] = byte;
+            Ok(io::Size::from_usize(1))
+        }
+    }
+
+    impl Write for &Line<'_> {
+        fn write(&mut self, buf: &[u8]) -> io::Result<io::Size> {
+            buf.iter().for_each(|byte| self.0.borrow_mut().answer(*byte));
+            Ok(io::Size::from_usize(buf.len()))
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Receives into `dest` from `sender`, returns the result and the bytes the receiver sent.
+    fn transfer(sender: Sender, dest: &mut [u8]) -> (Result<usize, XmodemError>, Vec<u8>) {
+        let line = Line(RefCell::new(sender));
+        let result = receive(&mut &line, &mut &line, dest);
+        (result, line.0.into_inner().replies)
+    }
+
+    fn test_data(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 7 + i / 256) as u8).collect()
+    }
+
+    #[test]
+    fn crc16_check_value() {
+        assert_eq!(0x31c3, crc16(b"123456789"));
+        assert_eq!(0, crc16(&[]));
+    }
+
+    #[test]
+    fn receives_128_byte_blocks() {
+        let data = test_data(300);
+        let mut sender = Sender::new(&data, 128);
+        sender.ignore_starts = 2;
+        let mut dest = [0; 512];
+        let (result, replies) = transfer(sender, &mut dest);
+        assert_eq!(Ok(384), result);
+        assert_eq!(&data[..], &dest[..300]);
+        assert!(dest[300..384].iter().all(|b| *b == PADDING));
+        assert!(dest[384..].iter().all(|b| *b == 0));
+        assert_eq!(&[CRC_START, CRC_START, CRC_START, ACK, ACK, ACK, ACK], &replies[..]);
+    }
+
+    #[test]
+    fn receives_1k_blocks() {
+        let data = test_data(2048);
+        let mut dest = std::vec![0; 2048];
+        assert_eq!(Ok(2048), transfer(Sender::new(&data, 1024), &mut dest).0);
+        assert_eq!(data, dest);
+    }
+
+    #[test]
+    fn repeats_corrupted_block() {
+        let data = test_data(256);
+        let mut sender = Sender::new(&data, 128);
+        sender.corrupt = Some(1);
+        let mut dest = [0; 256];
+        let (result, replies) = transfer(sender, &mut dest);
+        assert_eq!(Ok(256), result);
+        assert_eq!(&data[..], &dest[..]);
+        assert_eq!(&[CRC_START, ACK, NAK, ACK, ACK], &replies[..]);
+
+        let mut sender = Sender::new(&data, 128);
+        sender.lose = Some(1);
+        let (result, replies) = transfer(sender, &mut dest);
+        assert_eq!(Ok(256), result);
+        assert_eq!(&[CRC_START, ACK, NAK, ACK, ACK], &replies[..]);
+    }
+
+    #[test]
+    fn gives_up() {
+        let data = test_data(256);
+        let mut dest = [0; 200];
+        let (result, replies) = transfer(Sender::new(&data, 128), &mut dest);
+        assert_eq!(Err(XmodemError::TooLarge), result);
+        assert!(replies.ends_with(&[ACK, CAN, CAN]));
+
+        let mut sender = Sender::new(&data, 128);
+        sender.ignore_starts = usize::MAX;
+        let (result, replies) = transfer(sender, &mut dest);
+        assert_eq!(Err(XmodemError::TooManyErrors), result);
+        assert_eq!(MAX_ERRORS + 1, replies.iter().filter(|b| **b == CRC_START).count());
+        assert!(replies.ends_with(&[CAN, CAN]));
+
+        let mut input: &[u8] = &[CAN, CAN];
+        let mut output = [0; 4];
+        assert_eq!(
+            Err(XmodemError::Cancelled),
+            receive(&mut input, &mut &mut output[..], &mut dest)
+        );
+    }
+}
@@ -71,6 +71,15 @@ impl<T> Mutex<T> {
         }
     }
 
+    /// The value without the lock, for code that runs before the lock can work, e.g. before
+    /// the MMU makes the memory of an atomic lock cacheable.
+    /// ### Safety
+    /// Nothing else may use the value meanwhile, through the lock or like this.
+    #[allow(clippy::mut_from_ref)]
+    pub unsafe fn get_unlocked(&self) -> &mut T {
+        &mut *self.inner.get()
+    }
+
     // pub fn unlock(guard: MutexGuard<'_, T>) {
     //     drop(guard)
     // }
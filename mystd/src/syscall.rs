@@ -0,0 +1,85 @@
+//! The system calls of the kernel for programs at EL0.
+//!
+//! A program puts the number of the [Syscall] in `x8` and its arguments in `x0` to `x2`, then
+//! executes `svc #0`. The result comes back in `x0`, see [encode_result].
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u64)]
+pub enum Syscall {
+    /// Ends the program with the exit code in `x0`, doesn't return.
+    Exit = 0,
+    /// Writes the `x1` bytes at `x0` to the console, returns the number written.
+    Write = 1,
+    /// Reads up to `x1` bytes from the input to `x0`, waits for at least one. Returns the number
+    /// read.
+    Read = 2,
+    /// Sleeps for the nanoseconds in `x0`.
+    Sleep = 3,
+    /// Returns the nanoseconds since the system started.
+    Time = 4,
+}
+
+impl TryFrom<u64> for Syscall {
+    type Error = SyscallError;
+
+    fn try_from(value: u64) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(Self::Exit),
+            1 => Ok(Self::Write),
+            2 => Ok(Self::Read),
+            3 => Ok(Self::Sleep),
+            4 => Ok(Self::Time),
+            _ => Err(SyscallError::UnknownCall),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u64)]
+pub enum SyscallError {
+    UnknownCall = 1,
+    /// A buffer isn't mapped in the program, or not writable for a read.
+    BadAddress = 2,
+}
+
+impl SyscallError {
+    const ALL: [Self; 2] = [Self::UnknownCall, Self::BadAddress];
+}
+
+/// Errors are returned as their negated code, so the largest 4095 values of `x0` are errors, like
+/// on Linux.
+pub fn encode_result(result: Result<u64, SyscallError>) -> u64 {
+    match result {
+        Ok(value) => value,
+        Err(error) => (error as u64).wrapping_neg(),
+    }
+}
+
+pub fn decode_result(value: u64) -> Result<u64, SyscallError> {
+    let code = value.wrapping_neg();
+    match SyscallError::ALL
+        .iter()
+        .find(|&&error| error as u64 == code)
+    {
+        Some(&error) => Err(error),
+        None => Ok(value),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn results_survive_the_register() {
+        for result in [Ok(0), Ok(42), Ok(u64::MAX / 2)] {
+            assert_eq!(decode_result(encode_result(result)), result);
+        }
+        for error in SyscallError::ALL {
+            assert_eq!(encode_result(Err(error)) as i64, -(error as i64));
+            assert_eq!(decode_result(encode_result(Err(error))), Err(error));
+        }
+        assert_eq!(Syscall::try_from(2), Ok(Syscall::Read));
+        assert_eq!(Syscall::try_from(99), Err(SyscallError::UnknownCall));
+    }
+}
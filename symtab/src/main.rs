@@ -0,0 +1,337 @@
+//! Embeds the symbol table of the linked kernel into the kernel itself.
+//!
+//! The ELF symbols are demangled, sorted by address and written into the `.symbols` section that
+//! `link64.x` reserves, in the format `monitor::symbols::SymbolTable` reads. The ELF file is
+//! patched in place, so run this after building and before `objcopy`:
+//!
+//! ```sh
+//! cargo run --release -- ../kernel/target/aarch64-unknown-none/release/picrust
+//! ```
+
+use std::{env, fs, process};
+
+const MAGIC: &[u8; 4] = b"SYMT";
+const SECTION_NAME: &[u8] = b".symbols";
+
+const SHT_SYMTAB: u32 = 2;
+const SHN_UNDEF: u16 = 0;
+const SHN_LORESERVE: u16 = 0xff00;
+const STT_NOTYPE: u8 = 0;
+const STT_OBJECT: u8 = 1;
+const STT_FUNC: u8 = 2;
+
+fn main() {
+    let Some(path) = env::args().nth(1) else {
+        eprintln!("usage: symtab <kernel elf>");
+        process::exit(2);
+    };
+    if let Err(message) = run(&path) {
+        eprintln!("symtab: {message}");
+        process::exit(1);
+    }
+}
+
+fn run(path: &str) -> Result<(), String> {
+    let mut elf = fs::read(path).map_err(|e| format!("can't read {path}: {e}"))?;
+    let sections = read_sections(&elf)?;
+    let symbols = read_symbols(&elf, &sections)?;
+    let table = encode(&symbols);
+
+    let (offset, size) = sections
+        .iter()
+        .find(|section| section.name == SECTION_NAME)
+        .map(|section| (section.offset, section.size))
+        .ok_or("no .symbols section, is the kernel linked with link64.x?")?;
+    if table.len() > size {
+        return Err(format!(
+            "the table needs {} bytes, only {size} are reserved",
+            table.len()
+        ));
+    }
+    let reserved = &mut elf[offset..offset + size];
+    reserved.fill(0);
+    reserved[..table.len()].copy_from_slice(&table);
+    fs::write(path, &elf).map_err(|e| format!("can't write {path}: {e}"))?;
+
+    println!(
+        "symtab: {} symbols, {} of {} bytes",
+        symbols.len(),
+        table.len(),
+        size
+    );
+    Ok(())
+}
+
+struct Section<'a> {
+    name: &'a [u8],
+    kind: u32,
+    offset: usize,
+    size: usize,
+    link: usize,
+}
+
+#[derive(Debug, PartialEq)]
+struct Symbol {
+    address: u64,
+    size: u64,
+    name: String,
+}
+
+fn read<const N: usize>(data: &[u8], offset: usize) -> Result<[u8; N], String> {
+    data.get(offset..offset + N)
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| format!("truncated ELF file, can't read at {offset:#x}"))
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Result<u16, String> {
+    read(data, offset).map(u16::from_le_bytes)
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Result<u32, String> {
+    read(data, offset).map(u32::from_le_bytes)
+}
+
+fn read_u64(data: &[u8], offset: usize) -> Result<u64, String> {
+    read(data, offset).map(u64::from_le_bytes)
+}
+
+/// The NUL terminated string at `offset`.
+fn read_str(data: &[u8], offset: usize) -> Result<&[u8], String> {
+    let rest = data
+        .get(offset..)
+        .ok_or_else(|| format!("string at {offset:#x} is outside of the file"))?;
+    let len = rest.iter().position(|c| *c == 0).unwrap_or(rest.len());
+    Ok(&rest[..len])
+}
+
+fn read_sections(elf: &[u8]) -> Result<Vec<Section<'_>>, String> {
+    if !elf.starts_with(b"\x7fELF") || elf.get(4) != Some(&2) || elf.get(5) != Some(&1) {
+        return Err("not a 64 bit little endian ELF file".into());
+    }
+    let header_offset = read_u64(elf, 0x28)? as usize;
+    let header_size = read_u16(elf, 0x3a)? as usize;
+    let count = read_u16(elf, 0x3c)? as usize;
+    let names_index = read_u16(elf, 0x3e)? as usize;
+
+    let mut headers = Vec::with_capacity(count);
+    for i in 0..count {
+        let header = header_offset + i * header_size;
+        headers.push((
+            read_u32(elf, header)? as usize,
+            read_u32(elf, header + 4)?,
+            read_u64(elf, header + 0x18)? as usize,
+            read_u64(elf, header + 0x20)? as usize,
+            read_u32(elf, header + 0x28)? as usize,
+        ));
+    }
+    let names_offset = headers
+        .get(names_index)
+        .map(|(_, _, offset, _, _)| *offset)
+        .ok_or("missing section name table")?;
+    headers
+        .into_iter()
+        .map(|(name, kind, offset, size, link)| {
+            Ok(Section {
+                name: read_str(elf, names_offset + name)?,
+                kind,
+                offset,
+                size,
+                link,
+            })
+        })
+        .collect()
+}
+
+fn read_symbols(elf: &[u8], sections: &[Section]) -> Result<Vec<Symbol>, String> {
+    const ENTRY_LEN: usize = 24;
+    let table = sections
+        .iter()
+        .find(|section| section.kind == SHT_SYMTAB)
+        .ok_or("no symbol table, is the kernel stripped?")?;
+    let strings = sections
+        .get(table.link)
+        .ok_or("missing string table of the symbol table")?;
+
+    let mut symbols = Vec::new();
+    // the first entry is always the undefined symbol
+    for i in 1..table.size / ENTRY_LEN {
+        let entry = table.offset + i * ENTRY_LEN;
+        let name = read_u32(elf, entry)? as usize;
+        let kind = read(elf, entry + 4).map(|[info]: [u8; 1]| info & 0xf)?;
+        let section = read_u16(elf, entry + 6)?;
+        if !matches!(kind, STT_NOTYPE | STT_OBJECT | STT_FUNC)
+            || section == SHN_UNDEF
+            || section >= SHN_LORESERVE
+        {
+            continue;
+        }
+        let name = read_str(elf, strings.offset + name)?;
+        // skip mapping symbols ($x, $d) and assembler locals
+        if name.is_empty() || name.starts_with(b"$") || name.starts_with(b".L") {
+            continue;
+        }
+        symbols.push(Symbol {
+            address: read_u64(elf, entry + 8)?,
+            size: read_u64(elf, entry + 16)?,
+            name: demangle(&String::from_utf8_lossy(name)),
+        });
+    }
+    // labels before sized symbols at the same address, so an address lookup finds the latter
+    symbols.sort_by(|a, b| {
+        (a.address, a.size != 0, &a.name).cmp(&(b.address, b.size != 0, &b.name))
+    });
+    symbols.dedup();
+    Ok(symbols)
+}
+
+/// Encodes the table, see `monitor::symbols` for the format.
+fn encode(symbols: &[Symbol]) -> Vec<u8> {
+    const HEADER_LEN: usize = 8;
+    const ENTRY_LEN: usize = 16;
+    let mut table = Vec::new();
+    table.extend_from_slice(MAGIC);
+    table.extend_from_slice(&(symbols.len() as u32).to_le_bytes());
+
+    let mut name_offset = HEADER_LEN + symbols.len() * ENTRY_LEN;
+    for symbol in symbols {
+        table.extend_from_slice(&symbol.address.to_le_bytes());
+        table.extend_from_slice(&(symbol.size as u32).to_le_bytes());
+        table.extend_from_slice(&(name_offset as u32).to_le_bytes());
+        name_offset += symbol.name.len() + 1;
+    }
+    for symbol in symbols {
+        table.extend_from_slice(symbol.name.as_bytes());
+        table.push(0);
+    }
+    table
+}
+
+/// Demangles the legacy Rust mangling (`_ZN...E`), dropping the trailing hash.
+///
+/// Names in any other form are returned as they are.
+fn demangle(name: &str) -> String {
+    let Some(mut rest) = name.strip_prefix("_ZN") else {
+        return name.to_string();
+    };
+    let mut parts = Vec::new();
+    while !rest.starts_with('E') {
+        let digits = rest.bytes().take_while(u8::is_ascii_digit).count();
+        let Ok(len) = rest[..digits].parse::<usize>() else {
+            return name.to_string();
+        };
+        let Some(part) = rest.get(digits..digits + len) else {
+            return name.to_string();
+        };
+        parts.push(part);
+        rest = &rest[digits + len..];
+    }
+    if parts.last().is_some_and(|part| is_hash(part)) {
+        parts.pop();
+    }
+    parts
+        .iter()
+        .map(|part| unescape(part))
+        .collect::<Vec<_>>()
+        .join("::")
+}
+
+fn is_hash(part: &str) -> bool {
+    part.len() == 17
+        && part.starts_with('h')
+        && part[1..].bytes().all(|c| c.is_ascii_hexdigit())
+}
+
+fn unescape(part: &str) -> String {
+    // identifiers starting with an escape get a leading underscore
+    let mut rest = part.strip_prefix("_$").map_or(part, |_| &part[1..]);
+    let mut result = String::new();
+    while !rest.is_empty() {
+        if let Some(tail) = rest.strip_prefix("..") {
+            result.push_str("::");
+            rest = tail;
+        } else if let Some((escape, tail)) = rest
+            .strip_prefix('$')
+            .and_then(|escaped| escaped.split_once('$'))
+        {
+            match escape {
+                "SP" => result.push('@'),
+                "BP" => result.push('*'),
+                "RF" => result.push('&'),
+                "LT" => result.push('<'),
+                "GT" => result.push('>'),
+                "LP" => result.push('('),
+                "RP" => result.push(')'),
+                "C" => result.push(','),
+                _ => match escape
+                    .strip_prefix('u')
+                    .and_then(|code| u32::from_str_radix(code, 16).ok())
+                    .and_then(char::from_u32)
+                {
+                    Some(c) => result.push(c),
+                    None => {
+                        result.push('$');
+                        result.push_str(escape);
+                        result.push('$');
+                    }
+                },
+            }
+            rest = tail;
+        } else {
+            let c = rest.chars().next().unwrap_or_default();
+            result.push(c);
+            rest = &rest[c.len_utf8()..];
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn demangle_paths() {
+        assert_eq!(
+            "picrust::system::arm_core::reset",
+            demangle("_ZN7picrust6system8arm_core5reset17h74d193767c4c4d75E")
+        );
+        assert_eq!(
+            "<&T as core::fmt::Display>::fmt",
+            demangle("_ZN44_$LT$$RF$T$u20$as$u20$core..fmt..Display$GT$3fmt17h1845268594d98c17E")
+        );
+        assert_eq!(
+            "monitor::disasm::Instruction::load_store::{{closure}}",
+            demangle("_ZN7monitor6disasm11Instruction10load_store28_$u7b$$u7b$closure$u7d$$u7d$17haf0da5322e6f18e1E")
+        );
+    }
+
+    #[test]
+    fn demangle_leaves_other_names() {
+        assert_eq!("main", demangle("main"));
+        assert_eq!("__bss_start", demangle("__bss_start"));
+        assert_eq!("_ZN3foo", demangle("_ZN3foo"));
+    }
+
+    #[test]
+    fn encode_layout() {
+        let symbols = [
+            Symbol {
+                address: 0x80000,
+                size: 0x20,
+                name: "_start".into(),
+            },
+            Symbol {
+                address: 0x90000,
+                size: 0,
+                name: "__bss_start".into(),
+            },
+        ];
+        let table = encode(&symbols);
+        assert_eq!(b"SYMT\x02\x00\x00\x00", &table[..8]);
+        assert_eq!(&0x80000_u64.to_le_bytes(), &table[8..16]);
+        assert_eq!(&0x20_u32.to_le_bytes(), &table[16..20]);
+        assert_eq!(&40_u32.to_le_bytes(), &table[20..24]);
+        assert_eq!(&47_u32.to_le_bytes(), &table[36..40]);
+        assert_eq!(b"_start\0__bss_start\0", &table[40..]);
+    }
+}
@@ -0,0 +1,43 @@
+//! Greets, then echoes lines of input until an empty one.
+
+#![no_std]
+#![no_main]
+
+use core::time::Duration;
+
+use userland::{entry, print, println};
+
+entry!(main);
+
+fn main() -> i32 {
+    println!(
+        "Hello from EL0, the system is up for {:?}",
+        userland::uptime()
+    );
+    userland::sleep(Duration::from_millis(500));
+    println!("Type lines to echo, an empty one ends the program");
+    let mut line = [0; 80];
+    let mut len = 0;
+    loop {
+        let mut byte = [0];
+        if userland::read(&mut byte).is_err() {
+            return 1;
+        }
+        match byte[0] {
+            b'\r' | b'\n' if len == 0 => break,
+            b'\r' | b'\n' => {
+                let text = core::str::from_utf8(&line[..len]).unwrap_or("?");
+                println!("\n{}", text);
+                len = 0;
+            }
+            byte if len < line.len() => {
+                line[len] = byte;
+                len += 1;
+                print!("{}", byte as char);
+            }
+            _ => {}
+        }
+    }
+    println!("\nBye");
+    0
+}
@@ -0,0 +1,114 @@
+//! The system calls of the PiCrust kernel for programs at EL0, see [mystd::syscall].
+//!
+//! A program is a `#![no_std]` `#![no_main]` binary that names its main function with [entry!]
+//! and is linked with `user.x`. The kernel loads the ELF file to the user range of a new process.
+
+#![no_std]
+
+use core::arch::asm;
+use core::fmt;
+use core::time::Duration;
+
+use mystd::syscall::decode_result;
+pub use mystd::syscall::{Syscall, SyscallError};
+
+/// Calls the kernel with up to three arguments.
+///
+/// # Safety
+/// Pointers in the arguments must be valid for the call.
+pub unsafe fn syscall(call: Syscall, arguments: [u64; 3]) -> Result<u64, SyscallError> {
+    let mut result = arguments[0];
+    asm!(
+        "svc #0",
+        inout("x0") result,
+        in("x1") arguments[1],
+        in("x2") arguments[2],
+        in("x8") call as u64,
+        options(nostack)
+    );
+    decode_result(result)
+}
+
+/// Writes `buffer` to the console, returns the number of bytes written.
+pub fn write(buffer: &[u8]) -> Result<usize, SyscallError> {
+    // SAFETY: the kernel only reads the buffer
+    let arguments = [buffer.as_ptr() as u64, buffer.len() as u64, 0];
+    unsafe { syscall(Syscall::Write, arguments) }.map(|count| count as usize)
+}
+
+/// Reads at least one byte of input to `buffer`, waits for it if there is none.
+pub fn read(buffer: &mut [u8]) -> Result<usize, SyscallError> {
+    // SAFETY: the kernel writes no more than the length of the buffer
+    let arguments = [buffer.as_mut_ptr() as u64, buffer.len() as u64, 0];
+    unsafe { syscall(Syscall::Read, arguments) }.map(|count| count as usize)
+}
+
+pub fn sleep(duration: Duration) {
+    // SAFETY: no pointers
+    let _ = unsafe { syscall(Syscall::Sleep, [duration.as_nanos() as u64, 0, 0]) };
+}
+
+/// The time since the system started.
+pub fn uptime() -> Duration {
+    // SAFETY: no pointers
+    let nanos = unsafe { syscall(Syscall::Time, [0; 3]) };
+    Duration::from_nanos(nanos.unwrap_or(0))
+}
+
+/// Ends the program with `code`.
+pub fn exit(code: i32) -> ! {
+    // SAFETY: no pointers, and the call doesn't return
+    let _ = unsafe { syscall(Syscall::Exit, [code as u64, 0, 0]) };
+    unreachable!("the kernel ended the program")
+}
+
+/// The console, for [print!] and [println!].
+pub struct Console;
+
+impl fmt::Write for Console {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let mut bytes = s.as_bytes();
+        while !bytes.is_empty() {
+            let count = write(bytes).map_err(|_| fmt::Error)?;
+            bytes = &bytes[count..];
+        }
+        Ok(())
+    }
+}
+
+#[doc(hidden)]
+pub fn _print(args: fmt::Arguments) {
+    let _ = fmt::Write::write_fmt(&mut Console, args);
+}
+
+#[macro_export]
+macro_rules! print {
+    ($($arg:tt)*) => ($crate::_print(format_args!($($arg)*)));
+}
+
+#[macro_export]
+macro_rules! println {
+    () => ($crate::print!("\n"));
+    ($($arg:tt)*) => ($crate::print!("{}\n", format_args!($($arg)*)));
+}
+
+/// Defines the entry point of the program, which calls `$main` and exits with the code it returns.
+#[macro_export]
+macro_rules! entry {
+    ($main:path) => {
+        #[no_mangle]
+        #[link_section = ".text._start"]
+        pub extern "C" fn _start() -> ! {
+            let main: fn() -> i32 = $main;
+            $crate::exit(main())
+        }
+    };
+}
+
+/// A panicking program prints the message and exits with -1.
+#[cfg(not(test))]
+#[panic_handler]
+fn panic(info: &core::panic::PanicInfo) -> ! {
+    println!("{}", info);
+    exit(-1)
+}
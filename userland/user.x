@@ -0,0 +1,13 @@
+/* Programs run in the user range of the kernel's processes, the stack is at its end. */
+ENTRY(_start)
+
+SECTIONS
+{
+    . = 0x8000000000;
+    /* every section starts a page, the kernel maps segments page by page */
+    .text : ALIGN(4096) { KEEP(*(.text._start)) *(.text .text.*) }
+    .rodata : ALIGN(4096) { *(.rodata .rodata.*) }
+    .data : ALIGN(4096) { *(.data .data.*) }
+    .bss : ALIGN(16) { *(.bss .bss.*) *(COMMON) }
+    /DISCARD/ : { *(.comment) *(.ARM.exidx*) }
+}